@@ -0,0 +1,289 @@
+use anyhow::{anyhow, Error, Result};
+use std::collections::BTreeMap;
+use std::str::FromStr;
+
+/// Names (`;` separated) of the project variables set by the activation hook,
+/// so they could be unset once leaving the project.
+pub const ACTIVE_VARS_VARIABLE: &str = "ASDFW_ACTIVE_VARS";
+/// The values the project variables had before entering the project (a JSON
+/// map, `null` for variables that were not set), restored once leaving it.
+pub const SAVED_VARS_VARIABLE: &str = "ASDFW_SAVED_VARS";
+
+/// Interactive shells `asdfw activate` generates hooks for.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HookShell {
+    PowerShell,
+    /// cmd.exe with Clink.
+    Cmd,
+    Nu,
+}
+
+impl FromStr for HookShell {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "powershell" | "pwsh" => Ok(HookShell::PowerShell),
+            "cmd" | "clink" => Ok(HookShell::Cmd),
+            "nu" | "nushell" => Ok(HookShell::Nu),
+            _ => Err(anyhow!("unsupported shell '{}' (supported: powershell, cmd, nu)", s)),
+        }
+    }
+}
+
+const POWERSHELL_HOOK: &str = r#"# Generated by `asdfw activate powershell`
+asdfw.exe completion | Out-String | Invoke-Expression
+
+$global:AsdfwLastDir = $null
+# Activating again (e.g. reloading the profile) must not wrap our own prompt
+if (-not (Test-Path variable:global:AsdfwOriginalPrompt)) {
+    $global:AsdfwOriginalPrompt = $function:prompt
+}
+function global:prompt {
+    $dir = (Get-Location).ProviderPath
+    if ($dir -ne $global:AsdfwLastDir) {
+        $global:AsdfwLastDir = $dir
+        asdfw.exe hook-env powershell | Out-String | Invoke-Expression
+    }
+    & $global:AsdfwOriginalPrompt
+}
+"#;
+
+const CLINK_HOOK: &str = r#"-- Generated by `asdfw activate cmd`
+local function asdfw_lines(command)
+    local lines = {}
+    local f = io.popen(command .. " 2>nul")
+    if f then
+        for line in f:lines() do
+            table.insert(lines, line)
+        end
+        f:close()
+    end
+    return lines
+end
+
+local asdfw_last_dir
+local asdfw_filter = clink.promptfilter(1)
+function asdfw_filter:filter(prompt)
+    local dir = os.getcwd()
+    if dir == asdfw_last_dir then
+        return
+    end
+    asdfw_last_dir = dir
+    for _, line in ipairs(asdfw_lines("asdfw.exe hook-env cmd")) do
+        local key, value = line:match('^set "([^=]+)=(.*)"$')
+        if key then
+            os.setenv(key, value ~= "" and value or nil)
+        end
+    end
+end
+
+local function asdfw_tools()
+    return asdfw_lines("asdfw.exe complete tools")
+end
+
+local function asdfw_commands()
+    return asdfw_lines("asdfw.exe complete commands")
+end
+
+local asdfw_with_tool = clink.argmatcher():addarg(asdfw_tools)
+local asdfw_with_command = clink.argmatcher():addarg(asdfw_commands)
+clink.argmatcher("asdfw"):addarg({
+    "local" .. asdfw_with_tool,
+    "global" .. asdfw_with_tool,
+    "current" .. asdfw_with_tool,
+    "why" .. asdfw_with_tool,
+    "which" .. asdfw_with_command,
+    "tool-of" .. asdfw_with_command,
+    "exec" .. asdfw_with_command,
+    {SUBCOMMANDS}
+})
+"#;
+
+/// Subcommands the Clink script completes along with their arguments.
+const CLINK_COMPLETED_WITH_ARGS: &[&str] = &["local", "global", "current", "why", "which", "tool-of", "exec"];
+
+const NU_HOOK: &str = r#"# Generated by `asdfw activate nu`
+def "nu-complete asdfw subcommands" [] {
+    [{SUBCOMMANDS}]
+}
+
+def "nu-complete asdfw tools" [] {
+    ^asdfw complete tools | lines
+}
+
+export extern "asdfw" [
+    subcommand?: string@"nu-complete asdfw subcommands"
+    tool?: string@"nu-complete asdfw tools"
+    ...rest: string
+]
+
+$env.config = ($env.config | upsert hooks.env_change.PWD {|config|
+    let hooks = ($config.hooks.env_change.PWD? | default [])
+    $hooks | append {|before, after| ^asdfw hook-env nu | from json | load-env }
+})
+"#;
+
+/// Returns the script activating asdfw in the shell: a hook loading the
+/// project environment (`.asdfw.env`) whenever the directory changes, and
+/// completions of the provided subcommands (and of tools and commands).
+pub fn activation_script(shell: HookShell, subcommands: &[String]) -> String {
+    match shell {
+        HookShell::PowerShell => POWERSHELL_HOOK.to_owned(),
+        HookShell::Cmd => {
+            let names: Vec<String> = subcommands
+                .iter()
+                .filter(|name| !CLINK_COMPLETED_WITH_ARGS.contains(&name.as_str()))
+                .map(|name| format!("\"{}\",", name))
+                .collect();
+            CLINK_HOOK.replace("{SUBCOMMANDS}", &names.join("\n    "))
+        }
+        HookShell::Nu => {
+            let names: Vec<String> = subcommands.iter().map(|name| format!("\"{}\"", name)).collect();
+            NU_HOOK.replace("{SUBCOMMANDS}", &names.join(" "))
+        }
+    }
+}
+
+/// Returns the commands moving the shell from the previously active project
+/// variables (the value of `ASDFW_ACTIVE_VARS`) to the provided ones: setting
+/// the new values and restoring the variables of the previous project to the
+/// values saved when entering it (`ASDFW_SAVED_VARS`). `current` returns the
+/// current value of a variable (to save the ones the new project overrides).
+pub fn render_hook_env<F>(
+    shell: HookShell,
+    vars: &[(String, String)],
+    previous: Option<&str>,
+    saved: Option<&str>,
+    current: F,
+) -> Vec<String>
+where
+    F: Fn(&str) -> Option<String>,
+{
+    let saved: BTreeMap<String, Option<String>> =
+        saved.and_then(|saved| serde_json::from_str(saved).ok()).unwrap_or_default();
+    let previous: Vec<&str> = previous.unwrap_or_default().split(';').filter(|name| !name.is_empty()).collect();
+    let mut changes: BTreeMap<&str, Option<String>> = BTreeMap::new();
+    for name in &previous {
+        changes.insert(name, saved.get(*name).cloned().flatten());
+    }
+    let mut originals: BTreeMap<&str, Option<String>> = BTreeMap::new();
+    for (key, value) in vars {
+        let original = if previous.contains(&key.as_str()) {
+            saved.get(key).cloned().flatten()
+        } else {
+            current(key)
+        };
+        originals.insert(key, original);
+        changes.insert(key, Some(value.clone()));
+    }
+    if changes.is_empty() {
+        return vec![];
+    }
+    let active: Vec<&str> = vars.iter().map(|(key, _)| key.as_str()).collect();
+    let (active, originals) = if vars.is_empty() {
+        (None, None)
+    } else {
+        let originals = serde_json::to_string(&originals).expect("string maps are serializable");
+        (Some(active.join(";")), Some(originals))
+    };
+    changes.insert(ACTIVE_VARS_VARIABLE, active);
+    changes.insert(SAVED_VARS_VARIABLE, originals);
+    match shell {
+        HookShell::PowerShell => changes
+            .into_iter()
+            .map(|(key, value)| match value {
+                Some(value) => format!("$Env:{} = '{}'", key, value.replace('\'', "''")),
+                None => format!("Remove-Item Env:{} -ErrorAction SilentlyContinue", key),
+            })
+            .collect(),
+        HookShell::Cmd => changes
+            .into_iter()
+            .map(|(key, value)| format!("set \"{}={}\"", key, value.unwrap_or_default()))
+            .collect(),
+        HookShell::Nu => {
+            // `load-env` can't unset variables, they are set to empty strings
+            let record: BTreeMap<&str, String> =
+                changes.into_iter().map(|(key, value)| (key, value.unwrap_or_default())).collect();
+            vec![serde_json::to_string(&record).expect("string maps are serializable")]
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rstest::rstest;
+
+    fn project_vars() -> Vec<(String, String)> {
+        vec![("NODE_OPTIONS".to_owned(), "--max-old-space-size=4096".to_owned())]
+    }
+
+    fn no_vars(_: &str) -> Option<String> {
+        None
+    }
+
+    #[rustfmt::skip]
+    #[rstest]
+    #[case(HookShell::PowerShell, vec![
+        "$Env:ASDFW_ACTIVE_VARS = 'NODE_OPTIONS'",
+        r#"$Env:ASDFW_SAVED_VARS = '{"NODE_OPTIONS":null}'"#,
+        "$Env:GREETING = 'hello'",
+        "$Env:NODE_OPTIONS = '--max-old-space-size=4096'",
+    ])]
+    #[case(HookShell::Cmd, vec![
+        "set \"ASDFW_ACTIVE_VARS=NODE_OPTIONS\"",
+        r#"set "ASDFW_SAVED_VARS={"NODE_OPTIONS":null}""#,
+        "set \"GREETING=hello\"",
+        "set \"NODE_OPTIONS=--max-old-space-size=4096\"",
+    ])]
+    #[case(HookShell::Nu, vec![
+        r#"{"ASDFW_ACTIVE_VARS":"NODE_OPTIONS","ASDFW_SAVED_VARS":"{\"NODE_OPTIONS\":null}","GREETING":"hello","NODE_OPTIONS":"--max-old-space-size=4096"}"#,
+    ])]
+    fn render_hook_env_sets_new_and_restores_previous_vars(#[case] shell: HookShell, #[case] expected: Vec<&str>) {
+        let saved = r#"{"NODE_OPTIONS":null,"GREETING":"hello"}"#;
+        assert_eq!(render_hook_env(shell, &project_vars(), Some("NODE_OPTIONS;GREETING"), Some(saved), no_vars), expected);
+    }
+
+    #[test]
+    fn render_hook_env_saves_overridden_values() {
+        let current = |name: &str| match name {
+            "NODE_OPTIONS" => Some("--inspect".to_owned()),
+            _ => None,
+        };
+        let entering = render_hook_env(HookShell::PowerShell, &project_vars(), None, None, current);
+        assert!(entering.contains(&r#"$Env:ASDFW_SAVED_VARS = '{"NODE_OPTIONS":"--inspect"}'"#.to_owned()));
+        let saved = r#"{"NODE_OPTIONS":"--inspect"}"#;
+        let leaving = render_hook_env(HookShell::PowerShell, &[], Some("NODE_OPTIONS"), Some(saved), no_vars);
+        assert!(
+            leaving.contains(&"$Env:NODE_OPTIONS = '--inspect'".to_owned()),
+            "not restored: {:?}",
+            leaving
+        );
+        assert!(leaving.contains(&"Remove-Item Env:ASDFW_SAVED_VARS -ErrorAction SilentlyContinue".to_owned()));
+    }
+
+    #[test]
+    fn render_hook_env_outside_projects() {
+        assert!(render_hook_env(HookShell::Cmd, &[], None, None, no_vars).is_empty());
+        assert_eq!(
+            render_hook_env(HookShell::Cmd, &[], Some("GREETING"), None, no_vars),
+            vec![
+                "set \"ASDFW_ACTIVE_VARS=\"",
+                "set \"ASDFW_SAVED_VARS=\"",
+                "set \"GREETING=\""
+            ]
+        );
+    }
+
+    #[test]
+    fn activation_script_lists_subcommands() {
+        let subcommands = vec!["reshim".to_owned(), "local".to_owned(), "install".to_owned()];
+        let clink = activation_script(HookShell::Cmd, &subcommands);
+        assert!(clink.contains("    \"reshim\",\n    \"install\",\n"), "wrong subcommands: {}", clink);
+        assert!(!clink.contains("\"local\","), "local is completed with tools: {}", clink);
+        let nu = activation_script(HookShell::Nu, &subcommands);
+        assert!(nu.contains("[\"reshim\" \"local\" \"install\"]"), "wrong subcommands: {}", nu);
+        assert!(!activation_script(HookShell::PowerShell, &subcommands).contains("{SUBCOMMANDS}"));
+    }
+}
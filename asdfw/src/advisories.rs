@@ -0,0 +1,110 @@
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::Path;
+
+use crate::version::VersionRange;
+
+/// A known vulnerability affecting some versions of a tool.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+pub struct Advisory {
+    /// The advisory identifier (e.g. `CVE-2023-1234`).
+    pub id: String,
+    pub tool: String,
+    /// Version ranges (e.g. `>= 1.2, < 1.2.5`), a version matching any of
+    /// them is affected.
+    pub affected: Vec<String>,
+    #[serde(default)]
+    pub severity: Option<String>,
+    #[serde(default)]
+    pub summary: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct Feed {
+    advisories: Vec<Advisory>,
+}
+
+/// A version (installed or pinned) affected by an advisory.
+#[derive(Debug, PartialEq, Eq, Serialize)]
+pub struct Finding {
+    pub tool: String,
+    pub version: String,
+    pub advisory: String,
+    pub severity: Option<String>,
+    pub summary: String,
+    pub installed: bool,
+    pub pinned: bool,
+}
+
+/// Loads the advisories from a JSON feed (`{"advisories": [...]}`).
+pub fn load(path: &Path) -> Result<Vec<Advisory>> {
+    let context = format!("loading advisories from {:?}", &path);
+    let contents = fs::read_to_string(path).context(context.clone())?;
+    let feed: Feed = serde_json::from_str(&contents).context(context.clone())?;
+    for advisory in &feed.advisories {
+        for range in &advisory.affected {
+            VersionRange::parse(range)
+                .context(format!("advisory {}", &advisory.id))
+                .context(context.clone())?;
+        }
+    }
+    Ok(feed.advisories)
+}
+
+/// Returns the advisories affecting the version of the tool.
+pub fn affecting<'a>(advisories: &'a [Advisory], tool: &str, version: &str) -> Result<Vec<&'a Advisory>> {
+    let mut affecting = vec![];
+    for advisory in advisories.iter().filter(|a| a.tool == tool) {
+        for range in &advisory.affected {
+            if VersionRange::parse(range)?.matches(version) {
+                affecting.push(advisory);
+                break;
+            }
+        }
+    }
+    Ok(affecting)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use assert_fs::{prelude::*, TempDir};
+    use rstest::rstest;
+
+    const FEED: &str = r#"{
+        "advisories": [
+            {"id": "CVE-1", "tool": "kubectl", "affected": [">= 1.20, < 1.23.4", "1.24.0"], "severity": "high"},
+            {"id": "CVE-2", "tool": "kubectl", "affected": ["< 1.22"], "summary": "old"},
+            {"id": "CVE-3", "tool": "helm", "affected": ["< 3"]}
+        ]
+    }"#;
+
+    #[rstest]
+    #[case("kubectl", "1.21.0", vec!["CVE-1", "CVE-2"])]
+    #[case("kubectl", "1.24.0", vec!["CVE-1"])]
+    #[case("kubectl", "1.23.4", vec![])]
+    #[case("terraform", "0.1.0", vec![])]
+    fn affecting_matches_tool_and_ranges(#[case] tool: &str, #[case] version: &str, #[case] expected: Vec<&str>) {
+        let tmp_dir = TempDir::new().unwrap();
+        let file = tmp_dir.child("advisories.json");
+        file.write_str(FEED).unwrap();
+        let advisories = load(file.path()).unwrap();
+        let ids: Vec<&str> = affecting(&advisories, tool, version)
+            .unwrap()
+            .iter()
+            .map(|a| a.id.as_str())
+            .collect();
+        assert_eq!(ids, expected);
+    }
+
+    #[test]
+    fn load_rejects_invalid_ranges() {
+        let tmp_dir = TempDir::new().unwrap();
+        let file = tmp_dir.child("advisories.json");
+        file.write_str(r#"{"advisories": [{"id": "CVE-1", "tool": "kubectl", "affected": [">="]}]}"#)
+            .unwrap();
+        let err = load(file.path()).unwrap_err();
+        assert!(format!("{:#}", err).contains("CVE-1"), "wrong error: {:#}", err);
+    }
+}
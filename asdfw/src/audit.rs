@@ -0,0 +1,117 @@
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::ffi::OsString;
+use std::fs::OpenOptions;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+use crate::stats;
+
+/// A single execution through a shim (or `asdfw exec`). Appended to the audit
+/// file as a JSON line.
+#[derive(Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AuditRecord {
+    /// Seconds since the epoch.
+    pub timestamp: u64,
+    pub user: String,
+    pub cwd: PathBuf,
+    pub tool: String,
+    pub version: String,
+    /// The executable that ran followed by every argument it got (including
+    /// the script and default arguments).
+    pub command_line: Vec<String>,
+    /// `None` if the command could not be run (or did not finish).
+    pub exit_code: Option<i32>,
+    /// Why the command could not be run.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+impl AuditRecord {
+    /// Creates a record of an execution by the current user that just
+    /// finished (or failed to run).
+    pub fn new(cwd: &Path, tool: &str, version: &str, argv: &[OsString], outcome: &Result<i32>) -> Self {
+        AuditRecord {
+            timestamp: stats::now(),
+            user: current_user(),
+            cwd: cwd.to_path_buf(),
+            tool: tool.to_owned(),
+            version: version.to_owned(),
+            command_line: argv.iter().map(|arg| arg.to_string_lossy().into_owned()).collect(),
+            exit_code: outcome.as_ref().ok().copied(),
+            error: outcome.as_ref().err().map(|err| format!("{:#}", err)),
+        }
+    }
+}
+
+/// Appends the record to the audit file.
+pub fn record(path: &Path, record: &AuditRecord) -> Result<()> {
+    let context = || format!("writing audit record to {:?}", &path);
+    let mut line = serde_json::to_string(record).with_context(context)?;
+    line.push('\n');
+    let mut file = OpenOptions::new().create(true).append(true).open(path).with_context(context)?;
+    file.write_all(line.as_bytes()).with_context(context)
+}
+
+fn current_user() -> String {
+    imp::current_user().unwrap_or_else(|| "unknown".to_owned())
+}
+
+#[cfg(windows)]
+mod imp {
+    use std::ffi::OsString;
+    use std::os::windows::ffi::OsStringExt;
+    use winapi::shared::minwindef::DWORD;
+    use winapi::um::winbase::GetUserNameW;
+
+    /// The user of the process token (unlike `USERNAME`, can't be overridden
+    /// by the caller).
+    pub(super) fn current_user() -> Option<String> {
+        // UNLEN + 1
+        let mut buffer = [0u16; 257];
+        let mut len = buffer.len() as DWORD;
+        if unsafe { GetUserNameW(buffer.as_mut_ptr(), &mut len) } == 0 || len == 0 {
+            return None;
+        }
+        // The returned length includes the terminating null
+        let name = OsString::from_wide(&buffer[..len as usize - 1]);
+        Some(name.to_string_lossy().into_owned())
+    }
+}
+
+#[cfg(not(windows))]
+mod imp {
+    pub(super) fn current_user() -> Option<String> {
+        std::env::var("USER").ok()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use assert_fs::{prelude::*, TempDir};
+    use std::fs;
+
+    #[test]
+    fn record_appends_json_lines() {
+        let tmp_dir = TempDir::new().unwrap();
+        let file = tmp_dir.child("audit.jsonl");
+        let program = Path::new("installs").join("kubectl").join("1.23.0").join("bin").join("kubectl.exe");
+        let argv = vec![program.into_os_string(), OsString::from("get"), OsString::from("pods")];
+        let first = AuditRecord::new(tmp_dir.path(), "kubectl", "1.23.0", &argv, &Ok(0));
+        let failed = Err(anyhow::anyhow!("The system cannot find the file specified"));
+        let second = AuditRecord::new(tmp_dir.path(), "kubectl", "1.23.0", &argv[..1], &failed);
+        record(file.path(), &first).unwrap();
+        record(file.path(), &second).unwrap();
+        let contents = fs::read_to_string(file.path()).unwrap();
+        let lines: Vec<&str> = contents.lines().collect();
+        assert_eq!(lines.len(), 2);
+        let loaded: AuditRecord = serde_json::from_str(lines[0]).unwrap();
+        assert_eq!(loaded, first);
+        assert_eq!(loaded.command_line[1..], ["get", "pods"]);
+        assert!(!lines[0].contains("\"error\""));
+        let loaded: AuditRecord = serde_json::from_str(lines[1]).unwrap();
+        assert_eq!(loaded.exit_code, None);
+        assert_eq!(loaded.error.as_deref(), Some("The system cannot find the file specified"));
+    }
+}
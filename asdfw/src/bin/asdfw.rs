@@ -1,14 +1,47 @@
-use anyhow::Result;
+use anyhow::{anyhow, Context, Result};
+use asdfw::activate::{self, HookShell, ACTIVE_VARS_VARIABLE, SAVED_VARS_VARIABLE};
+use asdfw::advisories::{self, Finding};
+use asdfw::audit::{self, AuditRecord};
+use asdfw::check;
 use asdfw::common::*;
-use asdfw::runtime::RuntimeEnvironment;
-use asdfw::shims::Shims;
-use asdfw::{output::*, tool_versions::ToolVersions};
-use clap::{IntoApp, Parser};
+use asdfw::completion::{self, Candidates};
+use asdfw::config_edit;
+use asdfw::doctor::{self, diagnose};
+use asdfw::errors::{exit_code, ErrorKind, EXIT_GENERAL};
+use asdfw::gc;
+use asdfw::hooks::{HookEvent, Hooks};
+use asdfw::install::{cache_artifact, cached_artifact, reinstall_from_file};
+use asdfw::interrupt;
+use asdfw::manifest::{manifest_path, Manifest};
+use asdfw::output::*;
+use asdfw::porcelain::{self, Porcelain};
+use asdfw::project_env::{self, project_env, ENV_FILE_NAME};
+use asdfw::receipt::{receipt_path, Receipt};
+use asdfw::runtime::{self, RuntimeEnvironment};
+use asdfw::shell::{render_env, Shell};
+use asdfw::shim_health::{self, REPEATED_FAILURES};
+use asdfw::shims::{Conflict, InstalledVersion, ShimData};
+use asdfw::stats;
+use asdfw::subcommand::{command_line, exec, ExecutableContext};
+use asdfw::tool_versions::{ParseMode, VersionSource};
+use asdfw::wsl;
+use clap::{AppSettings, ArgGroup, IntoApp, Parser};
 use clap_complete::{generate, shells::PowerShell};
 use flexi_logger::{Cleanup, Criterion, FileSpec, Logger, LoggerHandle, Naming};
-use log::info;
+use log::{info, LevelFilter};
+use notify::{watcher, DebouncedEvent, RecursiveMode, Watcher};
+use std::borrow::Cow;
+use std::collections::BTreeMap;
+use std::fmt::Display;
+use std::fs;
+use std::io::{self, IsTerminal, Write};
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::channel;
+use std::time::{Duration, SystemTime};
 
 const APP_NAME: &str = "asdfw";
+/// The module printed by `asdfw powershell-module`.
+const POWERSHELL_MODULE: &str = include_str!("Asdfw.psm1");
 
 /// General Version Manager for Standalone Command Line Executables
 ///
@@ -21,6 +54,21 @@ struct Cli {
     #[clap(short, parse(from_occurrences), global = true)]
     verbose: usize,
 
+    /// Run as if asdfw was started in this directory instead of the current
+    /// one.
+    #[clap(short = 'C', long, global = true, value_name = "DIR")]
+    cwd: Option<PathBuf>,
+
+    /// Fail on invalid lines in `.tool-versions` files, even if the
+    /// configuration says to skip them (`tool_versions_parsing = "lenient"`).
+    #[clap(long, global = true)]
+    strict: bool,
+
+    /// Don't report the progress of long operations (it's only reported
+    /// when stderr is a console).
+    #[clap(long, global = true)]
+    no_progress: bool,
+
     #[clap(subcommand)]
     command: CliSubCommand,
 }
@@ -31,24 +79,198 @@ enum CliSubCommand {
     ///
     /// Recreate the shims.db and the shims (currently not working)
     Reshim {
-        /// Cleanup all existing shims before creating them. This will remove
-        /// dangling shims.
+        /// Replace all existing shims with freshly created ones. This will
+        /// remove dangling shims. The existing shims are kept if creating the
+        /// new ones fails.
         #[clap(long)]
         cleanup: bool,
+        /// Only report what would change, without touching the shims or the
+        /// shims db.
+        #[clap(long)]
+        dry_run: bool,
+    },
+    /// Manage shims of executables outside of the install directories.
+    #[clap(subcommand)]
+    Shim(ShimSubCommand),
+    /// Read and change the configuration file.
+    #[clap(subcommand)]
+    Config(ConfigSubCommand),
+    /// Maintain the shims db.
+    #[clap(subcommand)]
+    Shimdb(ShimdbSubCommand),
+    /// Check the installation for problems.
+    ///
+    /// Reports problems such as shims left behind after a tool version was
+    /// removed manually. Use `--fix` to fix the problems that could be fixed
+    /// automatically.
+    Doctor {
+        /// Fix the problems found (when possible)
+        #[clap(long)]
+        fix: bool,
+        /// Print the result of each check and the problems found as JSON
+        #[clap(long)]
+        json: bool,
+    },
+    /// Remove files that are no longer needed.
+    ///
+    /// Removes cached files and manifests of versions that are no longer
+    /// installed, staging directories left behind by interrupted installs,
+    /// rotated logs beyond the retention and orphaned shims, and reports the
+    /// space freed.
+    Gc {
+        /// Only report what would be removed
+        #[clap(long)]
+        dry_run: bool,
+    },
+    /// Watch the install directories and reshim on changes.
+    ///
+    /// Keeps running until interrupted. Whenever tools or versions are added to
+    /// or removed from the install directories, the missing shims are created
+    /// and the dangling ones removed.
+    Watch,
+    /// Install a tool version from a local file.
+    ///
+    /// Installs the version from a zip archive or a single executable (e.g.
+    /// for air-gapped environments where artifacts arrive on a share), runs
+    /// the `pre-install` and `post-install` hooks and updates the shims. If
+    /// extraction or a hook fails nothing is installed.
+    Install {
+        /// The tool to install
+        tool: String,
+        /// The version being installed
+        version: String,
+        /// The archive (.zip) or executable (.exe) to install from
+        #[clap(long)]
+        from_file: PathBuf,
+        /// Don't update the shims (e.g. when installing many versions, run
+        /// `asdfw reshim` once done)
+        #[clap(long)]
+        no_reshim: bool,
     },
+    /// Reinstall a tool version.
+    ///
+    /// Replaces the installed version with a fresh copy of the file it was
+    /// installed from (kept by `asdfw install`) or of the provided file. Use it
+    /// to fix corrupted installations (see `asdfw verify`).
+    Reinstall {
+        /// The tool to reinstall
+        tool: String,
+        /// The version to reinstall
+        version: String,
+        /// The archive (.zip) or executable (.exe) to reinstall from (defaults
+        /// to the file the version was installed from)
+        #[clap(long)]
+        from_file: Option<PathBuf>,
+        /// Don't update the shims
+        #[clap(long)]
+        no_reshim: bool,
+    },
+    /// Uninstall tool versions.
+    ///
+    /// Removes the specified version of the tool. With `--all-but-current`
+    /// removes all the versions of the tool (or of all tools if no tool is
+    /// specified) except those referenced by the global `.tool-versions` file
+    /// and by the `--keep-for` project directories. Versions installed in the
+    /// system install dir are never removed. The shims of removed executables
+    /// are removed as well.
+    Uninstall {
+        /// The tool to uninstall
+        #[clap(required_unless_present = "all-but-current")]
+        tool: Option<String>,
+        /// The version to uninstall
+        #[clap(required_unless_present = "all-but-current", conflicts_with = "all-but-current")]
+        version: Option<String>,
+        /// Remove all versions which are not currently referenced
+        #[clap(long)]
+        all_but_current: bool,
+        /// Also keep the versions referenced in this project directory (could
+        /// be specified multiple times)
+        #[clap(long, requires = "all-but-current", multiple_occurrences = true)]
+        keep_for: Vec<PathBuf>,
+        /// Don't update the shims
+        #[clap(long)]
+        no_reshim: bool,
+    },
+    /// Verify the integrity of installed versions.
+    ///
+    /// Compares the files of the installed versions (optionally limited to a
+    /// tool or a specific version) with the manifest recorded when the version
+    /// was first seen by `reshim`, and reports modified or missing files.
+    Verify {
+        /// The tool to verify
+        tool: Option<String>,
+        /// The version of the tool to verify
+        #[clap(requires = "tool")]
+        version: Option<String>,
+    },
+    /// List the installed versions.
+    ///
+    /// Marks the version of each tool resolved in the current directory and
+    /// the versions not referenced by the global `.tool-versions` file, the
+    /// current directory or the `--project` directories. With `--tree` also
+    /// shows the executables each version provides.
+    List {
+        /// Show tools, versions and executables as a tree
+        #[clap(long)]
+        tree: bool,
+        /// Print a stable line per version for scripts (see the README)
+        #[clap(
+            long,
+            value_name = "VERSION",
+            min_values = 0,
+            max_values = 1,
+            require_equals = true,
+            default_missing_value = "v1",
+            conflicts_with = "tree"
+        )]
+        porcelain: Option<Porcelain>,
+        /// Also consider the versions referenced in this project directory
+        /// (could be specified multiple times)
+        #[clap(long, multiple_occurrences = true)]
+        project: Vec<PathBuf>,
+    },
+    /// Show an overview of all the tools.
+    ///
+    /// Joins the tools configured in `config.toml`, the installed versions and
+    /// the versions pinned for the current directory into one table, flagging
+    /// tools configured without installed versions and pinned versions that
+    /// are not installed.
+    Tools,
     /// Configure tool's version in current directory.
     Local {
         /// The tool to configure the version for
         tool: String,
         /// The version to use locally for the specified tool
-        version: String,
+        #[clap(required_unless_present = "unset")]
+        version: Option<String>,
+        /// Configure the version even if it's not installed
+        #[clap(long, conflicts_with = "unset")]
+        force: bool,
+        /// Use the version as is instead of resolving a partial version (e.g.
+        /// `18`) to the highest installed version matching it
+        #[clap(long, conflicts_with = "unset")]
+        exact: bool,
+        /// Remove the tool from the local `.tool-versions` file instead
+        #[clap(long, conflicts_with = "version")]
+        unset: bool,
     },
     /// Configure tool's version globally.
     Global {
         /// The tool to configure the version for
         tool: String,
         /// The version to use globally for the specified tool
-        version: String,
+        #[clap(required_unless_present = "unset")]
+        version: Option<String>,
+        /// Configure the version even if it's not installed
+        #[clap(long, conflicts_with = "unset")]
+        force: bool,
+        /// Use the version as is instead of resolving a partial version (e.g.
+        /// `18`) to the highest installed version matching it
+        #[clap(long, conflicts_with = "unset")]
+        exact: bool,
+        /// Remove the tool from the global `.tool-versions` file instead
+        #[clap(long, conflicts_with = "version")]
+        unset: bool,
     },
     /// Generate completion.
     ///
@@ -56,12 +278,306 @@ enum CliSubCommand {
     /// shell initialization for invoked directly:
     ///
     ///     asdfw.exe completions | Out-String | Invoke-Expression
-    Completion,
+    ///
+    /// Or use `--install` to save the completion script and load it from your
+    /// PowerShell profile automatically.
+    #[clap(group(ArgGroup::new("profile_action").args(&["install", "uninstall"])))]
+    Completion {
+        /// Save the completion script and load it from the profile (rerun after
+        /// upgrading asdfw)
+        #[clap(long)]
+        install: bool,
+        /// Remove the completion script and the profile block added by
+        /// `--install`
+        #[clap(long)]
+        uninstall: bool,
+        /// The profile to modify (defaults to the current user's `$PROFILE`)
+        #[clap(long, value_name = "PATH", requires = "profile_action")]
+        profile: Option<PathBuf>,
+    },
+    /// Print a PowerShell module wrapping asdfw.
+    ///
+    /// The module provides cmdlets (`Set-AsdfwLocal`, `Set-AsdfwGlobal`,
+    /// `Get-AsdfwCurrent` and `Get-AsdfwWhich`) with argument completion of
+    /// tools, versions and commands. Regenerate it after upgrading asdfw.
+    PowershellModule {
+        /// Write `Asdfw.psm1` into this directory instead of printing it
+        #[clap(long, value_name = "DIR")]
+        out: Option<PathBuf>,
+    },
+    /// Print the script activating asdfw in an interactive shell.
+    ///
+    /// The script loads the project environment (`.asdfw.env`) whenever the
+    /// directory changes and sets up completions. Supported shells are
+    /// `powershell`, `cmd` (a Clink script) and `nu`. In powershell add to
+    /// your profile:
+    ///
+    ///     asdfw.exe activate powershell | Out-String | Invoke-Expression
+    Activate {
+        /// The shell to activate (powershell, cmd or nu)
+        shell: HookShell,
+    },
+    /// Print the environment changes for the current directory (used by the
+    /// activation scripts).
+    #[clap(hide = true)]
+    HookEnv { shell: HookShell },
+    /// Allow loading the project environment (`.asdfw.env`) of the current
+    /// directory.
+    ///
+    /// Project variables are only loaded from files you allowed. A file that
+    /// changed since has to be allowed again.
+    Allow,
+    /// List completion candidates (used by the PowerShell module).
+    #[clap(subcommand, hide = true)]
+    Complete(CompleteSubCommand),
     /// Get full path to configured version for command
+    #[clap(setting = AppSettings::DisableVersionFlag)]
     Which {
         /// The command to get the full path for (could omit extension)
         cmd: String,
+        /// Look the command up in this tool instead of the one selected by
+        /// reshim (useful when more than one tool provides the command)
+        #[clap(long)]
+        tool: Option<String>,
+        /// Print the path as seen from WSL (e.g. `/mnt/c/...`)
+        #[clap(long)]
+        wsl: bool,
+        /// Print the full command line running the executable directly
+        /// (e.g. the interpreter followed by the script)
+        #[clap(long, conflicts_with = "wsl")]
+        invocation: bool,
+        /// Print only the version the command runs with
+        #[clap(long, conflicts_with_all = &["wsl", "invocation"])]
+        version: bool,
+        /// Print the tool along with the version (`<tool> <version>`)
+        #[clap(long, requires = "version")]
+        with_tool: bool,
+        /// Print a stable line with the tool, version and path for scripts
+        /// (see the README)
+        #[clap(
+            long,
+            value_name = "VERSION",
+            min_values = 0,
+            max_values = 1,
+            require_equals = true,
+            default_missing_value = "v1",
+            conflicts_with_all = &["wsl", "invocation", "version"]
+        )]
+        porcelain: Option<Porcelain>,
+    },
+    /// Generate WSL scripts calling the shims.
+    ///
+    /// Writes a script (named after the shim without `.exe`) for each shim
+    /// into the directory, so the tools could be used from WSL with the same
+    /// `.tool-versions` files. Run it again after reshim to add new shims and
+    /// remove stale scripts. Should be run from Windows with a directory on
+    /// the WSL path (e.g. `\\wsl$\Ubuntu\home\me\.asdfw-shims`).
+    WslWrappers {
+        /// The directory to write the scripts into
+        dir: PathBuf,
+    },
+    /// Run a command with the configured version of its tool.
+    ///
+    /// Like running the command through its shim, but allows tweaking the
+    /// environment and working directory of a single invocation. The version
+    /// is resolved for the working directory.
+    #[clap(setting = AppSettings::TrailingVarArg)]
+    Exec {
+        /// Set an environment variable for the command (could be repeated)
+        #[clap(short, long = "env", value_name = "KEY=VALUE", parse(try_from_str = parse_env_var), multiple_occurrences = true)]
+        env: Vec<(String, String)>,
+        /// Kill the command (and the processes it started) if it does not
+        /// finish within this many seconds (overrides the tool's `timeout`)
+        #[clap(long, value_name = "SECS", parse(try_from_str = parse_timeout))]
+        timeout: Option<u64>,
+        /// Do not set the configured environment of these tools (`,`
+        /// separated, in addition to the ones listed in ASDFW_NO_PLUGIN_ENV)
+        #[clap(long, value_name = "TOOLS")]
+        no_plugin_env: Option<String>,
+        /// The command to run (could omit extension)
+        cmd: String,
+        /// Arguments passed to the command
+        #[clap(allow_hyphen_values = true)]
+        args: Vec<String>,
+    },
+    /// Print environment setup for the configured tools.
+    ///
+    /// Prints the commands required to add the configured version of every
+    /// tool pinned in the directory to the PATH, for use where shims are
+    /// impractical (e.g. CI). Evaluate it in powershell using:
+    ///
+    ///     asdfw.exe env | Out-String | Invoke-Expression
+    ///
+    /// Or in GitHub actions:
+    ///
+    ///     asdfw.exe env --github >> $env:GITHUB_ENV
+    #[clap(group(ArgGroup::new("shell").args(&["powershell", "cmd", "github"])))]
+    Env {
+        /// The directory to generate the environment for (defaults to the
+        /// current directory)
+        #[clap(long)]
+        dir: Option<PathBuf>,
+        /// Generate powershell commands (default)
+        #[clap(long)]
+        powershell: bool,
+        /// Generate cmd.exe commands
+        #[clap(long)]
+        cmd: bool,
+        /// Generate `$GITHUB_ENV` file format
+        #[clap(long)]
+        github: bool,
+    },
+    /// Explain where the version of a tool comes from.
+    ///
+    /// Prints every source checked while resolving the tool's version (the
+    /// environment variable, each `.tool-versions` file walking up from the
+    /// current directory and the global file) and the version selected.
+    Why {
+        /// The tool to explain the version of
+        tool: String,
     },
+    /// Show the current version of the tools.
+    ///
+    /// Without a tool prints the version of every tool configured for the
+    /// current directory. With a tool prints only its version and exits with
+    /// code 12 if no version is configured or 13 if the configured version is
+    /// not installed.
+    Current {
+        /// The tool to show the version of
+        tool: Option<String>,
+        /// Print the configured tools as JSON
+        #[clap(long, conflicts_with = "tool")]
+        json: bool,
+        /// Print a stable line per tool for scripts (see the README)
+        #[clap(
+            long,
+            value_name = "VERSION",
+            min_values = 0,
+            max_values = 1,
+            require_equals = true,
+            default_missing_value = "v1",
+            conflicts_with = "json"
+        )]
+        porcelain: Option<Porcelain>,
+    },
+    /// Summarize the commands executed through shims.
+    ///
+    /// Shows the most used commands, the slowest version resolutions and the
+    /// recent failures. Requires `record_stats = true` in the configuration.
+    Stats {
+        /// The number of entries to show in each section
+        #[clap(short, long, default_value = "10")]
+        limit: usize,
+    },
+    /// Print the user facing messages by id.
+    ///
+    /// The output is in the format of `messages.yaml` (in the configuration
+    /// directory), which overrides the messages, e.g. to translate them.
+    Messages,
+    /// Check that the current directory is ready to use.
+    ///
+    /// Fails (with exit code 19) if any tool pinned for the current directory
+    /// is not installed, is not allowed by the policy or has missing or
+    /// out-of-date shims. Intended as a single CI or pre-commit step.
+    Check,
+    /// Check the versions in use against security advisories.
+    ///
+    /// Reports installed versions and versions configured for the current
+    /// directory that are affected by an advisory in the feed (see
+    /// `advisory_feed` in the configuration). Exits with code 18 if any are
+    /// found.
+    Audit {
+        /// The advisory feed to use instead of the configured one
+        #[clap(long)]
+        feed: Option<PathBuf>,
+        /// Print the findings as JSON
+        #[clap(long)]
+        json: bool,
+    },
+    /// Show how a tool version was installed.
+    ///
+    /// Prints the installation directory along with the receipt recorded by
+    /// `asdfw install` (source file, checksum, install date and asdfw
+    /// version), and whether a manifest and a cached artifact exist.
+    Info {
+        /// The tool
+        tool: String,
+        /// The installed version
+        version: String,
+    },
+    /// Show which tool provides a command
+    ///
+    /// Prints the tool owning the command and the version of it that would
+    /// run in the current directory.
+    ToolOf {
+        /// The command to look up (could omit extension)
+        cmd: String,
+    },
+}
+
+#[derive(Debug, clap::Subcommand)]
+enum ShimSubCommand {
+    /// Register an executable as a shim.
+    ///
+    /// For tools that don't fit the install directories layout. The shim
+    /// always runs the provided executable (no version is resolved) and is
+    /// kept by reshim.
+    Add {
+        /// The name of the shim (`.exe` is appended if missing)
+        name: String,
+        /// The executable the shim runs
+        path: PathBuf,
+        /// The tool the executable belongs to (defaults to the shim name)
+        #[clap(long)]
+        tool: Option<String>,
+    },
+    /// Remove a shim registered using `shim add`.
+    Rm {
+        /// The name of the shim
+        name: String,
+    },
+}
+
+#[derive(Debug, clap::Subcommand)]
+enum CompleteSubCommand {
+    /// Installed tools and their aliases
+    Tools,
+    /// Installed versions of a tool
+    Versions { tool: String },
+    /// Shimmed commands
+    Commands,
+}
+
+#[derive(Debug, clap::Subcommand)]
+enum ShimdbSubCommand {
+    /// Rebuild a corrupt or out of sync shims db in place.
+    ///
+    /// Re-derives the entries from the installed tools, creates missing
+    /// shims, removes shims without an entry and reports what was fixed.
+    Repair,
+}
+
+#[derive(Debug, clap::Subcommand)]
+enum ConfigSubCommand {
+    /// Print the value of a key (e.g. `tools.kubectl.disabled`).
+    Get {
+        /// The key (nested keys are separated by dots)
+        key: String,
+    },
+    /// Set the value of a key.
+    ///
+    /// The value is validated according to the key's type. Lists are
+    /// provided either comma separated (`kubectl,helm`) or as a TOML array.
+    /// Comments and formatting of the rest of the file are preserved.
+    #[clap(setting = AppSettings::AllowHyphenValues)]
+    Set {
+        /// The key (nested keys are separated by dots)
+        key: String,
+        /// The new value
+        value: String,
+    },
+    /// List all the configured keys and their values.
+    List,
 }
 
 fn main() {
@@ -70,49 +586,806 @@ fn main() {
     match do_main(app) {
         Ok(_) => {}
         Err(err) => {
+            let code = exit_code(&err);
             let output = output_full_error(err, None);
             print_out(output);
-            std::process::exit(1);
+            std::process::exit(code);
         }
     }
 }
 
 fn do_main(app: Cli) -> Result<()> {
-    let env = RuntimeEnvironment::new()?;
+    // Works on the file alone, so a broken configuration could be fixed
+    if let CliSubCommand::Config(what) = &app.command {
+        return config_cmd(&runtime::config_file()?, what);
+    }
+    let mut env = RuntimeEnvironment::new()?;
+    if let Some(dir) = &app.cwd {
+        env = env.with_current_dir(dir)?;
+    }
+    if app.strict {
+        env.config.tool_versions_parsing = ParseMode::Strict;
+    }
+    if app.no_progress {
+        disable_progress();
+    }
     let log_level = match app.verbose {
-        0 => "info",
-        1 => "debug",
-        _ => "trace",
+        0 => env
+            .config
+            .log_level_for("asdfw", None)
+            .unwrap_or_else(|err| {
+                print_warning(&format!("{:#}, using the default", err));
+                None
+            })
+            .unwrap_or(LevelFilter::Info),
+        1 => LevelFilter::Debug,
+        _ => LevelFilter::Trace,
     };
-    log_to_file(&env, &log_level)?;
+    log_to_file(&env, &log_level.to_string())?;
 
     run(app, &env)
 }
 
 fn run(app: Cli, env: &RuntimeEnvironment) -> Result<()> {
     match app.command {
-        CliSubCommand::Reshim { cleanup } => reshim(&env, cleanup),
-        CliSubCommand::Local { tool, version } => set_local(env, &tool, &version),
-        CliSubCommand::Global { tool, version } => set_global(env, &tool, &version),
-        CliSubCommand::Completion => gen_completions(),
-        CliSubCommand::Which { cmd } => which(&env, &cmd),
+        CliSubCommand::Reshim { cleanup, dry_run } => reshim(&env, cleanup, dry_run),
+        CliSubCommand::Shim(ShimSubCommand::Add { name, path, tool }) => add_shim(env, &name, &path, tool.as_deref()),
+        CliSubCommand::Shim(ShimSubCommand::Rm { name }) => remove_shim(env, &name),
+        CliSubCommand::Config(what) => config_cmd(&env.config_file, &what),
+        CliSubCommand::Shimdb(ShimdbSubCommand::Repair) => repair_shims_db(env),
+        CliSubCommand::Doctor { fix, json } => doctor(env, fix, json),
+        CliSubCommand::Gc { dry_run } => collect_garbage(env, dry_run),
+        CliSubCommand::Watch => watch(env),
+        CliSubCommand::Install {
+            tool,
+            version,
+            from_file,
+            no_reshim,
+        } => install(env, &tool, &version, &from_file, no_reshim),
+        CliSubCommand::Reinstall {
+            tool,
+            version,
+            from_file,
+            no_reshim,
+        } => reinstall(env, &tool, &version, from_file.as_deref(), no_reshim),
+        CliSubCommand::Uninstall {
+            tool,
+            version,
+            all_but_current,
+            keep_for,
+            no_reshim,
+        } => match (tool, version) {
+            (tool, _) if all_but_current => uninstall_unreferenced(env, tool.as_deref(), &keep_for, no_reshim),
+            (Some(tool), Some(version)) => uninstall(env, &tool, &version, no_reshim),
+            _ => unreachable!("enforced by clap"),
+        },
+        CliSubCommand::List {
+            tree,
+            project,
+            porcelain,
+        } => list(env, tree, &project, porcelain),
+        CliSubCommand::Tools => tools(env),
+        CliSubCommand::Verify { tool, version } => verify(env, tool.as_deref(), version.as_deref()),
+        CliSubCommand::Local {
+            tool,
+            version,
+            force,
+            exact,
+            unset,
+        } => match version {
+            Some(version) if !unset => set_local(env, &tool, &version, exact, force),
+            _ => unset_version(env, &tool, false),
+        },
+        CliSubCommand::Global {
+            tool,
+            version,
+            force,
+            exact,
+            unset,
+        } => match version {
+            Some(version) if !unset => set_global(env, &tool, &version, exact, force),
+            _ => unset_version(env, &tool, true),
+        },
+        CliSubCommand::Completion {
+            install: true, profile, ..
+        } => install_completions(env, profile),
+        CliSubCommand::Completion {
+            uninstall: true,
+            profile,
+            ..
+        } => uninstall_completions(env, profile),
+        CliSubCommand::Completion { .. } => gen_completions(),
+        CliSubCommand::PowershellModule { out } => powershell_module(env, out.as_deref()),
+        CliSubCommand::Complete(what) => complete(env, what),
+        CliSubCommand::Activate { shell } => activate(shell),
+        CliSubCommand::HookEnv { shell } => hook_env(env, shell),
+        CliSubCommand::Allow => allow(env),
+        CliSubCommand::Which {
+            cmd,
+            tool,
+            wsl,
+            invocation,
+            version,
+            with_tool,
+            porcelain,
+        } => {
+            let format = match (porcelain, invocation, version, with_tool) {
+                (Some(porcelain), ..) => WhichFormat::Porcelain(porcelain),
+                (_, true, _, _) => WhichFormat::Invocation,
+                (_, _, true, false) => WhichFormat::Version,
+                (_, _, true, true) => WhichFormat::ToolVersion,
+                _ if wsl => WhichFormat::WslPath,
+                _ => WhichFormat::Path,
+            };
+            which(env, &cmd, tool.as_deref(), format)
+        }
+        CliSubCommand::WslWrappers { dir } => wsl_wrappers(env, &dir),
+        CliSubCommand::Exec {
+            env: vars,
+            timeout,
+            no_plugin_env,
+            cmd,
+            args,
+        } => exec_cmd(env, &cmd, &args, &vars, timeout, no_plugin_env.as_deref(), app.cwd.is_some()),
+        CliSubCommand::ToolOf { cmd } => tool_of(env, &cmd),
+        CliSubCommand::Info { tool, version } => info(env, &tool, &version),
+        CliSubCommand::Why { tool } => why(env, &tool),
+        CliSubCommand::Current { tool, json, porcelain } => current(env, tool.as_deref(), json, porcelain),
+        CliSubCommand::Stats { limit } => show_stats(env, limit),
+        CliSubCommand::Messages => show_messages(),
+        CliSubCommand::Check => check_dir(env),
+        CliSubCommand::Audit { feed, json } => audit_versions(env, feed.as_deref(), json),
+        CliSubCommand::Env {
+            dir,
+            powershell: _,
+            cmd,
+            github,
+        } => {
+            let shell = match (cmd, github) {
+                (true, _) => Shell::Cmd,
+                (_, true) => Shell::Github,
+                _ => Shell::PowerShell,
+            };
+            export_env(env, dir, shell)
+        }
     }
 }
 
-fn reshim(env: &RuntimeEnvironment, cleanup: bool) -> Result<()> {
-    info!("Create shims requested");
-    let shims = Shims::new(&env.shims_db, &env.installs_dir, &env.shims_dir, &env.shim_exe)?;
-    let db = shims.generate_db_from_installed_tools()?;
-    shims.save_db(&db)?;
-    shims.create_shims(cleanup)?;
-    let output = success_message("Reshim finished successfully.");
+fn reshim(env: &RuntimeEnvironment, cleanup: bool, dry_run: bool) -> Result<()> {
+    info!("Create shims requested (dry run: {})", dry_run);
+    let hooks = Hooks::new(&env.hooks_dir);
+    if !dry_run {
+        interrupt::handle_ctrl_c()?;
+        hooks.run(HookEvent::PreReshim, &[])?;
+    }
+    let shims = shims_for_env(env)?;
+    let (db, conflicts) = shims.generate_db_from_installed_tools(&env.config)?;
+    let changes = shims.plan_shims(&db, cleanup)?;
+    let mut tools: Vec<&str> = db
+        .values()
+        .filter_map(|shim| match shim {
+            ShimData::Tool(tool) => Some(tool),
+            ShimData::Manual { .. } => None,
+        })
+        .chain(conflicts.iter().flat_map(|c| c.tools.iter()))
+        .map(|t| t.as_str())
+        .collect();
+    tools.sort();
+    tools.dedup();
+    if !dry_run {
+        interrupt::check()?;
+        shims.save_db(&db)?;
+        shims.create_shims(cleanup)?;
+        for (installed, err) in record_missing_manifests(env, &shims)? {
+            let msg = format!("Could not record manifest of {} {}: {}", &installed.tool, &installed.version, err);
+            print_out(warning_message(&msg));
+        }
+        if env.config.current_links {
+            for (tool, err) in update_current_links(env, &shims, &tools) {
+                let args: [(&str, &dyn Display); 2] = [("tool", &tool), ("error", &err)];
+                print_out(warning_message(&message("warning.current_link_not_updated", &args)));
+            }
+        }
+        hooks.run(HookEvent::PostReshim, &[])?;
+    }
+    for conflict in &conflicts {
+        print_out(warning_message(&conflict_message(conflict)));
+    }
+    let failures = shim_health::load(&env.shim_failures_dir)?;
+    for failing in shim_health::failing_commands(&failures, REPEATED_FAILURES) {
+        let msg = format!(
+            "{} failed {} times since it last ran, most recently in {:?}: {}",
+            &failing.command, failing.count, &failing.last.dir, &failing.last.reason
+        );
+        print_out(warning_message(&msg));
+    }
+    let skipped = conflicts.iter().filter(|c| c.selected.is_none()).count();
+    let mut output: Vec<String> = if dry_run {
+        let mut lines = vec!["Reshim dry run, nothing was changed. Would have:".to_owned()];
+        lines.extend(changes.created.iter().map(|exe| format!("   + create {}", &exe)));
+        lines.extend(changes.removed.iter().map(|exe| format!("   - remove {}", &exe)));
+        lines
+    } else {
+        success_message(&message("success.reshim_finished", &[]))
+            .iter()
+            .map(|l| l.to_string())
+            .collect()
+    };
+    output.push(format!("   Tools scanned: {}", tools.len()));
+    output.push(format!("   Shims created: {}", changes.created.len()));
+    output.push(format!("   Shims updated: {}", changes.updated.len()));
+    output.push(format!("   Shims removed: {}", changes.removed.len()));
+    output.push(format!("   Duplicates skipped: {}", skipped));
     Ok(print_out(output))
 }
 
-fn set_global<'a>(env: &RuntimeEnvironment, tool: &'a str, version: &'a str) -> Result<()> {
-    let tvs = ToolVersions::new(&env.global_tool_versions_file, &env.current_dir, &tool);
+/// Reports what happened to the shims after the install directories were
+/// changed. A failure is only a warning, the change itself succeeded.
+fn report_shims_update(update: ShimsUpdate) {
+    match update {
+        ShimsUpdate::Skipped => print_out(warning_message(&message("warning.shims_not_updated", &[]))),
+        ShimsUpdate::Updated(changes) => print_out(vec![
+            format!("   Shims created: {}", changes.created.len()),
+            format!("   Shims removed: {}", changes.removed.len()),
+        ]),
+        ShimsUpdate::Failed(err) => {
+            let error = format!("{:#}", err);
+            print_out(warning_message(&message("warning.shims_update_failed", &[("error", &error)])))
+        }
+    }
+}
+
+/// Appends `.exe` to shim names without extension.
+fn shim_name(name: &str) -> String {
+    match Path::new(name).extension() {
+        Some(_) => name.to_owned(),
+        None => format!("{}.exe", name),
+    }
+}
+
+fn add_shim(env: &RuntimeEnvironment, name: &str, path: &Path, tool: Option<&str>) -> Result<()> {
+    info!("invoked `shim add` for {} -> {:?} (tool: {:?})", &name, &path, &tool);
+    let name = shim_name(name);
+    let tool = match tool {
+        Some(tool) => env.config.canonical_tool_name(tool).to_owned(),
+        None => Path::new(&name).file_stem().unwrap_or_default().to_string_lossy().into_owned(),
+    };
+    let path = env.current_dir.join(path);
+    let name = shims_for_env(env)?.add_manual_shim(&name, &tool, &path)?;
+    let msg = format!("Registered shim {} ({}) -> {:?}", &name, &tool, &path);
+    Ok(print_out(success_message(&msg)))
+}
+
+fn remove_shim(env: &RuntimeEnvironment, name: &str) -> Result<()> {
+    info!("invoked `shim rm` for {}", &name);
+    let name = shim_name(name);
+    let name = shims_for_env(env)?.remove_manual_shim(&name)?;
+    Ok(print_out(success_message(&format!("Removed shim {}", &name))))
+}
+
+fn config_cmd(config_file: &Path, what: &ConfigSubCommand) -> Result<()> {
+    match what {
+        ConfigSubCommand::Get { key } => get_config(config_file, key),
+        ConfigSubCommand::Set { key, value } => set_config(config_file, key, value),
+        ConfigSubCommand::List => list_config(config_file),
+    }
+}
+
+fn get_config(config_file: &Path, key: &str) -> Result<()> {
+    match config_edit::get(config_file, key)? {
+        Some(value) => Ok(print_out(vec![value])),
+        None => Err(anyhow!("{} is not set", key)),
+    }
+}
+
+fn set_config(config_file: &Path, key: &str, value: &str) -> Result<()> {
+    info!("invoked `config set` for {} = {}", &key, &value);
+    config_edit::set(config_file, key, value)?;
+    print_out(success_message(&format!("Set {}", &key)));
+    if let Err(err) = config_edit::validate(config_file) {
+        print_warning(&format!("The configuration is still invalid: {:#}", err));
+    }
+    Ok(())
+}
+
+fn list_config(config_file: &Path) -> Result<()> {
+    let values = config_edit::list(config_file)?;
+    Ok(print_out(values.iter().map(|(key, value)| format!("{} = {}", key, value)).collect()))
+}
+
+fn conflict_message(conflict: &Conflict) -> String {
+    match &conflict.selected {
+        Some(tool) => format!("{} appears in: {}. Using {}", &conflict.exe, conflict.tools.join(", "), &tool),
+        None => format!("{} appears in: {}. Skipped", &conflict.exe, conflict.tools.join(", ")),
+    }
+}
+
+fn repair_shims_db(env: &RuntimeEnvironment) -> Result<()> {
+    info!("invoked `shimdb repair`");
+    let repair = shims_for_env(env)?.repair_db(&env.config)?;
+    for conflict in &repair.conflicts {
+        print_out(warning_message(&conflict_message(conflict)));
+    }
+    if repair.is_clean() {
+        return Ok(print_out(success_message("The shims db is in sync, nothing to repair.")));
+    }
+    let mut output: Vec<String> = success_message("Repaired the shims db.").iter().map(|l| l.to_string()).collect();
+    if let Some(reason) = &repair.discarded {
+        output.push(format!("   Rebuilt from scratch: {}", reason));
+    }
+    output.extend(repair.added.iter().map(|exe| format!("   + added entry {}", &exe)));
+    output.extend(repair.changed.iter().map(|exe| format!("   ~ fixed entry {}", &exe)));
+    output.extend(repair.removed.iter().map(|exe| format!("   - removed entry {}", &exe)));
+    output.extend(repair.created_shims.iter().map(|exe| format!("   + created shim {}", &exe)));
+    output.extend(repair.removed_shims.iter().map(|exe| format!("   - removed shim {}", &exe)));
+    Ok(print_out(output))
+}
+
+fn doctor(env: &RuntimeEnvironment, fix: bool, json: bool) -> Result<()> {
+    info!("Doctor requested (fix: {}, json: {})", fix, json);
+    let problems = diagnose(env)?;
+    let fixed = if fix && !problems.is_empty() {
+        doctor::fix(env, &problems)?
+    } else {
+        vec![]
+    };
+    if json {
+        print_out(vec![serde_json::to_string_pretty(&doctor::report(&problems, &fixed))?]);
+        // Keep the output valid JSON, only signal the remaining problems with
+        // the exit code
+        if fixed.len() < problems.len() {
+            std::process::exit(EXIT_GENERAL);
+        }
+        return Ok(());
+    }
+    if problems.is_empty() {
+        return Ok(print_out(success_message(&message("success.no_problems", &[]))));
+    }
+    let mut remaining = 0;
+    for problem in &problems {
+        if fixed.contains(problem) {
+            print_out(success_message(&message("success.fixed", &[("problem", problem)])));
+        } else {
+            remaining += 1;
+            print_out(warning_message(&format!("{} ({})", problem, problem.hint())));
+        }
+    }
+    if remaining > 0 {
+        return Err(anyhow!("{} problem(s) found", remaining));
+    }
+    Ok(())
+}
+
+fn check_dir(env: &RuntimeEnvironment) -> Result<()> {
+    info!("invoked `check` in {:?}", &env.current_dir);
+    let report = check::check(env, &env.current_dir)?;
+    if report.checked == 0 {
+        return Ok(print_out(warning_message("No tools are pinned for the current directory")));
+    }
+    if report.failures.is_empty() {
+        let message = format!("All {} pinned tool(s) are ready", report.checked);
+        return Ok(print_out(success_message(&message)));
+    }
+    for failure in &report.failures {
+        print_out(warning_message(&failure.to_string()));
+    }
+    Err(ErrorKind::CheckFailed.error(format!(
+        "{} problem(s) found in {} pinned tool(s)",
+        report.failures.len(),
+        report.checked
+    )))
+}
+
+fn collect_garbage(env: &RuntimeEnvironment, dry_run: bool) -> Result<()> {
+    info!("invoked `gc` (dry run: {})", dry_run);
+    let garbage = gc::find(env, SystemTime::now())?;
+    if garbage.is_empty() {
+        return Ok(print_out(success_message("Nothing to clean up")));
+    }
+    let mut table = Table::new(vec!["Kind", "Size", "Path"]);
+    for item in &garbage {
+        let path = item.path.to_string_lossy().into_owned();
+        table.add_row(vec![item.kind.to_string(), gc::format_size(item.size), path]);
+    }
+    print_out(table.render(None));
+    let total: u64 = garbage.iter().map(|item| item.size).sum();
+    if dry_run {
+        let msg = format!("{} would be freed (dry run)", gc::format_size(total));
+        return Ok(print_out(success_message(&msg)));
+    }
+    let freed = gc::collect(env, &garbage)?;
+    let msg = format!("Removed {} item(s), {} freed", garbage.len(), gc::format_size(freed));
+    Ok(print_out(success_message(&msg)))
+}
+
+fn watch(env: &RuntimeEnvironment) -> Result<()> {
+    let shims = shims_for_env(env)?;
+    let (tx, rx) = channel();
+    let mut watcher = watcher(tx, Duration::from_secs(2)).context("creating file system watcher")?;
+    let mut dirs = vec![env.installs_dir.clone()];
+    dirs.extend(env.system_installs_dir.iter().filter(|dir| dir.is_dir()).cloned());
+    dirs.extend(
+        env.config
+            .tools
+            .values()
+            .filter_map(|t| t.install_dir.clone())
+            .filter(|dir| dir.is_dir()),
+    );
+    for dir in &dirs {
+        info!("Watching {:?}", &dir);
+        watcher
+            .watch(dir, RecursiveMode::Recursive)
+            .context(format!("watching {:?}", &dir))?;
+    }
+    print_out(success_message("Watching install directories for changes, press Ctrl+C to stop."));
+    loop {
+        match rx.recv().context("receiving file system events")? {
+            DebouncedEvent::Create(_) | DebouncedEvent::Remove(_) | DebouncedEvent::Rename(_, _) => {
+                // A single unpack produces many events, handle them all at once.
+                while rx.try_recv().is_ok() {}
+                match incremental_reshim(env, &shims) {
+                    Ok(changes) if changes.created.is_empty() && changes.removed.is_empty() => {}
+                    Ok(changes) => {
+                        let msg = format!(
+                            "Reshim: {} shims created, {} shims removed",
+                            changes.created.len(),
+                            changes.removed.len()
+                        );
+                        print_out(success_message(&msg));
+                    }
+                    Err(err) => print_out(output_full_error(err, None)),
+                }
+            }
+            DebouncedEvent::Error(err, path) => {
+                print_out(warning_message(&format!("Watch error ({:?}): {}", &path, err)));
+            }
+            _ => {}
+        }
+    }
+}
+
+fn install(env: &RuntimeEnvironment, tool: &str, version: &str, from_file: &Path, no_reshim: bool) -> Result<()> {
+    info!("Install requested for {} {} from {:?}", &tool, &version, &from_file);
+    let tool = env.config.canonical_tool_name(tool);
+    let from_file = env.current_dir.join(from_file);
+    interrupt::handle_ctrl_c()?;
+    let update = install_version(env, tool, version, &from_file, no_reshim)?;
+    print_out(success_message(&message(
+        "success.installed",
+        &[("version", &version), ("tool", &tool)],
+    )));
+    Ok(report_shims_update(update))
+}
+
+fn reinstall(
+    env: &RuntimeEnvironment,
+    tool: &str,
+    version: &str,
+    from_file: Option<&Path>,
+    no_reshim: bool,
+) -> Result<()> {
+    info!("Reinstall requested for {} {} (from: {:?})", &tool, &version, &from_file);
+    let tool = env.config.canonical_tool_name(tool);
+    ensure_allowed(env, tool, version)?;
+    let shims = shims_for_env(env)?;
+    let installed = shims
+        .installed_versions()?
+        .into_iter()
+        .find(|v| v.tool == tool && v.version == version)
+        .ok_or(anyhow!("Version '{}' of '{}' is not installed", &version, &tool))?;
+    if installed.system {
+        return Err(anyhow!(
+            "Version '{}' of '{}' is installed system wide ({:?}), refusing to reinstall it",
+            &version,
+            &tool,
+            &installed.dir
+        ));
+    }
+    let cache_dir = env.cache_dir.join(tool).join(version);
+    let from_file = match from_file {
+        Some(file) => env.current_dir.join(file),
+        None => cached_artifact(&cache_dir)?.ok_or(anyhow!(
+            "Version '{}' of '{}' was not installed using `asdfw install`, use --from-file to provide the file",
+            &version,
+            &tool
+        ))?,
+    };
+    interrupt::handle_ctrl_c()?;
+    run_pre_install_hooks(env, tool, version, &from_file)?;
+    reinstall_from_file(&from_file, &installed.dir, |staging| {
+        prepare_version(env, tool, version, &from_file, staging)
+    })
+    .context(format!("reinstalling version {} of {}", &version, &tool))?;
+    if !from_file.starts_with(&cache_dir) {
+        cache_artifact(&cache_dir, &from_file)?;
+    }
+    record_manifest_or_warn(env, &installed);
+    print_out(success_message(&message(
+        "success.reinstalled",
+        &[("version", &version), ("tool", &tool)],
+    )));
+    Ok(report_shims_update(update_shims_after_change(env, no_reshim)))
+}
+
+fn info(env: &RuntimeEnvironment, tool: &str, version: &str) -> Result<()> {
+    info!("invoked `info` on {} {}", &tool, &version);
+    let tool = env.config.canonical_tool_name(tool);
+    let installed = shims_for_env(env)?
+        .installed_versions()?
+        .into_iter()
+        .find(|v| v.tool == tool && v.version == version)
+        .ok_or(anyhow!("Version '{}' of '{}' is not installed", &version, &tool))?;
+    let yes_no = |b: bool| if b { "yes" } else { "no" }.to_owned();
+    let mut table = Table::new(vec!["Property", "Value"]);
+    table.add_row(vec!["Directory".to_owned(), installed.dir.to_string_lossy().into_owned()]);
+    table.add_row(vec!["System wide".to_owned(), yes_no(installed.system)]);
+    let manifest = manifest_path(&env.manifests_dir, &installed);
+    table.add_row(vec!["Manifest".to_owned(), yes_no(manifest.exists())]);
+    let cached = cached_artifact(&env.cache_dir.join(tool).join(version))?;
+    let cached = cached.map_or("none".to_owned(), |path| path.to_string_lossy().into_owned());
+    table.add_row(vec!["Cached file".to_owned(), cached]);
+    let path = receipt_path(&installed.dir);
+    if path.exists() {
+        let receipt = Receipt::load(&path)?;
+        table.add_row(vec!["Source".to_owned(), receipt.source.to_string_lossy().into_owned()]);
+        table.add_row(vec!["SHA256".to_owned(), receipt.checksum]);
+        let age = stats::format_age(receipt.installed_at, stats::now());
+        table.add_row(vec!["Installed".to_owned(), age]);
+        table.add_row(vec!["Installed by".to_owned(), format!("asdfw {}", &receipt.asdfw_version)]);
+    }
+    print_out(table.render(None));
+    if !path.exists() {
+        print_out(warning_message(
+            "No receipt recorded (the version was not installed using `asdfw install`)",
+        ));
+    }
+    Ok(())
+}
+
+fn uninstall(env: &RuntimeEnvironment, tool: &str, version: &str, no_reshim: bool) -> Result<()> {
+    info!("Uninstall requested for {} {}", &tool, &version);
+    let tool = env.config.canonical_tool_name(tool);
+    let shims = shims_for_env(env)?;
+    let installed = shims
+        .installed_versions()?
+        .into_iter()
+        .find(|v| v.tool == tool && v.version == version)
+        .ok_or(anyhow!("Version '{}' of '{}' is not installed", &version, &tool))?;
+    if installed.system {
+        return Err(anyhow!(
+            "Version '{}' of '{}' is installed system wide ({:?}), refusing to remove it",
+            &version,
+            &tool,
+            &installed.dir
+        ));
+    }
+    remove_versions(env, &[installed], no_reshim)
+}
+
+fn uninstall_unreferenced(
+    env: &RuntimeEnvironment,
+    tool: Option<&str>,
+    keep_for: &[PathBuf],
+    no_reshim: bool,
+) -> Result<()> {
+    info!("Uninstall of unreferenced versions requested (tool: {:?})", &tool);
+    let tool = tool.map(|t| env.config.canonical_tool_name(t));
+    let project_dirs: Vec<PathBuf> = keep_for.iter().map(|dir| env.current_dir.join(dir)).collect();
+    let versions = unreferenced_versions(env, tool, &project_dirs)?;
+    if versions.is_empty() {
+        return Ok(print_out(success_message("Nothing to uninstall.")));
+    }
+    remove_versions(env, &versions, no_reshim)
+}
+
+fn list(env: &RuntimeEnvironment, tree: bool, projects: &[PathBuf], porcelain: Option<Porcelain>) -> Result<()> {
+    info!(
+        "invoked `list` (tree: {}, projects: {:?}, porcelain: {:?})",
+        tree, &projects, porcelain
+    );
+    let shims = shims_for_env(env)?;
+    let current = configured_tools(env, &env.current_dir)?;
+    let mut project_dirs = vec![env.current_dir.clone()];
+    project_dirs.extend(projects.iter().map(|dir| env.current_dir.join(dir)));
+    let unreferenced = unreferenced_versions(env, None, &project_dirs)?;
+    let markers = |installed: &InstalledVersion| {
+        let mut markers = vec![];
+        if current.iter().any(|c| c.tool == installed.tool && c.version == installed.version) {
+            markers.push("current");
+        }
+        if unreferenced.contains(installed) {
+            markers.push("unreferenced");
+        }
+        if env.config.is_disabled(&installed.tool) {
+            markers.push("disabled");
+        }
+        markers
+    };
+    let versions = shims.installed_versions()?;
+    if let Some(porcelain) = porcelain {
+        let lines = versions.iter().map(|i| porcelain::list_line(porcelain, i, &markers(i))).collect();
+        return Ok(print_out::<String>(lines));
+    }
+    if versions.is_empty() {
+        return Ok(print_out(warning_message("No tools are installed")));
+    }
+    if !tree {
+        let mut table = Table::new(vec!["Tool", "Version", "Status"]);
+        for installed in &versions {
+            let status = markers(installed).join(", ");
+            table.add_row(vec![installed.tool.clone(), installed.version.clone(), status]);
+        }
+        return Ok(print_out(table.render(None)));
+    }
+    let mut output = vec![];
+    for (i, installed) in versions.iter().enumerate() {
+        if i == 0 || versions[i - 1].tool != installed.tool {
+            output.push(TreeNode::new(&installed.tool));
+        }
+        let mut label = installed.version.clone();
+        for marker in markers(installed) {
+            label.push_str(&format!(" ({})", marker));
+        }
+        let mut node = TreeNode::new(label);
+        for exe in shims.version_executables(&env.config, installed)? {
+            node.add_child(TreeNode::new(exe));
+        }
+        output.last_mut().expect("a node for the tool").add_child(node);
+    }
+    Ok(print_out(output.iter().flat_map(TreeNode::render).collect()))
+}
+
+fn tools(env: &RuntimeEnvironment) -> Result<()> {
+    info!("invoked `tools`");
+    let overview = tools_overview(env, &env.current_dir)?;
+    if overview.is_empty() {
+        return Ok(print_out(warning_message("No tools are configured or installed")));
+    }
+    let mut table = Table::new(vec!["Tool", "Configured", "Installed", "Pinned", "Issues"]);
+    for tool in &overview {
+        let mut configured = if tool.configured { "yes" } else { "no" }.to_owned();
+        if tool.disabled {
+            configured.push_str(" (disabled)");
+        }
+        let issues: Vec<String> = tool.issues.iter().map(ToString::to_string).collect();
+        table.add_row(vec![
+            tool.tool.clone(),
+            configured,
+            tool.installed.join(", "),
+            tool.pinned.clone().unwrap_or_default(),
+            issues.join(", "),
+        ]);
+    }
+    print_out(table.render(None));
+    let issues = overview.iter().filter(|tool| !tool.issues.is_empty()).count();
+    if issues > 0 {
+        print_out(warning_message(&format!("{} tool(s) with issues", issues)));
+    }
+    Ok(())
+}
+
+fn remove_versions(env: &RuntimeEnvironment, versions: &[InstalledVersion], no_reshim: bool) -> Result<()> {
+    interrupt::handle_ctrl_c()?;
+    let update = uninstall_versions(env, versions, no_reshim)?;
+    for installed in versions {
+        let msg = message("success.uninstalled", &[("version", &installed.version), ("tool", &installed.tool)]);
+        print_out(success_message(&msg));
+    }
+    Ok(report_shims_update(update))
+}
+
+fn verify(env: &RuntimeEnvironment, tool: Option<&str>, version: Option<&str>) -> Result<()> {
+    info!("Verify requested (tool: {:?}, version: {:?})", &tool, &version);
+    let tool = tool.map(|t| env.config.canonical_tool_name(t));
+    let shims = shims_for_env(env)?;
+    let versions: Vec<InstalledVersion> = shims
+        .installed_versions()?
+        .into_iter()
+        .filter(|v| tool.map_or(true, |t| t == v.tool) && version.map_or(true, |ver| ver == v.version))
+        .collect();
+    if versions.is_empty() {
+        return Err(anyhow!("No installed versions match the request"));
+    }
+    let mut failed = 0;
+    for installed in &versions {
+        let name = format!("{} {}", &installed.tool, &installed.version);
+        let path = manifest_path(&env.manifests_dir, installed);
+        if !path.exists() {
+            print_out(warning_message(&format!("{}: no manifest recorded (run `asdfw reshim`)", &name)));
+            continue;
+        }
+        let discrepancies = Manifest::load(&path)?.verify(&installed.dir)?;
+        if discrepancies.is_empty() {
+            print_out(success_message(&format!("{}: OK", &name)));
+        } else {
+            failed += 1;
+            let header = format!("{}: {} problem(s) found", &name, discrepancies.len());
+            let mut output = warning_message(&header);
+            output.extend(discrepancies.iter().map(|d| Cow::Owned(format!("   {}", d))));
+            print_out(output);
+        }
+    }
+    if failed > 0 {
+        return Err(anyhow!("Verification failed for {} version(s)", failed));
+    }
+    Ok(())
+}
+
+/// Resolves a partial version to the highest installed version matching it
+/// (unless `exact`), asking the user to confirm. Returns `None` if declined.
+/// Unless `force`, fails if the version is not installed.
+fn resolve_version(
+    env: &RuntimeEnvironment,
+    tool: &str,
+    version: &str,
+    exact: bool,
+    force: bool,
+) -> Result<Option<String>> {
+    let resolved = match resolve_version_prefix(env, tool, version)? {
+        Some(resolved) if !exact => resolved,
+        _ if force => return Ok(Some(version.to_owned())),
+        _ => {
+            let msg = format!(
+                "Refusing to configure version '{}' of '{}' (use --force to override)",
+                &version, &tool
+            );
+            ensure_installed(env, tool, version).context(msg)?;
+            return Ok(Some(version.to_owned()));
+        }
+    };
+    if !io::stdin().is_terminal() {
+        return Err(anyhow!(
+            "Version '{}' of '{}' resolves to {}, which can't be confirmed without a console. \
+             Pass the full version (or --exact to configure '{}' as is)",
+            &version,
+            &tool,
+            &resolved,
+            &version
+        ));
+    }
+    let question = format!("Use version {} of {} (the highest installed {})?", &resolved, &tool, &version);
+    if confirm(&question)? {
+        Ok(Some(resolved))
+    } else {
+        Ok(None)
+    }
+}
+
+/// Asks a yes/no question (on stderr, so it's not mixed with the output), an
+/// empty answer (or no input at all) means yes.
+fn confirm(question: &str) -> Result<bool> {
+    eprint!("{} [Y/n] ", question);
+    io::stderr().flush()?;
+    let mut answer = String::new();
+    if io::stdin().read_line(&mut answer).context("reading answer")? == 0 {
+        eprintln!();
+    }
+    Ok(matches!(answer.trim().to_lowercase().as_str(), "" | "y" | "yes"))
+}
+
+fn set_global<'a>(env: &RuntimeEnvironment, tool: &'a str, version: &'a str, exact: bool, force: bool) -> Result<()> {
+    let tool = env.config.canonical_tool_name(tool);
+    let version = match resolve_version(env, tool, version, exact, force)? {
+        Some(version) => version,
+        None => {
+            let msg = format!("Nothing changed, use --exact to configure '{}' as is", version);
+            return Ok(print_out(warning_message(&msg)));
+        }
+    };
+    let version = version.as_str();
+    ensure_allowed(env, tool, version)?;
+    let tvs = tool_versions_for(env, &env.current_dir, tool);
     tvs.save_global(&version)?;
-    let msg = format!("Successfully configured global version ({}) for {}", &version, &tool);
+    if env.config.current_links {
+        let shims = shims_for_env(env)?;
+        for (_, err) in update_current_links(env, &shims, &[tool]) {
+            let args: [(&str, &dyn Display); 2] = [("tool", &tool), ("error", &err)];
+            print_out(warning_message(&message("warning.current_link_not_updated", &args)));
+        }
+    }
+    let context = [("tool", tool), ("version", version), ("scope", "global")];
+    Hooks::new(&env.hooks_dir).run(HookEvent::PostVersionSwitch, &context)?;
+    let msg = message("success.global_configured", &[("version", &version), ("tool", &tool)]);
     let output = success_message(&msg);
     Ok(print_out(output))
 }
@@ -123,24 +1396,469 @@ fn gen_completions<'a>() -> Result<()> {
     Ok(())
 }
 
-fn set_local<'a>(env: &RuntimeEnvironment, tool: &'a str, version: &'a str) -> Result<()> {
-    let tvs = ToolVersions::new(&env.global_tool_versions_file, &env.current_dir, &tool);
+fn powershell_module(env: &RuntimeEnvironment, out: Option<&Path>) -> Result<()> {
+    let dir = match out {
+        Some(dir) => env.current_dir.join(dir),
+        None => return Ok(print!("{}", POWERSHELL_MODULE)),
+    };
+    info!("writing the powershell module into {:?}", &dir);
+    let path = dir.join("Asdfw.psm1");
+    fs::create_dir_all(&dir).context(format!("creating directory {:?}", &dir))?;
+    fs::write(&path, POWERSHELL_MODULE).context(format!("writing {:?}", &path))?;
+    Ok(print_out(success_message(&format!("Saved the module to {:?}", &path))))
+}
+
+fn activate(shell: HookShell) -> Result<()> {
+    let subcommands: Vec<String> = Cli::into_app()
+        .get_subcommands()
+        .filter(|cmd| !cmd.is_hide_set())
+        .map(|cmd| cmd.get_name().to_owned())
+        .collect();
+    Ok(print!("{}", activate::activation_script(shell, &subcommands)))
+}
+
+fn hook_env(env: &RuntimeEnvironment, shell: HookShell) -> Result<()> {
+    // Runs on every directory change, a broken `.asdfw.env` should not break
+    // the prompt
+    let vars = match project_env(&env.current_dir, &env.allowed_env_file) {
+        Ok(project) => {
+            project.warnings.iter().for_each(|warning| print_warning(warning));
+            project.vars
+        }
+        Err(err) => {
+            print_warning(&format!("{:#}", err));
+            vec![]
+        }
+    };
+    let previous = std::env::var(ACTIVE_VARS_VARIABLE).ok();
+    let saved = std::env::var(SAVED_VARS_VARIABLE).ok();
+    let lines =
+        activate::render_hook_env(shell, &vars, previous.as_deref(), saved.as_deref(), |name| std::env::var(name).ok());
+    Ok(print_out(lines))
+}
+
+fn allow(env: &RuntimeEnvironment) -> Result<()> {
+    info!("invoked `allow` in {:?}", &env.current_dir);
+    let file = project_env::find_env_file(&env.current_dir)
+        .ok_or_else(|| anyhow!("No {} file found in {:?} or its parents", ENV_FILE_NAME, &env.current_dir))?;
+    project_env::allow(&env.allowed_env_file, &file)?;
+    Ok(print_out(success_message(&format!("Allowed {:?}", &file))))
+}
+
+fn complete(env: &RuntimeEnvironment, what: CompleteSubCommand) -> Result<()> {
+    let candidates = match &what {
+        CompleteSubCommand::Tools => completion::candidates(env, Candidates::Tools)?,
+        CompleteSubCommand::Versions { tool } => completion::candidates(env, Candidates::Versions(tool))?,
+        CompleteSubCommand::Commands => completion::candidates(env, Candidates::Commands)?,
+    };
+    Ok(print_out(candidates))
+}
+
+fn profile_or_default(profile: Option<PathBuf>) -> Result<PathBuf> {
+    profile
+        .or_else(completion::default_profile)
+        .ok_or(anyhow!("Could not find the PowerShell profile, use --profile"))
+}
+
+fn install_completions(env: &RuntimeEnvironment, profile: Option<PathBuf>) -> Result<()> {
+    let profile = profile_or_default(profile)?;
+    info!("installing completions into {:?}", &profile);
+    let mut script = vec![];
+    generate(PowerShell, &mut Cli::into_app(), APP_NAME, &mut script);
+    let msg = match completion::install(&profile, &env.completion_script, &script)? {
+        true => format!("Completion is loaded from {:?}, restart the shell to enable it", &profile),
+        false => format!("Completion script updated, {:?} already loads it", &profile),
+    };
+    Ok(print_out(success_message(&msg)))
+}
+
+fn uninstall_completions(env: &RuntimeEnvironment, profile: Option<PathBuf>) -> Result<()> {
+    let profile = profile_or_default(profile)?;
+    info!("uninstalling completions from {:?}", &profile);
+    let msg = match completion::uninstall(&profile, &env.completion_script)? {
+        true => format!("Removed completion from {:?}", &profile),
+        false => format!("Completion was not installed in {:?}", &profile),
+    };
+    Ok(print_out(success_message(&msg)))
+}
+
+fn set_local<'a>(env: &RuntimeEnvironment, tool: &'a str, version: &'a str, exact: bool, force: bool) -> Result<()> {
+    let tool = env.config.canonical_tool_name(tool);
+    let version = match resolve_version(env, tool, version, exact, force)? {
+        Some(version) => version,
+        None => {
+            let msg = format!("Nothing changed, use --exact to configure '{}' as is", version);
+            return Ok(print_out(warning_message(&msg)));
+        }
+    };
+    let version = version.as_str();
+    ensure_allowed(env, tool, version)?;
+    let tvs = tool_versions_for(env, &env.current_dir, tool);
     tvs.save_local(&version)?;
-    let msg = format!("Successfully configured local version ({}) for {}", &version, &tool);
+    let context = [("tool", tool), ("version", version), ("scope", "local")];
+    Hooks::new(&env.hooks_dir).run(HookEvent::PostVersionSwitch, &context)?;
+    let msg = message("success.local_configured", &[("version", &version), ("tool", &tool)]);
     let output = success_message(&msg);
     Ok(print_out(output))
 }
 
-fn which(env: &RuntimeEnvironment, cmd: &str) -> Result<()> {
-    info!("invoked `which` on {}", &cmd);
-    let path = find_path_for_cmd(env, cmd)?;
-    Ok(print_out(vec![path]))
+fn unset_version(env: &RuntimeEnvironment, tool: &str, global: bool) -> Result<()> {
+    let tool = env.config.canonical_tool_name(tool);
+    let tvs = tool_versions_for(env, &env.current_dir, tool);
+    let (removed, scope) = if global {
+        (tvs.unset_global()?, "global")
+    } else {
+        (tvs.unset_local()?, "local")
+    };
+    if removed {
+        let msg = message("success.version_removed", &[("scope", &scope), ("tool", &tool)]);
+        Ok(print_out(success_message(&msg)))
+    } else {
+        let msg = format!("No {} version was configured for {}", &scope, &tool);
+        Ok(print_out(warning_message(&msg)))
+    }
+}
+
+/// What `which` prints about the command.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum WhichFormat {
+    Path,
+    WslPath,
+    Invocation,
+    Version,
+    ToolVersion,
+    Porcelain(Porcelain),
+}
+
+fn which(env: &RuntimeEnvironment, cmd: &str, tool: Option<&str>, format: WhichFormat) -> Result<()> {
+    info!("invoked `which` on {} (tool: {:?}, format: {:?})", &cmd, &tool, format);
+    let line = match format {
+        WhichFormat::Invocation => command_line(&find_invocation_for_cmd(env, cmd, tool)?),
+        WhichFormat::Version => find_version_for_cmd(env, cmd, tool)?.1,
+        WhichFormat::ToolVersion => {
+            let (tool, version) = find_version_for_cmd(env, cmd, tool)?;
+            format!("{} {}", &tool, &version)
+        }
+        WhichFormat::Porcelain(porcelain) => {
+            let (tool, version, path) = find_resolution_for_cmd(env, cmd, tool)?;
+            porcelain::which_line(porcelain, &tool, &version, &path)
+        }
+        WhichFormat::Path | WhichFormat::WslPath => {
+            let path = match tool {
+                Some(tool) => find_path_for_cmd_in_tool(env, cmd, tool)?,
+                None => find_path_for_cmd(env, cmd)?,
+            };
+            match format {
+                WhichFormat::WslPath => wsl::to_wsl_path(Path::new(&path))?,
+                _ => path,
+            }
+        }
+    };
+    Ok(print_out(vec![line]))
+}
+
+fn wsl_wrappers(env: &RuntimeEnvironment, dir: &Path) -> Result<()> {
+    info!("invoked `wsl-wrappers` into {:?}", &dir);
+    let dir = env.current_dir.join(dir);
+    let names = wsl::generate_wrappers(&env.shims_dir, &dir)?;
+    let msg = format!("Wrote {} WSL wrapper(s) into {:?}", names.len(), &dir);
+    Ok(print_out(success_message(&msg)))
+}
+
+fn exec_cmd(
+    env: &RuntimeEnvironment,
+    cmd: &str,
+    args: &[String],
+    vars: &[(String, String)],
+    timeout: Option<u64>,
+    no_plugin_env: Option<&str>,
+    change_dir: bool,
+) -> Result<()> {
+    info!(
+        "invoked `exec` on {} (change dir: {}, env: {:?}, timeout: {:?}, no plugin env: {:?})",
+        &cmd, change_dir, &vars, timeout, no_plugin_env
+    );
+    let dir = env.current_dir.as_path();
+    let resolved = resolve_cmd(env, cmd, dir)?;
+    ensure_allowed(env, &resolved.tool, &resolved.version)?;
+    let mut no_plugin_env_list = std::env::var(NO_PLUGIN_ENV_VARIABLE).unwrap_or_default();
+    if let Some(tools) = no_plugin_env {
+        no_plugin_env_list = format!("{},{}", no_plugin_env_list, tools);
+    }
+    // Variables passed on the command line override the configured ones
+    let mut all_vars = command_env(env, &resolved, dir, &no_plugin_env_list)?;
+    all_vars.extend(vars.iter().cloned());
+    let context = ExecutableContext {
+        // `--cwd` also sets the directory the command runs in
+        cwd: if change_dir { Some(dir) } else { None },
+        timeout: timeout.map(Duration::from_secs).or_else(|| env.config.timeout_for(&resolved.tool)),
+        ..resolved.context(env, &all_vars)
+    };
+    let program = &resolved.executable.program;
+    let outcome = exec(program, args, &context);
+    if let Some(audit_file) = &env.config.audit_file {
+        let argv = context.argv(program, args);
+        let record = AuditRecord::new(dir, &resolved.tool, &resolved.version, &argv, &outcome);
+        audit::record(audit_file, &record)?;
+    }
+    match outcome? {
+        0 => Ok(()),
+        code => std::process::exit(code),
+    }
+}
+
+/// Parses a `KEY=VALUE` environment variable argument.
+fn parse_env_var(arg: &str) -> Result<(String, String), String> {
+    match arg.split_once('=') {
+        Some((key, value)) if !key.is_empty() => Ok((key.to_owned(), value.to_owned())),
+        _ => Err(format!("expected KEY=VALUE, got '{}'", arg)),
+    }
+}
+
+fn parse_timeout(arg: &str) -> Result<u64, String> {
+    match arg.parse::<u64>() {
+        Ok(seconds) if seconds > 0 => Ok(seconds),
+        _ => Err(format!("expected a positive number of seconds, got '{}'", arg)),
+    }
+}
+
+fn export_env(env: &RuntimeEnvironment, dir: Option<PathBuf>, shell: Shell) -> Result<()> {
+    let dir = match dir {
+        Some(dir) => env.current_dir.join(dir),
+        None => env.current_dir.clone(),
+    };
+    info!("Exporting environment for {:?} ({:?})", &dir, &shell);
+    let shims = shims_for_env(env)?;
+    let mut paths = vec![];
+    for configured in configured_tools(env, &dir)? {
+        match configured.version_dir {
+            Some(version_dir) => {
+                ensure_allowed(env, &configured.tool, &configured.version)?;
+                paths.extend(shims.bin_dirs(&configured.tool, &version_dir));
+            }
+            None => {
+                let msg = format!("Version '{}' of '{}' is not installed", &configured.version, &configured.tool);
+                warning_message(&msg).iter().for_each(|l| eprintln!("{}", l));
+            }
+        }
+    }
+    let current_path = std::env::var("PATH").unwrap_or_default();
+    Ok(print_out(render_env(shell, &paths, &[], &current_path)))
+}
+
+fn tool_of(env: &RuntimeEnvironment, cmd: &str) -> Result<()> {
+    info!("invoked `tool-of` on {}", &cmd);
+    match find_tool_for_cmd(env, cmd)? {
+        (tool, Some(version)) => print_out(vec![format!("{} {}", &tool, &version)]),
+        (tool, None) => {
+            print_out(vec![tool.clone()]);
+            let msg = format!("No version of {} is configured for the current directory", &tool);
+            print_out(warning_message(&msg));
+        }
+    }
+    Ok(())
+}
+
+fn why(env: &RuntimeEnvironment, tool: &str) -> Result<()> {
+    info!("invoked `why` on {}", &tool);
+    let tool = env.config.canonical_tool_name(tool);
+    let steps = tool_versions_for(env, &env.current_dir, tool).resolution_chain()?;
+    let mut output = vec![format!("Resolving the version of {}:", &tool)];
+    for step in &steps {
+        let source = match &step.source {
+            VersionSource::Environment(var) => format!("environment variable {}", &var),
+            VersionSource::LocalFile(path) => format!("{}", path.display()),
+            VersionSource::GlobalFile(path) => format!("global file {}", path.display()),
+            VersionSource::SystemFile(path) => format!("system defaults {}", path.display()),
+        };
+        let verdict = match (&step.version, &step.source) {
+            (Some(version), _) => format!("{} (selected)", &version),
+            (None, VersionSource::Environment(_)) => "not set".to_owned(),
+            (None, VersionSource::GlobalFile(path) | VersionSource::SystemFile(path)) if !path.exists() => {
+                "does not exist".to_owned()
+            }
+            (None, _) => "not pinned".to_owned(),
+        };
+        output.push(format!("   {}: {}", &source, &verdict));
+    }
+    print_out(output);
+    match steps.last().and_then(|step| step.version.as_ref()) {
+        Some(version) => print_out(success_message(&format!("{} {}", &tool, &version))),
+        None => print_out(warning_message(&format!("No version of {} is configured", &tool))),
+    }
+    Ok(())
+}
+
+fn current(env: &RuntimeEnvironment, tool: Option<&str>, json: bool, porcelain: Option<Porcelain>) -> Result<()> {
+    info!("invoked `current` (tool: {:?}, json: {}, porcelain: {:?})", &tool, json, porcelain);
+    if let (None, Some(porcelain)) = (tool, porcelain) {
+        let configured = configured_tools(env, &env.current_dir)?;
+        let lines = configured.iter().map(|c| porcelain::current_line(porcelain, c)).collect();
+        return Ok(print_out::<String>(lines));
+    }
+    let tool = match tool {
+        Some(tool) => tool,
+        None if json => {
+            let configured = configured_tools(env, &env.current_dir)?;
+            return Ok(print_out(vec![serde_json::to_string_pretty(&configured)?]));
+        }
+        None => {
+            let mut table = Table::new(vec!["Tool", "Version", "Installed"]);
+            for c in configured_tools(env, &env.current_dir)? {
+                let installed = if c.version_dir.is_some() { "yes" } else { "no" };
+                table.add_row(vec![c.tool, c.version, installed.to_owned()]);
+            }
+            if table.is_empty() {
+                return Ok(print_out(warning_message("No tools are configured for the current directory")));
+            }
+            return Ok(print_out(table.render(None)));
+        }
+    };
+    match current_version(env, tool)? {
+        Some(
+            configured @ ConfiguredTool {
+                version_dir: Some(_), ..
+            },
+        ) => match porcelain {
+            Some(porcelain) => Ok(print_out(vec![porcelain::current_line(porcelain, &configured)])),
+            None => Ok(print_out(vec![configured.version])),
+        },
+        Some(configured) => Err(ErrorKind::NotInstalled.error_with_hint(
+            message(
+                "error.configured_not_installed",
+                &[("version", &configured.version), ("tool", &configured.tool)],
+            ),
+            install_hint(&configured.tool, &configured.version),
+        )),
+        None => Err(ErrorKind::NoVersion.error_with_hint(
+            message("error.no_version", &[("tool", &tool)]),
+            configure_hint(env.config.canonical_tool_name(tool)),
+        )),
+    }
+}
+
+fn show_messages() -> Result<()> {
+    info!("invoked `messages`");
+    Ok(print_out(vec![serde_yaml::to_string(&all_messages())?]))
+}
+
+fn show_stats(env: &RuntimeEnvironment, limit: usize) -> Result<()> {
+    info!("invoked `stats` (limit: {})", limit);
+    let invocations = stats::load(&env.stats_file)?;
+    if !env.config.record_stats {
+        print_out(warning_message(&format!(
+            "Statistics are not recorded, set `record_stats = true` in {:?} to enable",
+            &env.config_file
+        )));
+    }
+    if invocations.is_empty() {
+        return Ok(print_out(warning_message("No invocations recorded yet.")));
+    }
+    let mut top = Table::new(vec!["Command", "Runs", "Average"]);
+    for s in stats::top_commands(&invocations, limit) {
+        top.add_row(vec![
+            s.command.clone(),
+            s.count.to_string(),
+            format!("{:.0?}", s.average_duration()),
+        ]);
+    }
+    let mut slowest = Table::new(vec!["Command", "Resolution", "Tool", "Version"]);
+    for i in stats::slowest_resolutions(&invocations, limit) {
+        let resolution = format!("{:.1?}", i.resolution);
+        slowest.add_row(vec![i.command.clone(), resolution, i.tool.clone(), i.version.clone()]);
+    }
+    let mut output = vec![format!("Top commands ({} invocations):", invocations.len())];
+    output.extend(top.render(None));
+    output.push("".to_owned());
+    output.push("Slowest resolutions:".to_owned());
+    output.extend(slowest.render(None));
+    let failures = stats::recent_failures(&invocations, limit);
+    if !failures.is_empty() {
+        let now = stats::now();
+        let mut table = Table::new(vec!["Command", "Exit Code", "When"]);
+        for i in failures {
+            table.add_row(vec![
+                i.command.clone(),
+                i.exit_code.to_string(),
+                stats::format_age(i.timestamp, now),
+            ]);
+        }
+        output.push("".to_owned());
+        output.push("Recent failures:".to_owned());
+        output.extend(table.render(None));
+    }
+    Ok(print_out(output))
+}
+
+fn audit_versions(env: &RuntimeEnvironment, feed: Option<&Path>, json: bool) -> Result<()> {
+    info!("invoked `audit` (feed: {:?})", &feed);
+    let feed = feed.or(env.config.advisory_feed.as_deref()).ok_or(anyhow!(
+        "No advisory feed configured, set `advisory_feed` in {:?} or use --feed",
+        &env.config_file
+    ))?;
+    let advisories = advisories::load(feed)?;
+    // (installed, pinned) per tool and version
+    let mut versions: BTreeMap<(String, String), (bool, bool)> = BTreeMap::new();
+    for installed in shims_for_env(env)?.installed_versions()? {
+        versions.entry((installed.tool, installed.version)).or_default().0 = true;
+    }
+    for configured in configured_tools(env, &env.current_dir)? {
+        versions.entry((configured.tool, configured.version)).or_default().1 = true;
+    }
+    let mut findings = vec![];
+    for ((tool, version), (installed, pinned)) in &versions {
+        for advisory in advisories::affecting(&advisories, tool, version)? {
+            findings.push(Finding {
+                tool: tool.clone(),
+                version: version.clone(),
+                advisory: advisory.id.clone(),
+                severity: advisory.severity.clone(),
+                summary: advisory.summary.clone(),
+                installed: *installed,
+                pinned: *pinned,
+            });
+        }
+    }
+    if json {
+        print_out(vec![serde_json::to_string_pretty(&findings)?]);
+        // Keep the output valid JSON, only signal the findings with the exit code
+        if !findings.is_empty() {
+            std::process::exit(ErrorKind::VulnerableVersions.code());
+        }
+        return Ok(());
+    }
+    if findings.is_empty() {
+        let msg = format!("No known advisories affect the {} version(s) in use", versions.len());
+        return Ok(print_out(success_message(&msg)));
+    }
+    let mut table = Table::new(vec!["Tool", "Version", "Advisory", "Severity", "In Use", "Summary"]);
+    for f in &findings {
+        let in_use = match (f.installed, f.pinned) {
+            (true, true) => "installed, pinned",
+            (true, false) => "installed",
+            _ => "pinned",
+        };
+        table.add_row(vec![
+            f.tool.clone(),
+            f.version.clone(),
+            f.advisory.clone(),
+            f.severity.clone().unwrap_or_default(),
+            in_use.to_owned(),
+            f.summary.clone(),
+        ]);
+    }
+    print_out(table.render(None));
+    Err(ErrorKind::VulnerableVersions.error(format!("{} advisory finding(s)", findings.len())))
 }
 
 fn log_to_file(env: &RuntimeEnvironment, spec: &str) -> Result<LoggerHandle> {
     Ok(Logger::try_with_str(spec)?
         .log_to_file(FileSpec::default().directory(&env.log_dir))
-        .rotate(Criterion::Size(1_000_000), Naming::Numbers, Cleanup::KeepLogFiles(4))
+        .rotate(
+            Criterion::Size(1_000_000),
+            Naming::Numbers,
+            Cleanup::KeepLogFiles(gc::KEEP_LOG_FILES),
+        )
         .append()
         .start()?)
 }
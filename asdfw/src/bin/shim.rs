@@ -1,48 +1,254 @@
-use anyhow::{anyhow, Context, Result};
+use anyhow::Result;
+use asdfw::audit::{self, AuditRecord};
+use asdfw::common::{
+    command_env, configure_hint, ensure_allowed, ensure_enabled, extra_shims_dirs, find_in_path, install_hint,
+    is_skipped, resolve_executable, resolve_manual_shim, resolve_reentrant, shims_for_env, tool_versions_for,
+    ResolvedCommand, ResolvedExecutable, NO_PLUGIN_ENV_VARIABLE, PATH_FALLBACK_VERSION, SKIP_VARIABLE,
+};
+use asdfw::errors::{error_hint, error_kind, exit_code, ErrorKind};
+use asdfw::gc;
+use asdfw::output::{message, print_warning};
 use asdfw::runtime::RuntimeEnvironment;
-use asdfw::shims::Shims;
-use asdfw::subcommand::exec;
-use asdfw::tool_versions::ToolVersions;
+use asdfw::shim_health::{self, ShimFailure};
+use asdfw::shims::{ShimData, Shims};
+use asdfw::stats::{self, Invocation};
+use asdfw::subcommand::*;
 use flexi_logger::*;
-use std::{env, process};
+use log::{debug, info, LevelFilter};
+use std::{env, ffi::OsString, fmt::Display, path::PathBuf, process, time::Instant};
 
 const ERROR_PREFIX: &str = "ASDFW ERROR";
 const DEBUG_VARIABLE: &str = "ASDFW_DEBUG_SHIM";
 
-fn main() -> Result<()> {
+fn main() {
     match run() {
-        Ok(0) => Ok(()),
-        Ok(exit_code) => process::exit(exit_code),
-        Err(err) => Err(err).context(ERROR_PREFIX),
+        Ok(0) => {}
+        Ok(code) => process::exit(code),
+        Err(err) => {
+            let code = exit_code(&err);
+            let hint = error_hint(&err);
+            eprintln!("Error: {:?}", err.context(ERROR_PREFIX));
+            if let Some(hint) = hint {
+                eprintln!("\nHint: {}", hint);
+            }
+            process::exit(code)
+        }
     }
 }
 
 fn run() -> Result<i32> {
+    let started = Instant::now();
     let me = env::current_exe()?;
     let exe_name = me.file_name().unwrap().to_str().unwrap();
-    let args = env::args().skip(1);
+    let args = env::args_os().skip(1);
+    let mut chain = shim_chain();
+    let repeats = shim_repeats(&chain, exe_name);
+    if repeats >= MAX_SHIM_REPEATS {
+        let args: [(&str, &dyn Display); 2] = [("command", &exe_name), ("repeats", &repeats)];
+        return Err(ErrorKind::ShimLoop.error(message("error.shim_loop", &args)));
+    }
+    // Inherited by the executable (and anything it runs)
+    chain.push(exe_name.to_owned());
+    env::set_var(SHIM_CHAIN_VARIABLE, chain.join(";"));
     let runtime = RuntimeEnvironment::new()?;
-    if let Ok(_) = env::var(DEBUG_VARIABLE) {
-        configure_log(&runtime)?;
+    let debug_shim = env::var(DEBUG_VARIABLE).is_ok();
+    if debug_shim {
+        configure_log(&runtime, LevelFilter::Debug)?;
+    };
+    let (resolved, vars) = match resolve(&runtime, exe_name, debug_shim) {
+        Ok(resolved) => resolved,
+        Err(err) => {
+            if runtime.config.record_shim_failures {
+                record_failure(&runtime, exe_name, &err);
+            }
+            return Err(err);
+        }
+    };
+    if runtime.config.record_shim_failures {
+        if let Err(err) = shim_health::clear(&runtime.shim_failures_dir, exe_name) {
+            debug!("Failed to clear shim failures: {:#}", err);
+        }
+    }
+    let exe_name = resolved.exe_name.as_str();
+    let tool = &resolved.tool;
+    let version = &resolved.version;
+    let context = resolved.context(&runtime, &vars);
+    let program = &resolved.executable.program;
+    let resolution = started.elapsed();
+    let args: Vec<OsString> = args.collect();
+    let outcome = exec(program, &args, &context);
+    if let Some(audit_file) = &runtime.config.audit_file {
+        let argv = context.argv(program, &args);
+        let record = AuditRecord::new(&runtime.current_dir, tool, version, &argv, &outcome);
+        if let Err(err) = audit::record(audit_file, &record) {
+            eprintln!("{}: {:#}", ERROR_PREFIX, err);
+        }
+    }
+    let exit_code = outcome?;
+    if runtime.config.record_stats {
+        let invocation = Invocation {
+            timestamp: stats::now(),
+            command: exe_name.to_owned(),
+            tool: tool.clone(),
+            version: version.clone(),
+            resolution,
+            duration: started.elapsed() - resolution,
+            exit_code,
+        };
+        if let Err(err) = stats::record(&runtime.stats_file, &invocation) {
+            debug!("Failed to record stats: {:#}", err);
+        }
+    }
+    Ok(exit_code)
+}
+
+/// Resolves the executable the shim runs and the environment variables to run
+/// it with.
+fn resolve(
+    runtime: &RuntimeEnvironment,
+    exe_name: &str,
+    debug_shim: bool,
+) -> Result<(ResolvedCommand, Vec<(String, String)>)> {
+    let no_plugin_env = env::var(NO_PLUGIN_ENV_VARIABLE).unwrap_or_default();
+    if let Some(inherited) = inherited_context() {
+        if let Some(resolved) = resolve_reentrant(runtime, exe_name, &inherited)? {
+            debug!("Reusing {} {} of the outer shim", &resolved.tool, &resolved.version);
+            let vars = command_env(runtime, &resolved, &runtime.current_dir, &no_plugin_env)?;
+            return Ok((resolved, vars));
+        }
+    }
+    let shims = shims_for_env(runtime)?;
+    let (exe_name, shim) = shims
+        .find_shim_entry(exe_name)?
+        .ok_or_else(|| ErrorKind::NoTool.error(message("error.no_tool", &[("command", &exe_name)])))?;
+    if !debug_shim {
+        match runtime.config.log_level_for("shim", Some(shim.tool())) {
+            Ok(Some(level)) => {
+                configure_log(runtime, level)?;
+            }
+            Ok(None) => {}
+            Err(err) => print_warning(&format!("{:#}, not logging", err)),
+        }
+    }
+    let exe_name = exe_name.as_str();
+    let skip = env::var(SKIP_VARIABLE).unwrap_or_default();
+    let resolved = if is_skipped(runtime, &skip, shim.tool()) {
+        resolve_skipped(runtime, exe_name, shim.tool())?
+    } else {
+        ensure_enabled(runtime, shim.tool())?;
+        match shim {
+            ShimData::Manual { tool, path } => resolve_manual_shim(exe_name.to_owned(), tool, path)?,
+            ShimData::Tool(tool) => resolve_installed(runtime, &shims, exe_name, tool)?,
+        }
     };
-    let shims = Shims::new(&runtime.shims_db, &runtime.installs_dir, &runtime.shims_dir, &runtime.shim_exe)?;
-    let tool = shims
-        .find_plugin(&exe_name)?
-        .ok_or(anyhow!("No tool configured for the command: {}", &exe_name))?;
-    let tool_versions = ToolVersions::new(&runtime.global_tool_versions_file, &runtime.current_dir, &tool);
-    match tool_versions.get_version()? {
-        Some(version) => match shims.get_full_executable_path(&exe_name, &tool, &version)? {
-            Some(cmd) => exec(&cmd, args),
-            None => Err(anyhow!("Version '{}' of '{}' does not seems to be installed", &version, &tool)),
+    let vars = command_env(runtime, &resolved, &runtime.current_dir, &no_plugin_env)?;
+    Ok((resolved, vars))
+}
+
+/// Records the failure to resolve the command (reported by `asdfw doctor`
+/// once repeated). Errors recording it are only logged.
+fn record_failure(runtime: &RuntimeEnvironment, exe_name: &str, err: &anyhow::Error) {
+    let failure = ShimFailure {
+        timestamp: stats::now(),
+        command: exe_name.to_owned(),
+        error_id: error_kind(err).map_or("-", |kind| kind.id()).to_owned(),
+        dir: runtime.current_dir.clone(),
+        reason: format!("{:#}", err),
+    };
+    if let Err(err) = shim_health::record(&runtime.shim_failures_dir, &failure) {
+        debug!("Failed to record shim failure: {:#}", err);
+    }
+}
+
+/// Resolves the executable of the version of the tool configured for the
+/// current directory.
+fn resolve_installed(
+    runtime: &RuntimeEnvironment,
+    shims: &Shims,
+    exe_name: &str,
+    tool: String,
+) -> Result<ResolvedCommand> {
+    let tool_versions = tool_versions_for(runtime, &runtime.current_dir, &tool);
+    let version = tool_versions.get_version()?;
+    if let Some(version) = &version {
+        ensure_allowed(runtime, &tool, version)?;
+    }
+    match version {
+        Some(version) => match resolve_executable(runtime, shims, exe_name, &tool, &version)? {
+            Some(executable) => Ok(ResolvedCommand {
+                exe_name: exe_name.to_owned(),
+                install_dir: shims.find_version_dir(&tool, &version).unwrap_or_default(),
+                tool,
+                version,
+                executable,
+            }),
+            None if shims.find_orphaned_shims(&runtime.config)?.iter().any(|o| o == exe_name) => {
+                let args: [(&str, &dyn Display); 2] = [("command", &exe_name), ("tool", &tool)];
+                Err(ErrorKind::NotProvided.error(message("error.no_longer_provided", &args)))
+            }
+            None => Err(ErrorKind::NotInstalled.error_with_hint(
+                message("error.not_installed", &[("version", &version), ("tool", &tool)]),
+                install_hint(&tool, &version),
+            )),
+        },
+        None if runtime.config.path_fallback_for(&tool) => match resolve_from_path(runtime, exe_name, &tool) {
+            Some(resolved) => {
+                let program = &resolved.executable.program;
+                info!("No version of '{}' is configured, falling back to {:?}", &tool, program);
+                Ok(resolved)
+            }
+            None => Err(ErrorKind::NoVersion.error_with_hint(
+                message("error.no_version_not_in_path", &[("command", &exe_name), ("tool", &tool)]),
+                configure_hint(&tool),
+            )),
         },
-        None => Err(anyhow!("You don't have a version configured for '{}' ({})", &exe_name, &tool)),
+        None => Err(ErrorKind::NoVersion.error_with_hint(
+            message("error.no_version_for_command", &[("command", &exe_name), ("tool", &tool)]),
+            configure_hint(&tool),
+        )),
     }
 }
 
-fn configure_log(runtime: &RuntimeEnvironment) -> Result<LoggerHandle> {
-    Ok(Logger::try_with_str("debug")?
+/// Resolves the executable of a tool listed in `ASDFW_SKIP` to the one found
+/// in `PATH`, ignoring the configured version.
+fn resolve_skipped(runtime: &RuntimeEnvironment, exe_name: &str, tool: &str) -> Result<ResolvedCommand> {
+    match resolve_from_path(runtime, exe_name, tool) {
+        Some(resolved) => {
+            let program = &resolved.executable.program;
+            info!("'{}' is listed in {}, running {:?}", &tool, SKIP_VARIABLE, program);
+            Ok(resolved)
+        }
+        None => {
+            let args: [(&str, &dyn Display); 3] =
+                [("command", &exe_name), ("tool", &tool), ("variable", &SKIP_VARIABLE)];
+            Err(ErrorKind::NoTool.error_with_hint(
+                message("error.skipped_not_in_path", &args),
+                message("hint.remove_from_skip", &[("tool", &tool), ("variable", &SKIP_VARIABLE)]),
+            ))
+        }
+    }
+}
+
+/// Resolves the executable to the one found in `PATH` (skipping the shims),
+/// reported with the `path` version.
+fn resolve_from_path(runtime: &RuntimeEnvironment, exe_name: &str, tool: &str) -> Option<ResolvedCommand> {
+    let path = env::var_os("PATH").unwrap_or_default();
+    let mut shims_dirs = extra_shims_dirs(runtime, &runtime.current_dir);
+    shims_dirs.push(runtime.shims_dir.clone());
+    let program = find_in_path(exe_name, &path, &shims_dirs)?;
+    Some(ResolvedCommand {
+        exe_name: exe_name.to_owned(),
+        install_dir: program.parent().map(PathBuf::from).unwrap_or_default(),
+        tool: tool.to_owned(),
+        version: PATH_FALLBACK_VERSION.to_owned(),
+        executable: ResolvedExecutable { program, script: None },
+    })
+}
+
+fn configure_log(runtime: &RuntimeEnvironment, level: LevelFilter) -> Result<LoggerHandle> {
+    Ok(Logger::try_with_str(level.to_string())?
         .log_to_file(FileSpec::default().directory(&runtime.log_dir))
-        .rotate(Criterion::Size(100_000), Naming::Numbers, Cleanup::KeepLogFiles(6))
+        .rotate(Criterion::Size(100_000), Naming::Numbers, Cleanup::KeepLogFiles(gc::KEEP_LOG_FILES))
         .append()
         .start()?)
 }
@@ -0,0 +1,118 @@
+use anyhow::Result;
+use log::debug;
+use std::fmt::Display;
+use std::path::Path;
+
+use crate::common::{configured_tools, ensure_allowed, shims_for_env};
+use crate::errors::ClassifiedError;
+use crate::runtime::RuntimeEnvironment;
+use crate::shims::ShimState;
+
+/// A reason a tool pinned for a directory is not ready to use.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CheckFailure {
+    /// The pinned version is not installed.
+    NotInstalled { tool: String, version: String },
+    /// The pinned version is forbidden by the policy (the reason is the
+    /// policy violation message).
+    NotAllowed {
+        tool: String,
+        version: String,
+        reason: String,
+    },
+    /// An executable of the pinned version has no shim.
+    MissingShim { tool: String, exe: String },
+    /// The shim of an executable was created by an older shim executable.
+    StaleShim { tool: String, exe: String },
+    /// The shims db maps an executable of the pinned version to another tool
+    /// (`owner`), although reshim would map it to this one.
+    MisdirectedShim { tool: String, exe: String, owner: String },
+}
+
+impl Display for CheckFailure {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            CheckFailure::NotInstalled { tool, version } => write!(f, "{} {} is not installed", tool, version),
+            CheckFailure::NotAllowed { reason, .. } => write!(f, "{}", reason),
+            CheckFailure::MissingShim { tool, exe } => write!(f, "{} has no shim for {}", tool, exe),
+            CheckFailure::StaleShim { tool, exe } => write!(f, "{} has an out-of-date shim for {}", tool, exe),
+            CheckFailure::MisdirectedShim { tool, exe, owner } => {
+                write!(f, "{} has no shim for {} (the shims db maps it to {})", tool, exe, owner)
+            }
+        }
+    }
+}
+
+/// The result of checking a directory.
+#[derive(Debug, Default)]
+pub struct CheckReport {
+    /// The number of tools pinned for the directory.
+    pub checked: usize,
+    pub failures: Vec<CheckFailure>,
+}
+
+/// Checks that every tool pinned for the directory (in its `.tool-versions`
+/// files or the global one) is installed, allowed by the policy and has up
+/// to date shims for all of its executables. Only executables reshim would
+/// map to the tool are checked: the ones of other tools by the conflict
+/// policy, skipped as conflicts or registered manually are not.
+pub fn check(env: &RuntimeEnvironment, dir: &Path) -> Result<CheckReport> {
+    let shims = shims_for_env(env)?;
+    let (expected, _) = shims.generate_db_from_installed_tools(&env.config)?;
+    let mut report = CheckReport::default();
+    for configured in configured_tools(env, dir)? {
+        report.checked += 1;
+        let (tool, version) = (configured.tool, configured.version);
+        if let Err(err) = ensure_allowed(env, &tool, &version) {
+            let reason = err
+                .chain()
+                .find_map(|cause| cause.downcast_ref::<ClassifiedError>())
+                .map_or_else(|| format!("{:#}", err), |classified| classified.message.clone());
+            report.failures.push(CheckFailure::NotAllowed {
+                tool: tool.clone(),
+                version: version.clone(),
+                reason,
+            });
+        }
+        let installed = match shims.find_installed_version(&tool, &version) {
+            Some(installed) => installed,
+            None => {
+                report.failures.push(CheckFailure::NotInstalled { tool, version });
+                continue;
+            }
+        };
+        for exe in shims.version_executables(&env.config, &installed)? {
+            let owner = expected
+                .iter()
+                .find(|(name, _)| name.eq_ignore_ascii_case(&exe))
+                .map(|(_, shim)| shim.tool());
+            if owner != Some(tool.as_str()) {
+                debug!("Not checking {} of {}, reshim maps it to {:?}", &exe, &tool, owner);
+                continue;
+            }
+            if let Some((_, shim)) = shims.find_db_entry(&exe)? {
+                if shim.tool() != tool {
+                    report.failures.push(CheckFailure::MisdirectedShim {
+                        tool: tool.clone(),
+                        exe,
+                        owner: shim.tool().to_owned(),
+                    });
+                    continue;
+                }
+            }
+            match shims.shim_state(&exe)? {
+                ShimState::Current => {}
+                ShimState::Stale => report.failures.push(CheckFailure::StaleShim {
+                    tool: tool.clone(),
+                    exe,
+                }),
+                ShimState::Missing => report.failures.push(CheckFailure::MissingShim {
+                    tool: tool.clone(),
+                    exe,
+                }),
+            }
+        }
+    }
+    debug!("Checked {} tools, {} failures", report.checked, report.failures.len());
+    Ok(report)
+}
@@ -1,31 +1,931 @@
-use anyhow::{anyhow, Context, Result};
+use anyhow::{anyhow, Context, Error, Result};
 use log::debug;
+use serde::Serialize;
 
-use crate::{runtime::RuntimeEnvironment, shims::Shims, tool_versions::ToolVersions};
+use crate::tool_versions::{pinned_tools, ToolVersions};
+use crate::{
+    errors::ErrorKind,
+    hooks::{HookEvent, Hooks},
+    install::{cache_artifact, install_from_file},
+    interrupt,
+    manifest::{manifest_path, Manifest},
+    output::{message, print_warning, Progress},
+    project_env::project_env,
+    receipt::{receipt_path, Receipt},
+    runtime::RuntimeEnvironment,
+    shims::{path_version_dir, InstalledVersion, ShimData, Shims, ShimsChanges},
+    subcommand::{self, shim_depth, ExecutableContext, InheritedContext},
+    version::{compare_versions, highest_matching},
+};
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::ffi::OsStr;
+use std::fmt::Display;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::{Child, Command, Output};
+
+/// A tool pinned in one of the `.tool-versions` files.
+#[derive(Debug, PartialEq, Eq, Serialize)]
+pub struct ConfiguredTool {
+    pub tool: String,
+    pub version: String,
+    /// The directory of the configured version, `None` if not installed.
+    pub version_dir: Option<PathBuf>,
+}
+
+/// Returns the effective version of every tool pinned in the provided
+/// directory (or its parents, or the global file).
+pub fn configured_tools(env: &RuntimeEnvironment, dir: &Path) -> Result<Vec<ConfiguredTool>> {
+    let shims = shims_for_env(env)?;
+    let mut configured = vec![];
+    let mut tools: Vec<&str> = vec![];
+    let pinned = pinned_tools(
+        &env.global_tool_versions_file,
+        env.system_tool_versions_file.as_deref(),
+        dir,
+        env.config.tool_versions_parsing,
+    )?;
+    let installed = shims.installed_versions()?;
+    for name in &pinned {
+        let tool = env.config.canonical_tool_name(name);
+        // Tool names are case-insensitive, prefer the name it's installed as
+        let tool = installed
+            .iter()
+            .map(|installed| installed.tool.as_str())
+            .find(|installed| installed.eq_ignore_ascii_case(tool))
+            .unwrap_or(tool);
+        if !tools.contains(&tool) {
+            tools.push(tool);
+        }
+    }
+    tools.sort_unstable();
+    for tool in tools.into_iter().filter(|tool| !env.config.is_disabled(tool)) {
+        let tvs = tool_versions_for(env, dir, &tool);
+        if let Some(version) = tvs.get_version()? {
+            let version_dir = shims.find_version_dir(&tool, &version);
+            configured.push(ConfiguredTool {
+                tool: tool.to_owned(),
+                version,
+                version_dir,
+            });
+        }
+    }
+    Ok(configured)
+}
+
+/// An inconsistency between the configuration, the installed versions and the
+/// pinned version of a tool.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ToolIssue {
+    /// Configured in `config.toml` but no version is installed.
+    NoInstalls,
+    /// The custom install dir configured for the tool does not exist.
+    MissingInstallDir,
+    /// The pinned version is not installed.
+    PinnedNotInstalled,
+}
+
+impl Display for ToolIssue {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let description = match self {
+            ToolIssue::NoInstalls => "configured but not installed",
+            ToolIssue::MissingInstallDir => "install dir does not exist",
+            ToolIssue::PinnedNotInstalled => "pinned version not installed",
+        };
+        write!(f, "{}", description)
+    }
+}
+
+/// The state of a tool on this machine.
+#[derive(Debug, PartialEq, Eq)]
+pub struct ToolOverview {
+    pub tool: String,
+    /// Whether the tool has a `[tools.<name>]` table in the configuration.
+    pub configured: bool,
+    pub disabled: bool,
+    /// The installed versions (sorted by version).
+    pub installed: Vec<String>,
+    /// The effective version pinned for the directory.
+    pub pinned: Option<String>,
+    pub issues: Vec<ToolIssue>,
+}
+
+/// Joins the configured tools, the installed versions and the versions
+/// pinned for the directory into an overview of every tool (sorted by name).
+pub fn tools_overview(env: &RuntimeEnvironment, dir: &Path) -> Result<Vec<ToolOverview>> {
+    let shims = shims_for_env(env)?;
+    let mut installed: BTreeMap<String, Vec<String>> = BTreeMap::new();
+    for version in shims.installed_versions()? {
+        installed.entry(version.tool).or_default().push(version.version);
+    }
+    let pinned: BTreeMap<String, ConfiguredTool> =
+        configured_tools(env, dir)?.into_iter().map(|c| (c.tool.clone(), c)).collect();
+    let mut tools: BTreeSet<&str> = env.config.tools.keys().map(String::as_str).collect();
+    tools.extend(installed.keys().map(String::as_str));
+    tools.extend(pinned.keys().map(String::as_str));
+    let mut overview = vec![];
+    for tool in tools {
+        let config = env.config.tools.get(tool);
+        let mut versions = installed.get(tool).cloned().unwrap_or_default();
+        versions.sort_by(|a, b| compare_versions(a, b));
+        let mut issues = vec![];
+        if config.and_then(|c| c.install_dir.as_ref()).map_or(false, |dir| !dir.is_dir()) {
+            issues.push(ToolIssue::MissingInstallDir);
+        } else if config.is_some() && versions.is_empty() {
+            issues.push(ToolIssue::NoInstalls);
+        }
+        let pinned = pinned.get(tool);
+        if pinned.map_or(false, |p| p.version_dir.is_none()) {
+            issues.push(ToolIssue::PinnedNotInstalled);
+        }
+        overview.push(ToolOverview {
+            tool: tool.to_owned(),
+            configured: config.is_some(),
+            disabled: env.config.is_disabled(tool),
+            installed: versions,
+            pinned: pinned.map(|p| p.version.clone()),
+            issues,
+        });
+    }
+    Ok(overview)
+}
+
+/// Returns the effective version of the tool in the current directory, `None`
+/// if no version is configured.
+pub fn current_version(env: &RuntimeEnvironment, tool: &str) -> Result<Option<ConfiguredTool>> {
+    let tool = env.config.canonical_tool_name(tool);
+    ensure_enabled(env, tool)?;
+    let shims = shims_for_env(env)?;
+    let version = tool_versions_for(env, &env.current_dir, tool).get_version()?;
+    Ok(version.map(|version| ConfiguredTool {
+        tool: tool.to_owned(),
+        version_dir: shims.find_version_dir(tool, &version),
+        version,
+    }))
+}
+
+/// Returns the installed versions of the tool (or of all tools) which are not
+/// referenced by the global file nor by any of the provided project
+/// directories. Versions in the system install dir are never returned.
+pub fn unreferenced_versions(
+    env: &RuntimeEnvironment,
+    tool: Option<&str>,
+    project_dirs: &[PathBuf],
+) -> Result<Vec<InstalledVersion>> {
+    let shims = shims_for_env(env)?;
+    let mut unreferenced = vec![];
+    for installed in shims.installed_versions()? {
+        if installed.system || tool.map_or(false, |t| t != installed.tool) {
+            continue;
+        }
+        let tvs = tool_versions_for(env, &env.current_dir, &installed.tool);
+        let mut referenced = vec![tvs.get_global_version()?];
+        for dir in project_dirs {
+            let tvs = tool_versions_for(env, dir, &installed.tool);
+            referenced.push(tvs.get_pinned_version()?);
+        }
+        if !referenced.contains(&Some(installed.version.clone())) {
+            unreferenced.push(installed);
+        }
+    }
+    Ok(unreferenced)
+}
+
+/// Regenerates the shims db and only creates the missing shims and removes
+/// the dangling ones (existing shims are left untouched). Returns the changes
+/// performed. Nothing is changed if interrupted with Ctrl-C before the db is
+/// saved, afterwards it stops between shims.
+pub fn incremental_reshim(env: &RuntimeEnvironment, shims: &Shims) -> Result<ShimsChanges> {
+    let (db, _) = shims.generate_db_from_installed_tools(&env.config)?;
+    let changes = shims.plan_shims(&db, true)?;
+    interrupt::check()?;
+    shims.save_db(&db)?;
+    let mut progress = Progress::new("Creating shims", Some(changes.created.len()));
+    for exe in &changes.created {
+        interrupt::check()?;
+        shims.create_shim(exe)?;
+        progress.inc();
+    }
+    if !changes.removed.is_empty() {
+        shims.remove_shims(&changes.removed)?;
+    }
+    Ok(changes)
+}
+
+/// What happened to the shims after the install directories were changed
+/// (see `update_shims_after_change`).
+#[derive(Debug)]
+pub enum ShimsUpdate {
+    /// Not updated (`--no-reshim`).
+    Skipped,
+    Updated(ShimsChanges),
+    /// Updating the shims failed. The install directories were changed
+    /// anyway, so this is only a warning.
+    Failed(Error),
+}
+
+/// Brings the shims up to date after the install directories were changed,
+/// unless `no_reshim` is set: runs the reshim hooks around
+/// `incremental_reshim`, records the manifests of new versions and updates
+/// the `current` links (problems with the last two are printed as warnings).
+pub fn update_shims_after_change(env: &RuntimeEnvironment, no_reshim: bool) -> ShimsUpdate {
+    if no_reshim {
+        return ShimsUpdate::Skipped;
+    }
+    match update_shims(env) {
+        Ok(changes) => ShimsUpdate::Updated(changes),
+        Err(err) => ShimsUpdate::Failed(err),
+    }
+}
+
+fn update_shims(env: &RuntimeEnvironment) -> Result<ShimsChanges> {
+    debug!("Updating shims after the install directories changed");
+    let hooks = Hooks::new(&env.hooks_dir);
+    hooks.run(HookEvent::PreReshim, &[])?;
+    let shims = shims_for_env(env)?;
+    let changes = incremental_reshim(env, &shims)?;
+    for (installed, err) in record_missing_manifests(env, &shims)? {
+        let args: [(&str, &dyn Display); 3] = [
+            ("tool", &installed.tool),
+            ("version", &installed.version),
+            ("error", &err),
+        ];
+        print_warning(&message("warning.manifest_not_recorded", &args));
+    }
+    if env.config.current_links {
+        let installed = shims.installed_versions()?;
+        let mut tools: Vec<&str> = installed.iter().map(|i| i.tool.as_str()).collect();
+        tools.sort();
+        tools.dedup();
+        for (tool, err) in update_current_links(env, &shims, &tools) {
+            let args: [(&str, &dyn Display); 2] = [("tool", &tool), ("error", &err)];
+            print_warning(&message("warning.current_link_not_updated", &args));
+        }
+    }
+    hooks.run(HookEvent::PostReshim, &[])?;
+    Ok(changes)
+}
+
+/// Installs the version of the tool from the archive (or installer), caches
+/// the archive for `reinstall` and updates the shims (see
+/// `update_shims_after_change`). Failing to update the shims does not fail the
+/// install.
+pub fn install_version(
+    env: &RuntimeEnvironment,
+    tool: &str,
+    version: &str,
+    from_file: &Path,
+    no_reshim: bool,
+) -> Result<ShimsUpdate> {
+    ensure_allowed(env, tool, version)?;
+    let shims = shims_for_env(env)?;
+    if let Some(dir) = shims.find_version_dir(tool, version) {
+        return Err(anyhow!("Version '{}' of '{}' is already installed ({:?})", &version, &tool, &dir));
+    }
+    run_pre_install_hooks(env, tool, version, from_file)?;
+    let target = shims.install_target(tool, version);
+    install_from_file(from_file, &target, |staging| prepare_version(env, tool, version, from_file, staging))
+        .context(format!("installing version {} of {}", &version, &tool))?;
+    cache_artifact(&env.cache_dir.join(tool).join(version), from_file)?;
+    if let Some(installed) = shims.find_installed_version(tool, version) {
+        record_manifest_or_warn(env, &installed);
+    }
+    Ok(update_shims_after_change(env, no_reshim))
+}
+
+/// Runs the `pre-install` hooks of the version about to be installed from the
+/// source file (before anything is extracted). A failing hook aborts the
+/// install.
+pub fn run_pre_install_hooks(env: &RuntimeEnvironment, tool: &str, version: &str, source: &Path) -> Result<()> {
+    let source = source.to_string_lossy();
+    let context = [("tool", tool), ("version", version), ("source", source.as_ref())];
+    Hooks::new(&env.hooks_dir).run(HookEvent::PreInstall, &context)
+}
+
+/// Records the receipt of the staged version and runs the `post-install`
+/// hooks on it (before it's moved into place).
+pub fn prepare_version(env: &RuntimeEnvironment, tool: &str, version: &str, source: &Path, dir: &Path) -> Result<()> {
+    Receipt::new(tool, version, source)?.save(&receipt_path(dir))?;
+    let dir = dir.to_string_lossy();
+    let source = source.to_string_lossy();
+    let context = [
+        ("tool", tool),
+        ("version", version),
+        ("source", source.as_ref()),
+        ("dir", dir.as_ref()),
+    ];
+    Hooks::new(&env.hooks_dir).run(HookEvent::PostInstall, &context)
+}
+
+/// Removes the installed versions along with their manifests and cached
+/// files, then updates the shims (see `update_shims_after_change`).
+pub fn uninstall_versions(
+    env: &RuntimeEnvironment,
+    versions: &[InstalledVersion],
+    no_reshim: bool,
+) -> Result<ShimsUpdate> {
+    for installed in versions {
+        debug!("Removing {:?}", &installed.dir);
+        fs::remove_dir_all(&installed.dir).context(format!("removing {:?}", &installed.dir))?;
+        let manifest = manifest_path(&env.manifests_dir, installed);
+        if manifest.exists() {
+            fs::remove_file(&manifest).context(format!("removing manifest {:?}", &manifest))?;
+        }
+        let cache_dir = env.cache_dir.join(&installed.tool).join(&installed.version);
+        if cache_dir.exists() {
+            fs::remove_dir_all(&cache_dir).context(format!("removing cached files {:?}", &cache_dir))?;
+        }
+    }
+    // The shims update points the junctions at the global versions again (if
+    // enabled), but never leaves a junction to a removed version behind
+    let shims = shims_for_env(env)?;
+    let mut tools: Vec<&str> = versions.iter().map(|installed| installed.tool.as_str()).collect();
+    tools.sort_unstable();
+    tools.dedup();
+    for tool in tools {
+        if let Err(err) = shims.remove_dangling_current_links(tool) {
+            let args: [(&str, &dyn Display); 2] = [("tool", &tool), ("error", &err)];
+            print_warning(&message("warning.current_link_not_updated", &args));
+        }
+    }
+    Ok(update_shims_after_change(env, no_reshim))
+}
+
+/// Records the manifest of the installed version, replacing an existing one.
+pub fn record_manifest(env: &RuntimeEnvironment, installed: &InstalledVersion) -> Result<()> {
+    debug!("Recording manifest for {} {}", &installed.tool, &installed.version);
+    let path = manifest_path(&env.manifests_dir, installed);
+    Manifest::generate(&installed.dir).and_then(|m| m.save(&path))
+}
+
+/// Records the manifest of a version that was just (re)installed. The version
+/// is in place already, so failing to record it is only a warning.
+pub fn record_manifest_or_warn(env: &RuntimeEnvironment, installed: &InstalledVersion) {
+    if let Err(err) = record_manifest(env, installed) {
+        let args: [(&str, &dyn Display); 3] = [
+            ("tool", &installed.tool),
+            ("version", &installed.version),
+            ("error", &err),
+        ];
+        print_warning(&message("warning.manifest_not_recorded", &args));
+    }
+}
+
+/// Records a manifest for every installed version which does not have one yet
+/// (e.g. versions extracted manually). Versions that could not be hashed are
+/// returned along with the error.
+pub fn record_missing_manifests(env: &RuntimeEnvironment, shims: &Shims) -> Result<Vec<(InstalledVersion, Error)>> {
+    let mut failures = vec![];
+    for installed in shims.installed_versions()? {
+        if manifest_path(&env.manifests_dir, &installed).exists() {
+            continue;
+        }
+        if let Err(err) = record_manifest(env, &installed) {
+            failures.push((installed, err));
+        }
+    }
+    Ok(failures)
+}
+
+/// Suggests configuring a version of the tool (error hint).
+pub fn configure_hint(tool: &str) -> String {
+    message("hint.configure_version", &[("tool", &tool)])
+}
+
+/// Suggests installing the version of the tool (error hint).
+pub fn install_hint(tool: &str, version: &str) -> String {
+    if let Some(dir) = path_version_dir(version) {
+        return message("hint.path_version_missing", &[("dir", &format!("{:?}", dir))]);
+    }
+    message("hint.install_version", &[("tool", &tool), ("version", &version)])
+}
+
+/// Fails if the tool was disabled in the configuration.
+pub fn ensure_enabled(env: &RuntimeEnvironment, tool: &str) -> Result<()> {
+    if env.config.is_disabled(tool) {
+        let config_file = format!("{:?}", &env.config_file);
+        return Err(ErrorKind::ToolDisabled.error_with_hint(
+            message("error.tool_disabled", &[("tool", &tool), ("config_file", &config_file)]),
+            message("hint.enable_tool", &[("tool", &tool)]),
+        ));
+    }
+    Ok(())
+}
+
+/// Fails if the version of the tool is forbidden by the policy.
+pub fn ensure_allowed(env: &RuntimeEnvironment, tool: &str, version: &str) -> Result<()> {
+    env.policy()?.check(tool, version)
+}
+
+/// Fails if the version of the tool is not installed, listing the installed
+/// versions.
+pub fn ensure_installed(env: &RuntimeEnvironment, tool: &str, version: &str) -> Result<()> {
+    let shims = shims_for_env(env)?;
+    if shims.find_version_dir(tool, version).is_some() {
+        return Ok(());
+    }
+    let installed: Vec<String> = shims
+        .installed_versions()?
+        .into_iter()
+        .filter(|installed| installed.tool == tool)
+        .map(|installed| installed.version)
+        .collect();
+    let available = match installed.is_empty() {
+        true => message("error.no_versions_installed", &[]),
+        false => message("error.installed_versions", &[("versions", &installed.join(", "))]),
+    };
+    let args: [(&str, &dyn Display); 3] = [("version", &version), ("tool", &tool), ("installed", &available)];
+    Err(ErrorKind::NotInstalled
+        .error_with_hint(message("error.not_installed_among", &args), install_hint(tool, version)))
+}
+
+/// Returns the highest installed version of the tool starting with the
+/// provided (partial) version, `None` if the version is installed as is or
+/// no installed version matches.
+pub fn resolve_version_prefix(env: &RuntimeEnvironment, tool: &str, version: &str) -> Result<Option<String>> {
+    let shims = shims_for_env(env)?;
+    if shims.find_version_dir(tool, version).is_some() {
+        return Ok(None);
+    }
+    let installed = shims.installed_versions()?;
+    let versions = installed.iter().filter(|i| i.tool == tool).map(|i| i.version.as_str());
+    Ok(highest_matching(versions, version).map(str::to_owned))
+}
+
+/// Creates `ToolVersions` for the tool in the provided directory, honoring the
+/// aliases configured for it.
+pub fn tool_versions_for(env: &RuntimeEnvironment, dir: &Path, tool: &str) -> ToolVersions {
+    ToolVersions::new(&env.global_tool_versions_file, dir, tool)
+        .with_aliases(env.config.aliases_for(tool))
+        .with_system_file(env.system_tool_versions_file.as_deref())
+        .with_mode(env.config.tool_versions_parsing)
+}
+
+/// Creates `Shims` with all the installations directories of the environment.
+pub fn shims_for_env(env: &RuntimeEnvironment) -> Result<Shims> {
+    let shims = Shims::new(&env.shims_db, &env.installs_dir, &env.shims_dir, &env.shim_exe)?
+        .with_extra_shims_dirs(extra_shims_dirs(env, &env.current_dir));
+    let tool_install_dirs = env
+        .config
+        .tools
+        .iter()
+        .filter_map(|(tool, config)| config.install_dir.as_deref().map(|dir| (tool.as_str(), dir)))
+        .collect();
+    let tool_bin_dirs: HashMap<&str, Vec<&Path>> = env
+        .config
+        .tools
+        .iter()
+        .filter(|(_, config)| !config.bin_dirs.is_empty())
+        .map(|(tool, config)| (tool.as_str(), config.bin_dirs.iter().map(PathBuf::as_path).collect()))
+        .collect();
+    Ok(shims
+        .with_system_install_dir(env.system_installs_dir.as_deref())
+        .with_tool_install_dirs(tool_install_dirs)
+        .with_tool_bin_dirs(tool_bin_dirs))
+}
+
+/// The additional shim directories configured (`shim_dirs`) in priority
+/// order. Relative directories are looked up in the directory and its
+/// parents (the closest one is used), directories not found are skipped.
+pub fn extra_shims_dirs(env: &RuntimeEnvironment, dir: &Path) -> Vec<PathBuf> {
+    env.config
+        .shim_dirs
+        .iter()
+        .filter_map(|shims_dir| match shims_dir.is_absolute() {
+            true => Some(shims_dir.clone()).filter(|shims_dir| shims_dir.is_dir()),
+            false => dir.ancestors().map(|d| d.join(shims_dir)).find(|d| d.is_dir()),
+        })
+        .collect()
+}
+
+/// Points the `current` junction of each of the provided tools at its global
+/// version. Tools without installed global version (or pinned to a `path:`
+/// version) are skipped. Returns the tools that could not be linked along
+/// with the error.
+pub fn update_current_links(env: &RuntimeEnvironment, shims: &Shims, tools: &[&str]) -> Vec<(String, Error)> {
+    let mut failures = vec![];
+    for tool in tools {
+        let tvs = tool_versions_for(env, &env.current_dir, tool);
+        let result = tvs.get_global_version().and_then(|version| match version {
+            Some(version) if path_version_dir(&version).is_some() => Ok(()),
+            Some(version) if shims.find_version_dir(tool, &version).is_some() => {
+                shims.update_current_link(tool, &version).map(|_| ())
+            }
+            _ => Ok(()),
+        });
+        if let Err(err) = result {
+            failures.push((tool.to_string(), err));
+        }
+    }
+    failures
+}
+
+/// Returns the tool which owns the command and the version of it configured
+/// for the current directory (if any).
+pub fn find_tool_for_cmd(env: &RuntimeEnvironment, cmd: &str) -> Result<(String, Option<String>)> {
+    let shims = shims_for_env(env)?;
+    let (_, tool) = resolve_tool(&shims, cmd)?;
+    ensure_enabled(env, &tool)?;
+    let tvs = tool_versions_for(env, &env.current_dir, &tool);
+    let version = tvs.get_version()?;
+    Ok((tool, version))
+}
+
+/// The command to run for a shim.
+#[derive(Debug, PartialEq, Eq)]
+pub struct ResolvedExecutable {
+    pub program: PathBuf,
+    /// The script to pass to `program` if the shim is a configured script.
+    pub script: Option<PathBuf>,
+}
+
+impl ResolvedExecutable {
+    /// The file provided by the tool (the script if it's a script).
+    pub fn path(&self) -> &Path {
+        self.script.as_deref().unwrap_or(&self.program)
+    }
+}
+
+/// Returns the arguments running the executable without the shim: batch and
+/// PowerShell scripts through their launcher, configured scripts through
+/// their interpreter, followed by the default arguments.
+pub fn invocation(program: &Path, script: Option<&Path>, default_args: &[String]) -> Vec<String> {
+    let extension = program.extension().and_then(OsStr::to_str).map(str::to_ascii_lowercase);
+    let launcher: &[&str] = match extension.as_deref() {
+        Some("cmd" | "bat") => &["cmd.exe", "/d", "/c"],
+        Some("ps1") => &["powershell.exe", "-NoProfile", "-ExecutionPolicy", "Bypass", "-File"],
+        _ => &[],
+    };
+    let mut args: Vec<String> = launcher.iter().map(|arg| arg.to_string()).collect();
+    args.push(program.to_string_lossy().into_owned());
+    args.extend(script.map(|script| script.to_string_lossy().into_owned()));
+    args.extend(default_args.iter().cloned());
+    args
+}
+
+/// Resolves the executable behind the shim in the provided version of the
+/// tool. Configured scripts are run by their interpreter, which is looked up
+/// in the same version first and then in the tool selected by the shims db.
+pub fn resolve_executable(
+    env: &RuntimeEnvironment,
+    shims: &Shims,
+    exe_name: &str,
+    tool: &str,
+    version: &str,
+) -> Result<Option<ResolvedExecutable>> {
+    if let Some(program) = shims.get_full_executable_path(exe_name, tool, version)? {
+        return Ok(Some(ResolvedExecutable { program, script: None }));
+    }
+    let (script, interpreter) = match env.config.script_for(tool, exe_name) {
+        Some(mapping) => mapping,
+        None => return Ok(None),
+    };
+    let script = match shims.get_full_executable_path(script, tool, version)? {
+        Some(path) => path,
+        None => return Ok(None),
+    };
+    debug!("Running script {:?} with interpreter '{}'", &script, &interpreter);
+    let program = match shims.find_executable_in_tool(interpreter, tool, version)? {
+        Some(path) => path,
+        None => find_path_for_cmd(env, interpreter)
+            .context(format!("resolving interpreter '{}' for {:?}", &interpreter, &script))?
+            .into(),
+    };
+    Ok(Some(ResolvedExecutable {
+        program,
+        script: Some(script),
+    }))
+}
+
+/// The version reported for shims registered using `asdfw shim add` (which
+/// always run the same executable).
+pub const MANUAL_VERSION: &str = "manual";
+
+/// The version reported for shims falling back to the executable in PATH
+/// (see `path_fallback` in the configuration).
+pub const PATH_FALLBACK_VERSION: &str = "path";
+
+/// Tools (`,` separated, aliases allowed) whose shims skip version management
+/// and run the executable found in `PATH`, e.g. `ASDFW_SKIP=nodejs,python`.
+pub const SKIP_VARIABLE: &str = "ASDFW_SKIP";
+
+/// Tools (`,` separated, aliases allowed) whose configured environment
+/// (`[tools.<name>.env]`) is not set for their commands, e.g.
+/// `ASDFW_NO_PLUGIN_ENV=java` when a build script sets `JAVA_HOME` itself.
+pub const NO_PLUGIN_ENV_VARIABLE: &str = "ASDFW_NO_PLUGIN_ENV";
+
+/// Whether the tool is listed in `skip` (the value of `ASDFW_SKIP`).
+pub fn is_skipped(env: &RuntimeEnvironment, skip: &str, tool: &str) -> bool {
+    is_listed(env, skip, tool)
+}
+
+/// Whether the tool is listed in `no_plugin_env` (the value of
+/// `ASDFW_NO_PLUGIN_ENV`, along with `exec --no-plugin-env`).
+pub fn is_plugin_env_disabled(env: &RuntimeEnvironment, no_plugin_env: &str, tool: &str) -> bool {
+    is_listed(env, no_plugin_env, tool)
+}
+
+/// Whether the tool is listed in `list` (`,` separated tool names or
+/// aliases).
+fn is_listed(env: &RuntimeEnvironment, list: &str, tool: &str) -> bool {
+    list.split(',')
+        .map(str::trim)
+        .filter(|name| !name.is_empty())
+        .any(|name| env.config.canonical_tool_name(name).eq_ignore_ascii_case(tool))
+}
+
+/// Looks the executable up in the directories of `path` (the value of the
+/// `PATH` variable), skipping the shim directories so the shim doesn't find
+/// itself.
+pub fn find_in_path(exe_name: &str, path: &OsStr, shims_dirs: &[PathBuf]) -> Option<PathBuf> {
+    let shims_dirs: Vec<PathBuf> = shims_dirs
+        .iter()
+        .map(|dir| fs::canonicalize(dir).unwrap_or_else(|_| dir.clone()))
+        .collect();
+    std::env::split_paths(path)
+        .filter(|dir| fs::canonicalize(dir).map_or(true, |dir| !shims_dirs.contains(&dir)))
+        .map(|dir| dir.join(exe_name))
+        .find(|candidate| candidate.is_file())
+}
+
+/// A command resolved to the executable of the configured version of the
+/// tool providing it.
+#[derive(Debug)]
+pub struct ResolvedCommand {
+    /// The name of the executable (e.g. `kubectl.exe` for `kubectl`).
+    pub exe_name: String,
+    pub tool: String,
+    pub version: String,
+    pub install_dir: PathBuf,
+    pub executable: ResolvedExecutable,
+}
+
+impl ResolvedCommand {
+    /// The context running the executable (with its configured default
+    /// arguments and the extra environment variables).
+    pub fn context<'a>(&'a self, env: &'a RuntimeEnvironment, vars: &'a [(String, String)]) -> ExecutableContext<'a> {
+        ExecutableContext {
+            tool: &self.tool,
+            version: &self.version,
+            install_dir: &self.install_dir,
+            depth: shim_depth(),
+            env: vars,
+            cwd: None,
+            script: self.executable.script.as_deref(),
+            default_args: env.config.default_args_for(&self.tool, &self.exe_name),
+            timeout: None,
+        }
+    }
+}
+
+/// Returns the invocation (see `invocation`) of the command in the current
+/// directory, looked up in the provided tool (if any).
+pub fn find_invocation_for_cmd(env: &RuntimeEnvironment, cmd: &str, tool: Option<&str>) -> Result<Vec<String>> {
+    let tool = match tool {
+        Some(tool) => env.config.canonical_tool_name(tool),
+        None => {
+            let resolved = resolve_cmd(env, cmd, &env.current_dir)?;
+            let executable = &resolved.executable;
+            let default_args = env.config.default_args_for(&resolved.tool, &resolved.exe_name);
+            return Ok(invocation(&executable.program, executable.script.as_deref(), default_args));
+        }
+    };
+    let path = PathBuf::from(find_path_for_cmd_in_tool(env, cmd, tool)?);
+    let exe_name = path.file_name().unwrap_or_default().to_string_lossy();
+    Ok(invocation(&path, None, env.config.default_args_for(tool, &exe_name)))
+}
+
+/// Returns the variables set for the resolved command: the environment
+/// configured for its tool (with `{install_dir}`, `{version}` and `{tool}`
+/// expanded) followed by the project environment of the directory, which
+/// takes precedence (only loaded if allowed, problems with it are printed as
+/// warnings). Commands not running an installed version (`manual`
+/// and `path` versions) and tools listed in `no_plugin_env` (see
+/// `is_plugin_env_disabled`) only get the project environment.
+pub fn command_env(
+    env: &RuntimeEnvironment,
+    resolved: &ResolvedCommand,
+    dir: &Path,
+    no_plugin_env: &str,
+) -> Result<Vec<(String, String)>> {
+    let mut vars = vec![];
+    let managed = resolved.version != MANUAL_VERSION && resolved.version != PATH_FALLBACK_VERSION;
+    let suppressed = is_plugin_env_disabled(env, no_plugin_env, &resolved.tool);
+    if suppressed {
+        debug!(
+            "'{}' is listed in {}, not setting its environment",
+            &resolved.tool, NO_PLUGIN_ENV_VARIABLE
+        );
+    }
+    if let Some(config) = env.config.tools.get(&resolved.tool).filter(|_| managed && !suppressed) {
+        let install_dir = resolved.install_dir.to_string_lossy();
+        for (key, value) in &config.env {
+            let value = value
+                .replace("{install_dir}", &install_dir)
+                .replace("{version}", &resolved.version)
+                .replace("{tool}", &resolved.tool);
+            vars.push((key.clone(), value));
+        }
+    }
+    let project = project_env(dir, &env.allowed_env_file)?;
+    project.warnings.iter().for_each(|warning| print_warning(warning));
+    vars.extend(project.vars);
+    Ok(vars)
+}
+
+/// Runs the command (resolved in the current directory, with its environment,
+/// see `command_env`) to completion, capturing its stdout and stderr.
+pub fn command_output<I, S>(env: &RuntimeEnvironment, cmd: &str, args: I) -> Result<Output>
+where
+    I: IntoIterator<Item = S>,
+    S: AsRef<OsStr>,
+{
+    let resolved = resolve_cmd(env, cmd, &env.current_dir)?;
+    ensure_allowed(env, &resolved.tool, &resolved.version)?;
+    let no_plugin_env = std::env::var(NO_PLUGIN_ENV_VARIABLE).unwrap_or_default();
+    let vars = command_env(env, &resolved, &env.current_dir, &no_plugin_env)?;
+    subcommand::output(&resolved.executable.program, args, &resolved.context(env, &vars))
+}
+
+/// Starts the command (resolved in the current directory, with its
+/// environment, see `command_env`) without waiting for it. `configure` could redirect the
+/// standard streams of the child.
+pub fn spawn_command<I, S, F>(env: &RuntimeEnvironment, cmd: &str, args: I, configure: F) -> Result<Child>
+where
+    I: IntoIterator<Item = S>,
+    S: AsRef<OsStr>,
+    F: FnOnce(&mut Command),
+{
+    let resolved = resolve_cmd(env, cmd, &env.current_dir)?;
+    ensure_allowed(env, &resolved.tool, &resolved.version)?;
+    let no_plugin_env = std::env::var(NO_PLUGIN_ENV_VARIABLE).unwrap_or_default();
+    let vars = command_env(env, &resolved, &env.current_dir, &no_plugin_env)?;
+    subcommand::spawn(&resolved.executable.program, args, &resolved.context(env, &vars), configure)
+}
+
+/// Resolves the command using the version configured in the provided
+/// directory.
+pub fn resolve_cmd(env: &RuntimeEnvironment, cmd: &str, dir: &Path) -> Result<ResolvedCommand> {
+    let shims = shims_for_env(env)?;
+    let (cmd_name, tool) = resolve_tool(&shims, cmd)?;
+    ensure_enabled(env, &tool)?;
+    if let Some(ShimData::Manual { path, .. }) = shims.find_shim(&cmd_name)? {
+        return resolve_manual_shim(cmd_name, tool, path);
+    }
+    let tvs = tool_versions_for(env, dir, &tool);
+    let version = tvs.get_version()?.ok_or_else(|| {
+        ErrorKind::NoVersion.error_with_hint(message("error.no_version", &[("tool", &tool)]), configure_hint(&tool))
+    })?;
+    match resolve_executable(env, &shims, &cmd_name, &tool, &version)? {
+        Some(executable) => Ok(ResolvedCommand {
+            install_dir: shims.find_version_dir(&tool, &version).unwrap_or_default(),
+            exe_name: cmd_name,
+            tool,
+            version,
+            executable,
+        }),
+        None => Err(ErrorKind::NotInstalled.error_with_hint(
+            message("error.configured_not_installed", &[("version", &version), ("tool", &tool)]),
+            install_hint(&tool, &version),
+        )),
+    }
+}
+
+/// Resolves a command re-invoked by an executable of the tool an outer shim
+/// resolved (e.g. a wrapper calling `python` again) to the executable in the
+/// inherited install dir, without looking it up in the shims db. Only when
+/// running in the directory the outer shim resolved the version for, which
+/// still resolves to the same version (`None` otherwise). The tool must still
+/// be enabled and the version allowed.
+pub fn resolve_reentrant(
+    env: &RuntimeEnvironment,
+    exe_name: &str,
+    inherited: &InheritedContext,
+) -> Result<Option<ResolvedCommand>> {
+    let same_dir = match (fs::canonicalize(&env.current_dir), fs::canonicalize(&inherited.dir)) {
+        (Ok(current), Ok(inherited)) => current == inherited,
+        _ => false,
+    };
+    if !same_dir {
+        debug!("Not reusing the version of {}, resolved for {:?}", &inherited.tool, &inherited.dir);
+        return Ok(None);
+    }
+    let tool = &inherited.tool;
+    ensure_enabled(env, tool)?;
+    if tool_versions_for(env, &env.current_dir, tool).get_version()?.as_ref() != Some(&inherited.version) {
+        debug!("The version of {} changed since {} was resolved", tool, &inherited.version);
+        return Ok(None);
+    }
+    ensure_allowed(env, tool, &inherited.version)?;
+    let program = shims_for_env(env)?.find_in_bin_dirs(tool, &inherited.install_dir, exe_name);
+    Ok(program.map(|program| ResolvedCommand {
+        exe_name: exe_name.to_owned(),
+        tool: tool.clone(),
+        version: inherited.version.clone(),
+        install_dir: inherited.install_dir.clone(),
+        executable: ResolvedExecutable { program, script: None },
+    }))
+}
+
+/// Resolves a shim registered using `asdfw shim add` to its executable (the
+/// install dir is the executable's directory).
+pub fn resolve_manual_shim(exe_name: String, tool: String, path: PathBuf) -> Result<ResolvedCommand> {
+    if !path.is_file() {
+        let args: [(&str, &dyn Display); 2] = [("command", &exe_name), ("path", &format!("{:?}", &path))];
+        return Err(ErrorKind::NotProvided.error(message("error.manual_shim_missing", &args)));
+    }
+    Ok(ResolvedCommand {
+        install_dir: path.parent().map(Path::to_path_buf).unwrap_or_default(),
+        exe_name,
+        tool,
+        version: MANUAL_VERSION.to_owned(),
+        executable: ResolvedExecutable {
+            program: path,
+            script: None,
+        },
+    })
+}
 
 pub fn find_path_for_cmd(env: &RuntimeEnvironment, cmd: &str) -> Result<String> {
-    let shims = Shims::new(&env.shims_db, &env.installs_dir, &env.shims_dir, &env.shim_exe)?;
+    let resolved = resolve_cmd(env, cmd, &env.current_dir)?;
+    executable_path_to_string(resolved.executable.path())
+}
+
+/// Like `find_path_for_cmd` but looks the command up in the provided tool
+/// instead of the tool selected in the shims db. Used to pick a specific
+/// provider of a command offered by more than one tool.
+pub fn find_path_for_cmd_in_tool(env: &RuntimeEnvironment, cmd: &str, tool: &str) -> Result<String> {
+    let (_, path) = resolve_cmd_in_tool(env, cmd, tool)?;
+    executable_path_to_string(&path)
+}
+
+/// Returns the tool providing the command and the version of it the command
+/// runs with in the current directory (looked up in the provided tool if
+/// any). Fails like `find_path_for_cmd` if the command can't be run.
+pub fn find_version_for_cmd(env: &RuntimeEnvironment, cmd: &str, tool: Option<&str>) -> Result<(String, String)> {
+    let (tool, version, _) = find_resolution_for_cmd(env, cmd, tool)?;
+    Ok((tool, version))
+}
+
+/// Returns the tool, version and path of the executable the command runs in
+/// the current directory (looked up in the provided tool if any).
+pub fn find_resolution_for_cmd(
+    env: &RuntimeEnvironment,
+    cmd: &str,
+    tool: Option<&str>,
+) -> Result<(String, String, PathBuf)> {
+    match tool {
+        Some(tool) => {
+            let (version, path) = resolve_cmd_in_tool(env, cmd, tool)?;
+            Ok((env.config.canonical_tool_name(tool).to_owned(), version, path))
+        }
+        None => {
+            let resolved = resolve_cmd(env, cmd, &env.current_dir)?;
+            let path = resolved.executable.path().to_path_buf();
+            Ok((resolved.tool, resolved.version, path))
+        }
+    }
+}
+
+/// Returns the configured version of the tool and the path of the command
+/// in it.
+fn resolve_cmd_in_tool(env: &RuntimeEnvironment, cmd: &str, tool: &str) -> Result<(String, PathBuf)> {
+    let shims = shims_for_env(env)?;
+    let tool = env.config.canonical_tool_name(tool);
+    ensure_enabled(env, tool)?;
+    let tvs = tool_versions_for(env, &env.current_dir, tool);
+    let version = tvs.get_version()?.ok_or_else(|| {
+        ErrorKind::NoVersion.error_with_hint(message("error.no_version", &[("tool", &tool)]), configure_hint(tool))
+    })?;
+    if shims.find_version_dir(tool, &version).is_none() {
+        return Err(ErrorKind::NotInstalled.error_with_hint(
+            message("error.configured_not_installed", &[("version", &version), ("tool", &tool)]),
+            install_hint(tool, &version),
+        ));
+    }
+    match shims.find_executable_in_tool(cmd, tool, &version)? {
+        Some(path) => Ok((version, path)),
+        None => {
+            let args: [(&str, &dyn Display); 3] = [("version", &version), ("tool", &tool), ("command", &cmd)];
+            Err(ErrorKind::NotProvided.error(message("error.not_provided", &args)))
+        }
+    }
+}
+
+fn executable_path_to_string(path: &Path) -> Result<String> {
+    let path_str = path.to_str().ok_or(anyhow!(
+        "Couldn't convert '{:?}' to UTF8 string. Are you using non UTF8 file system?",
+        path
+    ))?;
+    Ok(path_str.to_owned())
+}
+
+fn resolve_tool(shims: &Shims, cmd: &str) -> Result<(String, String)> {
     let context = format!("resolving command ({})", &cmd);
     let cmd_name = shims.resolve_command(&cmd).context(context)?.unwrap_or(cmd.to_string());
     debug!("Command '{}' resolved to: '{}'", &cmd, &cmd_name);
     let tool = shims
         .find_plugin(&cmd_name)?
-        .ok_or(anyhow!("No tool configured for the command: {}", &cmd_name))?;
-    let tvs = ToolVersions::new(&env.global_tool_versions_file, &env.current_dir, &tool);
-    let version = tvs.get_version()?.ok_or(anyhow!("No version configured for {}", &tool))?;
-    match shims.get_full_executable_path(&cmd_name, &tool, &version)? {
-        Some(path) => {
-            let path_str = path.to_str().ok_or(anyhow!(
-                "Couldn't convert '{:?}' to UTF8 string. Are you using non UTF8 file system?",
-                path
-            ))?;
-            Ok(path_str.to_owned())
-        }
-        None => Err(anyhow!(
-            "Version '{}' of '{}' configured but not installed (tool: {})",
-            &version,
-            &cmd_name,
-            &tool
-        )),
-    }
+        .ok_or_else(|| ErrorKind::NoTool.error(message("error.no_tool", &[("command", &cmd_name)])))?;
+    Ok((cmd_name, tool))
 }
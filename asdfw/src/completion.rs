@@ -0,0 +1,199 @@
+use anyhow::{Context, Result};
+use log::debug;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::common::shims_for_env;
+use crate::runtime::RuntimeEnvironment;
+
+const BLOCK_START: &str = "# >>> asdfw completion >>>";
+const BLOCK_END: &str = "# <<< asdfw completion <<<";
+
+/// What the dynamic completion endpoint (`asdfw complete`) lists.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Candidates<'a> {
+    /// Installed (and enabled) tools along with their aliases.
+    Tools,
+    /// Installed versions of the tool (or of the tool it is an alias of).
+    Versions(&'a str),
+    /// Shimmed commands (without extension).
+    Commands,
+}
+
+/// Returns the completion candidates, sorted and without duplicates.
+pub fn candidates(env: &RuntimeEnvironment, what: Candidates) -> Result<Vec<String>> {
+    let shims = shims_for_env(env)?;
+    let mut candidates: Vec<String> = match what {
+        Candidates::Tools => shims
+            .installed_versions()?
+            .into_iter()
+            .filter(|installed| !env.config.is_disabled(&installed.tool))
+            .flat_map(|installed| {
+                let aliases = env.config.aliases_for(&installed.tool).to_vec();
+                aliases.into_iter().chain(Some(installed.tool))
+            })
+            .collect(),
+        Candidates::Versions(tool) => {
+            let tool = env.config.canonical_tool_name(tool);
+            shims
+                .installed_versions()?
+                .into_iter()
+                .filter(|installed| installed.tool == tool)
+                .map(|installed| installed.version)
+                .collect()
+        }
+        Candidates::Commands if !env.shims_db.exists() => vec![],
+        Candidates::Commands => shims
+            .shim_names()?
+            .into_iter()
+            .map(|exe| exe.strip_suffix(".exe").map(str::to_owned).unwrap_or(exe))
+            .collect(),
+    };
+    candidates.sort();
+    candidates.dedup();
+    Ok(candidates)
+}
+
+/// The current user's PowerShell profile (`$PROFILE`): the PowerShell 7 one
+/// if its directory exists, the Windows PowerShell one otherwise.
+pub fn default_profile() -> Option<PathBuf> {
+    let documents = dirs::document_dir()?;
+    let dir = match documents.join("PowerShell") {
+        dir if dir.is_dir() => dir,
+        _ => documents.join("WindowsPowerShell"),
+    };
+    Some(dir.join("Microsoft.PowerShell_profile.ps1"))
+}
+
+/// Saves the completion script and adds a block dot-sourcing it to the
+/// profile, replacing the block if it already exists. Returns whether the
+/// profile was changed.
+pub fn install(profile: &Path, script_path: &Path, script: &[u8]) -> Result<bool> {
+    if let Some(parent) = script_path.parent() {
+        fs::create_dir_all(parent).context(format!("creating directory {:?}", &parent))?;
+    }
+    fs::write(script_path, script).context(format!("saving completion script {:?}", &script_path))?;
+    let contents = read_profile(profile)?;
+    let updated = add_block(&contents, script_path);
+    if updated == contents {
+        debug!("Profile {:?} is up to date", &profile);
+        return Ok(false);
+    }
+    if let Some(parent) = profile.parent() {
+        fs::create_dir_all(parent).context(format!("creating directory {:?}", &parent))?;
+    }
+    fs::write(profile, updated).context(format!("updating profile {:?}", &profile))?;
+    Ok(true)
+}
+
+/// Removes the block added by `install` from the profile and the completion
+/// script. Returns whether the profile was changed.
+pub fn uninstall(profile: &Path, script_path: &Path) -> Result<bool> {
+    if script_path.exists() {
+        fs::remove_file(script_path).context(format!("removing completion script {:?}", &script_path))?;
+    }
+    let contents = read_profile(profile)?;
+    let updated = remove_block(&contents);
+    if updated == contents {
+        return Ok(false);
+    }
+    fs::write(profile, updated).context(format!("updating profile {:?}", &profile))?;
+    Ok(true)
+}
+
+fn read_profile(profile: &Path) -> Result<String> {
+    if !profile.exists() {
+        return Ok(String::new());
+    }
+    fs::read_to_string(profile).context(format!("reading profile {:?}", &profile))
+}
+
+fn block(script_path: &Path) -> Vec<String> {
+    let path = script_path.to_string_lossy().replace('\'', "''");
+    vec![BLOCK_START.to_owned(), format!(". '{}'", path), BLOCK_END.to_owned()]
+}
+
+/// Returns the contents with the block dot-sourcing the script in place of
+/// the existing block, or appended if there is none.
+fn add_block(contents: &str, script_path: &Path) -> String {
+    let newline = newline_of(contents);
+    let mut lines: Vec<String> = contents.lines().map(str::to_owned).collect();
+    match block_range(&lines) {
+        Some((start, end)) => {
+            lines.splice(start..=end, block(script_path));
+        }
+        None => lines.extend(block(script_path)),
+    }
+    lines.join(newline) + newline
+}
+
+/// Returns the contents without the block (if any).
+fn remove_block(contents: &str) -> String {
+    let lines: Vec<&str> = contents.lines().collect();
+    let (start, end) = match block_range(&lines) {
+        Some(range) => range,
+        None => return contents.to_owned(),
+    };
+    let newline = newline_of(contents);
+    let remaining: Vec<&str> = lines[..start].iter().chain(&lines[end + 1..]).copied().collect();
+    match remaining.is_empty() {
+        true => String::new(),
+        false => remaining.join(newline) + newline,
+    }
+}
+
+fn block_range<S: AsRef<str>>(lines: &[S]) -> Option<(usize, usize)> {
+    let start = lines.iter().position(|line| line.as_ref().trim() == BLOCK_START)?;
+    let end = lines[start..].iter().position(|line| line.as_ref().trim() == BLOCK_END)?;
+    Some((start, start + end))
+}
+
+fn newline_of(contents: &str) -> &'static str {
+    if contents.contains("\r\n") {
+        "\r\n"
+    } else {
+        "\n"
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use assert_fs::{prelude::*, TempDir};
+    use rstest::rstest;
+
+    #[rstest]
+    #[case(
+        "",
+        "# >>> asdfw completion >>>\n. 'C:\\asdfw\\completion.ps1'\n# <<< asdfw completion <<<\n"
+    )]
+    #[case(
+        "Set-PSReadLineOption -EditMode Emacs\r\n",
+        "Set-PSReadLineOption -EditMode Emacs\r\n# >>> asdfw completion >>>\r\n. 'C:\\asdfw\\completion.ps1'\r\n# <<< asdfw completion <<<\r\n"
+    )]
+    #[case(
+        "# >>> asdfw completion >>>\n. 'C:\\old\\completion.ps1'\n# <<< asdfw completion <<<\nImport-Module posh-git\n",
+        "# >>> asdfw completion >>>\n. 'C:\\asdfw\\completion.ps1'\n# <<< asdfw completion <<<\nImport-Module posh-git\n"
+    )]
+    fn add_block_appends_or_replaces(#[case] contents: &str, #[case] expected: &str) {
+        assert_eq!(add_block(contents, Path::new("C:\\asdfw\\completion.ps1")), expected);
+    }
+
+    #[test]
+    fn install_is_idempotent_and_uninstall_restores_profile() {
+        let tmp_dir = TempDir::new().unwrap();
+        let profile = tmp_dir.child("WindowsPowerShell").child("profile.ps1");
+        let script = tmp_dir.child("lib").child("completion.ps1");
+        assert!(install(profile.path(), script.path(), b"# completions").unwrap());
+        assert!(!install(profile.path(), script.path(), b"# completions").unwrap());
+        script.assert("# completions");
+        let contents = fs::read_to_string(profile.path()).unwrap();
+        assert_eq!(contents.matches(BLOCK_START).count(), 1);
+
+        fs::write(profile.path(), format!("Import-Module posh-git\n{}# end\n", contents)).unwrap();
+        assert!(uninstall(profile.path(), script.path()).unwrap());
+        profile.assert("Import-Module posh-git\n# end\n");
+        assert!(!script.path().exists());
+        assert!(!uninstall(profile.path(), script.path()).unwrap());
+    }
+}
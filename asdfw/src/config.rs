@@ -0,0 +1,395 @@
+use anyhow::{anyhow, Context, Result};
+use log::{debug, LevelFilter};
+use serde::Deserialize;
+use std::collections::{BTreeMap, HashMap};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::time::Duration;
+
+use crate::tool_versions::ParseMode;
+
+/// User configuration loaded from `config.toml` in the app directory. All
+/// fields are optional and a missing file means default configuration.
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+pub struct Config {
+    /// What to do when the same executable is provided by more than one tool.
+    pub conflict_policy: ConflictPolicy,
+    /// Tools ordered by priority, used by the `priority` conflict policy.
+    pub tool_priority: Vec<String>,
+    /// Machine level directory (e.g. `C:\ProgramData\asdfw`) containing tools
+    /// shared by all users. Could be overridden by `ASDFW_SYSTEM_DIR`.
+    pub system_dir: Option<PathBuf>,
+    /// Maintain an `installs/<tool>/current` junction pointing at the global
+    /// version of each tool.
+    pub current_links: bool,
+    /// Record every command executed through a shim (see `asdfw stats`).
+    pub record_stats: bool,
+    /// Record shims failing to resolve the executable to run, so commands
+    /// failing repeatedly are reported by `asdfw doctor` and `asdfw reshim`.
+    pub record_shim_failures: bool,
+    /// Append an audit record (JSON line) of every execution to this file.
+    pub audit_file: Option<PathBuf>,
+    /// JSON feed of security advisories checked by `asdfw audit`.
+    pub advisory_feed: Option<PathBuf>,
+    /// When a shim finds no configured version, run the executable found in
+    /// PATH (skipping the shims directory) instead of failing.
+    pub path_fallback: bool,
+    /// Additional shim directories (e.g. a project's `.asdfw\shims`)
+    /// consulted before the shims directory, in the listed order. Relative
+    /// directories are looked up in the current directory and its parents.
+    pub shim_dirs: Vec<PathBuf>,
+    /// How invalid lines in `.tool-versions` files are treated while
+    /// resolving versions: `strict` (fail, the default) or `lenient` (skip
+    /// them with a warning).
+    pub tool_versions_parsing: ParseMode,
+    /// Log level per binary (`asdfw`, `shim`), e.g. `shim = "debug"`.
+    pub log_levels: HashMap<String, String>,
+    /// Per tool configuration (`[tools.<name>]` tables).
+    pub tools: HashMap<String, ToolConfig>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+pub struct ToolConfig {
+    /// Custom directory containing the tool's versions (instead of
+    /// `installs/<tool>`).
+    pub install_dir: Option<PathBuf>,
+    /// Alternate names the tool answers to in `.tool-versions` files and on
+    /// the command line (e.g. `go` for `golang`).
+    pub aliases: Vec<String>,
+    /// Temporarily exclude the tool from reshim and version resolution
+    /// without removing its installations.
+    pub disabled: bool,
+    /// Extension-less scripts in the tool's `bin` directory mapped to the
+    /// interpreter command running them (e.g. `http = 'python'`). Each
+    /// script gets a `<script>.exe` shim.
+    pub scripts: HashMap<String, String>,
+    /// Arguments prepended to the user's arguments, per executable (e.g.
+    /// `npm = ['--no-update-notifier']`).
+    pub default_args: HashMap<String, Vec<String>>,
+    /// Directories (relative to the version directory) containing
+    /// executables in addition to `bin`, e.g. `Scripts` where pip installs
+    /// the executables of packages. They may be created or populated after
+    /// installation.
+    pub bin_dirs: Vec<PathBuf>,
+    /// Environment variables set for every executable of the tool, e.g. a
+    /// per version prefix for globally installed packages. `{install_dir}`,
+    /// `{version}` and `{tool}` in values are replaced with those of the
+    /// version running.
+    pub env: BTreeMap<String, String>,
+    /// Overrides the global `path_fallback` for the tool.
+    pub path_fallback: Option<bool>,
+    /// Log level of the tool's shims, overriding the one of the `shim`
+    /// binary.
+    pub log_level: Option<String>,
+    /// Seconds after which `asdfw exec` kills the tool's executables (and
+    /// the processes they started). Overridden by `--timeout`. Doesn't apply
+    /// to shims, which never time out.
+    pub timeout: Option<u64>,
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ConflictPolicy {
+    /// Fail reshim (the default).
+    #[default]
+    Error,
+    /// Use the tool that appears first in `tool_priority`.
+    Priority,
+    /// Do not create a shim for the conflicting executable.
+    Skip,
+}
+
+impl Config {
+    pub fn load(path: &Path) -> Result<Self> {
+        if !path.exists() {
+            debug!("Config file {:?} does not exist, using defaults", &path);
+            return Ok(Config::default());
+        }
+        let context = format!("loading configuration from {:?}", &path);
+        let contents = fs::read_to_string(path).context(context.clone())?;
+        let config: Config = toml::from_str(&contents).context(context.clone())?;
+        config.validate().context(context)?;
+        Ok(config)
+    }
+
+    /// Tool names and aliases are case-insensitive, so each could only name
+    /// a single tool. Timeouts must be positive.
+    fn validate(&self) -> Result<()> {
+        let mut tools: Vec<&String> = self.tools.keys().collect();
+        tools.sort();
+        let mut names: HashMap<String, &str> = HashMap::new();
+        for tool in &tools {
+            if let Some(other) = names.insert(tool.to_lowercase(), tool) {
+                return Err(anyhow!("tools {} and {} differ only by case", other, tool));
+            }
+        }
+        for tool in tools {
+            if self.tools[tool].timeout == Some(0) {
+                return Err(anyhow!("timeout of {} must be positive", tool));
+            }
+            for alias in self.aliases_for(tool) {
+                match names.insert(alias.to_lowercase(), tool) {
+                    Some(other) if other != tool.as_str() => {
+                        return Err(anyhow!("alias '{}' of {} already names {}", alias, tool, other));
+                    }
+                    _ => {}
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Returns the timeout of `asdfw exec` configured for the tool.
+    pub fn timeout_for(&self, tool: &str) -> Option<Duration> {
+        self.tools.get(tool).and_then(|t| t.timeout).map(Duration::from_secs)
+    }
+
+    /// Returns the aliases configured for the tool.
+    pub fn aliases_for(&self, tool: &str) -> &[String] {
+        self.tools.get(tool).map(|t| t.aliases.as_slice()).unwrap_or(&[])
+    }
+
+    pub fn is_disabled(&self, tool: &str) -> bool {
+        self.tools.get(tool).map_or(false, |t| t.disabled)
+    }
+
+    /// Whether shims of the tool fall back to PATH when no version is
+    /// configured.
+    pub fn path_fallback_for(&self, tool: &str) -> bool {
+        self.tools.get(tool).and_then(|t| t.path_fallback).unwrap_or(self.path_fallback)
+    }
+
+    /// Returns the log level configured for the binary, the level configured
+    /// for the tool (if provided) taking precedence. Fails if the level is
+    /// invalid (callers warn and use their default).
+    pub fn log_level_for(&self, binary: &str, tool: Option<&str>) -> Result<Option<LevelFilter>> {
+        let level = tool
+            .and_then(|tool| self.tools.get(tool))
+            .and_then(|t| t.log_level.as_ref())
+            .or_else(|| self.log_levels.get(binary));
+        level
+            .map(|level| {
+                LevelFilter::from_str(level).map_err(|_| {
+                    anyhow!("invalid log level '{}' (expected off, error, warn, info, debug or trace)", level)
+                })
+            })
+            .transpose()
+    }
+
+    /// Returns the script and interpreter behind the provided shim name (e.g.
+    /// `http.exe`) if it's configured as a script of the tool.
+    pub fn script_for(&self, tool: &str, exe_name: &str) -> Option<(&str, &str)> {
+        let script = exe_name.strip_suffix(".exe")?;
+        let (script, interpreter) = self.tools.get(tool)?.scripts.get_key_value(script)?;
+        Some((script.as_str(), interpreter.as_str()))
+    }
+
+    /// Returns the default arguments of the executable (configured either with
+    /// or without extension).
+    pub fn default_args_for(&self, tool: &str, exe_name: &str) -> &[String] {
+        let default_args = match self.tools.get(tool) {
+            Some(config) => &config.default_args,
+            None => return &[],
+        };
+        let stem = Path::new(exe_name).file_stem().and_then(|s| s.to_str()).unwrap_or(exe_name);
+        // Commands are case-insensitive, and a nested shim only knows the name
+        // it was invoked with (not the one in the shims db)
+        let find = |name: &str| {
+            default_args.get(name).or_else(|| {
+                default_args
+                    .iter()
+                    .find(|(key, _)| key.eq_ignore_ascii_case(name))
+                    .map(|(_, args)| args)
+            })
+        };
+        find(exe_name).or_else(|| find(stem)).map_or(&[], |args| args.as_slice())
+    }
+
+    /// Returns the tool the provided name is an alias of (or the configured
+    /// tool it differs from only by case), or the name itself otherwise.
+    /// Tool names take precedence over aliases (the first tool by name wins
+    /// if the configuration was not validated by `load`).
+    pub fn canonical_tool_name<'a>(&'a self, name: &'a str) -> &'a str {
+        let by_name = self.tools.keys().filter(|tool| tool.eq_ignore_ascii_case(name)).min();
+        let by_alias = || {
+            self.tools
+                .iter()
+                .filter(|(_, config)| config.aliases.iter().any(|alias| alias.eq_ignore_ascii_case(name)))
+                .map(|(tool, _)| tool)
+                .min()
+        };
+        by_name.or_else(by_alias).map_or(name, String::as_str)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use assert_fs::{prelude::*, TempDir};
+    use rstest::rstest;
+
+    #[test]
+    fn load_with_missing_file_returns_defaults() {
+        let tmp_dir = TempDir::new().unwrap();
+        let config = Config::load(tmp_dir.child("config.toml").path()).unwrap();
+        assert_eq!(config.conflict_policy, ConflictPolicy::Error);
+        assert!(config.tool_priority.is_empty());
+    }
+
+    #[test]
+    fn load_parses_conflict_policy() {
+        let tmp_dir = TempDir::new().unwrap();
+        let file = tmp_dir.child("config.toml");
+        file.write_str("conflict_policy = \"priority\"\ntool_priority = [\"kubectl\", \"gcloud\"]\n")
+            .unwrap();
+        let config = Config::load(file.path()).unwrap();
+        assert_eq!(config.conflict_policy, ConflictPolicy::Priority);
+        assert_eq!(config.tool_priority, vec!["kubectl", "gcloud"]);
+    }
+
+    #[test]
+    fn load_parses_tool_versions_parsing() {
+        let tmp_dir = TempDir::new().unwrap();
+        let file = tmp_dir.child("config.toml");
+        assert_eq!(Config::default().tool_versions_parsing, ParseMode::Strict);
+        file.write_str("tool_versions_parsing = \"lenient\"\n").unwrap();
+        let config = Config::load(file.path()).unwrap();
+        assert_eq!(config.tool_versions_parsing, ParseMode::Lenient);
+    }
+
+    #[test]
+    fn load_parses_tool_configuration() {
+        let tmp_dir = TempDir::new().unwrap();
+        let file = tmp_dir.child("config.toml");
+        file.write_str("[tools.android-sdk]\ninstall_dir = 'D:\\sdk'\n").unwrap();
+        let config = Config::load(file.path()).unwrap();
+        let tool = config.tools.get("android-sdk").unwrap();
+        assert_eq!(tool.install_dir, Some(PathBuf::from("D:\\sdk")));
+        assert!(!config.is_disabled("android-sdk"));
+    }
+
+    #[test]
+    fn timeout_for_tool() {
+        let tmp_dir = TempDir::new().unwrap();
+        let file = tmp_dir.child("config.toml");
+        file.write_str("[tools.terraform]\ntimeout = 600\n").unwrap();
+        let config = Config::load(file.path()).unwrap();
+        assert_eq!(config.timeout_for("terraform"), Some(Duration::from_secs(600)));
+        assert_eq!(config.timeout_for("kubectl"), None);
+    }
+
+    #[test]
+    fn load_parses_disabled_tools() {
+        let tmp_dir = TempDir::new().unwrap();
+        let file = tmp_dir.child("config.toml");
+        file.write_str("[tools.kubectl]\ndisabled = true\n").unwrap();
+        let config = Config::load(file.path()).unwrap();
+        assert!(config.is_disabled("kubectl"));
+        assert!(!config.is_disabled("gcloud"));
+    }
+
+    #[rstest]
+    #[case("kubectl", "http.exe", None)]
+    #[case("httpie", "http.exe", Some(("http", "python")))]
+    #[case("httpie", "http", None)]
+    #[case("httpie", "https.exe", None)]
+    fn script_for_maps_shims_to_interpreters(
+        #[case] tool: &str,
+        #[case] exe_name: &str,
+        #[case] expected: Option<(&str, &str)>,
+    ) {
+        let config: Config = toml::from_str("[tools.httpie.scripts]\nhttp = 'python'\n").unwrap();
+        assert_eq!(config.script_for(tool, exe_name), expected);
+    }
+
+    #[rstest]
+    #[case("nodejs", "npm.exe", &["--no-update-notifier"])]
+    #[case("nodejs", "NPM.exe", &["--no-update-notifier"])]
+    #[case("nodejs", "npx.exe", &["--yes"])]
+    #[case("nodejs", "node.exe", &[])]
+    #[case("kubectl", "npm.exe", &[])]
+    fn default_args_for_executables(#[case] tool: &str, #[case] exe_name: &str, #[case] expected: &[&str]) {
+        let config: Config =
+            toml::from_str("[tools.nodejs.default_args]\nnpm = ['--no-update-notifier']\n'npx.exe' = ['--yes']\n")
+                .unwrap();
+        assert_eq!(config.default_args_for(tool, exe_name), expected);
+    }
+
+    #[rstest]
+    #[case("go", "golang")]
+    #[case("Go", "golang")]
+    #[case("golang", "golang")]
+    #[case("GoLang", "golang")]
+    #[case("kubectl", "kubectl")]
+    fn canonical_tool_name_resolves_aliases(#[case] name: &str, #[case] expected: &str) {
+        let config: Config = toml::from_str("[tools.golang]\naliases = ['go']\n").unwrap();
+        assert_eq!(config.canonical_tool_name(name), expected);
+        assert_eq!(config.aliases_for("golang"), ["go"]);
+        assert!(config.aliases_for("kubectl").is_empty());
+    }
+
+    #[rstest]
+    #[case("path_fallback = true\n", "kubectl", true)]
+    #[case("path_fallback = true\n[tools.kubectl]\npath_fallback = false\n", "kubectl", false)]
+    #[case("[tools.kubectl]\npath_fallback = true\n", "kubectl", true)]
+    #[case("[tools.kubectl]\npath_fallback = true\n", "helm", false)]
+    fn path_fallback_per_tool_overrides_global(#[case] config: &str, #[case] tool: &str, #[case] expected: bool) {
+        let config: Config = toml::from_str(config).unwrap();
+        assert_eq!(config.path_fallback_for(tool), expected);
+    }
+
+    #[rstest]
+    #[case("shim", None, Some(LevelFilter::Debug))]
+    #[case("shim", Some("terraform"), Some(LevelFilter::Trace))]
+    #[case("shim", Some("helm"), Some(LevelFilter::Debug))]
+    #[case("asdfw", None, None)]
+    fn log_level_per_tool_overrides_binary(
+        #[case] binary: &str,
+        #[case] tool: Option<&str>,
+        #[case] expected: Option<LevelFilter>,
+    ) {
+        let config = "[log_levels]\nshim = 'debug'\n[tools.terraform]\nlog_level = 'TRACE'\n";
+        let config: Config = toml::from_str(config).unwrap();
+        assert_eq!(config.log_level_for(binary, tool).unwrap(), expected);
+    }
+
+    #[test]
+    fn log_level_for_with_invalid_level_fails() {
+        let config: Config = toml::from_str("[log_levels]\nasdfw = 'verbose'\n").unwrap();
+        let err = config.log_level_for("asdfw", None).unwrap_err();
+        assert!(format!("{}", err).contains("'verbose'"), "wrong error: {}", err);
+    }
+
+    #[rstest]
+    #[case(
+        "[tools.golang]\naliases = ['go']\n[tools.gopls]\naliases = ['Go']\n",
+        "alias 'Go' of gopls already names golang"
+    )]
+    #[case(
+        "[tools.golang]\n[tools.go]\naliases = ['golang']\n",
+        "alias 'golang' of go already names golang"
+    )]
+    #[case(
+        "[tools.kubectl]\n[tools.Kubectl]\n",
+        "tools Kubectl and kubectl differ only by case"
+    )]
+    #[case("[tools.terraform]\ntimeout = 0\n", "timeout of terraform must be positive")]
+    fn load_with_invalid_tools_fails(#[case] contents: &str, #[case] expected: &str) {
+        let tmp_dir = TempDir::new().unwrap();
+        let file = tmp_dir.child("config.toml");
+        file.write_str(contents).unwrap();
+        let err = Config::load(file.path()).unwrap_err();
+        assert_eq!(err.root_cause().to_string(), expected);
+    }
+
+    #[test]
+    fn load_with_invalid_value_fails() {
+        let tmp_dir = TempDir::new().unwrap();
+        let file = tmp_dir.child("config.toml");
+        file.write_str("conflict_policy = \"whatever\"\n").unwrap();
+        assert!(Config::load(file.path()).is_err(), "invalid policy should fail loading");
+    }
+}
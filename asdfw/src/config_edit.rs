@@ -0,0 +1,316 @@
+use anyhow::{anyhow, bail, Context, Result};
+use log::{debug, LevelFilter};
+use std::fs;
+use std::path::Path;
+use std::str::FromStr;
+use toml_edit::{Array, Document, Item, Key, Table, TableLike, Value};
+
+use crate::config::Config;
+use crate::fsutil::write_atomically;
+
+/// The type of the value of a configuration key. Determines how values
+/// passed to `asdfw config set` are parsed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ValueKind {
+    String,
+    Bool,
+    /// A positive integer.
+    PositiveInteger,
+    /// A log level (`off`, `error`, `warn`, `info`, `debug` or `trace`).
+    LogLevel,
+    /// A list of strings, either comma separated (`a, b`) or a TOML array
+    /// (`['a', 'b']`).
+    List,
+}
+
+/// Returns the kind of the value of a known key (split into its segments),
+/// or `None` if the key is unknown.
+fn value_kind(segments: &[&str]) -> Option<ValueKind> {
+    match segments {
+        ["conflict_policy" | "tool_versions_parsing" | "system_dir" | "audit_file" | "advisory_feed"] => {
+            Some(ValueKind::String)
+        }
+        ["current_links" | "record_stats" | "record_shim_failures" | "path_fallback"] => Some(ValueKind::Bool),
+        ["tool_priority" | "shim_dirs"] => Some(ValueKind::List),
+        ["log_levels", _] | ["tools", _, "log_level"] => Some(ValueKind::LogLevel),
+        ["tools", _, "install_dir"] => Some(ValueKind::String),
+        ["tools", _, "aliases" | "bin_dirs"] => Some(ValueKind::List),
+        ["tools", _, "disabled" | "path_fallback"] => Some(ValueKind::Bool),
+        ["tools", _, "timeout"] => Some(ValueKind::PositiveInteger),
+        ["tools", _, "scripts" | "env", _] => Some(ValueKind::String),
+        ["tools", _, "default_args", _] => Some(ValueKind::List),
+        _ => None,
+    }
+}
+
+/// Splits a dotted key (e.g. `tools.nodejs.default_args."npx.exe"`) into its
+/// segments and returns them along with the kind of its value.
+fn parse_key(key: &str) -> Result<(Vec<String>, ValueKind)> {
+    let segments: Vec<String> = Key::parse(key)
+        .map_err(|err| anyhow!("invalid key '{}': {}", key, err))?
+        .iter()
+        .map(|k| k.get().to_owned())
+        .collect();
+    let borrowed: Vec<&str> = segments.iter().map(String::as_str).collect();
+    match value_kind(&borrowed) {
+        Some(kind) => Ok((segments, kind)),
+        None => bail!("unknown configuration key '{}'", key),
+    }
+}
+
+fn parse_value(key: &str, kind: ValueKind, value: &str) -> Result<Value> {
+    match kind {
+        ValueKind::String => Ok(Value::from(value)),
+        ValueKind::Bool => match value.trim() {
+            "true" => Ok(Value::from(true)),
+            "false" => Ok(Value::from(false)),
+            _ => bail!("{} expects true or false, got '{}'", key, value),
+        },
+        ValueKind::PositiveInteger => match value.trim().parse::<u32>() {
+            Ok(number) if number > 0 => Ok(Value::from(i64::from(number))),
+            _ => bail!("{} expects a positive number, got '{}'", key, value),
+        },
+        ValueKind::LogLevel => match LevelFilter::from_str(value.trim()) {
+            Ok(_) => Ok(Value::from(value.trim())),
+            Err(_) => bail!("{} expects off, error, warn, info, debug or trace, got '{}'", key, value),
+        },
+        ValueKind::List if value.trim_start().starts_with('[') => {
+            let array = value.parse::<Value>().ok().and_then(|v| v.as_array().cloned());
+            match array {
+                Some(array) if array.iter().all(|item| item.is_str()) => Ok(Value::Array(array)),
+                _ => bail!("{} expects a list of strings, got '{}'", key, value),
+            }
+        }
+        ValueKind::List => {
+            let items: Array = value.split(',').map(str::trim).filter(|item| !item.is_empty()).collect();
+            Ok(Value::Array(items))
+        }
+    }
+}
+
+/// Returns the value of the key in the configuration file (strings are
+/// returned as is, other values in their TOML representation), or `None` if
+/// it's not set.
+pub fn get(path: &Path, key: &str) -> Result<Option<String>> {
+    let (segments, _) = parse_key(key)?;
+    let mut value = &load_values(path)?;
+    for segment in &segments {
+        value = match value.get(segment) {
+            Some(value) => value,
+            None => return Ok(None),
+        };
+    }
+    Ok(Some(format_value(value)))
+}
+
+/// Sets the key in the configuration file, creating the file if it doesn't
+/// exist. Comments and formatting of the rest of the file are preserved. The
+/// file is left untouched if the change makes a valid configuration invalid
+/// (a configuration that was already invalid could be fixed key by key, see
+/// `validate`).
+pub fn set(path: &Path, key: &str, value: &str) -> Result<()> {
+    let (segments, kind) = parse_key(key)?;
+    let value = parse_value(key, kind, value)?;
+    let mut doc = load_document(path)?;
+    let was_valid = toml::from_str::<Config>(&doc.to_string()).is_ok();
+    let (last, parents) = segments.split_last().expect("keys have at least one segment");
+    let mut table: &mut dyn TableLike = doc.as_table_mut();
+    for segment in parents {
+        table = table
+            .entry(segment)
+            .or_insert_with(|| {
+                let mut table = Table::new();
+                table.set_implicit(true);
+                Item::Table(table)
+            })
+            .as_table_like_mut()
+            .ok_or_else(|| anyhow!("cannot set {}: '{}' is not a table", key, segment))?;
+    }
+    match table.get_mut(last).and_then(Item::as_value_mut) {
+        // Replace the value only, keeping the key and its comments
+        Some(existing) => {
+            let decor = existing.decor().clone();
+            *existing = value;
+            *existing.decor_mut() = decor;
+        }
+        None => {
+            table.insert(last, Item::Value(value));
+        }
+    }
+
+    let contents = doc.to_string();
+    if was_valid {
+        toml::from_str::<Config>(&contents).context(format!("invalid value for {}", key))?;
+    }
+    debug!("Setting {} in {:?}", key, &path);
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent).context(format!("creating directory {:?}", &parent))?;
+    }
+    write_atomically(path, contents.trim_start().as_bytes()).context(format!("saving configuration to {:?}", &path))
+}
+
+/// Checks that the configuration file (if any) is valid.
+pub fn validate(path: &Path) -> Result<()> {
+    Config::load(path).map(|_| ())
+}
+
+/// Returns all the values set in the configuration file as dotted keys
+/// (sorted) and values formatted like `get`.
+pub fn list(path: &Path) -> Result<Vec<(String, String)>> {
+    let mut values = vec![];
+    if let toml::Value::Table(table) = load_values(path)? {
+        flatten("", &table, &mut values);
+    }
+    values.sort();
+    Ok(values)
+}
+
+fn flatten(prefix: &str, table: &toml::value::Table, values: &mut Vec<(String, String)>) {
+    for (name, value) in table {
+        let key = format!("{}{}", prefix, format_key_segment(name));
+        match value {
+            toml::Value::Table(table) => flatten(&format!("{}.", key), table, values),
+            value => values.push((key, format_value(value))),
+        }
+    }
+}
+
+fn format_key_segment(segment: &str) -> String {
+    let bare = !segment.is_empty() && segment.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_');
+    if bare {
+        segment.to_owned()
+    } else {
+        format!("\"{}\"", segment.replace('\\', "\\\\").replace('"', "\\\""))
+    }
+}
+
+fn format_value(value: &toml::Value) -> String {
+    match value {
+        toml::Value::String(s) => s.clone(),
+        value => value.to_string(),
+    }
+}
+
+fn read_config(path: &Path) -> Result<String> {
+    if !path.exists() {
+        return Ok(String::new());
+    }
+    fs::read_to_string(path).context(format!("loading configuration from {:?}", &path))
+}
+
+fn load_values(path: &Path) -> Result<toml::Value> {
+    toml::from_str(&read_config(path)?).context(format!("loading configuration from {:?}", &path))
+}
+
+fn load_document(path: &Path) -> Result<Document> {
+    read_config(path)?
+        .parse::<Document>()
+        .context(format!("loading configuration from {:?}", &path))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use assert_fs::{prelude::*, TempDir};
+    use rstest::rstest;
+
+    #[rstest]
+    #[case("record_stats", "true", "record_stats = true")]
+    #[case("tool_priority", "kubectl, gcloud", "tool_priority = [\"kubectl\", \"gcloud\"]")]
+    #[case("tool_priority", "['kubectl']", "tool_priority = ['kubectl']")]
+    #[case("tools.golang.aliases", "go", "[tools.golang]\naliases = [\"go\"]")]
+    #[case("tools.terraform.timeout", "600", "[tools.terraform]\ntimeout = 600")]
+    #[case("log_levels.shim", "debug", "[log_levels]\nshim = \"debug\"")]
+    #[case(
+        "tools.nodejs.default_args.\"npx.exe\"",
+        "--yes",
+        "[tools.nodejs.default_args]\n\"npx.exe\" = [\"--yes\"]"
+    )]
+    fn set_writes_typed_values(#[case] key: &str, #[case] value: &str, #[case] expected: &str) {
+        let tmp_dir = TempDir::new().unwrap();
+        let file = tmp_dir.child("config.toml");
+        set(file.path(), key, value).unwrap();
+        let contents = fs::read_to_string(file.path()).unwrap();
+        assert!(contents.contains(expected), "unexpected contents: {}", contents);
+        assert!(!contents.contains("[tools]\n"), "parent table written: {}", contents);
+        Config::load(file.path()).unwrap();
+    }
+
+    #[rstest]
+    #[case("no_such_key", "true", "unknown configuration key")]
+    #[case("tools.kubectl.color", "true", "unknown configuration key")]
+    #[case("current_links", "yes", "expects true or false")]
+    #[case("tool_priority", "[1, 2]", "expects a list of strings")]
+    #[case("conflict_policy", "whatever", "invalid value for conflict_policy")]
+    #[case("log_levels.asdfw", "verbose", "expects off, error, warn, info, debug or trace")]
+    #[case("tools.terraform.timeout", "0", "expects a positive number")]
+    #[case("tools.terraform.timeout", "-5", "expects a positive number")]
+    #[case(
+        "tools.terraform.log_level",
+        "loud",
+        "expects off, error, warn, info, debug or trace"
+    )]
+    fn set_rejects_invalid_keys_and_values(#[case] key: &str, #[case] value: &str, #[case] expected: &str) {
+        let tmp_dir = TempDir::new().unwrap();
+        let file = tmp_dir.child("config.toml");
+        file.write_str("# my settings\nrecord_stats = true\n").unwrap();
+        let err = set(file.path(), key, value).unwrap_err();
+        assert!(format!("{:#}", err).contains(expected), "wrong error: {:#}", err);
+        file.assert("# my settings\nrecord_stats = true\n");
+    }
+
+    #[test]
+    fn set_fixes_invalid_configuration() {
+        let tmp_dir = TempDir::new().unwrap();
+        let file = tmp_dir.child("config.toml");
+        file.write_str("conflict_policy = 'whatever'\nrecord_stats = 'yes'\n").unwrap();
+        assert_eq!(get(file.path(), "conflict_policy").unwrap(), Some("whatever".to_owned()));
+        assert_eq!(list(file.path()).unwrap().len(), 2);
+        set(file.path(), "conflict_policy", "skip").unwrap();
+        assert!(validate(file.path()).is_err(), "record_stats is still invalid");
+        set(file.path(), "record_stats", "true").unwrap();
+        validate(file.path()).unwrap();
+        assert!(
+            set(file.path(), "conflict_policy", "whatever").is_err(),
+            "made a valid configuration invalid"
+        );
+    }
+
+    #[test]
+    fn set_preserves_comments() {
+        let tmp_dir = TempDir::new().unwrap();
+        let file = tmp_dir.child("config.toml");
+        file.write_str(
+            "# shared by the team\nconflict_policy = 'skip' # for now\n\n[tools.kubectl]\ndisabled = true\n",
+        )
+        .unwrap();
+        set(file.path(), "conflict_policy", "priority").unwrap();
+        set(file.path(), "tools.kubectl.disabled", "false").unwrap();
+        let contents = fs::read_to_string(file.path()).unwrap();
+        assert!(contents.starts_with("# shared by the team\n"), "comment lost: {}", contents);
+        let config = Config::load(file.path()).unwrap();
+        assert_eq!(config.conflict_policy, crate::config::ConflictPolicy::Priority);
+        assert!(!config.is_disabled("kubectl"));
+    }
+
+    #[test]
+    fn get_and_list_values() {
+        let tmp_dir = TempDir::new().unwrap();
+        let file = tmp_dir.child("config.toml");
+        file.write_str("system_dir = 'C:\\ProgramData\\asdfw'\n[tools.nodejs.default_args]\n'npx.exe' = ['--yes']\n")
+            .unwrap();
+        assert_eq!(get(file.path(), "system_dir").unwrap(), Some("C:\\ProgramData\\asdfw".to_owned()));
+        assert_eq!(
+            get(file.path(), "tools.nodejs.default_args.'npx.exe'").unwrap(),
+            Some("[\"--yes\"]".to_owned())
+        );
+        assert_eq!(get(file.path(), "record_stats").unwrap(), None);
+        assert!(get(file.path(), "unknown").is_err());
+        let expected = vec![
+            ("system_dir".to_owned(), "C:\\ProgramData\\asdfw".to_owned()),
+            ("tools.nodejs.default_args.\"npx.exe\"".to_owned(), "[\"--yes\"]".to_owned()),
+        ];
+        assert_eq!(list(file.path()).unwrap(), expected);
+        assert!(list(tmp_dir.child("missing.toml").path()).unwrap().is_empty());
+    }
+}
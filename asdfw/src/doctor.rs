@@ -0,0 +1,432 @@
+use anyhow::Result;
+use log::debug;
+use serde::Serialize;
+use std::ffi::OsStr;
+use std::fmt::Display;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::common::shims_for_env;
+use crate::output::message;
+use crate::runtime::RuntimeEnvironment;
+use crate::shim_health::{self, REPEATED_FAILURES};
+
+/// The checks `diagnose` runs. Their ids are part of the JSON report and
+/// must never change once released.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Check {
+    /// The shims db could be loaded.
+    ShimsDb,
+    /// Every shim is backed by an installed version.
+    OrphanedShims,
+    /// No shim is shadowed by an executable found earlier in `PATH`.
+    ShadowedShims,
+    /// No shim is overridden by an `App Paths` registry entry.
+    AppPaths,
+    /// No shim failed to resolve repeatedly (see `record_shim_failures`).
+    FailingShims,
+}
+
+pub const ALL_CHECKS: [Check; 5] = [
+    Check::ShimsDb,
+    Check::OrphanedShims,
+    Check::ShadowedShims,
+    Check::AppPaths,
+    Check::FailingShims,
+];
+
+impl Check {
+    pub fn id(&self) -> &'static str {
+        match self {
+            Check::ShimsDb => "shims_db",
+            Check::OrphanedShims => "orphaned_shims",
+            Check::ShadowedShims => "shadowed_shims",
+            Check::AppPaths => "app_paths",
+            Check::FailingShims => "failing_shims",
+        }
+    }
+}
+
+/// A problem detected in the installation.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Problem {
+    /// The shims db could not be loaded.
+    InvalidShimsDb(String),
+    /// A shim which is no longer backed by any installed version.
+    OrphanedShim(String),
+    /// A shim shadowed by an executable found earlier in `PATH`.
+    ShadowedShim { exe: String, path: PathBuf },
+    /// A shim overridden by an `App Paths` registry entry (used by the Run
+    /// dialog and `Start-Process`).
+    AppPathsEntry { exe: String, path: PathBuf },
+    /// A shim that failed to resolve repeatedly since it last succeeded.
+    FailingShim { exe: String, count: usize, reason: String },
+}
+
+impl Problem {
+    /// Whether `fix` knows how to fix the problem.
+    pub fn fixable(&self) -> bool {
+        match self {
+            Problem::InvalidShimsDb(_) => false,
+            Problem::OrphanedShim(_) => true,
+            Problem::ShadowedShim { .. } | Problem::AppPathsEntry { .. } => false,
+            Problem::FailingShim { .. } => false,
+        }
+    }
+
+    /// The check that detected the problem.
+    pub fn check(&self) -> Check {
+        match self {
+            Problem::InvalidShimsDb(_) => Check::ShimsDb,
+            Problem::OrphanedShim(_) => Check::OrphanedShims,
+            Problem::ShadowedShim { .. } => Check::ShadowedShims,
+            Problem::AppPathsEntry { .. } => Check::AppPaths,
+            Problem::FailingShim { .. } => Check::FailingShims,
+        }
+    }
+
+    /// Stable identifier of the way to fix the problem (see `hint`), so
+    /// reports from many machines could be aggregated.
+    pub fn remediation(&self) -> &'static str {
+        match self {
+            Problem::InvalidShimsDb(_) => "reshim",
+            Problem::OrphanedShim(_) => "doctor_fix",
+            Problem::ShadowedShim { .. } => "reorder_path",
+            Problem::AppPathsEntry { .. } => "remove_app_paths_entry",
+            Problem::FailingShim { .. } => "fix_resolution",
+        }
+    }
+
+    /// How to fix the problem manually (the `hint.doctor.<remediation>`
+    /// message).
+    pub fn hint(&self) -> String {
+        message(&format!("hint.doctor.{}", self.remediation()), &[])
+    }
+}
+
+impl Display for Problem {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Problem::InvalidShimsDb(err) => write!(f, "The shims db could not be loaded: {}", err),
+            Problem::OrphanedShim(exe) => write!(f, "The shim {} is not provided by any installed tool", exe),
+            Problem::ShadowedShim { exe, path } => write!(f, "The shim {} is shadowed by {:?}", exe, path),
+            Problem::AppPathsEntry { exe, path } => {
+                write!(f, "The shim {} is overridden by an App Paths entry pointing to {:?}", exe, path)
+            }
+            Problem::FailingShim { exe, count, reason } => {
+                write!(f, "The shim {} failed {} times, most recently with: {}", exe, count, reason)
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Status {
+    Passed,
+    Failed,
+    /// Not run since the shims db could not be loaded.
+    Skipped,
+}
+
+#[derive(Debug, PartialEq, Eq, Serialize)]
+pub struct CheckResult {
+    pub check: &'static str,
+    pub status: Status,
+}
+
+#[derive(Debug, PartialEq, Eq, Serialize)]
+pub struct ProblemRecord {
+    pub check: &'static str,
+    pub message: String,
+    /// The shim the problem is about (if any).
+    pub shim: Option<String>,
+    /// The offending executable (if any).
+    pub path: Option<PathBuf>,
+    pub remediation: &'static str,
+    pub hint: String,
+    pub fixable: bool,
+    pub fixed: bool,
+}
+
+/// The machine readable result of `doctor` (`--json`).
+#[derive(Debug, PartialEq, Eq, Serialize)]
+pub struct Report {
+    pub checks: Vec<CheckResult>,
+    pub problems: Vec<ProblemRecord>,
+}
+
+/// Builds the report of the problems found by `diagnose` (and the ones of
+/// them that were fixed).
+pub fn report(problems: &[Problem], fixed: &[Problem]) -> Report {
+    let db_failed = problems.iter().any(|p| p.check() == Check::ShimsDb);
+    let checks = ALL_CHECKS
+        .iter()
+        .map(|check| {
+            let status = match check {
+                _ if problems.iter().any(|p| p.check() == *check) => Status::Failed,
+                Check::ShimsDb | Check::FailingShims => Status::Passed,
+                _ if db_failed => Status::Skipped,
+                _ => Status::Passed,
+            };
+            CheckResult {
+                check: check.id(),
+                status,
+            }
+        })
+        .collect();
+    let problems = problems
+        .iter()
+        .map(|problem| {
+            let (shim, path) = match problem {
+                Problem::InvalidShimsDb(_) => (None, None),
+                Problem::OrphanedShim(exe) | Problem::FailingShim { exe, .. } => (Some(exe.clone()), None),
+                Problem::ShadowedShim { exe, path } | Problem::AppPathsEntry { exe, path } => {
+                    (Some(exe.clone()), Some(path.clone()))
+                }
+            };
+            ProblemRecord {
+                check: problem.check().id(),
+                message: problem.to_string(),
+                shim,
+                path,
+                remediation: problem.remediation(),
+                hint: problem.hint(),
+                fixable: problem.fixable(),
+                fixed: fixed.contains(problem),
+            }
+        })
+        .collect();
+    Report { checks, problems }
+}
+
+/// Checks the installation for problems.
+pub fn diagnose(env: &RuntimeEnvironment) -> Result<Vec<Problem>> {
+    let shims = shims_for_env(env)?;
+    let mut problems = vec![];
+    match shims.find_orphaned_shims(&env.config) {
+        Ok(orphans) => problems.extend(orphans.into_iter().map(Problem::OrphanedShim)),
+        Err(err) => problems.push(Problem::InvalidShimsDb(format!("{:#}", err))),
+    }
+    if let Ok(names) = shims.shim_names() {
+        let path = std::env::var_os("PATH").unwrap_or_default();
+        let extensions = path_extensions(std::env::var("PATHEXT").ok().as_deref());
+        for exe in names {
+            if let Some(path) = shadowing_executable(&exe, &path, &env.shims_dir, &extensions) {
+                problems.push(Problem::ShadowedShim { exe: exe.clone(), path });
+            }
+            if let Some(path) = imp::app_path(&exe) {
+                problems.push(Problem::AppPathsEntry { exe, path });
+            }
+        }
+    }
+    let failures = shim_health::load(&env.shim_failures_dir)?;
+    for failing in shim_health::failing_commands(&failures, REPEATED_FAILURES) {
+        problems.push(Problem::FailingShim {
+            exe: failing.command,
+            count: failing.count,
+            reason: failing.last.reason,
+        });
+    }
+    debug!("Found {} problems", problems.len());
+    Ok(problems)
+}
+
+/// Fixes the fixable problems. Returns the problems that were fixed.
+pub fn fix(env: &RuntimeEnvironment, problems: &[Problem]) -> Result<Vec<Problem>> {
+    let shims = shims_for_env(env)?;
+    let orphans: Vec<String> = problems
+        .iter()
+        .filter_map(|p| match p {
+            Problem::OrphanedShim(exe) => Some(exe.clone()),
+            _ => None,
+        })
+        .collect();
+    if !orphans.is_empty() {
+        shims.remove_shims(&orphans)?;
+    }
+    Ok(problems.iter().filter(|p| p.fixable()).cloned().collect())
+}
+
+/// The extensions (from `PATHEXT`) a command is looked up with.
+fn path_extensions(pathext: Option<&str>) -> Vec<String> {
+    pathext
+        .unwrap_or(".COM;.EXE;.BAT;.CMD")
+        .split(';')
+        .filter(|ext| !ext.is_empty())
+        .map(str::to_owned)
+        .collect()
+}
+
+/// Looks the command of the shim up the way `where.exe` does: each directory
+/// of `path` in order, trying each of the extensions in every directory.
+/// Returns the executable found if it comes before the shims directory,
+/// `None` if the shim is found first (or the shims directory isn't in `path`).
+pub fn shadowing_executable(exe: &str, path: &OsStr, shims_dir: &Path, extensions: &[String]) -> Option<PathBuf> {
+    let shims_dir = fs::canonicalize(shims_dir).unwrap_or_else(|_| shims_dir.to_path_buf());
+    let command = match exe.len().checked_sub(4).filter(|&i| exe[i..].eq_ignore_ascii_case(".exe")) {
+        Some(i) => &exe[..i],
+        None => exe,
+    };
+    for dir in std::env::split_paths(path) {
+        if fs::canonicalize(&dir).unwrap_or_else(|_| dir.clone()) == shims_dir {
+            return None;
+        }
+        let found = extensions
+            .iter()
+            .map(|ext| dir.join(format!("{}{}", command, ext)))
+            .find(|candidate| candidate.is_file());
+        if found.is_some() {
+            debug!("{} is shadowed by {:?}", exe, &found);
+            return found;
+        }
+    }
+    None
+}
+
+#[cfg(windows)]
+mod imp {
+    use std::ffi::{OsStr, OsString};
+    use std::os::windows::ffi::{OsStrExt, OsStringExt};
+    use std::path::PathBuf;
+    use std::ptr;
+    use winapi::shared::minwindef::{DWORD, HKEY};
+    use winapi::shared::winerror::ERROR_SUCCESS;
+    use winapi::um::winreg::{RegGetValueW, HKEY_CURRENT_USER, HKEY_LOCAL_MACHINE, RRF_RT_REG_SZ};
+
+    const APP_PATHS: &str = r"Software\Microsoft\Windows\CurrentVersion\App Paths";
+
+    fn wide(s: &str) -> Vec<u16> {
+        OsStr::new(s).encode_wide().chain(Some(0)).collect()
+    }
+
+    /// Reads the default value of the key (expanding environment variables).
+    fn default_value(root: HKEY, key: &str) -> Option<String> {
+        let key = wide(key);
+        let mut size: DWORD = 0;
+        let status = unsafe {
+            RegGetValueW(
+                root,
+                key.as_ptr(),
+                ptr::null(),
+                RRF_RT_REG_SZ,
+                ptr::null_mut(),
+                ptr::null_mut(),
+                &mut size,
+            )
+        };
+        if status as DWORD != ERROR_SUCCESS || size == 0 {
+            return None;
+        }
+        let mut buffer: Vec<u16> = vec![0; size as usize / 2];
+        let status = unsafe {
+            RegGetValueW(
+                root,
+                key.as_ptr(),
+                ptr::null(),
+                RRF_RT_REG_SZ,
+                ptr::null_mut(),
+                buffer.as_mut_ptr().cast(),
+                &mut size,
+            )
+        };
+        if status as DWORD != ERROR_SUCCESS {
+            return None;
+        }
+        let len = buffer.iter().position(|c| *c == 0).unwrap_or(buffer.len());
+        Some(OsString::from_wide(&buffer[..len]).to_string_lossy().into_owned())
+    }
+
+    /// The executable the `App Paths` entry of the command (the user's or the
+    /// machine's) points to.
+    pub(super) fn app_path(exe: &str) -> Option<PathBuf> {
+        let key = format!("{}\\{}", APP_PATHS, exe);
+        default_value(HKEY_CURRENT_USER, &key)
+            .or_else(|| default_value(HKEY_LOCAL_MACHINE, &key))
+            .map(|path| PathBuf::from(path.trim_matches('"')))
+    }
+}
+
+#[cfg(not(windows))]
+mod imp {
+    use std::path::PathBuf;
+
+    pub(super) fn app_path(_exe: &str) -> Option<PathBuf> {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use assert_fs::{prelude::*, TempDir};
+
+    #[test]
+    fn shadowing_executable_finds_commands_before_the_shims_dir() {
+        let tmp_dir = TempDir::new().unwrap();
+        let shims = tmp_dir.child("shims");
+        shims.child("kubectl.exe").touch().unwrap();
+        shims.child("helm.exe").touch().unwrap();
+        let system = tmp_dir.child("system");
+        system.child("kubectl.cmd").touch().unwrap();
+        let late = tmp_dir.child("late");
+        late.child("helm.exe").touch().unwrap();
+        let path = std::env::join_paths([system.path(), shims.path(), late.path()]).unwrap();
+        let extensions = vec![".exe".to_owned(), ".cmd".to_owned()];
+        assert_eq!(
+            shadowing_executable("kubectl.exe", &path, shims.path(), &extensions),
+            Some(system.child("kubectl.cmd").to_path_buf())
+        );
+        assert_eq!(shadowing_executable("helm.exe", &path, shims.path(), &extensions), None);
+        let without_shims = std::env::join_paths([system.path()]).unwrap();
+        assert_eq!(shadowing_executable("helm.exe", &without_shims, shims.path(), &extensions), None);
+    }
+
+    #[test]
+    fn report_lists_checks_and_problems() {
+        let shadowed = Problem::ShadowedShim {
+            exe: "kubectl.exe".to_owned(),
+            path: PathBuf::from("system").join("kubectl.exe"),
+        };
+        let orphaned = Problem::OrphanedShim("helm.exe".to_owned());
+        let report = report(&[shadowed, orphaned.clone()], &[orphaned]);
+        let statuses: Vec<(&str, Status)> = report.checks.iter().map(|c| (c.check, c.status)).collect();
+        let expected = [
+            ("shims_db", Status::Passed),
+            ("orphaned_shims", Status::Failed),
+            ("shadowed_shims", Status::Failed),
+            ("app_paths", Status::Passed),
+            ("failing_shims", Status::Passed),
+        ];
+        assert_eq!(statuses, expected);
+        assert_eq!(report.problems[0].remediation, "reorder_path");
+        assert_eq!(report.problems[0].path, Some(PathBuf::from("system").join("kubectl.exe")));
+        assert!(!report.problems[0].fixed);
+        assert_eq!(report.problems[1].shim.as_deref(), Some("helm.exe"));
+        assert!(report.problems[1].fixed);
+    }
+
+    #[test]
+    fn report_skips_checks_without_shims_db() {
+        let report = report(&[Problem::InvalidShimsDb("corrupt".to_owned())], &[]);
+        let statuses: Vec<Status> = report.checks.iter().map(|c| c.status).collect();
+        assert_eq!(
+            statuses,
+            [
+                Status::Failed,
+                Status::Skipped,
+                Status::Skipped,
+                Status::Skipped,
+                Status::Passed
+            ]
+        );
+        let json = serde_json::to_string(&report.problems[0]).unwrap();
+        assert!(json.contains(r#""remediation":"reshim""#), "wrong json: {}", json);
+    }
+
+    #[test]
+    fn path_extensions_defaults_without_pathext() {
+        assert_eq!(path_extensions(None), vec![".COM", ".EXE", ".BAT", ".CMD"]);
+        assert_eq!(path_extensions(Some(".EXE;;.PS1")), vec![".EXE", ".PS1"]);
+    }
+}
@@ -0,0 +1,209 @@
+use crate::output::message;
+use std::fmt::Display;
+
+/// The exit code of failures that are not classified.
+pub const EXIT_GENERAL: i32 = 1;
+
+/// Classes of failures wrapper scripts may want to handle. Each has a stable
+/// exit code and identifier (included in the error message) which must never
+/// change once released.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ErrorKind {
+    /// No tool provides the command.
+    NoTool,
+    /// The tool is disabled in the configuration.
+    ToolDisabled,
+    /// No version of the tool is configured.
+    NoVersion,
+    /// The configured version is not installed.
+    NotInstalled,
+    /// The configured version does not provide the command.
+    NotProvided,
+    /// A `.tool-versions` file could not be parsed.
+    InvalidToolVersions,
+    /// Shims invoked each other in an endless loop.
+    ShimLoop,
+    /// The version of the tool is forbidden by the administrator's policy.
+    PolicyViolation,
+    /// Versions in use are affected by known advisories (`asdfw audit`).
+    VulnerableVersions,
+    /// The directory is not ready to use (`asdfw check`).
+    CheckFailed,
+    /// The operation was cancelled with Ctrl-C.
+    Interrupted,
+    /// The command did not finish within its timeout and was killed.
+    TimedOut,
+}
+
+pub const ALL_ERROR_KINDS: [ErrorKind; 12] = [
+    ErrorKind::NoTool,
+    ErrorKind::ToolDisabled,
+    ErrorKind::NoVersion,
+    ErrorKind::NotInstalled,
+    ErrorKind::NotProvided,
+    ErrorKind::InvalidToolVersions,
+    ErrorKind::ShimLoop,
+    ErrorKind::PolicyViolation,
+    ErrorKind::VulnerableVersions,
+    ErrorKind::CheckFailed,
+    ErrorKind::Interrupted,
+    ErrorKind::TimedOut,
+];
+
+impl ErrorKind {
+    pub fn code(&self) -> i32 {
+        match self {
+            ErrorKind::NoTool => 10,
+            ErrorKind::ToolDisabled => 11,
+            ErrorKind::NoVersion => 12,
+            ErrorKind::NotInstalled => 13,
+            ErrorKind::NotProvided => 14,
+            ErrorKind::InvalidToolVersions => 15,
+            ErrorKind::ShimLoop => 16,
+            ErrorKind::PolicyViolation => 17,
+            ErrorKind::VulnerableVersions => 18,
+            ErrorKind::CheckFailed => 19,
+            ErrorKind::Interrupted => 20,
+            ErrorKind::TimedOut => 21,
+        }
+    }
+
+    pub fn id(&self) -> &'static str {
+        match self {
+            ErrorKind::NoTool => "E_NO_TOOL",
+            ErrorKind::ToolDisabled => "E_TOOL_DISABLED",
+            ErrorKind::NoVersion => "E_NO_VERSION",
+            ErrorKind::NotInstalled => "E_NOT_INSTALLED",
+            ErrorKind::NotProvided => "E_NOT_PROVIDED",
+            ErrorKind::InvalidToolVersions => "E_INVALID_TOOL_VERSIONS",
+            ErrorKind::ShimLoop => "E_SHIM_LOOP",
+            ErrorKind::PolicyViolation => "E_POLICY_VIOLATION",
+            ErrorKind::VulnerableVersions => "E_VULNERABLE_VERSIONS",
+            ErrorKind::CheckFailed => "E_CHECK_FAILED",
+            ErrorKind::Interrupted => "E_INTERRUPTED",
+            ErrorKind::TimedOut => "E_TIMED_OUT",
+        }
+    }
+
+    /// The next step suggested for errors of this kind which don't provide a
+    /// more specific hint.
+    pub fn default_hint(&self) -> Option<String> {
+        let id = match self {
+            ErrorKind::NoTool => "hint.no_tool",
+            ErrorKind::ToolDisabled => return None,
+            ErrorKind::NoVersion => "hint.no_version",
+            ErrorKind::NotInstalled => "hint.not_installed",
+            ErrorKind::NotProvided => return None,
+            ErrorKind::InvalidToolVersions => "hint.invalid_tool_versions",
+            ErrorKind::ShimLoop => "hint.shim_loop",
+            ErrorKind::PolicyViolation => "hint.policy_violation",
+            ErrorKind::VulnerableVersions => "hint.vulnerable_versions",
+            ErrorKind::CheckFailed => "hint.check_failed",
+            ErrorKind::Interrupted => return None,
+            ErrorKind::TimedOut => "hint.timed_out",
+        };
+        Some(message(id, &[]))
+    }
+
+    /// Creates an error of this kind with the provided message.
+    pub fn error<S: Into<String>>(self, message: S) -> anyhow::Error {
+        ClassifiedError {
+            kind: self,
+            message: message.into(),
+            hint: None,
+        }
+        .into()
+    }
+
+    /// Creates an error of this kind with the provided message and the next
+    /// step suggested to the user.
+    pub fn error_with_hint<S: Into<String>, H: Into<String>>(self, message: S, hint: H) -> anyhow::Error {
+        ClassifiedError {
+            kind: self,
+            message: message.into(),
+            hint: Some(hint.into()),
+        }
+        .into()
+    }
+}
+
+/// An error with a known `ErrorKind`. Displayed as `<id>: <message>`.
+#[derive(Debug)]
+pub struct ClassifiedError {
+    pub kind: ErrorKind,
+    pub message: String,
+    /// Suggested next step (displayed separately from the message).
+    pub hint: Option<String>,
+}
+
+impl Display for ClassifiedError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}: {}", self.kind.id(), &self.message)
+    }
+}
+
+impl std::error::Error for ClassifiedError {}
+
+/// Returns the kind of the error (or of any of its causes) if classified.
+pub fn error_kind(err: &anyhow::Error) -> Option<ErrorKind> {
+    err.chain()
+        .find_map(|cause| cause.downcast_ref::<ClassifiedError>())
+        .map(|classified| classified.kind)
+}
+
+/// Returns the next step suggested for the error (or for any of its causes)
+/// if classified: its own hint or the default hint of its kind.
+pub fn error_hint(err: &anyhow::Error) -> Option<String> {
+    let classified = err.chain().find_map(|cause| cause.downcast_ref::<ClassifiedError>())?;
+    match &classified.hint {
+        Some(hint) => Some(hint.clone()),
+        None => classified.kind.default_hint(),
+    }
+}
+
+/// Returns the exit code the process should terminate with on the error.
+pub fn exit_code(err: &anyhow::Error) -> i32 {
+    error_kind(err).map_or(EXIT_GENERAL, |kind| kind.code())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow::{anyhow, Context, Result};
+    use std::collections::HashSet;
+
+    #[test]
+    fn codes_and_ids_are_unique() {
+        let codes: HashSet<i32> = ALL_ERROR_KINDS.iter().map(|k| k.code()).collect();
+        let ids: HashSet<&str> = ALL_ERROR_KINDS.iter().map(|k| k.id()).collect();
+        assert_eq!(codes.len(), ALL_ERROR_KINDS.len());
+        assert_eq!(ids.len(), ALL_ERROR_KINDS.len());
+        assert!(!codes.contains(&EXIT_GENERAL));
+    }
+
+    #[test]
+    fn classified_error_is_found_behind_context() {
+        let result: Result<()> = Err(ErrorKind::NoVersion.error("No version configured for kubectl"));
+        let err = result.context("running kubectl").unwrap_err();
+        assert_eq!(error_kind(&err), Some(ErrorKind::NoVersion));
+        assert_eq!(exit_code(&err), 12);
+        let message = format!("{:#}", err);
+        assert!(message.contains("E_NO_VERSION: No version"), "wrong message: {}", message);
+    }
+
+    #[test]
+    fn unclassified_error_exits_with_general_code() {
+        assert_eq!(exit_code(&anyhow!("something failed")), EXIT_GENERAL);
+    }
+
+    #[test]
+    fn error_hint_prefers_specific_hint() {
+        let err = ErrorKind::NoVersion
+            .error_with_hint("No version configured for kubectl", "Run `asdfw local kubectl <version>`");
+        let err = err.context("running kubectl");
+        assert_eq!(error_hint(&err).as_deref(), Some("Run `asdfw local kubectl <version>`"));
+        let err = ErrorKind::NoTool.error("No tool configured for the command: helm.exe");
+        assert_eq!(error_hint(&err), ErrorKind::NoTool.default_hint());
+        assert_eq!(error_hint(&anyhow!("something failed")), None);
+    }
+}
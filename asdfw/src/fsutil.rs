@@ -0,0 +1,117 @@
+use anyhow::{Context, Result};
+use log::debug;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::thread;
+use std::time::Duration;
+
+/// How many times replacing the file is attempted (it might be briefly held
+/// by a reader without delete sharing, e.g. an antivirus scanner).
+const REPLACE_ATTEMPTS: u32 = 5;
+
+/// Writes the contents into a temporary file next to the path and moves it
+/// over the path. Concurrent readers (e.g. shims started in parallel by a
+/// build system) see either the previous or the new contents, never a
+/// partially written file. A symlink is followed, so the file it points to is
+/// replaced rather than the link.
+pub fn write_atomically(path: &Path, contents: &[u8]) -> Result<()> {
+    let link = fs::symlink_metadata(path).map_or(false, |meta| meta.file_type().is_symlink());
+    let target = match link {
+        true => fs::canonicalize(path).context(format!("resolving link {:?}", &path))?,
+        false => path.to_path_buf(),
+    };
+    let path = target.as_path();
+    let temp = temp_path(path);
+    fs::write(&temp, contents).context(format!("writing {:?}", &temp))?;
+    let mut attempt = 1;
+    loop {
+        match fs::rename(&temp, path) {
+            Ok(()) => return Ok(()),
+            Err(err) if attempt < REPLACE_ATTEMPTS => {
+                debug!("Replacing {:?} failed (attempt {}): {}", &path, attempt, err);
+                thread::sleep(Duration::from_millis(10 * u64::from(attempt)));
+                attempt += 1;
+            }
+            Err(err) => {
+                let _ = fs::remove_file(&temp);
+                return Err(err).context(format!("replacing {:?}", &path));
+            }
+        }
+    }
+}
+
+/// Replaces the target directory with the staged one (a sibling of the
+/// target, renaming across volumes isn't atomic). The target is moved aside
+/// (to `previous`) and restored if moving the staged directory in fails, so
+/// the target is only missing in between the two renames. The staged
+/// directory is removed on failure.
+pub fn replace_dir(staged: &Path, target: &Path, previous: &Path) -> Result<()> {
+    if let Err(err) = fs::rename(target, previous) {
+        let _ = fs::remove_dir_all(staged);
+        return Err(err).context(format!("moving {:?} aside", &target));
+    }
+    if let Err(err) = fs::rename(staged, target) {
+        let _ = fs::rename(previous, target);
+        let _ = fs::remove_dir_all(staged);
+        return Err(err).context(format!("moving {:?} to {:?}", &staged, &target));
+    }
+    fs::remove_dir_all(previous).context(format!("removing previous {:?}", &previous))
+}
+
+/// A temporary file in the same directory (renaming across volumes isn't
+/// atomic), unique per process and call.
+fn temp_path(path: &Path) -> PathBuf {
+    static COUNTER: AtomicUsize = AtomicUsize::new(0);
+    let name = path.file_name().unwrap_or_default().to_string_lossy();
+    let count = COUNTER.fetch_add(1, Ordering::Relaxed);
+    path.with_file_name(format!(".{}.{}.{}.tmp", name, std::process::id(), count))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use assert_fs::{prelude::*, TempDir};
+
+    #[test]
+    fn write_atomically_replaces_contents_without_leftovers() {
+        let tmp_dir = TempDir::new().unwrap();
+        let file = tmp_dir.child("shims.db");
+        write_atomically(file.path(), b"first").unwrap();
+        write_atomically(file.path(), b"second").unwrap();
+        file.assert("second");
+        assert_eq!(fs::read_dir(tmp_dir.path()).unwrap().count(), 1, "temporary file left behind");
+    }
+
+    #[test]
+    fn replace_dir_swaps_in_staged_dir() {
+        let tmp_dir = TempDir::new().unwrap();
+        tmp_dir.child("shims").child("old.exe").touch().unwrap();
+        tmp_dir.child(".staging-shims").child("new.exe").touch().unwrap();
+        let previous = tmp_dir.child(".staging-previous-shims");
+        replace_dir(
+            tmp_dir.child(".staging-shims").path(),
+            tmp_dir.child("shims").path(),
+            previous.path(),
+        )
+        .unwrap();
+        assert!(tmp_dir.child("shims").child("new.exe").exists());
+        assert!(!tmp_dir.child("shims").child("old.exe").exists());
+        assert_eq!(fs::read_dir(tmp_dir.path()).unwrap().count(), 1, "staging dirs left behind");
+    }
+
+    #[test]
+    fn replace_dir_keeps_target_on_failure() {
+        let tmp_dir = TempDir::new().unwrap();
+        tmp_dir.child("shims").child("old.exe").touch().unwrap();
+        let staged = tmp_dir.child(".staging-shims");
+        let err = replace_dir(
+            staged.path(),
+            tmp_dir.child("shims").path(),
+            tmp_dir.child(".staging-previous-shims").path(),
+        );
+        assert!(err.is_err(), "replacing with a missing staged dir should fail");
+        assert!(tmp_dir.child("shims").child("old.exe").exists());
+        assert_eq!(fs::read_dir(tmp_dir.path()).unwrap().count(), 1, "staging dirs left behind");
+    }
+}
@@ -0,0 +1,266 @@
+use anyhow::{Context, Result};
+use log::debug;
+use std::collections::HashMap;
+use std::fmt::Display;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+
+use crate::common::shims_for_env;
+use crate::manifest::SYSTEM_MANIFESTS_DIR;
+use crate::runtime::RuntimeEnvironment;
+use crate::shim_health;
+use crate::shims::InstalledVersion;
+
+/// The number of rotated log files kept per log (by the loggers of `asdfw`
+/// and the shims, and by `find`).
+pub const KEEP_LOG_FILES: usize = 6;
+/// Staging directories modified more recently may belong to an install (or
+/// reshim) still running, so they are not garbage yet.
+pub const STAGING_DIR_MIN_AGE: Duration = Duration::from_secs(24 * 60 * 60);
+/// The failures of a command that did not fail for this long are no longer
+/// interesting (the command is probably not used anymore).
+pub const SHIM_FAILURES_MAX_AGE: Duration = Duration::from_secs(30 * 24 * 60 * 60);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum GarbageKind {
+    /// The cached installation file of a version that is no longer
+    /// installed.
+    CachedArtifact,
+    /// The manifest of a version that is no longer installed.
+    Manifest,
+    /// A staging directory left behind by an interrupted install.
+    StagingDir,
+    /// A rotated log file beyond the retention.
+    RotatedLog,
+    /// A shim (and its db entry) not backed by any installed version.
+    OrphanedShim,
+    /// The recorded failures of a command that no longer has a shim or did
+    /// not fail recently.
+    ShimFailures,
+}
+
+impl Display for GarbageKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let name = match self {
+            GarbageKind::CachedArtifact => "cached file",
+            GarbageKind::Manifest => "manifest",
+            GarbageKind::StagingDir => "staging directory",
+            GarbageKind::RotatedLog => "rotated log",
+            GarbageKind::OrphanedShim => "orphaned shim",
+            GarbageKind::ShimFailures => "shim failures",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+/// A file or directory `collect` removes.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Garbage {
+    pub kind: GarbageKind,
+    pub path: PathBuf,
+    /// The size in bytes (of all the files for directories).
+    pub size: u64,
+}
+
+impl Garbage {
+    fn new(kind: GarbageKind, path: PathBuf) -> Result<Self> {
+        let size = disk_usage(&path)?;
+        Ok(Garbage { kind, path, size })
+    }
+}
+
+/// Finds everything that could be safely removed at `now`, sorted by kind and
+/// path.
+pub fn find(env: &RuntimeEnvironment, now: SystemTime) -> Result<Vec<Garbage>> {
+    let shims = shims_for_env(env)?;
+    let installed = shims.installed_versions()?;
+    let is_installed = |tool: &str, version: &str| installed.iter().any(|v| v.tool == tool && v.version == version);
+    let mut garbage = vec![];
+    for (tool, dir) in tool_subdirs(&env.cache_dir)? {
+        for version_dir in list_dir(&dir)? {
+            let version = file_name(&version_dir);
+            if !is_installed(&tool, &version) {
+                garbage.push(Garbage::new(GarbageKind::CachedArtifact, version_dir)?);
+            }
+        }
+    }
+    let system_manifests_dir = env.manifests_dir.join(SYSTEM_MANIFESTS_DIR);
+    for (root, system) in [(&env.manifests_dir, false), (&system_manifests_dir, true)] {
+        for (tool, dir) in tool_subdirs(root)? {
+            if !system && tool == SYSTEM_MANIFESTS_DIR {
+                continue;
+            }
+            for manifest in list_dir(&dir)? {
+                let version = manifest.file_stem().unwrap_or_default().to_string_lossy().into_owned();
+                let recorded = |v: &InstalledVersion| v.tool == tool && v.version == version && v.system == system;
+                if !installed.iter().any(recorded) {
+                    garbage.push(Garbage::new(GarbageKind::Manifest, manifest)?);
+                }
+            }
+        }
+    }
+    for dir in shims.leftover_staging_dirs()? {
+        if is_older_than(&dir, STAGING_DIR_MIN_AGE, now)? {
+            garbage.push(Garbage::new(GarbageKind::StagingDir, dir)?);
+        } else {
+            debug!("Keeping recent staging directory {:?}", &dir);
+        }
+    }
+    for log in rotated_logs(&env.log_dir)? {
+        garbage.push(Garbage::new(GarbageKind::RotatedLog, log)?);
+    }
+    if env.shims_db.exists() {
+        for exe in shims.find_orphaned_shims(&env.config)? {
+            garbage.push(Garbage::new(GarbageKind::OrphanedShim, env.shims_dir.join(exe))?);
+        }
+    }
+    for file in shim_health::failures_files(&env.shim_failures_dir)? {
+        let command = file.file_stem().unwrap_or_default();
+        if !env.shims_dir.join(command).exists() || is_older_than(&file, SHIM_FAILURES_MAX_AGE, now)? {
+            garbage.push(Garbage::new(GarbageKind::ShimFailures, file)?);
+        }
+    }
+    garbage.sort_by(|a, b| (a.kind, &a.path).cmp(&(b.kind, &b.path)));
+    debug!("Found {} garbage items", garbage.len());
+    Ok(garbage)
+}
+
+/// Removes the garbage. Returns the number of bytes freed.
+pub fn collect(env: &RuntimeEnvironment, garbage: &[Garbage]) -> Result<u64> {
+    let mut orphans = vec![];
+    for item in garbage {
+        debug!("Removing {} {:?}", &item.kind, &item.path);
+        match item.kind {
+            GarbageKind::OrphanedShim => orphans.push(file_name(&item.path)),
+            _ if item.path.is_dir() => {
+                fs::remove_dir_all(&item.path).context(format!("removing {:?}", &item.path))?;
+            }
+            _ => fs::remove_file(&item.path).context(format!("removing {:?}", &item.path))?,
+        }
+    }
+    if !orphans.is_empty() {
+        shims_for_env(env)?.remove_shims(&orphans)?;
+    }
+    Ok(garbage.iter().map(|item| item.size).sum())
+}
+
+/// Formats a number of bytes for humans (e.g. `1.5 MB`).
+pub fn format_size(bytes: u64) -> String {
+    const UNITS: [&str; 4] = ["KB", "MB", "GB", "TB"];
+    if bytes < 1024 {
+        return format!("{} B", bytes);
+    }
+    let mut size = bytes as f64 / 1024.0;
+    let mut unit = 0;
+    while size >= 1024.0 && unit < UNITS.len() - 1 {
+        size /= 1024.0;
+        unit += 1;
+    }
+    format!("{:.1} {}", size, UNITS[unit])
+}
+
+/// Rotated log files (`<name>_r<number>.log`) beyond the newest
+/// `KEEP_LOG_FILES` of each log.
+fn rotated_logs(log_dir: &Path) -> Result<Vec<PathBuf>> {
+    let mut logs: HashMap<String, Vec<(u64, PathBuf)>> = HashMap::new();
+    for path in list_dir(log_dir)? {
+        let name = file_name(&path);
+        let rotation = name
+            .strip_suffix(".log")
+            .and_then(|stem| stem.rsplit_once("_r"))
+            .and_then(|(base, number)| number.parse::<u64>().ok().map(|n| (base.to_owned(), n)));
+        if let Some((base, number)) = rotation {
+            logs.entry(base).or_default().push((number, path));
+        }
+    }
+    let mut old = vec![];
+    for (_, mut rotated) in logs {
+        // Higher numbers are newer
+        rotated.sort_by(|a, b| b.0.cmp(&a.0));
+        old.extend(rotated.into_iter().skip(KEEP_LOG_FILES).map(|(_, path)| path));
+    }
+    Ok(old)
+}
+
+/// The sub directories of the directory (if it exists) along with their
+/// names.
+fn tool_subdirs(dir: &Path) -> Result<Vec<(String, PathBuf)>> {
+    Ok(list_dir(dir)?
+        .into_iter()
+        .filter(|path| path.is_dir())
+        .map(|path| (file_name(&path), path))
+        .collect())
+}
+
+/// Whether the file (or directory) was last modified more than `age` before
+/// `now`.
+fn is_older_than(path: &Path, age: Duration, now: SystemTime) -> Result<bool> {
+    let modified = fs::metadata(path)
+        .and_then(|metadata| metadata.modified())
+        .context(format!("reading the modification time of {:?}", &path))?;
+    Ok(matches!(now.duration_since(modified), Ok(elapsed) if elapsed > age))
+}
+
+fn list_dir(dir: &Path) -> Result<Vec<PathBuf>> {
+    if !dir.is_dir() {
+        return Ok(vec![]);
+    }
+    let mut entries = vec![];
+    for entry in fs::read_dir(dir).context(format!("reading directory {:?}", &dir))? {
+        entries.push(entry?.path());
+    }
+    Ok(entries)
+}
+
+fn file_name(path: &Path) -> String {
+    path.file_name().unwrap_or_default().to_string_lossy().into_owned()
+}
+
+/// The size of the file or of all the files in the directory (0 if missing).
+fn disk_usage(path: &Path) -> Result<u64> {
+    let metadata = match fs::symlink_metadata(path) {
+        Ok(metadata) => metadata,
+        Err(_) => return Ok(0),
+    };
+    if !metadata.is_dir() {
+        return Ok(metadata.len());
+    }
+    let mut size = 0;
+    for entry in list_dir(path)? {
+        size += disk_usage(&entry)?;
+    }
+    Ok(size)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use assert_fs::{prelude::*, TempDir};
+    use rstest::rstest;
+
+    #[rstest]
+    #[case(512, "512 B")]
+    #[case(1536, "1.5 KB")]
+    #[case(5 * 1024 * 1024, "5.0 MB")]
+    fn format_size_uses_largest_unit(#[case] bytes: u64, #[case] expected: &str) {
+        assert_eq!(format_size(bytes), expected);
+    }
+
+    #[test]
+    fn rotated_logs_keeps_newest_of_each_log() {
+        let tmp_dir = TempDir::new().unwrap();
+        for n in 1..=8 {
+            tmp_dir.child(format!("asdfw_r{:05}.log", n)).touch().unwrap();
+        }
+        tmp_dir.child("asdfw_rCURRENT.log").touch().unwrap();
+        tmp_dir.child("kubectl_r00001.log").touch().unwrap();
+        let mut old = rotated_logs(tmp_dir.path()).unwrap();
+        old.sort();
+        let expected = vec![
+            tmp_dir.child("asdfw_r00001.log").to_path_buf(),
+            tmp_dir.child("asdfw_r00002.log").to_path_buf(),
+        ];
+        assert_eq!(old, expected);
+    }
+}
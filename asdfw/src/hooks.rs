@@ -0,0 +1,163 @@
+use anyhow::{anyhow, Context, Result};
+use log::{debug, info};
+use std::fmt::Display;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+/// Lifecycle events user hooks can be attached to. The hook scripts are
+/// located in the hooks directory and named after the event (e.g.
+/// `post-reshim.ps1`).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HookEvent {
+    PreReshim,
+    PostReshim,
+    PostVersionSwitch,
+    PreInstall,
+    PostInstall,
+}
+
+impl HookEvent {
+    pub fn name(&self) -> &'static str {
+        match self {
+            HookEvent::PreReshim => "pre-reshim",
+            HookEvent::PostReshim => "post-reshim",
+            HookEvent::PostVersionSwitch => "post-version-switch",
+            HookEvent::PreInstall => "pre-install",
+            HookEvent::PostInstall => "post-install",
+        }
+    }
+}
+
+impl Display for HookEvent {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.name())
+    }
+}
+
+/// Runs user provided scripts on lifecycle events.
+pub struct Hooks<'a> {
+    dir: &'a Path,
+}
+
+impl<'a> Hooks<'a> {
+    pub fn new(dir: &'a Path) -> Self {
+        Hooks { dir }
+    }
+
+    /// Returns all the scripts registered for the provided event (sorted by
+    /// name). A missing hooks directory means no hooks.
+    pub fn scripts_for(&self, event: HookEvent) -> Result<Vec<PathBuf>> {
+        if !self.dir.is_dir() {
+            return Ok(vec![]);
+        }
+        let mut scripts = vec![];
+        for entry in fs::read_dir(self.dir).context(format!("reading hooks directory {:?}", self.dir))? {
+            let path = entry?.path();
+            let stem = path.file_stem().and_then(|s| s.to_str());
+            if path.is_file() && stem == Some(event.name()) && command_for_script(&path).is_some() {
+                scripts.push(path);
+            }
+        }
+        scripts.sort();
+        Ok(scripts)
+    }
+
+    /// Runs all the scripts registered for the event. The context is passed
+    /// to the scripts as `ASDFW_HOOK_<KEY>` environment variables. Fails on the
+    /// first script that exits with non zero exit code.
+    pub fn run(&self, event: HookEvent, context: &[(&str, &str)]) -> Result<()> {
+        for script in self.scripts_for(event)? {
+            info!("Running {} hook: {:?}", &event, &script);
+            let mut command = command_for_script(&script).unwrap();
+            command.env("ASDFW_HOOK_EVENT", event.name());
+            for (key, value) in context {
+                command.env(format!("ASDFW_HOOK_{}", key.to_uppercase()), value);
+            }
+            let status = command.status().context(format!("running {} hook {:?}", &event, &script))?;
+            debug!("{} hook {:?} exited with: {}", &event, &script, &status);
+            if !status.success() {
+                return Err(anyhow!(
+                    "{} hook {:?} failed with exit code {}",
+                    &event,
+                    &script,
+                    status.code().unwrap_or(-1)
+                ));
+            }
+        }
+        Ok(())
+    }
+}
+
+fn command_for_script(path: &Path) -> Option<Command> {
+    let ext = path.extension()?.to_str()?.to_lowercase();
+    match ext.as_str() {
+        "exe" => Some(Command::new(path)),
+        "cmd" | "bat" => {
+            let mut command = Command::new("cmd.exe");
+            command.arg("/C").arg(path);
+            Some(command)
+        }
+        "ps1" => {
+            let mut command = Command::new("powershell.exe");
+            command.args(["-NoProfile", "-NonInteractive", "-ExecutionPolicy", "Bypass", "-File"]);
+            command.arg(path);
+            Some(command)
+        }
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use assert_fs::{prelude::*, TempDir};
+
+    #[test]
+    fn scripts_for_returns_only_scripts_matching_the_event() {
+        let tmp_dir = TempDir::new().unwrap();
+        tmp_dir.child("post-reshim.ps1").touch().unwrap();
+        tmp_dir.child("post-reshim.cmd").touch().unwrap();
+        tmp_dir.child("post-reshim.txt").touch().unwrap();
+        tmp_dir.child("pre-reshim.cmd").touch().unwrap();
+        let hooks = Hooks::new(tmp_dir.path());
+        let result = hooks.scripts_for(HookEvent::PostReshim).unwrap();
+        let expected = vec![
+            tmp_dir.child("post-reshim.cmd").to_path_buf(),
+            tmp_dir.child("post-reshim.ps1").to_path_buf(),
+        ];
+        assert_eq!(result, expected);
+    }
+
+    #[test]
+    fn scripts_for_with_missing_hooks_dir_returns_no_scripts() {
+        let tmp_dir = TempDir::new().unwrap();
+        let missing = tmp_dir.child("hooks");
+        let hooks = Hooks::new(missing.path());
+        let result = hooks.scripts_for(HookEvent::PreReshim).unwrap();
+        assert!(result.is_empty(), "missing hooks dir should produce no scripts");
+    }
+
+    #[cfg(windows)]
+    #[test]
+    fn run_passes_context_as_environment_variables() {
+        let tmp_dir = TempDir::new().unwrap();
+        let output = tmp_dir.child("output.txt");
+        let script = format!("@echo %ASDFW_HOOK_EVENT% %ASDFW_HOOK_TOOL%> \"{}\"\r\n", output.path().display());
+        tmp_dir.child("post-version-switch.cmd").write_str(&script).unwrap();
+        let hooks = Hooks::new(tmp_dir.path());
+        hooks.run(HookEvent::PostVersionSwitch, &[("tool", "mytool")]).unwrap();
+        let result = fs::read_to_string(output.path()).unwrap();
+        assert_eq!(result.trim(), "post-version-switch mytool");
+    }
+
+    #[cfg(windows)]
+    #[test]
+    fn run_fails_when_script_fails() {
+        let tmp_dir = TempDir::new().unwrap();
+        tmp_dir.child("pre-reshim.cmd").write_str("@exit /b 3\r\n").unwrap();
+        let hooks = Hooks::new(tmp_dir.path());
+        let err = hooks.run(HookEvent::PreReshim, &[]).unwrap_err();
+        assert!(format!("{}", err).contains("exit code 3"), "wrong error: {}", err);
+    }
+}
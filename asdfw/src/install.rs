@@ -0,0 +1,340 @@
+use anyhow::{anyhow, Context, Result};
+use log::{debug, info};
+use std::ffi::OsStr;
+use std::fs::{self, File};
+use std::io;
+use std::path::{Path, PathBuf};
+use zip::ZipArchive;
+
+use crate::fsutil::replace_dir;
+use crate::interrupt;
+use crate::output::Progress;
+
+/// Prefix of the staging directories versions are extracted into before
+/// being moved into place. Ignored when scanning for installed versions.
+pub const STAGING_PREFIX: &str = ".staging-";
+
+/// Installs a version from a local file into `target` (the version
+/// directory). Supported files are zip archives and single executables.
+///
+/// The archive is extracted into a staging directory next to the target,
+/// `prepare` runs on it (e.g. the `post-install` hooks), and only then is it
+/// moved into place, so a failed install never leaves a partial version
+/// behind. Archives wrapping everything in a single top level directory are
+/// unwrapped, and if the result has no `bin` directory the whole content is
+/// placed in `bin`.
+pub fn install_from_file<F>(archive: &Path, target: &Path, prepare: F) -> Result<()>
+where
+    F: FnOnce(&Path) -> Result<()>,
+{
+    info!("Installing {:?} into {:?}", &archive, &target);
+    if target.exists() {
+        return Err(anyhow!("{:?} already exists", &target));
+    }
+    let staging = stage(archive, target, prepare)?;
+    fs::rename(&staging, target)
+        .context(format!("moving {:?} to {:?}", &staging, &target))
+        .map_err(|err| {
+            let _ = fs::remove_dir_all(&staging);
+            err
+        })
+}
+
+/// Replaces the existing version directory (`target`) with the content of
+/// the file. The new version is fully extracted and prepared (see
+/// `install_from_file`) before the existing one is swapped out, and the
+/// existing one is restored if the swap fails.
+pub fn reinstall_from_file<F>(archive: &Path, target: &Path, prepare: F) -> Result<()>
+where
+    F: FnOnce(&Path) -> Result<()>,
+{
+    info!("Reinstalling {:?} into {:?}", &archive, &target);
+    if !target.is_dir() {
+        return Err(anyhow!("{:?} does not exist", &target));
+    }
+    let staging = stage(archive, target, prepare)?;
+    let previous = sibling_with_prefix(target, &format!("{}previous-", STAGING_PREFIX))?;
+    remove_leftover(&previous)?;
+    replace_dir(&staging, target, &previous)
+}
+
+/// Extracts the file into a staging directory next to the target, prepares
+/// it and returns it. The staging directory is removed on failure, including
+/// when interrupted with Ctrl-C (see `interrupt::handle_ctrl_c`).
+fn stage<F>(archive: &Path, target: &Path, prepare: F) -> Result<PathBuf>
+where
+    F: FnOnce(&Path) -> Result<()>,
+{
+    if !archive.is_file() {
+        return Err(anyhow!("{:?} does not exist or is not a file", &archive));
+    }
+    let staging = sibling_with_prefix(target, STAGING_PREFIX)?;
+    remove_leftover(&staging)?;
+    fs::create_dir_all(&staging).context(format!("creating {:?}", &staging))?;
+    let result = extract(archive, &staging)
+        .and_then(|_| normalize_layout(&staging))
+        .and_then(|_| interrupt::check())
+        .and_then(|_| prepare(&staging));
+    if result.is_err() {
+        let _ = fs::remove_dir_all(&staging);
+    }
+    result.map(|_| staging)
+}
+
+/// The path next to the target, named after it with the prefix.
+pub fn sibling_with_prefix(target: &Path, prefix: &str) -> Result<PathBuf> {
+    let parent = target.parent().ok_or(anyhow!("invalid install target: {:?}", &target))?;
+    let name = target.file_name().and_then(OsStr::to_str).unwrap_or_default();
+    Ok(parent.join(format!("{}{}", prefix, name)))
+}
+
+/// Removes a directory left behind by an interrupted operation (if any).
+pub fn remove_leftover(dir: &Path) -> Result<()> {
+    if dir.exists() {
+        debug!("Removing leftover directory {:?}", &dir);
+        fs::remove_dir_all(dir).context(format!("removing {:?}", &dir))?;
+    }
+    Ok(())
+}
+
+/// Keeps a copy of the file a version was installed from, so it could later
+/// be reinstalled without the original file. Replaces any previously cached
+/// file of the version.
+pub fn cache_artifact(cache_dir: &Path, archive: &Path) -> Result<()> {
+    remove_leftover(cache_dir)?;
+    fs::create_dir_all(cache_dir).context(format!("creating {:?}", &cache_dir))?;
+    let file_name = archive.file_name().ok_or(anyhow!("invalid file: {:?}", &archive))?;
+    fs::copy(archive, cache_dir.join(file_name)).context(format!("caching {:?}", &archive))?;
+    Ok(())
+}
+
+/// Returns the file cached for the version (if any).
+pub fn cached_artifact(cache_dir: &Path) -> Result<Option<PathBuf>> {
+    if !cache_dir.is_dir() {
+        return Ok(None);
+    }
+    Ok(list_dir(cache_dir)?.into_iter().find(|path| path.is_file()))
+}
+
+fn extract(archive: &Path, dest: &Path) -> Result<()> {
+    let ext = archive
+        .extension()
+        .and_then(OsStr::to_str)
+        .map(|e| e.to_lowercase())
+        .unwrap_or_default();
+    match ext.as_str() {
+        "zip" => extract_zip(archive, dest),
+        "exe" => {
+            let bin = dest.join("bin");
+            fs::create_dir(&bin)?;
+            let file_name = archive.file_name().unwrap();
+            fs::copy(archive, bin.join(file_name)).context(format!("copying {:?}", &archive))?;
+            Ok(())
+        }
+        _ => Err(anyhow!("Unsupported file type: {:?} (supported: .zip, .exe)", &archive)),
+    }
+}
+
+fn extract_zip(archive: &Path, dest: &Path) -> Result<()> {
+    let context = format!("extracting {:?}", &archive);
+    let file = File::open(archive).context(context.clone())?;
+    let mut zip = ZipArchive::new(file).context(context.clone())?;
+    let name = archive.file_name().unwrap_or_default().to_string_lossy();
+    let mut progress = Progress::new(format!("Extracting {}", name), Some(zip.len()));
+    for i in 0..zip.len() {
+        interrupt::check()?;
+        let mut entry = zip.by_index(i).context(context.clone())?;
+        let relative = entry
+            .enclosed_name()
+            .map(Path::to_path_buf)
+            .ok_or(anyhow!("Refusing to extract unsafe path: {}", entry.name()))?;
+        let path = dest.join(relative);
+        if entry.is_dir() {
+            fs::create_dir_all(&path)?;
+        } else {
+            if let Some(parent) = path.parent() {
+                fs::create_dir_all(parent)?;
+            }
+            let mut out = File::create(&path).context(format!("creating {:?}", &path))?;
+            io::copy(&mut entry, &mut out).context(context.clone())?;
+        }
+        progress.inc();
+    }
+    Ok(())
+}
+
+fn normalize_layout(dir: &Path) -> Result<()> {
+    let entries = list_dir(dir)?;
+    if let [single] = entries.as_slice() {
+        if single.is_dir() && single.file_name() != Some(OsStr::new("bin")) {
+            debug!("Unwrapping top level directory {:?}", &single);
+            for entry in list_dir(single)? {
+                fs::rename(&entry, dir.join(entry.file_name().unwrap()))?;
+            }
+            fs::remove_dir(single)?;
+        }
+    }
+    if !dir.join("bin").is_dir() {
+        debug!("No bin directory in {:?}, moving everything into bin", &dir);
+        let bin = dir.join("bin");
+        let entries = list_dir(dir)?;
+        fs::create_dir(&bin)?;
+        for entry in entries {
+            fs::rename(&entry, bin.join(entry.file_name().unwrap()))?;
+        }
+    }
+    Ok(())
+}
+
+fn list_dir(dir: &Path) -> Result<Vec<PathBuf>> {
+    let mut entries = vec![];
+    for entry in fs::read_dir(dir).context(format!("reading {:?}", &dir))? {
+        entries.push(entry?.path());
+    }
+    Ok(entries)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use assert_fs::{prelude::*, TempDir};
+    use rstest::rstest;
+    use std::io::Write;
+    use zip::{write::FileOptions, ZipWriter};
+
+    fn fixture_zip(path: &Path, files: &[&str]) {
+        let mut zip = ZipWriter::new(File::create(path).unwrap());
+        for file in files {
+            zip.start_file(*file, FileOptions::default()).unwrap();
+            zip.write_all(b"content").unwrap();
+        }
+        zip.finish().unwrap();
+    }
+
+    fn no_prepare(_: &Path) -> Result<()> {
+        Ok(())
+    }
+
+    #[rstest]
+    #[case(&["bin/tool.exe", "lib/data.txt"], "archive with bin")]
+    #[case(&["tool-1.0/bin/tool.exe", "tool-1.0/lib/data.txt"], "wrapped in top level directory")]
+    fn install_from_zip(#[case] files: &[&str], #[case] msg: &str) {
+        let tmp_dir = TempDir::new().unwrap();
+        let archive = tmp_dir.child("tool.zip");
+        fixture_zip(archive.path(), files);
+        let target = tmp_dir.child("installs").child("tool").child("1.0");
+        install_from_file(archive.path(), target.path(), no_prepare).unwrap();
+        assert!(target.child("bin").child("tool.exe").exists(), "{}: missing executable", msg);
+        assert!(target.child("lib").child("data.txt").exists(), "{}: missing data", msg);
+        let staging = tmp_dir.child("installs").child("tool").child(".staging-1.0");
+        assert!(!staging.exists(), "{}: staging dir should be removed", msg);
+    }
+
+    #[test]
+    fn install_from_zip_without_bin_places_content_in_bin() {
+        let tmp_dir = TempDir::new().unwrap();
+        let archive = tmp_dir.child("tool.zip");
+        fixture_zip(archive.path(), &["tool.exe", "tool.dll"]);
+        let target = tmp_dir.child("1.0");
+        install_from_file(archive.path(), target.path(), no_prepare).unwrap();
+        assert!(target.child("bin").child("tool.exe").exists());
+        assert!(target.child("bin").child("tool.dll").exists());
+    }
+
+    #[test]
+    fn install_from_executable() {
+        let tmp_dir = TempDir::new().unwrap();
+        let exe = tmp_dir.child("kubectl.exe");
+        exe.touch().unwrap();
+        let target = tmp_dir.child("installs").child("kubectl").child("1.23.0");
+        install_from_file(exe.path(), target.path(), no_prepare).unwrap();
+        assert!(target.child("bin").child("kubectl.exe").exists());
+    }
+
+    #[test]
+    fn install_from_file_fails_if_target_exists() {
+        let tmp_dir = TempDir::new().unwrap();
+        let exe = tmp_dir.child("kubectl.exe");
+        exe.touch().unwrap();
+        let target = tmp_dir.child("1.23.0");
+        target.create_dir_all().unwrap();
+        let err = install_from_file(exe.path(), target.path(), no_prepare).unwrap_err();
+        assert!(format!("{}", err).contains("already exists"), "wrong error: {}", err);
+    }
+
+    #[test]
+    fn install_from_unsupported_file_leaves_nothing_behind() {
+        let tmp_dir = TempDir::new().unwrap();
+        let archive = tmp_dir.child("tool.rar");
+        archive.touch().unwrap();
+        let target = tmp_dir.child("installs").child("1.0");
+        assert!(install_from_file(archive.path(), target.path(), no_prepare).is_err());
+        assert!(!target.exists());
+        assert!(!tmp_dir.child("installs").child(".staging-1.0").exists());
+    }
+
+    #[test]
+    fn install_is_rolled_back_if_prepare_fails() {
+        let tmp_dir = TempDir::new().unwrap();
+        let exe = tmp_dir.child("kubectl.exe");
+        exe.touch().unwrap();
+        let target = tmp_dir.child("installs").child("1.23.0");
+        let staging = tmp_dir.child("installs").child(".staging-1.23.0");
+        let result = install_from_file(exe.path(), target.path(), |dir| {
+            assert_eq!(dir, staging.path());
+            assert!(
+                staging.child("bin").child("kubectl.exe").exists(),
+                "should prepare extracted version"
+            );
+            Err(anyhow!("hook failed"))
+        });
+        assert!(result.is_err());
+        assert!(!target.exists());
+        assert!(!staging.exists());
+    }
+
+    #[test]
+    fn reinstall_replaces_existing_installation() {
+        let tmp_dir = TempDir::new().unwrap();
+        let target = tmp_dir.child("installs").child("tool").child("1.0");
+        target.child("bin").child("tool.exe").write_str("corrupted").unwrap();
+        target.child("bin").child("stale.dll").touch().unwrap();
+        let archive = tmp_dir.child("tool.zip");
+        fixture_zip(archive.path(), &["bin/tool.exe"]);
+        reinstall_from_file(archive.path(), target.path(), no_prepare).unwrap();
+        target.child("bin").child("tool.exe").assert("content");
+        assert!(!target.child("bin").child("stale.dll").exists(), "old files should be removed");
+        let leftovers: Vec<PathBuf> = list_dir(tmp_dir.child("installs").child("tool").path()).unwrap();
+        assert_eq!(leftovers, vec![target.to_path_buf()]);
+    }
+
+    #[test]
+    fn reinstall_with_invalid_file_keeps_existing_installation() {
+        let tmp_dir = TempDir::new().unwrap();
+        let target = tmp_dir.child("1.0");
+        target.child("bin").child("tool.exe").write_str("original").unwrap();
+        let archive = tmp_dir.child("tool.rar");
+        archive.touch().unwrap();
+        assert!(reinstall_from_file(archive.path(), target.path(), no_prepare).is_err());
+        target.child("bin").child("tool.exe").assert("original");
+        let exe = tmp_dir.child("tool.exe");
+        exe.touch().unwrap();
+        assert!(reinstall_from_file(exe.path(), target.path(), |_| Err(anyhow!("hook failed"))).is_err());
+        target.child("bin").child("tool.exe").assert("original");
+    }
+
+    #[test]
+    fn cache_artifact_replaces_previous_file() {
+        let tmp_dir = TempDir::new().unwrap();
+        let cache_dir = tmp_dir.child("cache").child("tool").child("1.0");
+        let first = tmp_dir.child("first.zip");
+        first.touch().unwrap();
+        let second = tmp_dir.child("second.exe");
+        second.touch().unwrap();
+        assert_eq!(cached_artifact(cache_dir.path()).unwrap(), None);
+        cache_artifact(cache_dir.path(), first.path()).unwrap();
+        cache_artifact(cache_dir.path(), second.path()).unwrap();
+        let expected = cache_dir.child("second.exe").to_path_buf();
+        assert_eq!(cached_artifact(cache_dir.path()).unwrap(), Some(expected));
+    }
+}
@@ -0,0 +1,72 @@
+use anyhow::Result;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use crate::errors::ErrorKind;
+use crate::output::message;
+
+static INTERRUPTED: AtomicBool = AtomicBool::new(false);
+
+/// Makes Ctrl-C request cancellation (see `check`) instead of terminating the
+/// process, so long operations could stop where it's safe and roll back. A
+/// second Ctrl-C terminates the process as usual.
+pub fn handle_ctrl_c() -> Result<()> {
+    imp::set_handler()
+}
+
+/// Whether Ctrl-C was pressed since `handle_ctrl_c` was called.
+pub fn interrupted() -> bool {
+    INTERRUPTED.load(Ordering::SeqCst)
+}
+
+/// Fails if Ctrl-C was pressed. Called by long operations between steps.
+pub fn check() -> Result<()> {
+    match interrupted() {
+        true => Err(ErrorKind::Interrupted.error(message("error.interrupted", &[]))),
+        false => Ok(()),
+    }
+}
+
+#[cfg(windows)]
+mod imp {
+    use super::INTERRUPTED;
+    use anyhow::{anyhow, Result};
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use winapi::shared::minwindef::{BOOL, DWORD, FALSE, TRUE};
+    use winapi::um::consoleapi::SetConsoleCtrlHandler;
+    use winapi::um::wincon::{CTRL_BREAK_EVENT, CTRL_C_EVENT};
+
+    static HANDLER_SET: AtomicBool = AtomicBool::new(false);
+
+    unsafe extern "system" fn ctrlc_handler(event: DWORD) -> BOOL {
+        if event != CTRL_C_EVENT && event != CTRL_BREAK_EVENT {
+            return FALSE;
+        }
+        // The first Ctrl-C is handled, the next one falls through to the
+        // default handler (terminating the process)
+        match INTERRUPTED.swap(true, Ordering::SeqCst) {
+            true => FALSE,
+            false => TRUE,
+        }
+    }
+
+    pub(super) fn set_handler() -> Result<()> {
+        if HANDLER_SET.swap(true, Ordering::SeqCst) {
+            return Ok(());
+        }
+        unsafe {
+            if SetConsoleCtrlHandler(Some(ctrlc_handler), TRUE) == FALSE {
+                return Err(anyhow!("Could not set Ctrl-C handler."));
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(not(windows))]
+mod imp {
+    use anyhow::Result;
+
+    pub(super) fn set_handler() -> Result<()> {
+        Ok(())
+    }
+}
@@ -1,6 +1,30 @@
+pub mod activate;
+pub mod advisories;
+pub mod audit;
+pub mod check;
 pub mod common;
+pub mod completion;
+pub mod config;
+pub mod config_edit;
+pub mod doctor;
+pub mod errors;
+pub mod fsutil;
+pub mod gc;
+pub mod hooks;
+pub mod install;
+pub mod interrupt;
+pub mod manifest;
 pub mod output;
+pub mod policy;
+pub mod porcelain;
+pub mod project_env;
+pub mod receipt;
 pub mod runtime;
+pub mod shell;
+pub mod shim_health;
 pub mod shims;
+pub mod stats;
 pub mod subcommand;
 pub mod tool_versions;
+pub mod version;
+pub mod wsl;
@@ -0,0 +1,203 @@
+use crate::shims::InstalledVersion;
+use anyhow::{anyhow, Context, Result};
+use log::debug;
+use sha2::{Digest, Sha256};
+use std::collections::BTreeMap;
+use std::fmt::Display;
+use std::fs::{self, File};
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// A list of all the files of an installed version along with their sha256
+/// hashes. Saved in `sha256sum` format (`<hash>  <relative path>`) with `/` as
+/// path separator.
+#[derive(Debug, Default, PartialEq)]
+pub struct Manifest {
+    files: BTreeMap<String, String>,
+}
+
+/// A difference between the manifest and the files on disk.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Discrepancy {
+    Modified(String),
+    Missing(String),
+}
+
+impl Display for Discrepancy {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Discrepancy::Modified(file) => write!(f, "modified: {}", file),
+            Discrepancy::Missing(file) => write!(f, "missing:  {}", file),
+        }
+    }
+}
+
+/// The subdirectory of the manifests dir with the manifests of the versions in
+/// the system install dir. The manifests of the user's versions are directly
+/// in the manifests dir.
+pub const SYSTEM_MANIFESTS_DIR: &str = ".system";
+
+/// Returns the location of the manifest of the installed version. Versions in
+/// the system install dir have their own manifests, so a version installed
+/// both system wide and by the user is not verified against the wrong files.
+pub fn manifest_path(manifests_dir: &Path, installed: &InstalledVersion) -> PathBuf {
+    let dir = if installed.system {
+        manifests_dir.join(SYSTEM_MANIFESTS_DIR)
+    } else {
+        manifests_dir.to_path_buf()
+    };
+    dir.join(&installed.tool).join(format!("{}.sha256", &installed.version))
+}
+
+impl Manifest {
+    /// Generates a manifest by hashing all files in the provided directory.
+    pub fn generate(dir: &Path) -> Result<Self> {
+        debug!("Generating manifest for {:?}", &dir);
+        let mut files = BTreeMap::new();
+        for path in list_files(dir)? {
+            let relative = relative_name(dir, &path)?;
+            files.insert(relative, hash_file(&path)?);
+        }
+        Ok(Manifest { files })
+    }
+
+    pub fn load(path: &Path) -> Result<Self> {
+        let context = || format!("loading manifest {:?}", &path);
+        let contents = fs::read_to_string(path).with_context(context)?;
+        let mut files = BTreeMap::new();
+        for line in contents.lines().filter(|l| !l.trim().is_empty()) {
+            let (hash, file) = line
+                .split_once("  ")
+                .ok_or(anyhow!("invalid manifest line: '{}'", &line))
+                .with_context(context)?;
+            files.insert(file.to_owned(), hash.to_owned());
+        }
+        Ok(Manifest { files })
+    }
+
+    pub fn save(&self, path: &Path) -> Result<()> {
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        let contents: String = self.files.iter().map(|(file, hash)| format!("{}  {}\n", hash, file)).collect();
+        fs::write(path, contents).context(format!("saving manifest {:?}", &path))
+    }
+
+    pub fn len(&self) -> usize {
+        self.files.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.files.is_empty()
+    }
+
+    /// Compares the manifest with the files currently in the provided
+    /// directory. Files added after the manifest was recorded are ignored.
+    pub fn verify(&self, dir: &Path) -> Result<Vec<Discrepancy>> {
+        let mut discrepancies = vec![];
+        for (file, hash) in &self.files {
+            let path = dir.join(file);
+            if !path.is_file() {
+                discrepancies.push(Discrepancy::Missing(file.clone()));
+            } else if &hash_file(&path)? != hash {
+                discrepancies.push(Discrepancy::Modified(file.clone()));
+            }
+        }
+        Ok(discrepancies)
+    }
+}
+
+fn list_files(dir: &Path) -> Result<Vec<PathBuf>> {
+    let mut files = vec![];
+    for entry in fs::read_dir(dir).context(format!("reading directory {:?}", &dir))? {
+        let path = entry?.path();
+        if path.is_dir() {
+            files.extend(list_files(&path)?);
+        } else {
+            files.push(path);
+        }
+    }
+    Ok(files)
+}
+
+fn relative_name(root: &Path, path: &Path) -> Result<String> {
+    let relative = path.strip_prefix(root)?;
+    let parts: Vec<String> = relative.components().map(|c| c.as_os_str().to_string_lossy().into_owned()).collect();
+    Ok(parts.join("/"))
+}
+
+/// Returns the hex encoded sha256 hash of the file.
+pub fn hash_file(path: &Path) -> Result<String> {
+    let mut file = File::open(path).context(format!("opening {:?}", &path))?;
+    let mut hasher = Sha256::new();
+    io::copy(&mut file, &mut hasher).context(format!("reading {:?}", &path))?;
+    Ok(format!("{:x}", hasher.finalize()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use assert_fs::{prelude::*, TempDir};
+
+    fn fixture_version_dir(tmp_dir: &TempDir) -> assert_fs::fixture::ChildPath {
+        let dir = tmp_dir.child("installs").child("mytool").child("1.0");
+        dir.child("bin").child("mytool.exe").write_str("binary").unwrap();
+        dir.child("lib").child("nested").child("data.txt").write_str("data").unwrap();
+        dir
+    }
+
+    fn installed_version(dir: &Path, system: bool) -> InstalledVersion {
+        InstalledVersion {
+            tool: "mytool".to_owned(),
+            version: "1.0".to_owned(),
+            dir: dir.to_path_buf(),
+            system,
+        }
+    }
+
+    #[test]
+    fn manifest_path_is_keyed_by_install_root() {
+        let manifests_dir = Path::new("manifests");
+        let user = manifest_path(manifests_dir, &installed_version(Path::new("installs"), false));
+        let system = manifest_path(manifests_dir, &installed_version(Path::new("system"), true));
+        assert_eq!(user, manifests_dir.join("mytool").join("1.0.sha256"));
+        assert_eq!(system, manifests_dir.join(SYSTEM_MANIFESTS_DIR).join("mytool").join("1.0.sha256"));
+    }
+
+    #[test]
+    fn save_and_load_roundtrip() {
+        let tmp_dir = TempDir::new().unwrap();
+        let dir = fixture_version_dir(&tmp_dir);
+        let manifest = Manifest::generate(dir.path()).unwrap();
+        let installed = installed_version(&dir, false);
+        let path = manifest_path(tmp_dir.child("manifests").path(), &installed);
+        manifest.save(&path).unwrap();
+        let contents = fs::read_to_string(&path).unwrap();
+        assert!(contents.contains("  lib/nested/data.txt\n"), "wrong manifest: {}", contents);
+        assert_eq!(Manifest::load(&path).unwrap(), manifest);
+        assert_eq!(manifest.len(), 2);
+    }
+
+    #[test]
+    fn verify_unchanged_directory_has_no_discrepancies() {
+        let tmp_dir = TempDir::new().unwrap();
+        let dir = fixture_version_dir(&tmp_dir);
+        let manifest = Manifest::generate(dir.path()).unwrap();
+        dir.child("bin").child("added.exe").touch().unwrap();
+        assert_eq!(manifest.verify(dir.path()).unwrap(), vec![]);
+    }
+
+    #[test]
+    fn verify_detects_modified_and_missing_files() {
+        let tmp_dir = TempDir::new().unwrap();
+        let dir = fixture_version_dir(&tmp_dir);
+        let manifest = Manifest::generate(dir.path()).unwrap();
+        dir.child("bin").child("mytool.exe").write_str("tampered").unwrap();
+        fs::remove_file(dir.child("lib").child("nested").child("data.txt").path()).unwrap();
+        let expected = vec![
+            Discrepancy::Modified("bin/mytool.exe".to_owned()),
+            Discrepancy::Missing("lib/nested/data.txt".to_owned()),
+        ];
+        assert_eq!(manifest.verify(dir.path()).unwrap(), expected);
+    }
+}
@@ -1,7 +1,15 @@
+use std::collections::{BTreeMap, HashMap};
 use std::fmt::Display;
+use std::fs;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Mutex, RwLock};
+use std::time::{Duration, Instant};
 
+use crate::errors::error_hint;
 use anyhow::Error;
-use textwrap::{wrap, Options};
+use textwrap::{core::display_width, wrap, Options};
 use yansi::Paint;
 
 pub fn print_out<T: Display>(lines: Vec<T>) {
@@ -35,6 +43,14 @@ pub fn output_full_error(err: Error, width: Option<usize>) -> Vec<String> {
         }
     });
 
+    if let Some(hint) = error_hint(&err) {
+        output.append(&mut vec!["".to_owned(), " Hint:".to_owned()]);
+        let hint_options = Options::new(width).initial_indent("   ").subsequent_indent("   ");
+        for line in wrap(&hint, &hint_options) {
+            output.push(line.into_owned());
+        }
+    }
+
     output
 }
 
@@ -46,9 +62,436 @@ pub fn success_message(msg: &str) -> Vec<std::borrow::Cow<str>> {
     wrap(msg, &options)
 }
 
+pub fn warning_message(msg: &str) -> Vec<std::borrow::Cow<str>> {
+    let prefix = format!(" {}  ", Paint::yellow(""));
+    let options = Options::new(textwrap::termwidth() - 4)
+        .initial_indent(&prefix)
+        .subsequent_indent("    ");
+    wrap(msg, &options)
+}
+
+/// Prints the warning to stderr (so it's not mixed with the output of the
+/// command, or of a shim's executable).
+pub fn print_warning(msg: &str) {
+    warning_message(msg).iter().for_each(|line| eprintln!("{}", line));
+}
+
+const COLUMN_SEPARATOR: &str = "  ";
+const MIN_COLUMN_WIDTH: usize = 4;
+
+/// A table with aligned columns. Columns that don't fit the width are
+/// truncated, widest first.
+#[derive(Debug, Default)]
+pub struct Table {
+    header: Vec<String>,
+    rows: Vec<Vec<String>>,
+}
+
+impl Table {
+    pub fn new<S: Into<String>>(header: Vec<S>) -> Self {
+        Table {
+            header: header.into_iter().map(Into::into).collect(),
+            rows: vec![],
+        }
+    }
+
+    pub fn add_row(&mut self, row: Vec<String>) {
+        self.rows.push(row);
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.rows.is_empty()
+    }
+
+    /// Renders the header and rows. The width defaults to the terminal width.
+    pub fn render(&self, width: Option<usize>) -> Vec<String> {
+        let width = match width {
+            Some(n) => n,
+            None => textwrap::termwidth(),
+        };
+        let mut widths: Vec<usize> = self.header.iter().map(|h| display_width(h)).collect();
+        for row in &self.rows {
+            for (i, cell) in row.iter().enumerate().take(widths.len()) {
+                widths[i] = widths[i].max(display_width(cell));
+            }
+        }
+        let separators = display_width(COLUMN_SEPARATOR) * widths.len().saturating_sub(1);
+        while widths.iter().sum::<usize>() + separators > width {
+            match widths.iter_mut().max() {
+                Some(widest) if *widest > MIN_COLUMN_WIDTH => *widest -= 1,
+                _ => break,
+            }
+        }
+        std::iter::once(&self.header)
+            .chain(self.rows.iter())
+            .map(|row| render_row(row, &widths))
+            .collect()
+    }
+}
+
+fn render_row(row: &[String], widths: &[usize]) -> String {
+    let cells: Vec<String> = widths
+        .iter()
+        .enumerate()
+        .map(|(i, width)| {
+            let cell = truncate(row.get(i).map_or("", String::as_str), *width);
+            let padding = width.saturating_sub(display_width(&cell));
+            format!("{}{}", cell, " ".repeat(padding))
+        })
+        .collect();
+    cells.join(COLUMN_SEPARATOR).trim_end().to_owned()
+}
+
+fn truncate(text: &str, width: usize) -> String {
+    if display_width(text) <= width {
+        return text.to_owned();
+    }
+    // Wide characters (e.g. CJK) take two columns, so count columns rather
+    // than characters
+    let mut truncated = String::new();
+    let mut used = display_width("…");
+    let mut buf = [0; 4];
+    for c in text.chars() {
+        let char_width = display_width(c.encode_utf8(&mut buf));
+        if used + char_width > width {
+            break;
+        }
+        used += char_width;
+        truncated.push(c);
+    }
+    truncated.push('…');
+    truncated
+}
+
+/// A node of a tree rendered with box-drawing connectors.
+#[derive(Debug, Default)]
+pub struct TreeNode {
+    label: String,
+    children: Vec<TreeNode>,
+}
+
+impl TreeNode {
+    pub fn new<S: Into<String>>(label: S) -> Self {
+        TreeNode {
+            label: label.into(),
+            children: vec![],
+        }
+    }
+
+    pub fn add_child(&mut self, child: TreeNode) {
+        self.children.push(child);
+    }
+
+    /// Renders the node and its descendants, one line per node.
+    pub fn render(&self) -> Vec<String> {
+        let mut lines = vec![self.label.clone()];
+        render_children(&self.children, "", &mut lines);
+        lines
+    }
+}
+
+fn render_children(children: &[TreeNode], prefix: &str, lines: &mut Vec<String>) {
+    for (i, child) in children.iter().enumerate() {
+        let last = i + 1 == children.len();
+        let (connector, indent) = if last {
+            ("└── ", "    ")
+        } else {
+            ("├── ", "│   ")
+        };
+        lines.push(format!("{}{}{}", prefix, connector, &child.label));
+        render_children(&child.children, &format!("{}{}", prefix, indent), lines);
+    }
+}
+
+static PROGRESS_DISABLED: AtomicBool = AtomicBool::new(false);
+
+/// Minimal interval between redraws of a progress line.
+const PROGRESS_REDRAW_INTERVAL: Duration = Duration::from_millis(100);
+const PROGRESS_BAR_WIDTH: usize = 24;
+const SPINNER: [char; 4] = ['|', '/', '-', '\\'];
+
+/// Disables progress reporting for the rest of the process (`--no-progress`).
+pub fn disable_progress() {
+    PROGRESS_DISABLED.store(true, Ordering::SeqCst);
+}
+
+/// Reports the progress of a long operation on a single line of stderr: a
+/// bar with an ETA if the number of steps is known, a spinner otherwise.
+/// Nothing is drawn if progress is disabled or stderr is not a console, so
+/// redirected output stays clean. The line is cleared once dropped.
+#[derive(Debug)]
+pub struct Progress {
+    label: String,
+    total: Option<usize>,
+    done: usize,
+    started: Instant,
+    last_draw: Option<Instant>,
+    visible: bool,
+}
+
+impl Progress {
+    pub fn new<S: Into<String>>(label: S, total: Option<usize>) -> Self {
+        Progress {
+            label: label.into(),
+            total,
+            done: 0,
+            started: Instant::now(),
+            last_draw: None,
+            visible: !PROGRESS_DISABLED.load(Ordering::SeqCst) && stderr_is_console(),
+        }
+    }
+
+    /// Marks one more step as done.
+    pub fn inc(&mut self) {
+        self.done += 1;
+        if !self.visible {
+            return;
+        }
+        let now = Instant::now();
+        if matches!(self.last_draw, Some(last) if now - last < PROGRESS_REDRAW_INTERVAL) {
+            return;
+        }
+        self.last_draw = Some(now);
+        let line = self.line(textwrap::termwidth(), now - self.started);
+        let _ = write!(io::stderr(), "\r{}", line);
+    }
+
+    /// Renders the progress line (at most `width` columns).
+    fn line(&self, width: usize, elapsed: Duration) -> String {
+        let status = match self.total {
+            Some(total) => {
+                let done = self.done.min(total);
+                let filled = match total {
+                    0 => PROGRESS_BAR_WIDTH,
+                    _ => PROGRESS_BAR_WIDTH * done / total,
+                };
+                let mut status = format!(
+                    "[{}{}] {}/{}",
+                    "#".repeat(filled),
+                    " ".repeat(PROGRESS_BAR_WIDTH - filled),
+                    done,
+                    total
+                );
+                if done > 0 && done < total {
+                    let remaining = elapsed.as_secs_f64() * (total - done) as f64 / done as f64;
+                    status.push_str(&format!(" ETA {}s", remaining.ceil() as u64));
+                }
+                status
+            }
+            None => format!("{} {}", SPINNER[self.done % SPINNER.len()], self.done),
+        };
+        let line = format!("{} {}", &self.label, status);
+        let line = truncate(&line, width.saturating_sub(1));
+        format!("{}{}", &line, " ".repeat(width.saturating_sub(1) - display_width(&line)))
+    }
+}
+
+impl Drop for Progress {
+    fn drop(&mut self) {
+        if self.last_draw.is_some() {
+            let _ = write!(io::stderr(), "\r{}\r", " ".repeat(textwrap::termwidth().saturating_sub(1)));
+        }
+    }
+}
+
+#[cfg(windows)]
+fn stderr_is_console() -> bool {
+    use winapi::um::consoleapi::GetConsoleMode;
+    use winapi::um::processenv::GetStdHandle;
+    use winapi::um::winbase::STD_ERROR_HANDLE;
+
+    let mut mode = 0;
+    unsafe { GetConsoleMode(GetStdHandle(STD_ERROR_HANDLE), &mut mode) != 0 }
+}
+
+#[cfg(not(windows))]
+fn stderr_is_console() -> bool {
+    false
+}
+
+/// Name of the file (in the configuration directory) overriding the built in
+/// messages, e.g. to translate them.
+pub const MESSAGES_FILE_NAME: &str = "messages.yaml";
+
+/// The built in user facing messages by id. `{name}` placeholders are
+/// replaced with the arguments of `message`.
+#[rustfmt::skip]
+const DEFAULT_MESSAGES: &[(&str, &str)] = &[
+    ("error.no_tool", "No tool configured for the command: {command}"),
+    ("error.tool_disabled", "The tool '{tool}' is disabled (see [tools.{tool}] in {config_file})"),
+    ("error.no_version", "No version configured for {tool}"),
+    ("error.no_version_for_command", "You don't have a version configured for '{command}' ({tool})"),
+    ("error.no_version_not_in_path", "You don't have a version configured for '{command}' ({tool}) and it's not found in PATH"),
+    ("error.not_installed", "Version '{version}' of '{tool}' does not seems to be installed"),
+    ("error.not_installed_among", "Version '{version}' of '{tool}' is not installed ({installed})"),
+    ("error.no_versions_installed", "no versions are installed"),
+    ("error.installed_versions", "installed: {versions}"),
+    ("error.configured_not_installed", "Version '{version}' of '{tool}' configured but not installed"),
+    ("error.not_provided", "Version '{version}' of '{tool}' does not provide '{command}'"),
+    ("error.no_longer_provided", "'{command}' is no longer provided by any installed version of '{tool}'. Run `asdfw doctor --fix` to remove stale shims"),
+    ("error.manual_shim_missing", "'{command}' is registered to {path} which no longer exists. Run `asdfw shim rm {command}` to remove it"),
+    ("error.skipped_not_in_path", "'{command}' ({tool}) is listed in {variable} but it's not found in PATH"),
+    ("error.shim_loop", "'{command}' was invoked through {repeats} nested shims of itself, aborting what looks like an endless loop"),
+    ("error.invalid_tool_versions_line", "Invalid tools versions line: {line}"),
+    ("error.interrupted", "Interrupted by the user"),
+    ("error.timed_out", "Did not finish within {seconds} second(s), killed it along with the processes it started"),
+    ("hint.no_tool", "Install the tool providing the command and run `asdfw reshim`"),
+    ("hint.no_version", "Run `asdfw local <tool> <version>` or `asdfw global <tool> <version>`"),
+    ("hint.not_installed", "Run `asdfw install <tool> <version> --from-file <file>`"),
+    ("hint.invalid_tool_versions", "Each line should be `<tool> <version>` (`#` starts a comment)"),
+    ("hint.shim_loop", "Make sure no installed executable runs its own shim"),
+    ("hint.policy_violation", "Configure one of the allowed versions (`asdfw why <tool>`)"),
+    ("hint.vulnerable_versions", "Upgrade the affected versions and uninstall the old ones"),
+    ("hint.check_failed", "Install the missing versions and run `asdfw reshim` to update the shims"),
+    ("hint.timed_out", "Increase the timeout (`--timeout` or `timeout` in the tool's configuration)"),
+    ("hint.configure_version", "Run `asdfw local {tool} <version>` (or `asdfw global {tool} <version>`) to configure a version"),
+    ("hint.install_version", "Run `asdfw install {tool} {version} --from-file <file>` to install it"),
+    ("hint.path_version_missing", "Make sure {dir} exists (with the executables in its `bin` directory)"),
+    ("hint.enable_tool", "Run `asdfw config set tools.{tool}.disabled false` to enable it"),
+    ("hint.remove_from_skip", "Remove '{tool}' from {variable} to use the configured version"),
+    ("hint.doctor.reshim", "run `asdfw reshim` to rebuild it"),
+    ("hint.doctor.doctor_fix", "run `asdfw doctor --fix` or `asdfw reshim --cleanup` to remove it"),
+    ("hint.doctor.reorder_path", "move the shims directory before it in PATH or remove it"),
+    ("hint.doctor.remove_app_paths_entry", "remove the command's key under `App Paths` in the registry"),
+    ("hint.doctor.fix_resolution", "fix the recorded reason, the record is cleared once the command runs"),
+    ("warning.shims_not_updated", "Shims were not updated, run `asdfw reshim` once done."),
+    ("warning.shims_update_failed", "Shims were not updated ({error}), run `asdfw reshim` to retry."),
+    ("warning.manifest_not_recorded", "Could not record manifest of {tool} {version}: {error}"),
+    ("warning.current_link_not_updated", "Could not update current link of {tool}: {error}"),
+    ("warning.env_file_not_allowed", "Ignoring {file}, it's new or changed since allowed (run `asdfw allow` to load it)"),
+    ("warning.tool_versions_invalid_line", "Skipping invalid line in {file}: {error}"),
+    ("warning.env_file_invalid_line", "Skipping line {line} of {file}: expected KEY=VALUE, got '{text}'"),
+    ("success.reshim_finished", "Reshim finished successfully."),
+    ("success.no_problems", "No problems found."),
+    ("success.fixed", "Fixed: {problem}"),
+    ("success.installed", "Installed version {version} of {tool}"),
+    ("success.reinstalled", "Reinstalled version {version} of {tool}"),
+    ("success.uninstalled", "Uninstalled version {version} of {tool}"),
+    ("success.global_configured", "Successfully configured global version ({version}) for {tool}"),
+    ("success.local_configured", "Successfully configured local version ({version}) for {tool}"),
+    ("success.version_removed", "Successfully removed {scope} version of {tool}"),
+];
+
+/// The messages loaded from `messages.yaml` (if any), used by `message`.
+static MESSAGES: RwLock<Option<Messages>> = RwLock::new(None);
+/// The messages file set by `set_messages_file`, until it is loaded.
+static MESSAGES_FILE: Mutex<Option<PathBuf>> = Mutex::new(None);
+
+/// User facing messages: the built in ones with the overrides (by id) applied.
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct Messages {
+    overrides: HashMap<String, String>,
+}
+
+impl Messages {
+    /// Parses the overrides (a YAML map of message ids to messages). Entries
+    /// with unknown ids (or that are not text) are skipped, and contents that
+    /// are not a map override nothing. Returns the problems along with the
+    /// messages, so typos don't go unnoticed.
+    pub fn parse(contents: &str) -> (Self, Vec<String>) {
+        if contents.trim().is_empty() {
+            return (Messages::default(), vec![]);
+        }
+        let entries: HashMap<String, serde_yaml::Value> = match serde_yaml::from_str(contents) {
+            Ok(entries) => entries,
+            Err(err) => return (Messages::default(), vec![format!("not a map of message ids to messages: {}", err)]),
+        };
+        let mut overrides = HashMap::new();
+        let mut warnings = vec![];
+        for (id, value) in entries {
+            match value {
+                _ if default_message(&id).is_none() => warnings.push(format!("unknown message id '{}'", id)),
+                serde_yaml::Value::String(text) => {
+                    overrides.insert(id, text);
+                }
+                _ => warnings.push(format!("the message '{}' is not text", id)),
+            }
+        }
+        warnings.sort();
+        (Messages { overrides }, warnings)
+    }
+
+    /// Renders the message, replacing its `{name}` placeholders with the
+    /// arguments.
+    pub fn get(&self, id: &str, args: &[(&str, &dyn Display)]) -> String {
+        let template = match self.overrides.get(id) {
+            Some(template) => template.as_str(),
+            None => default_message(id).unwrap_or(id),
+        };
+        args.iter().fold(template.to_owned(), |text, (name, value)| {
+            text.replace(&format!("{{{}}}", name), &value.to_string())
+        })
+    }
+
+    /// All the messages (by id) as they are displayed.
+    pub fn all(&self) -> BTreeMap<&'static str, String> {
+        DEFAULT_MESSAGES
+            .iter()
+            .map(|(id, default)| (*id, self.overrides.get(*id).map_or(*default, String::as_str).to_owned()))
+            .collect()
+    }
+}
+
+fn default_message(id: &str) -> Option<&'static str> {
+    DEFAULT_MESSAGES.iter().find(|(i, _)| *i == id).map(|(_, m)| *m)
+}
+
+/// Loads the messages overriding the built in ones for the rest of the
+/// process. A missing file overrides nothing and problems with the file are
+/// printed as warnings (the built in messages are used instead).
+pub fn load_messages(path: &Path) {
+    if !path.exists() {
+        return;
+    }
+    let contents = match fs::read_to_string(path) {
+        Ok(contents) => contents,
+        Err(err) => return print_warning(&format!("Ignoring messages in {:?}: {}", &path, err)),
+    };
+    let (messages, warnings) = Messages::parse(&contents);
+    for warning in warnings {
+        print_warning(&format!("Ignoring messages in {:?}: {}", &path, warning));
+    }
+    *MESSAGES.write().unwrap_or_else(|e| e.into_inner()) = Some(messages);
+}
+
+/// Like `load_messages`, but the file is only read when the first message is
+/// rendered, so a shim that has nothing to say never parses it.
+pub fn set_messages_file(path: &Path) {
+    *MESSAGES_FILE.lock().unwrap_or_else(|e| e.into_inner()) = Some(path.to_path_buf());
+}
+
+fn load_pending_messages() {
+    let path = MESSAGES_FILE.lock().unwrap_or_else(|e| e.into_inner()).take();
+    if let Some(path) = path {
+        load_messages(&path);
+    }
+}
+
+/// Renders the user facing message by id (see `Messages::get`), using the
+/// overrides loaded by `load_messages` (or `set_messages_file`).
+pub fn message(id: &str, args: &[(&str, &dyn Display)]) -> String {
+    load_pending_messages();
+    let messages = MESSAGES.read().unwrap_or_else(|e| e.into_inner());
+    match messages.as_ref() {
+        Some(messages) => messages.get(id, args),
+        None => Messages::default().get(id, args),
+    }
+}
+
+/// All the messages as they are displayed (see `Messages::all`).
+pub fn all_messages() -> BTreeMap<&'static str, String> {
+    load_pending_messages();
+    let messages = MESSAGES.read().unwrap_or_else(|e| e.into_inner());
+    match messages.as_ref() {
+        Some(messages) => messages.all(),
+        None => Messages::default().all(),
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::errors::ErrorKind;
     use anyhow::{anyhow, Context, Result};
 
     #[test]
@@ -69,6 +512,104 @@ mod tests {
         assert_eq!(result, expected)
     }
 
+    fn sample_table() -> Table {
+        let mut table = Table::new(vec!["Tool", "Version", "Directory"]);
+        table.add_row(vec![
+            "kubectl".to_owned(),
+            "1.23.0".to_owned(),
+            "C:\\asdfw\\installs\\kubectl".to_owned(),
+        ]);
+        table.add_row(vec!["go".to_owned(), "1.17".to_owned(), "".to_owned()]);
+        table
+    }
+
+    #[test]
+    fn table_aligns_columns() {
+        let expected = [
+            "Tool     Version  Directory",
+            "kubectl  1.23.0   C:\\asdfw\\installs\\kubectl",
+            "go       1.17",
+        ];
+        assert_eq!(sample_table().render(Some(80)), expected);
+    }
+
+    #[test]
+    fn table_truncates_widest_column_to_fit_width() {
+        let expected = [
+            "Tool     Version  Directory",
+            "kubectl  1.23.0   C:\\asdfw\\instal…",
+            "go       1.17",
+        ];
+        assert_eq!(sample_table().render(Some(34)), expected);
+    }
+
+    #[test]
+    fn table_truncates_wide_characters_by_display_width() {
+        let mut table = Table::new(vec!["Tool", "Description"]);
+        table.add_row(vec!["go".to_owned(), "编程语言工具链".to_owned()]);
+        let rendered = table.render(Some(16));
+        assert_eq!(rendered, ["Tool  Descripti…", "go    编程语言…"]);
+        assert!(rendered.iter().all(|line| display_width(line) <= 16));
+    }
+
+    #[test]
+    fn tree_renders_nested_nodes() {
+        let mut kubectl = TreeNode::new("kubectl");
+        let mut version = TreeNode::new("1.23.0");
+        version.add_child(TreeNode::new("kubectl.exe"));
+        kubectl.add_child(version);
+        kubectl.add_child(TreeNode::new("1.24.1"));
+        let mut root = TreeNode::new("tools");
+        root.add_child(kubectl);
+        root.add_child(TreeNode::new("go"));
+        let expected = [
+            "tools",
+            "├── kubectl",
+            "│   ├── 1.23.0",
+            "│   │   └── kubectl.exe",
+            "│   └── 1.24.1",
+            "└── go",
+        ];
+        assert_eq!(root.render(), expected);
+    }
+
+    #[test]
+    fn progress_line_shows_bar_and_eta() {
+        let mut progress = Progress::new("Extracting tool.zip", Some(8));
+        for _ in 0..2 {
+            progress.inc();
+        }
+        let line = progress.line(60, Duration::from_secs(3));
+        assert_eq!(line.trim_end(), "Extracting tool.zip [######                  ] 2/8 ETA 9s");
+        assert_eq!(line.len(), 59);
+        assert_eq!(progress.line(20, Duration::from_secs(3)), "Extracting tool.zi…");
+    }
+
+    #[test]
+    fn progress_line_without_total_shows_spinner() {
+        let mut progress = Progress::new("Creating shims", None);
+        progress.inc();
+        assert_eq!(progress.line(40, Duration::from_secs(1)).trim_end(), "Creating shims / 1");
+    }
+
+    #[test]
+    fn test_output_full_error_with_hint() {
+        let expected = [
+            " \u{1b}[31m\u{1b}[0m  E_NO_VERSION: No version configured for",
+            "    kubectl",
+            "",
+            " Hint:",
+            "   Run `asdfw local kubectl <version>` or `asdfw",
+            "   global kubectl <version>`",
+        ];
+        let err = ErrorKind::NoVersion.error_with_hint(
+            "No version configured for kubectl",
+            "Run `asdfw local kubectl <version>` or `asdfw global kubectl <version>`",
+        );
+        let result = output_full_error(err, Some(50));
+        assert_eq!(result, expected)
+    }
+
     #[test]
     fn test_output_full_error_with_simple_error() {
         let expected = [
@@ -79,4 +620,38 @@ mod tests {
         let result = output_full_error(err, Some(50));
         assert_eq!(result, expected)
     }
+
+    #[test]
+    fn messages_apply_overrides_and_arguments() {
+        let (messages, warnings) = Messages::parse("error.no_version: \"Keine Version für {tool} konfiguriert\"\n");
+        assert!(warnings.is_empty());
+        let tool = "kubectl";
+        assert_eq!(
+            messages.get("error.no_version", &[("tool", &tool)]),
+            "Keine Version für kubectl konfiguriert"
+        );
+        assert_eq!(
+            messages.get("success.installed", &[("tool", &tool), ("version", &"1.22")]),
+            "Installed version 1.22 of kubectl"
+        );
+        assert_eq!(messages.all()["error.no_version"], "Keine Version für {tool} konfiguriert");
+        assert_eq!(Messages::parse(""), (Messages::default(), vec![]));
+    }
+
+    #[test]
+    fn messages_skip_invalid_entries() {
+        let contents =
+            "error.no_such_message: oops\nerror.no_version: [1, 2]\nerror.no_tool: \"Kein Tool für {command}\"\n";
+        let (messages, warnings) = Messages::parse(contents);
+        assert_eq!(warnings.len(), 2, "wrong warnings: {:?}", warnings);
+        assert!(warnings[1].contains("error.no_such_message"), "wrong warnings: {:?}", warnings);
+        assert_eq!(messages.get("error.no_tool", &[("command", &"kubectl")]), "Kein Tool für kubectl");
+        assert_eq!(
+            messages.get("error.no_version", &[("tool", &"kubectl")]),
+            "No version configured for kubectl"
+        );
+        let (messages, warnings) = Messages::parse("- not a map\n");
+        assert_eq!(messages, Messages::default());
+        assert_eq!(warnings.len(), 1);
+    }
 }
@@ -0,0 +1,199 @@
+use anyhow::{Context, Result};
+use log::debug;
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::errors::ErrorKind;
+use crate::shims::PATH_VERSION_PREFIX;
+use crate::version::VersionRange;
+
+pub const POLICY_FILE_NAME: &str = "policy.toml";
+
+/// Administrator provided restrictions on the versions of tools that could be
+/// installed, configured and executed. Loaded from `policy.toml` in the
+/// machine directory (see `machine_policy_file`).
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+pub struct Policy {
+    /// Per tool policy (`[tools.<name>]` tables).
+    pub tools: HashMap<String, ToolPolicy>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+pub struct ToolPolicy {
+    /// If not empty, versions must match at least one of these ranges.
+    pub allowed: Vec<String>,
+    /// Versions matching any of these ranges are forbidden.
+    pub forbidden: Vec<String>,
+    /// Shown along with violations (e.g. where to ask for exceptions).
+    pub message: Option<String>,
+}
+
+/// The policy file: `%ProgramData%\asdfw\policy.toml` (looked up with the
+/// shell rather than the environment). Unlike the system directory, it can't
+/// be changed by the user's configuration or environment, so users can't
+/// replace or drop the policy.
+pub fn machine_policy_file() -> Option<PathBuf> {
+    machine_dir().map(|dir| dir.join(POLICY_FILE_NAME))
+}
+
+/// The machine directory (`%ProgramData%\asdfw`) holding the policy and, unless
+/// a system directory is configured, the organization-wide `tool-versions`.
+pub fn machine_dir() -> Option<PathBuf> {
+    imp::program_data_dir().map(|dir| dir.join("asdfw"))
+}
+
+impl Policy {
+    pub fn load(path: &Path) -> Result<Self> {
+        if !path.exists() {
+            debug!("Policy file {:?} does not exist, no restrictions", &path);
+            return Ok(Policy::default());
+        }
+        let context = format!("loading policy from {:?}", &path);
+        let contents = fs::read_to_string(path).context(context.clone())?;
+        let policy: Policy = toml::from_str(&contents).context(context.clone())?;
+        policy.validate().context(context)?;
+        Ok(policy)
+    }
+
+    fn validate(&self) -> Result<()> {
+        for (tool, policy) in &self.tools {
+            for range in policy.allowed.iter().chain(policy.forbidden.iter()) {
+                VersionRange::parse(range).context(format!("policy of {}", &tool))?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Fails with a policy violation if the version of the tool is not
+    /// allowed. `path:` versions of tools with a policy are never allowed, as
+    /// the version in the directory they point at is unknown.
+    pub fn check(&self, tool: &str, version: &str) -> Result<()> {
+        let policy = match self.tools.get(tool) {
+            Some(policy) => policy,
+            None => return Ok(()),
+        };
+        let violation = if version.starts_with(PATH_VERSION_PREFIX) {
+            format!("{} {} is forbidden by policy (path versions)", &tool, &version)
+        } else {
+            match forbidding_range(policy, version)? {
+                Some(range) => format!("{} {} is forbidden by policy ({})", &tool, &version, &range),
+                None => return Ok(()),
+            }
+        };
+        let message = match &policy.message {
+            Some(message) => format!("{}. {}", &violation, message),
+            None => violation,
+        };
+        Err(ErrorKind::PolicyViolation.error(message))
+    }
+}
+
+/// Returns the range the version violates (for versions not matching any of
+/// the allowed ranges, all the allowed ranges).
+fn forbidding_range(policy: &ToolPolicy, version: &str) -> Result<Option<String>> {
+    for range in &policy.forbidden {
+        if VersionRange::parse(range)?.matches(version) {
+            return Ok(Some(range.clone()));
+        }
+    }
+    if policy.allowed.is_empty() {
+        return Ok(None);
+    }
+    for range in &policy.allowed {
+        if VersionRange::parse(range)?.matches(version) {
+            return Ok(None);
+        }
+    }
+    Ok(Some(format!("allowed: {}", policy.allowed.join(" | "))))
+}
+
+#[cfg(windows)]
+mod imp {
+    use std::ffi::OsString;
+    use std::os::windows::ffi::OsStringExt;
+    use std::path::PathBuf;
+    use std::ptr;
+    use winapi::shared::winerror::S_OK;
+    use winapi::um::combaseapi::CoTaskMemFree;
+    use winapi::um::knownfolders::FOLDERID_ProgramData;
+    use winapi::um::shlobj::SHGetKnownFolderPath;
+
+    pub(super) fn program_data_dir() -> Option<PathBuf> {
+        unsafe {
+            let mut path = ptr::null_mut();
+            let result = SHGetKnownFolderPath(&FOLDERID_ProgramData, 0, ptr::null_mut(), &mut path);
+            if result != S_OK {
+                CoTaskMemFree(path as *mut _);
+                return None;
+            }
+            let len = (0..).take_while(|&i| *path.offset(i) != 0).count();
+            let dir = OsString::from_wide(std::slice::from_raw_parts(path, len));
+            CoTaskMemFree(path as *mut _);
+            Some(PathBuf::from(dir))
+        }
+    }
+}
+
+#[cfg(not(windows))]
+mod imp {
+    use std::path::PathBuf;
+
+    pub(super) fn program_data_dir() -> Option<PathBuf> {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::errors::error_kind;
+    use assert_fs::{prelude::*, TempDir};
+    use rstest::rstest;
+
+    const POLICY: &str = "[tools.terraform]\nforbidden = ['< 1.6']\n\n\
+                          [tools.kubectl]\nallowed = ['>= 1.25, < 1.27', '1.22.17']\nforbidden = ['1.26.0']\n\
+                          message = 'Contact #devtools for exceptions'\n";
+
+    #[rstest]
+    #[case("terraform", "1.5.7", false)]
+    #[case("terraform", "1.6.0", true)]
+    #[case("kubectl", "1.25.3", true)]
+    #[case("kubectl", "1.22.17", true)]
+    #[case("kubectl", "1.26.0", false)]
+    #[case("kubectl", "1.27.1", false)]
+    #[case("helm", "2.0.0", true)]
+    #[case("terraform", "path:C:\\src\\terraform", false)]
+    #[case("helm", "path:C:\\src\\helm", true)]
+    fn check_versions_against_policy(#[case] tool: &str, #[case] version: &str, #[case] allowed: bool) {
+        let policy: Policy = toml::from_str(POLICY).unwrap();
+        match policy.check(tool, version) {
+            Ok(()) => assert!(allowed, "{} {} should be forbidden", tool, version),
+            Err(err) => {
+                assert!(!allowed, "{} {} should be allowed: {}", tool, version, err);
+                assert_eq!(error_kind(&err), Some(ErrorKind::PolicyViolation));
+            }
+        }
+    }
+
+    #[test]
+    fn violation_includes_policy_message() {
+        let policy: Policy = toml::from_str(POLICY).unwrap();
+        let err = policy.check("kubectl", "1.27.1").unwrap_err();
+        let msg = format!("{}", err);
+        assert!(msg.contains("allowed: >= 1.25, < 1.27 | 1.22.17"), "wrong message: {}", msg);
+        assert!(msg.ends_with("Contact #devtools for exceptions"), "wrong message: {}", msg);
+    }
+
+    #[test]
+    fn load_rejects_invalid_ranges() {
+        let tmp_dir = TempDir::new().unwrap();
+        let file = tmp_dir.child(POLICY_FILE_NAME);
+        file.write_str("[tools.terraform]\nforbidden = ['<']\n").unwrap();
+        assert!(Policy::load(file.path()).is_err(), "invalid range should fail loading");
+        assert!(Policy::load(tmp_dir.child("missing.toml").path()).unwrap().tools.is_empty());
+    }
+}
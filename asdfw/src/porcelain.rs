@@ -0,0 +1,100 @@
+use anyhow::{anyhow, Error, Result};
+use std::path::Path;
+use std::str::FromStr;
+
+use crate::common::ConfiguredTool;
+use crate::shims::InstalledVersion;
+
+/// Versions of the `--porcelain` line format. Once released, the format of a
+/// version never changes, changes are introduced as a new version.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Porcelain {
+    V1,
+}
+
+impl FromStr for Porcelain {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "v1" | "1" => Ok(Porcelain::V1),
+            _ => Err(anyhow!("unsupported porcelain version '{}' (supported: v1)", s)),
+        }
+    }
+}
+
+/// The line of a configured tool (`current`):
+/// `<tool>\t<version>\t<installed|missing>\t<version dir>`.
+pub fn current_line(porcelain: Porcelain, configured: &ConfiguredTool) -> String {
+    match porcelain {
+        Porcelain::V1 => {
+            let (status, dir) = match &configured.version_dir {
+                Some(dir) => ("installed", dir.to_string_lossy()),
+                None => ("missing", "".into()),
+            };
+            line(&[&configured.tool, &configured.version, status, &dir])
+        }
+    }
+}
+
+/// The line of an installed version (`list`): `<tool>\t<version>\t<markers>`
+/// with the markers (`current`, `unreferenced`, `disabled`) comma separated.
+pub fn list_line(porcelain: Porcelain, installed: &InstalledVersion, markers: &[&str]) -> String {
+    match porcelain {
+        Porcelain::V1 => line(&[&installed.tool, &installed.version, &markers.join(",")]),
+    }
+}
+
+/// The line of a resolved command (`which`): `<tool>\t<version>\t<path>`.
+pub fn which_line(porcelain: Porcelain, tool: &str, version: &str, path: &Path) -> String {
+    match porcelain {
+        Porcelain::V1 => line(&[tool, version, &path.to_string_lossy()]),
+    }
+}
+
+/// Fields are tab separated (tabs are not allowed in Windows paths, tool
+/// names or versions).
+fn line(fields: &[&str]) -> String {
+    fields.join("\t")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+
+    #[test]
+    fn v1_lines() {
+        let dir = PathBuf::from("installs").join("kubectl").join("1.22");
+        let configured = ConfiguredTool {
+            tool: "kubectl".to_owned(),
+            version: "1.22".to_owned(),
+            version_dir: Some(dir.clone()),
+        };
+        let expected = format!("kubectl\t1.22\tinstalled\t{}", dir.display());
+        assert_eq!(current_line(Porcelain::V1, &configured), expected);
+        let missing = ConfiguredTool {
+            version_dir: None,
+            ..configured
+        };
+        assert_eq!(current_line(Porcelain::V1, &missing), "kubectl\t1.22\tmissing\t");
+        let installed = InstalledVersion {
+            tool: "kubectl".to_owned(),
+            version: "1.22".to_owned(),
+            dir: dir.clone(),
+            system: false,
+        };
+        let markers = ["current", "disabled"];
+        assert_eq!(list_line(Porcelain::V1, &installed, &markers), "kubectl\t1.22\tcurrent,disabled");
+        assert_eq!(list_line(Porcelain::V1, &installed, &[]), "kubectl\t1.22\t");
+        let path = dir.join("bin").join("kubectl.exe");
+        let expected = format!("kubectl\t1.22\t{}", path.display());
+        assert_eq!(which_line(Porcelain::V1, "kubectl", "1.22", &path), expected);
+    }
+
+    #[test]
+    fn parse_porcelain_version() {
+        assert_eq!("v1".parse::<Porcelain>().unwrap(), Porcelain::V1);
+        assert!("v2".parse::<Porcelain>().is_err());
+    }
+}
@@ -0,0 +1,200 @@
+use anyhow::{anyhow, Context, Result};
+use log::debug;
+use std::collections::BTreeMap;
+use std::fmt::Display;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::manifest::hash_file;
+use crate::output::message;
+
+pub const ENV_FILE_NAME: &str = ".asdfw.env";
+
+/// Returns the `.asdfw.env` file closest to the provided directory (searching
+/// its parents like `.tool-versions` files).
+pub fn find_env_file(dir: &Path) -> Option<PathBuf> {
+    dir.ancestors().map(|d| d.join(ENV_FILE_NAME)).find(|path| path.is_file())
+}
+
+/// The variables of a project along with the problems found loading them
+/// (the offending lines, or the whole file, are skipped).
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct ProjectEnv {
+    pub vars: Vec<(String, String)>,
+    pub warnings: Vec<String>,
+}
+
+/// Loads the variables of the project the directory belongs to (empty if
+/// there's no `.asdfw.env` file). A file the user did not allow (see
+/// `is_allowed`) is not loaded.
+pub fn project_env(dir: &Path, allowed_env_file: &Path) -> Result<ProjectEnv> {
+    match find_env_file(dir) {
+        Some(path) if !is_allowed(allowed_env_file, &path)? => Ok(ProjectEnv {
+            vars: vec![],
+            warnings: vec![message(
+                "warning.env_file_not_allowed",
+                &[("file", &format!("{:?}", &path))],
+            )],
+        }),
+        Some(path) => {
+            debug!("Loading project environment from {:?}", &path);
+            load(&path)
+        }
+        None => Ok(ProjectEnv::default()),
+    }
+}
+
+/// Parses `KEY=VALUE` lines. Blank lines and lines starting with `#` are
+/// ignored and values could be wrapped in single or double quotes. Other lines
+/// are skipped with a warning.
+pub fn load(path: &Path) -> Result<ProjectEnv> {
+    let contents = fs::read_to_string(path).context(format!("loading project environment from {:?}", &path))?;
+    let mut env = ProjectEnv::default();
+    for (i, line) in contents.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        match line.split_once('=').filter(|(key, _)| !key.trim().is_empty()) {
+            Some((key, value)) => env.vars.push((key.trim().to_owned(), unquote(value.trim()).to_owned())),
+            None => {
+                let args: [(&str, &dyn Display); 3] =
+                    [("line", &(i + 1)), ("file", &format!("{:?}", &path)), ("text", &line)];
+                env.warnings.push(message("warning.env_file_invalid_line", &args));
+            }
+        }
+    }
+    Ok(env)
+}
+
+/// Whether the user allowed (`asdfw allow`) the file as it is now. The allowed
+/// files are kept in the trust file along with their sha256 hash (in
+/// `sha256sum` format), so any change to a file has to be allowed again.
+pub fn is_allowed(trust_file: &Path, env_file: &Path) -> Result<bool> {
+    let allowed = load_allowed(trust_file)?;
+    match allowed.get(&trusted_name(env_file)) {
+        Some(hash) => Ok(hash == &hash_file(env_file)?),
+        None => Ok(false),
+    }
+}
+
+/// Allows loading the file (as it is now).
+pub fn allow(trust_file: &Path, env_file: &Path) -> Result<()> {
+    let mut allowed = load_allowed(trust_file)?;
+    allowed.insert(trusted_name(env_file), hash_file(env_file)?);
+    if let Some(parent) = trust_file.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    let contents: String = allowed.iter().map(|(file, hash)| format!("{}  {}\n", hash, file)).collect();
+    fs::write(trust_file, contents).context(format!("saving allowed environment files to {:?}", &trust_file))
+}
+
+fn load_allowed(trust_file: &Path) -> Result<BTreeMap<String, String>> {
+    if !trust_file.exists() {
+        return Ok(BTreeMap::new());
+    }
+    let context = || format!("loading allowed environment files from {:?}", &trust_file);
+    let contents = fs::read_to_string(trust_file).with_context(context)?;
+    let mut allowed = BTreeMap::new();
+    for line in contents.lines().filter(|l| !l.trim().is_empty()) {
+        let (hash, file) = line
+            .split_once("  ")
+            .ok_or(anyhow!("invalid line: '{}'", &line))
+            .with_context(context)?;
+        allowed.insert(file.to_owned(), hash.to_owned());
+    }
+    Ok(allowed)
+}
+
+fn trusted_name(env_file: &Path) -> String {
+    let path = fs::canonicalize(env_file).unwrap_or_else(|_| env_file.to_path_buf());
+    path.to_string_lossy().into_owned()
+}
+
+fn unquote(value: &str) -> &str {
+    for quote in ['"', '\''] {
+        if value.len() >= 2 && value.starts_with(quote) && value.ends_with(quote) {
+            return &value[1..value.len() - 1];
+        }
+    }
+    value
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use assert_fs::{prelude::*, TempDir};
+
+    #[test]
+    fn project_env_loads_closest_file() {
+        let tmp_dir = TempDir::new().unwrap();
+        let trust_file = tmp_dir.child("allowed-env.sha256");
+        tmp_dir.child(ENV_FILE_NAME).write_str("OUTER=1\n").unwrap();
+        let project = tmp_dir.child("project");
+        let content = "# build settings\nNODE_OPTIONS = \"--max-old-space-size=4096\"\n\nGREETING='a=b'\nEMPTY=\n";
+        project.child(ENV_FILE_NAME).write_str(content).unwrap();
+        project.child("src").create_dir_all().unwrap();
+        allow(trust_file.path(), tmp_dir.child(ENV_FILE_NAME).path()).unwrap();
+        allow(trust_file.path(), project.child(ENV_FILE_NAME).path()).unwrap();
+        let expected = vec![
+            ("NODE_OPTIONS".to_owned(), "--max-old-space-size=4096".to_owned()),
+            ("GREETING".to_owned(), "a=b".to_owned()),
+            ("EMPTY".to_owned(), "".to_owned()),
+        ];
+        assert_eq!(project_env(project.child("src").path(), trust_file.path()).unwrap().vars, expected);
+        let outer = project_env(tmp_dir.path(), trust_file.path()).unwrap();
+        assert_eq!(outer.vars, vec![("OUTER".to_owned(), "1".to_owned())]);
+    }
+
+    #[test]
+    fn project_env_without_file_is_empty() {
+        let tmp_dir = TempDir::new().unwrap();
+        let trust_file = tmp_dir.child("allowed-env.sha256");
+        assert_eq!(project_env(tmp_dir.path(), trust_file.path()).unwrap(), ProjectEnv::default());
+    }
+
+    #[test]
+    fn project_env_ignores_files_not_allowed() {
+        let tmp_dir = TempDir::new().unwrap();
+        let trust_file = tmp_dir.child("allowed-env.sha256");
+        tmp_dir.child(ENV_FILE_NAME).write_str("PATH=C:\\evil\n").unwrap();
+        let env = project_env(tmp_dir.path(), trust_file.path()).unwrap();
+        assert!(env.vars.is_empty());
+        assert!(env.warnings[0].contains("asdfw allow"), "wrong warning: {:?}", env.warnings);
+    }
+
+    #[test]
+    fn load_skips_lines_without_key() {
+        let tmp_dir = TempDir::new().unwrap();
+        let file = tmp_dir.child(ENV_FILE_NAME);
+        file.write_str("GOOD=1\n=value\nexport BAD\nALSO_GOOD=2\n").unwrap();
+        let env = load(file.path()).unwrap();
+        assert_eq!(
+            env.vars,
+            vec![
+                ("GOOD".to_owned(), "1".to_owned()),
+                ("ALSO_GOOD".to_owned(), "2".to_owned())
+            ]
+        );
+        assert_eq!(env.warnings.len(), 2);
+        assert!(env.warnings[0].contains("line 2"), "wrong warning: {:?}", env.warnings);
+    }
+
+    #[test]
+    fn files_are_allowed_until_changed() {
+        let tmp_dir = TempDir::new().unwrap();
+        let trust_file = tmp_dir.child("allowed-env.sha256");
+        let first = tmp_dir.child("first").child(ENV_FILE_NAME);
+        let second = tmp_dir.child("second").child(ENV_FILE_NAME);
+        first.write_str("GREETING=hello\n").unwrap();
+        second.write_str("GREETING=hi\n").unwrap();
+        assert!(!is_allowed(trust_file.path(), first.path()).unwrap());
+        allow(trust_file.path(), first.path()).unwrap();
+        allow(trust_file.path(), second.path()).unwrap();
+        assert!(is_allowed(trust_file.path(), first.path()).unwrap());
+        assert!(is_allowed(trust_file.path(), second.path()).unwrap());
+        first.write_str("GREETING=hello\nPATH=C:\\evil\n").unwrap();
+        assert!(!is_allowed(trust_file.path(), first.path()).unwrap());
+        assert!(is_allowed(trust_file.path(), second.path()).unwrap());
+    }
+}
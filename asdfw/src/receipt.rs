@@ -0,0 +1,75 @@
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::manifest::hash_file;
+use crate::stats;
+
+pub const RECEIPT_FILE_NAME: &str = "receipt.json";
+
+/// Details of how a version was installed by `asdfw install` (or
+/// `reinstall`). Saved as `receipt.json` in the version directory.
+#[derive(Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Receipt {
+    pub tool: String,
+    pub version: String,
+    /// The file the version was installed from.
+    pub source: PathBuf,
+    /// The sha256 hash of the source file.
+    pub checksum: String,
+    /// Seconds since the epoch.
+    pub installed_at: u64,
+    /// The version of asdfw that installed the version.
+    pub asdfw_version: String,
+}
+
+/// Returns the location of the receipt of the provided version directory.
+pub fn receipt_path(version_dir: &Path) -> PathBuf {
+    version_dir.join(RECEIPT_FILE_NAME)
+}
+
+impl Receipt {
+    /// Creates a receipt of installing the version from the file now.
+    pub fn new(tool: &str, version: &str, source: &Path) -> Result<Self> {
+        Ok(Receipt {
+            tool: tool.to_owned(),
+            version: version.to_owned(),
+            source: source.to_path_buf(),
+            checksum: hash_file(source)?,
+            installed_at: stats::now(),
+            asdfw_version: env!("CARGO_PKG_VERSION").to_owned(),
+        })
+    }
+
+    pub fn load(path: &Path) -> Result<Self> {
+        let context = format!("loading receipt {:?}", &path);
+        let contents = fs::read_to_string(path).context(context.clone())?;
+        serde_json::from_str(&contents).context(context)
+    }
+
+    pub fn save(&self, path: &Path) -> Result<()> {
+        let context = format!("saving receipt {:?}", &path);
+        let contents = serde_json::to_string_pretty(self).context(context.clone())?;
+        fs::write(path, contents).context(context)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use assert_fs::{prelude::*, TempDir};
+
+    #[test]
+    fn save_and_load_roundtrip() {
+        let tmp_dir = TempDir::new().unwrap();
+        let source = tmp_dir.child("kubectl.exe");
+        source.write_str("binary").unwrap();
+        let receipt = Receipt::new("kubectl", "1.23.0", source.path()).unwrap();
+        assert_eq!(receipt.checksum, "9a3a45d01531a20e89ac6ae10b0b0beb0492acd7216a368aa062d1a5fecaf9cd");
+        let path = receipt_path(tmp_dir.child("1.23.0").path());
+        tmp_dir.child("1.23.0").create_dir_all().unwrap();
+        receipt.save(&path).unwrap();
+        assert_eq!(Receipt::load(&path).unwrap(), receipt);
+    }
+}
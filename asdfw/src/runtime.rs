@@ -1,5 +1,29 @@
+use crate::config::Config;
+use crate::output::{set_messages_file, MESSAGES_FILE_NAME};
+use crate::policy::{machine_dir, machine_policy_file, Policy};
+use crate::tool_versions::SYSTEM_FILE_NAME;
 use anyhow::{anyhow, Result};
-use std::path::PathBuf;
+use log::debug;
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
+
+const HOOKS_DIR_VARIABLE: &str = "ASDFW_HOOKS_DIR";
+const SYSTEM_DIR_VARIABLE: &str = "ASDFW_SYSTEM_DIR";
+const CONFIG_DIR_VARIABLE: &str = "ASDFW_CONFIG_DIR";
+const DATA_DIR_VARIABLE: &str = "ASDFW_DATA_DIR";
+const CACHE_DIR_VARIABLE: &str = "ASDFW_CACHE_DIR";
+const PORTABLE_MARKER: &str = "portable.marker";
+const CONFIG_FILE_NAME: &str = "config.toml";
+
+/// Returns the location of the configuration file without loading anything
+/// (so a broken configuration could still be fixed using `asdfw config`).
+pub fn config_file() -> Result<PathBuf> {
+    let home_dir = dirs::home_dir().ok_or(anyhow!("Could not get home directory"))?;
+    let portable_dir = std::env::current_exe().ok().and_then(|exe| find_portable_dir(&exe));
+    Ok(AppDirs::for_process(&home_dir, portable_dir.as_deref())
+        .config
+        .join(CONFIG_FILE_NAME))
+}
 
 #[derive(Debug)]
 pub struct RuntimeEnvironment {
@@ -7,32 +31,236 @@ pub struct RuntimeEnvironment {
     pub home_dir: PathBuf,
     pub shims_db: PathBuf,
     pub installs_dir: PathBuf,
+    pub system_installs_dir: Option<PathBuf>,
     pub shims_dir: PathBuf,
     pub shim_exe: PathBuf,
+    /// The PowerShell completion script dot-sourced by the user's profile.
+    pub completion_script: PathBuf,
     pub log_dir: PathBuf,
+    pub hooks_dir: PathBuf,
+    pub manifests_dir: PathBuf,
+    pub cache_dir: PathBuf,
+    pub stats_file: PathBuf,
+    /// Shims that failed to resolve (see `record_shim_failures`).
+    pub shim_failures_dir: PathBuf,
+    /// The `.asdfw.env` files the user allowed (see `asdfw allow`).
+    pub allowed_env_file: PathBuf,
+    pub config_file: PathBuf,
+    pub config: Config,
+    /// The machine policy, loaded on first use (see `policy`).
+    pub policy: OnceLock<Policy>,
     pub global_tool_versions_file: PathBuf,
+    /// Machine-level defaults consulted after the global file (`tool-versions`
+    /// in the system directory).
+    pub system_tool_versions_file: Option<PathBuf>,
 }
 
 impl RuntimeEnvironment {
     pub fn new() -> Result<Self> {
         let home_dir = dirs::home_dir().ok_or(anyhow!("Could not get home directory"))?;
         let current_dir = std::env::current_dir()?;
-        let app_dir = home_dir.join(".asdfw");
-        let shims_db = app_dir.join("shims.db");
-        let installs_dir = app_dir.join("installs");
-        let shims_dir = app_dir.join("shims");
-        let shim_exe = app_dir.join("lib").join("shim.exe");
-        let log_dir = app_dir.join("logs");
-        let global_tool_versions_file = home_dir.join(".tool-versions");
+        let portable_dir = std::env::current_exe().ok().and_then(|exe| find_portable_dir(&exe));
+        let dirs = AppDirs::for_process(&home_dir, portable_dir.as_deref());
+        let shims_db = dirs.data.join("shims.db");
+        let installs_dir = dirs.data.join("installs");
+        let shims_dir = dirs.data.join("shims");
+        let shim_exe = dirs.data.join("lib").join("shim.exe");
+        let completion_script = dirs.data.join("lib").join("completion.ps1");
+        let log_dir = dirs.cache.join("logs");
+        let hooks_dir = match std::env::var_os(HOOKS_DIR_VARIABLE) {
+            Some(dir) => PathBuf::from(dir),
+            None => dirs.config.join("hooks"),
+        };
+        let manifests_dir = dirs.data.join("manifests");
+        let cache_dir = dirs.cache.join("cache");
+        let stats_file = dirs.cache.join("stats.log");
+        let shim_failures_dir = dirs.data.join("shim-failures");
+        let allowed_env_file = dirs.data.join("allowed-env.sha256");
+        let config_file = dirs.config.join(CONFIG_FILE_NAME);
+        let global_tool_versions_file = match &portable_dir {
+            Some(dir) => dir.join(".tool-versions"),
+            None => home_dir.join(".tool-versions"),
+        };
+        let config = Config::load(&config_file)?;
+        set_messages_file(&dirs.config.join(MESSAGES_FILE_NAME));
+        let system_dir = std::env::var_os(SYSTEM_DIR_VARIABLE)
+            .map(PathBuf::from)
+            .or(config.system_dir.clone());
+        // Without a system directory the defaults are still read from the
+        // machine directory
+        let system_tool_versions_file = system_dir
+            .as_ref()
+            .map(|dir| dir.join(SYSTEM_FILE_NAME))
+            .or_else(|| machine_dir().map(|dir| dir.join(SYSTEM_FILE_NAME)));
+        let system_installs_dir = system_dir.map(|dir| dir.join("installs"));
         Ok(RuntimeEnvironment {
             home_dir,
             current_dir,
             shims_db,
             installs_dir,
+            system_installs_dir,
             shims_dir,
             shim_exe,
+            completion_script,
             log_dir,
+            hooks_dir,
+            manifests_dir,
+            cache_dir,
+            stats_file,
+            shim_failures_dir,
+            allowed_env_file,
+            config_file,
+            config,
+            policy: OnceLock::new(),
             global_tool_versions_file,
+            system_tool_versions_file,
         })
     }
+
+    /// Returns the machine policy, loaded the first time it is needed (most
+    /// shims resolve a command without it).
+    pub fn policy(&self) -> Result<&Policy> {
+        if let Some(policy) = self.policy.get() {
+            return Ok(policy);
+        }
+        // Never from the (user controlled) system dir, see `machine_policy_file`
+        let policy = match machine_policy_file() {
+            Some(file) => Policy::load(&file)?,
+            None => Policy::default(),
+        };
+        Ok(self.policy.get_or_init(|| policy))
+    }
+
+    /// Operates on the provided directory (relative to the current one)
+    /// instead of the working directory of the process.
+    pub fn with_current_dir(mut self, dir: &Path) -> Result<Self> {
+        let dir = self.current_dir.join(dir);
+        if !dir.is_dir() {
+            return Err(anyhow!("{:?} is not a directory", &dir));
+        }
+        debug!("Using {:?} as the current directory", &dir);
+        self.current_dir = dir;
+        Ok(self)
+    }
+}
+
+/// Where each kind of files is kept. Each could be overridden using an
+/// environment variable (`ASDFW_CONFIG_DIR`, `ASDFW_DATA_DIR` and
+/// `ASDFW_CACHE_DIR`).
+#[derive(Debug, PartialEq, Eq)]
+struct AppDirs {
+    /// The configuration file and hooks.
+    config: PathBuf,
+    /// Installed versions, shims and their db.
+    data: PathBuf,
+    /// Cached installation files, logs and statistics. Could be removed at
+    /// any time.
+    cache: PathBuf,
+}
+
+impl AppDirs {
+    /// The directories of this process (see `resolve`), the app directory
+    /// being the portable directory (if any) or `~\.asdfw`.
+    fn for_process(home_dir: &Path, portable_dir: Option<&Path>) -> Self {
+        let app_dir = match portable_dir {
+            Some(dir) => {
+                debug!("Running in portable mode from {:?}", &dir);
+                dir.to_path_buf()
+            }
+            None => home_dir.join(".asdfw"),
+        };
+        let dirs = AppDirs::resolve(&app_dir, portable_dir.is_some(), dirs::cache_dir(), |name| {
+            std::env::var_os(name).map(PathBuf::from)
+        });
+        debug!("Using directories: {:?}", &dirs);
+        dirs
+    }
+
+    /// Configuration and data default to the app directory. Caches default to
+    /// the local (non roaming) cache directory (`%LOCALAPPDATA%\asdfw`) so they
+    /// don't bloat roaming profiles, unless running in portable mode.
+    fn resolve<F>(app_dir: &Path, portable: bool, local_cache_dir: Option<PathBuf>, var: F) -> Self
+    where
+        F: Fn(&str) -> Option<PathBuf>,
+    {
+        let default_cache = match local_cache_dir {
+            Some(dir) if !portable => dir.join("asdfw"),
+            _ => app_dir.to_path_buf(),
+        };
+        AppDirs {
+            config: var(CONFIG_DIR_VARIABLE).unwrap_or(app_dir.to_path_buf()),
+            data: var(DATA_DIR_VARIABLE).unwrap_or(app_dir.to_path_buf()),
+            cache: var(CACHE_DIR_VARIABLE).unwrap_or(default_cache),
+        }
+    }
+}
+
+/// Portable mode is enabled by placing a `portable.marker` file in the app
+/// directory. As `asdfw.exe` could reside either directly in the app directory
+/// or in a sub directory (as do the shims), the marker is searched in the
+/// executable's directory and its parent.
+fn find_portable_dir(exe: &Path) -> Option<PathBuf> {
+    exe.parent()?
+        .ancestors()
+        .take(2)
+        .find(|dir| dir.join(PORTABLE_MARKER).is_file())
+        .map(Path::to_path_buf)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use assert_fs::{prelude::*, TempDir};
+    use rstest::rstest;
+
+    #[test]
+    fn find_portable_dir_with_marker_next_to_executable() {
+        let tmp_dir = TempDir::new().unwrap();
+        tmp_dir.child(PORTABLE_MARKER).touch().unwrap();
+        let exe = tmp_dir.child("asdfw.exe");
+        assert_eq!(find_portable_dir(exe.path()), Some(tmp_dir.to_path_buf()));
+    }
+
+    #[test]
+    fn find_portable_dir_with_marker_in_parent_of_executable() {
+        let tmp_dir = TempDir::new().unwrap();
+        tmp_dir.child(PORTABLE_MARKER).touch().unwrap();
+        let exe = tmp_dir.child("shims").child("mycmd.exe");
+        assert_eq!(find_portable_dir(exe.path()), Some(tmp_dir.to_path_buf()));
+    }
+
+    #[rstest]
+    #[case(false, &[], "/local/asdfw", "defaults")]
+    #[case(true, &[], "/app", "portable")]
+    #[case(false, &[("ASDFW_CACHE_DIR", "/cache")], "/cache", "cache override")]
+    fn app_dirs_resolve(#[case] portable: bool, #[case] vars: &[(&str, &str)], #[case] cache: &str, #[case] msg: &str) {
+        let dirs = AppDirs::resolve(Path::new("/app"), portable, Some(PathBuf::from("/local")), |name| {
+            vars.iter().find(|(key, _)| *key == name).map(|(_, value)| PathBuf::from(value))
+        });
+        assert_eq!(dirs.config, Path::new("/app"), "{}: wrong config dir", msg);
+        assert_eq!(dirs.data, Path::new("/app"), "{}: wrong data dir", msg);
+        assert_eq!(dirs.cache, Path::new(cache), "{}: wrong cache dir", msg);
+    }
+
+    #[test]
+    fn app_dirs_resolve_with_overrides() {
+        let dirs = AppDirs::resolve(Path::new("/app"), true, None, |name| match name {
+            CONFIG_DIR_VARIABLE => Some(PathBuf::from("/roaming/asdfw")),
+            DATA_DIR_VARIABLE => Some(PathBuf::from("/data")),
+            _ => None,
+        });
+        let expected = AppDirs {
+            config: PathBuf::from("/roaming/asdfw"),
+            data: PathBuf::from("/data"),
+            cache: PathBuf::from("/app"),
+        };
+        assert_eq!(dirs, expected);
+    }
+
+    #[test]
+    fn find_portable_dir_without_marker_returns_none() {
+        let tmp_dir = TempDir::new().unwrap();
+        let exe = tmp_dir.child("bin").child("asdfw.exe");
+        assert_eq!(find_portable_dir(exe.path()), None);
+    }
 }
@@ -0,0 +1,74 @@
+use std::path::PathBuf;
+
+/// Shells (or environments) we can generate environment setup scripts for.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Shell {
+    PowerShell,
+    Cmd,
+    /// The `$GITHUB_ENV` file format of GitHub actions.
+    Github,
+}
+
+/// Renders the commands setting up the provided environment: prepending
+/// `paths` to `PATH` and setting `vars`. `current_path` is only used by formats
+/// which can not reference the existing `PATH`.
+pub fn render_env(shell: Shell, paths: &[PathBuf], vars: &[(String, String)], current_path: &str) -> Vec<String> {
+    let paths: Vec<String> = paths.iter().map(|p| p.to_string_lossy().into_owned()).collect();
+    let mut lines = vec![];
+    match shell {
+        Shell::PowerShell => {
+            if !paths.is_empty() {
+                lines.push(format!("$Env:PATH = '{};' + $Env:PATH", ps_escape(&paths.join(";"))));
+            }
+            for (key, value) in vars {
+                lines.push(format!("$Env:{} = '{}'", key, ps_escape(value)));
+            }
+        }
+        Shell::Cmd => {
+            if !paths.is_empty() {
+                lines.push(format!("set \"PATH={};%PATH%\"", paths.join(";")));
+            }
+            for (key, value) in vars {
+                lines.push(format!("set \"{}={}\"", key, value));
+            }
+        }
+        Shell::Github => {
+            if !paths.is_empty() {
+                lines.push(format!("PATH={};{}", paths.join(";"), current_path));
+            }
+            for (key, value) in vars {
+                lines.push(format!("{}={}", key, value));
+            }
+        }
+    }
+    lines
+}
+
+/// Escapes a value for a single quoted powershell string.
+fn ps_escape(value: &str) -> String {
+    value.replace('\'', "''")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rstest::rstest;
+
+    #[rustfmt::skip]
+    #[rstest]
+    #[case(Shell::PowerShell, vec!["$Env:PATH = 'C:\\a b\\bin;C:\\it''s\\bin;' + $Env:PATH", "$Env:MY_VAR = 'value'"])]
+    #[case(Shell::Cmd, vec!["set \"PATH=C:\\a b\\bin;C:\\it's\\bin;%PATH%\"", "set \"MY_VAR=value\""])]
+    #[case(Shell::Github, vec!["PATH=C:\\a b\\bin;C:\\it's\\bin;C:\\Windows", "MY_VAR=value"])]
+    fn render_env_renders_paths_and_variables(#[case] shell: Shell, #[case] expected: Vec<&str>) {
+        let paths = vec![PathBuf::from("C:\\a b\\bin"), PathBuf::from("C:\\it's\\bin")];
+        let vars = vec![("MY_VAR".to_string(), "value".to_string())];
+        let result = render_env(shell, &paths, &vars, "C:\\Windows");
+        assert_eq!(result, expected);
+    }
+
+    #[test]
+    fn render_env_without_paths_does_not_touch_path() {
+        let result = render_env(Shell::PowerShell, &[], &[], "C:\\Windows");
+        assert!(result.is_empty(), "nothing should be rendered: {:?}", result);
+    }
+}
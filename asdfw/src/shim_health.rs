@@ -0,0 +1,204 @@
+use anyhow::{anyhow, Context, Result};
+use log::debug;
+use std::collections::HashMap;
+use std::fs::{self, OpenOptions};
+use std::io::{ErrorKind, Write};
+use std::path::{Path, PathBuf};
+
+/// The number of failures (since the command last resolved successfully)
+/// that makes a command reported by `doctor` and `reshim`.
+pub const REPEATED_FAILURES: usize = 3;
+
+/// A shim that failed to resolve the executable to run. Saved as a tab
+/// separated line (`<timestamp> <command> <error id> <directory> <reason>`)
+/// with the timestamp in seconds since the epoch, in a file per command (see
+/// `failures_file`).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ShimFailure {
+    pub timestamp: u64,
+    pub command: String,
+    /// The id of the error (e.g. `E_NO_VERSION`), `-` for unclassified
+    /// errors.
+    pub error_id: String,
+    /// The directory the shim was invoked in.
+    pub dir: PathBuf,
+    pub reason: String,
+}
+
+impl ShimFailure {
+    fn to_line(&self) -> String {
+        format!(
+            "{}\t{}\t{}\t{}\t{}\n",
+            self.timestamp,
+            &self.command,
+            &self.error_id,
+            self.dir.display(),
+            self.reason.replace(['\t', '\r', '\n'], " ")
+        )
+    }
+
+    fn parse(line: &str) -> Result<Self> {
+        let fields: Vec<&str> = line.splitn(5, '\t').collect();
+        if fields.len() != 5 {
+            return Err(anyhow!("expected 5 fields, found {}", fields.len()));
+        }
+        Ok(ShimFailure {
+            timestamp: fields[0].parse()?,
+            command: fields[1].to_owned(),
+            error_id: fields[2].to_owned(),
+            dir: PathBuf::from(fields[3]),
+            reason: fields[4].to_owned(),
+        })
+    }
+}
+
+/// A command failing repeatedly.
+#[derive(Debug, PartialEq, Eq)]
+pub struct FailingCommand {
+    pub command: String,
+    pub count: usize,
+    /// The most recent failure.
+    pub last: ShimFailure,
+}
+
+/// The file of the failures directory with the failures of the command
+/// (`<command>.log`, lower case). Each command has its own file, so a shim
+/// forgetting its failures never races with other shims recording theirs.
+pub fn failures_file(dir: &Path, command: &str) -> PathBuf {
+    dir.join(format!("{}.log", command.to_lowercase()))
+}
+
+/// The failure files in the failures directory (none if it does not exist).
+pub fn failures_files(dir: &Path) -> Result<Vec<PathBuf>> {
+    if !dir.is_dir() {
+        return Ok(vec![]);
+    }
+    let mut files = vec![];
+    for entry in fs::read_dir(dir).context(format!("reading shim failures from {:?}", &dir))? {
+        let path = entry?.path();
+        if path.is_file() && matches!(path.extension(), Some(ext) if ext == "log") {
+            files.push(path);
+        }
+    }
+    files.sort();
+    Ok(files)
+}
+
+/// Appends the failure to the file of its command in the failures directory.
+pub fn record(dir: &Path, failure: &ShimFailure) -> Result<()> {
+    let path = failures_file(dir, &failure.command);
+    let context = || format!("recording shim failure in {:?}", &path);
+    fs::create_dir_all(dir).with_context(context)?;
+    let mut file = OpenOptions::new().create(true).append(true).open(&path).with_context(context)?;
+    file.write_all(failure.to_line().as_bytes()).with_context(context)
+}
+
+/// Loads all the recorded failures (oldest first). A missing directory means
+/// no failures and malformed lines (e.g. a partial write) are skipped.
+pub fn load(dir: &Path) -> Result<Vec<ShimFailure>> {
+    let mut failures = vec![];
+    for path in failures_files(dir)? {
+        let contents = fs::read_to_string(&path).context(format!("loading shim failures from {:?}", &path))?;
+        for line in contents.lines().filter(|l| !l.trim().is_empty()) {
+            match ShimFailure::parse(line) {
+                Ok(failure) => failures.push(failure),
+                Err(err) => debug!("Skipping invalid shim failure line '{}': {}", &line, err),
+            }
+        }
+    }
+    failures.sort_by_key(|f| f.timestamp);
+    Ok(failures)
+}
+
+/// Forgets the failures of the command (once it resolves successfully) by
+/// removing its file, so healthy shims only pay for checking it exists.
+pub fn clear(dir: &Path, command: &str) -> Result<()> {
+    let path = failures_file(dir, command);
+    match fs::remove_file(&path) {
+        Err(err) if err.kind() != ErrorKind::NotFound => {
+            Err(err).context(format!("clearing shim failures {:?}", &path))
+        }
+        _ => Ok(()),
+    }
+}
+
+/// The commands with at least `min_count` recorded failures (most failing
+/// first).
+pub fn failing_commands(failures: &[ShimFailure], min_count: usize) -> Vec<FailingCommand> {
+    let mut by_command: HashMap<String, FailingCommand> = HashMap::new();
+    for failure in failures {
+        let failing = by_command.entry(failure.command.to_lowercase()).or_insert_with(|| FailingCommand {
+            command: failure.command.clone(),
+            count: 0,
+            last: failure.clone(),
+        });
+        failing.count += 1;
+        failing.last = failure.clone();
+    }
+    let mut failing: Vec<FailingCommand> = by_command.into_values().filter(|f| f.count >= min_count).collect();
+    failing.sort_by(|a, b| b.count.cmp(&a.count).then_with(|| a.command.cmp(&b.command)));
+    failing
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use assert_fs::{prelude::*, TempDir};
+
+    fn failure(timestamp: u64, command: &str, reason: &str) -> ShimFailure {
+        ShimFailure {
+            timestamp,
+            command: command.to_owned(),
+            error_id: "E_NO_VERSION".to_owned(),
+            dir: PathBuf::from("projects").join("app"),
+            reason: reason.to_owned(),
+        }
+    }
+
+    #[test]
+    fn record_and_load_roundtrip_skipping_invalid_lines() {
+        let tmp_dir = TempDir::new().unwrap();
+        let dir = tmp_dir.child("shim-failures");
+        record(dir.path(), &failure(1, "kubectl.exe", "no version\tconfigured")).unwrap();
+        record(dir.path(), &failure(2, "helm.exe", "not installed")).unwrap();
+        dir.child("helm.exe.log").write_str("garbage\n").unwrap();
+        record(dir.path(), &failure(3, "helm.exe", "not installed")).unwrap();
+        let loaded = load(dir.path()).unwrap();
+        assert_eq!(
+            loaded,
+            vec![
+                failure(1, "kubectl.exe", "no version configured"),
+                failure(3, "helm.exe", "not installed")
+            ]
+        );
+    }
+
+    #[test]
+    fn clear_removes_only_failures_of_the_command() {
+        let tmp_dir = TempDir::new().unwrap();
+        let dir = tmp_dir.child("shim-failures");
+        clear(dir.path(), "kubectl.exe").unwrap();
+        assert!(!dir.path().exists());
+        record(dir.path(), &failure(1, "kubectl.exe", "no version")).unwrap();
+        record(dir.path(), &failure(2, "helm.exe", "not installed")).unwrap();
+        clear(dir.path(), "KUBECTL.exe").unwrap();
+        assert_eq!(load(dir.path()).unwrap(), vec![failure(2, "helm.exe", "not installed")]);
+        assert_eq!(failures_files(dir.path()).unwrap(), vec![dir.child("helm.exe.log").to_path_buf()]);
+    }
+
+    #[test]
+    fn failing_commands_reports_repeated_failures_with_last_reason() {
+        let failures = vec![
+            failure(1, "kubectl.exe", "no version"),
+            failure(2, "helm.exe", "not installed"),
+            failure(3, "kubectl.exe", "no version"),
+            failure(4, "Kubectl.exe", "not installed"),
+        ];
+        let failing = failing_commands(&failures, 2);
+        assert_eq!(failing.len(), 1);
+        assert_eq!(failing[0].command, "kubectl.exe");
+        assert_eq!(failing[0].count, 3);
+        assert_eq!(failing[0].last, failure(4, "Kubectl.exe", "not installed"));
+        assert_eq!(failing_commands(&failures, 1).len(), 2);
+    }
+}
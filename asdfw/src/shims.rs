@@ -1,31 +1,155 @@
+use crate::config::{Config, ConflictPolicy};
+use crate::fsutil::{replace_dir, write_atomically};
+use crate::install::{remove_leftover, sibling_with_prefix, STAGING_PREFIX};
+use crate::interrupt;
+use crate::output::Progress;
 use anyhow::{anyhow, Context, Result};
-use log::{debug, info};
+use log::{debug, info, warn};
+use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
 use std::ffi::OsStr;
 use std::fs;
 use std::path::{Path, PathBuf};
+use std::process::Command;
 
 const EXTENSIONS: &'static [&str] = &["exe"];
+/// Batch launchers (e.g. the ones `npm install -g` creates) in the additional
+/// bin dirs of a tool get a `.exe` shim named after them and run through
+/// `cmd.exe`.
+const LAUNCHER_EXTENSIONS: &[&str] = &["cmd", "bat"];
+/// Name of the junction (in the tool's directory) pointing at the global
+/// version.
+pub const CURRENT_LINK: &str = "current";
+/// Versions starting with this prefix (e.g. `path:C:\dev\kubectl`) point at
+/// a directory outside of the install dirs, such as a local build of the tool.
+pub const PATH_VERSION_PREFIX: &str = "path:";
+/// The file in an additional shim directory (see `with_extra_shims_dirs`)
+/// mapping its shims to tools (`"<exe>" = "<tool>"`, e.g. committed to the
+/// project along with the shims).
+pub const SHIMS_MAP_FILE_NAME: &str = "shims.toml";
+/// Every shims db file starts with this header followed by the schema version.
+const DB_MAGIC: &[u8] = b"ASDFWDB";
+/// The current schema version of the shims db. Bump it (and add migration in
+/// `decode_db`) whenever the shape of `ShimsDB` changes.
+const DB_SCHEMA_VERSION: u32 = 3;
+/// Size of an index entry: key offset, key length, record offset and record
+/// length (all little endian `u32`, offsets relative to the data section).
+const INDEX_ENTRY_SIZE: usize = 16;
 
-pub type ShimsDB = HashMap<String, String>;
+/// What a shim runs.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ShimData {
+    /// An executable provided by the installed versions of the tool. The
+    /// version is resolved on every invocation.
+    Tool(String),
+    /// An executable registered using `asdfw shim add`. Always runs `path`.
+    Manual { tool: String, path: PathBuf },
+}
+
+impl ShimData {
+    pub fn tool(&self) -> &str {
+        match self {
+            ShimData::Tool(tool) => tool,
+            ShimData::Manual { tool, .. } => tool,
+        }
+    }
+}
+
+pub type ShimsDB = HashMap<String, ShimData>;
+
+/// An executable provided by more than one tool and how it was resolved.
+#[derive(Debug, PartialEq, Eq)]
+pub struct Conflict {
+    pub exe: String,
+    pub tools: Vec<String>,
+    /// The tool chosen to own the executable, `None` if it was skipped.
+    pub selected: Option<String>,
+}
+
+/// An installed version of a tool.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct InstalledVersion {
+    pub tool: String,
+    pub version: String,
+    /// The directory of the installed version.
+    pub dir: PathBuf,
+    /// Whether the version is installed in the system install dir.
+    pub system: bool,
+}
+
+/// Changes to the shims directory required to match a shims db (sorted by
+/// name).
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct ShimsChanges {
+    pub created: Vec<String>,
+    pub updated: Vec<String>,
+    pub removed: Vec<String>,
+}
+
+/// What `repair_db` fixed (each list sorted by name).
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct DbRepair {
+    /// Why the existing db was discarded (missing or corrupt), if it was.
+    pub discarded: Option<String>,
+    /// Entries of executables provided by installed tools that were missing.
+    pub added: Vec<String>,
+    /// Entries pointing at the wrong tool (or kind of shim).
+    pub changed: Vec<String>,
+    /// Entries of executables no longer provided by any tool.
+    pub removed: Vec<String>,
+    /// Missing shim files created.
+    pub created_shims: Vec<String>,
+    /// Shim files without a db entry removed.
+    pub removed_shims: Vec<String>,
+    /// Executables provided by more than one tool and how they were resolved.
+    pub conflicts: Vec<Conflict>,
+}
+
+impl DbRepair {
+    /// Whether the db and the shims directory were already in sync.
+    pub fn is_clean(&self) -> bool {
+        self.discarded.is_none()
+            && self.added.is_empty()
+            && self.changed.is_empty()
+            && self.removed.is_empty()
+            && self.created_shims.is_empty()
+            && self.removed_shims.is_empty()
+    }
+}
+
+/// The state of the shim of an executable.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ShimState {
+    Current,
+    /// The shim is a copy of an older shim executable.
+    Stale,
+    Missing,
+}
 
 /// The Shims struct contains data required for handling shims.
-pub struct Shims<'a> {
-    path: &'a Path,
-    tools_install_dir: &'a Path,
-    shims_dir: &'a Path,
-    shim_exe: &'a Path,
+#[derive(Debug, Clone)]
+pub struct Shims {
+    path: PathBuf,
+    tools_install_dir: PathBuf,
+    system_install_dir: Option<PathBuf>,
+    tool_install_dirs: HashMap<String, PathBuf>,
+    tool_bin_dirs: HashMap<String, Vec<PathBuf>>,
+    shims_dir: PathBuf,
+    extra_shims_dirs: Vec<PathBuf>,
+    shim_exe: PathBuf,
 }
 
-impl<'a> Shims<'a> {
+impl Shims {
     /// Create a new Shims struct from the provided db path and installations
     /// directory.
-    pub fn new(
-        db_path: &'a Path,
-        tools_install_dir: &'a Path,
-        shims_dir: &'a Path,
-        shim_exe: &'a Path,
-    ) -> Result<Self> {
+    pub fn new<P, I, S, E>(db_path: P, tools_install_dir: I, shims_dir: S, shim_exe: E) -> Result<Self>
+    where
+        P: AsRef<Path>,
+        I: AsRef<Path>,
+        S: AsRef<Path>,
+        E: AsRef<Path>,
+    {
+        let tools_install_dir = tools_install_dir.as_ref();
         if !tools_install_dir.is_dir() {
             return Err(anyhow!(
                 "Supplied tools install dir ({:?}) is not an existing directory",
@@ -33,122 +157,994 @@ impl<'a> Shims<'a> {
             ));
         };
         Ok(Shims {
-            path: db_path,
-            tools_install_dir,
-            shims_dir,
-            shim_exe,
+            path: db_path.as_ref().to_path_buf(),
+            tools_install_dir: tools_install_dir.to_path_buf(),
+            system_install_dir: None,
+            tool_install_dirs: HashMap::new(),
+            tool_bin_dirs: HashMap::new(),
+            shims_dir: shims_dir.as_ref().to_path_buf(),
+            extra_shims_dirs: vec![],
+            shim_exe: shim_exe.as_ref().to_path_buf(),
         })
     }
 
+    /// Adds shim directories (e.g. a project's `.asdfw\shims`) consulted
+    /// before the shims db, in priority order. Each directory maps its shims
+    /// to tools in its own `shims.toml` (see `SHIMS_MAP_FILE_NAME`).
+    pub fn with_extra_shims_dirs(mut self, dirs: Vec<PathBuf>) -> Self {
+        self.extra_shims_dirs = dirs;
+        self
+    }
+
+    /// Adds a machine level (system wide) installations directory. Tools
+    /// installed in the user's installations directory take precedence.
+    pub fn with_system_install_dir<P: AsRef<Path>>(mut self, dir: Option<P>) -> Self {
+        self.system_install_dir = dir.map(|dir| dir.as_ref().to_path_buf());
+        self
+    }
+
+    /// Redirects specific tools to custom directories containing their
+    /// versions (instead of `<install dir>/<tool>`).
+    pub fn with_tool_install_dirs<T, P>(mut self, dirs: HashMap<T, P>) -> Self
+    where
+        T: Into<String>,
+        P: AsRef<Path>,
+    {
+        self.tool_install_dirs = dirs
+            .into_iter()
+            .map(|(tool, dir)| (tool.into(), dir.as_ref().to_path_buf()))
+            .collect();
+        self
+    }
+
+    /// Adds directories (relative to the version directory) the executables
+    /// of specific tools are looked up in after `bin`.
+    pub fn with_tool_bin_dirs<T, P>(mut self, dirs: HashMap<T, Vec<P>>) -> Self
+    where
+        T: Into<String>,
+        P: AsRef<Path>,
+    {
+        self.tool_bin_dirs = dirs
+            .into_iter()
+            .map(|(tool, dirs)| (tool.into(), dirs.iter().map(|dir| dir.as_ref().to_path_buf()).collect()))
+            .collect();
+        self
+    }
+
+    /// The directories containing the executables of the version (located at
+    /// `version_dir`): `bin` followed by the tool's additional bin dirs.
+    pub fn bin_dirs(&self, tool: &str, version_dir: &Path) -> Vec<PathBuf> {
+        let extra = self.tool_bin_dirs.get(tool).into_iter().flatten();
+        std::iter::once(version_dir.join("bin"))
+            .chain(extra.map(|dir| version_dir.join(dir)))
+            .collect()
+    }
+
+    /// All the installations directories ordered by precedence.
+    fn install_dirs(&self) -> Vec<&Path> {
+        let mut dirs = vec![self.tools_install_dir.as_path()];
+        if let Some(dir) = self.system_install_dir.as_deref() {
+            if dir.is_dir() {
+                dirs.push(dir);
+            } else {
+                debug!("System install dir ({:?}) does not exist, ignoring", &dir);
+            }
+        }
+        dirs
+    }
+
     fn load_db(&self) -> Result<ShimsDB> {
-        let contents = fs::read(self.path)?;
-        bincode::deserialize(&contents).map_err(|err| anyhow!("Error deserializing ShimsDB: {}", err))
+        let contents = fs::read(&self.path)?;
+        decode_db(&contents).context(self.load_error())
+    }
+
+    fn load_error(&self) -> String {
+        format!(
+            "The shims db ({:?}) could not be loaded. Run `asdfw shimdb repair` (or `asdfw reshim`) to rebuild it",
+            self.path
+        )
     }
 
     /// Save the provided shims db to a file.
     pub fn save_db(&self, db: &ShimsDB) -> Result<()> {
-        let serialized = bincode::serialize(db)?;
-        fs::write(self.path, &serialized)?;
+        let serialized = encode_db(db)?;
+        write_atomically(&self.path, &serialized)?;
         info!("Successfully saved db");
         Ok(())
     }
 
     /// Returns the full path to the shimmed executable.
     pub fn get_full_executable_path(&self, exe: &str, tool: &str, version: &str) -> Result<Option<PathBuf>> {
-        let root = self
-            .tools_install_dir
-            .to_str()
-            .ok_or(anyhow!("Couldn't parse install dir as string."))?;
-        let path: PathBuf = [&root, tool, version, "bin", exe].iter().collect();
-        Ok(if path.exists() { Some(path) } else { None })
+        let path = self
+            .version_dir_candidates(tool, version)
+            .into_iter()
+            .find_map(|dir| self.find_in_bin_dirs(tool, &dir, exe));
+        Ok(path)
     }
 
-    /// Resolve executable name as shim even if entered without extension.
-    pub fn resolve_command(&self, exe: &str) -> Result<Option<String>> {
-        for entry in fs::read_dir(&self.shims_dir)? {
-            let name = entry?
-                .file_name()
-                .into_string()
-                .map_err(|e| anyhow!("could not convert {:?} to string", e))?;
-            if exe == &name {
-                return Ok(Some(name));
+    /// Returns the executable (or the launcher of a shim named after it, see
+    /// `LAUNCHER_EXTENSIONS`) in the bin dirs of the version directory.
+    pub fn find_in_bin_dirs(&self, tool: &str, version_dir: &Path, exe: &str) -> Option<PathBuf> {
+        let launchers: Vec<String> = match exe.strip_suffix(".exe") {
+            Some(stem) => LAUNCHER_EXTENSIONS.iter().map(|ext| format!("{}.{}", stem, ext)).collect(),
+            None => vec![],
+        };
+        for (i, dir) in self.bin_dirs(tool, version_dir).iter().enumerate() {
+            let path = dir.join(exe);
+            if path.exists() {
+                return Some(path);
             }
-            for ext in EXTENSIONS.iter() {
-                let with_ext = format!("{}.{}", exe, ext);
-                if with_ext == name {
-                    return Ok(Some(name));
+            if i > 0 {
+                if let Some(path) = launchers.iter().map(|name| dir.join(name)).find(|path| path.is_file()) {
+                    return Some(path);
                 }
             }
         }
-        // Fix: test!
+        None
+    }
+
+    /// Like `get_full_executable_path` but also tries the command with each
+    /// of the supported extensions (e.g. `kubectl` -> `kubectl.exe`).
+    pub fn find_executable_in_tool(&self, cmd: &str, tool: &str, version: &str) -> Result<Option<PathBuf>> {
+        let mut names = vec![cmd.to_owned()];
+        names.extend(EXTENSIONS.iter().map(|ext| format!("{}.{}", cmd, ext)));
+        for name in names {
+            if let Some(path) = self.get_full_executable_path(&name, tool, version)? {
+                return Ok(Some(path));
+            }
+        }
         Ok(None)
     }
 
+    /// Returns the directory a new version of the tool should be installed
+    /// into (the tool's custom install dir if configured).
+    pub fn install_target(&self, tool: &str, version: &str) -> PathBuf {
+        match self.tool_install_dirs.get(tool) {
+            Some(dir) => dir.join(version),
+            None => self.tools_install_dir.join(tool).join(version),
+        }
+    }
+
+    /// Returns the directory of the installed version of the tool (if
+    /// installed).
+    pub fn find_version_dir(&self, tool: &str, version: &str) -> Option<PathBuf> {
+        self.version_dir_candidates(tool, version).into_iter().find(|dir| dir.is_dir())
+    }
+
+    /// Returns the installed version of the tool (if installed), see
+    /// `find_version_dir`.
+    pub fn find_installed_version(&self, tool: &str, version: &str) -> Option<InstalledVersion> {
+        let dir = self.find_version_dir(tool, version)?;
+        let system = matches!(self.system_install_dir.as_deref(), Some(system) if dir.starts_with(system));
+        Some(InstalledVersion {
+            tool: tool.to_owned(),
+            version: version.to_owned(),
+            dir,
+            system,
+        })
+    }
+
+    /// All the possible directories of a version ordered by precedence.
+    fn version_dir_candidates(&self, tool: &str, version: &str) -> Vec<PathBuf> {
+        if let Some(dir) = path_version_dir(version) {
+            return vec![dir.to_path_buf()];
+        }
+        let mut candidates = vec![];
+        if let Some(dir) = self.tool_install_dirs.get(tool) {
+            candidates.push(dir.join(version));
+        }
+        for dir in self.install_dirs() {
+            candidates.push(dir.join(tool).join(version));
+        }
+        candidates
+    }
+
+    /// Points the `current` junction next to the directory of the provided
+    /// version (in the tool's directory of the install dir it is installed in,
+    /// or in the tool's custom install dir) at it, removing the tool's
+    /// junctions in the other install dirs. Returns the path of the junction.
+    /// `path:` versions are not installed by asdfw and never linked.
+    pub fn update_current_link(&self, tool: &str, version: &str) -> Result<PathBuf> {
+        if path_version_dir(version).is_some() {
+            return Err(anyhow!("Version '{}' of '{}' is not installed by asdfw", &version, &tool));
+        }
+        let target = self.find_version_dir(tool, version).ok_or(anyhow!(
+            "Version '{}' of '{}' is not installed",
+            &version,
+            &tool
+        ))?;
+        let link = target.with_file_name(CURRENT_LINK);
+        self.remove_current_links(tool, |_| true)?;
+        debug!("Linking {:?} -> {:?}", &link, &target);
+        let output = Command::new("cmd.exe")
+            .arg("/C")
+            .arg("mklink")
+            .arg("/J")
+            .arg(&link)
+            .arg(&target)
+            .output()
+            .context(format!("creating current link for {}", &tool))?;
+        if !output.status.success() {
+            return Err(anyhow!(
+                "Could not create current link for {}: {}",
+                &tool,
+                String::from_utf8_lossy(&output.stderr).trim()
+            ));
+        }
+        Ok(link)
+    }
+
+    /// Removes the `current` junctions of the tool (see `update_current_link`)
+    /// matching the predicate, which is called with the junction's path.
+    pub fn remove_current_links<P>(&self, tool: &str, predicate: P) -> Result<()>
+    where
+        P: Fn(&Path) -> bool,
+    {
+        let mut dirs: Vec<PathBuf> = self.tool_install_dirs.get(tool).into_iter().cloned().collect();
+        dirs.extend(self.install_dirs().into_iter().map(|dir| dir.join(tool)));
+        for link in dirs.iter().map(|dir| dir.join(CURRENT_LINK)) {
+            if fs::symlink_metadata(&link).is_ok() && predicate(&link) {
+                debug!("Removing current link: {:?}", &link);
+                // Removing a junction does not touch the directory it points at.
+                fs::remove_dir(&link)
+                    .or_else(|_| fs::remove_file(&link))
+                    .context(format!("removing current link {:?}", &link))?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Removes the `current` junctions of the tool pointing at versions that
+    /// are no longer installed.
+    pub fn remove_dangling_current_links(&self, tool: &str) -> Result<()> {
+        self.remove_current_links(tool, |link| !link.is_dir())
+    }
+
+    /// Resolve executable name as shim even if entered without extension.
+    /// The additional shim directories are consulted first (in order), then
+    /// the shims directory.
+    pub fn resolve_command(&self, exe: &str) -> Result<Option<String>> {
+        if let Some((name, _)) = self.find_extra_shim_entry(exe)? {
+            return Ok(Some(name));
+        }
+        resolve_command_in(&self.shims_dir, exe)
+    }
+
     /// Find a plugin which owns this exe
     pub fn find_plugin(&self, exe: &str) -> Result<Option<String>> {
-        let shims = self.load_db()?;
-        Ok(shims.get(exe).map(|s| s.to_string()))
+        Ok(self.find_shim(exe)?.map(|s| s.tool().to_string()))
     }
 
-    /// Generates all required shims. Cleans up the shims directory before if desired.
-    pub fn create_shims(&self, cleanup: bool) -> Result<()> {
-        if cleanup {
-            debug!("resetting shims directory");
-            fs::remove_dir_all(self.shims_dir).context("cleaning up shims directory")?;
-            fs::create_dir(self.shims_dir).context("recreating shims directory after cleanup")?;
+    /// Returns the names of all the shims in the db.
+    pub fn shim_names(&self) -> Result<Vec<String>> {
+        Ok(self.load_db()?.into_keys().collect())
+    }
+
+    /// Returns what the shim of the executable runs.
+    pub fn find_shim(&self, exe: &str) -> Result<Option<ShimData>> {
+        Ok(self.find_shim_entry(exe)?.map(|(_, shim)| shim))
+    }
+
+    /// Like `find_shim` but also returns the name of the shim as registered
+    /// in the db (executable names are case-insensitive).
+    /// Only the matching record is decoded (unless the db was saved by an
+    /// older version).
+    /// The additional shim directories are consulted first (in order).
+    pub fn find_shim_entry(&self, exe: &str) -> Result<Option<(String, ShimData)>> {
+        match self.find_extra_shim_entry(exe)? {
+            Some(entry) => Ok(Some(entry)),
+            None => self.find_db_entry(exe),
+        }
+    }
+
+    /// Looks the executable up in the maps of the additional shim
+    /// directories, the first one mapping it wins.
+    fn find_extra_shim_entry(&self, exe: &str) -> Result<Option<(String, ShimData)>> {
+        for dir in &self.extra_shims_dirs {
+            let map_file = dir.join(SHIMS_MAP_FILE_NAME);
+            if !map_file.is_file() {
+                debug!("Skipping shim directory {:?} without {}", &dir, SHIMS_MAP_FILE_NAME);
+                continue;
+            }
+            let contents = fs::read_to_string(&map_file).context(format!("reading {:?}", &map_file))?;
+            let map: HashMap<String, String> = toml::from_str(&contents).context(format!("parsing {:?}", &map_file))?;
+            let found = map.into_iter().find(|(name, _)| {
+                name.eq_ignore_ascii_case(exe)
+                    || EXTENSIONS.iter().any(|ext| name.eq_ignore_ascii_case(&format!("{}.{}", exe, ext)))
+            });
+            if let Some((name, tool)) = found {
+                debug!("Command '{}' mapped to {} in shim directory {:?}", &exe, &tool, &dir);
+                return Ok(Some((name, ShimData::Tool(tool))));
+            }
+        }
+        Ok(None)
+    }
+
+    /// Looks the executable up in the shims db only.
+    pub fn find_db_entry(&self, exe: &str) -> Result<Option<(String, ShimData)>> {
+        let contents = fs::read(&self.path)?;
+        if let Some(payload) = indexed_payload(&contents) {
+            return IndexedDb::parse(payload).and_then(|db| db.find(exe)).context(self.load_error());
+        }
+        let mut shims = decode_db(&contents).context(self.load_error())?;
+        if let Some(shim) = shims.remove(exe) {
+            return Ok(Some((exe.to_owned(), shim)));
+        }
+        Ok(shims.into_iter().find(|(name, _)| name.eq_ignore_ascii_case(exe)))
+    }
+
+    /// The shims registered using `add_manual_shim`. Unlike `load_db`, a
+    /// missing or unreadable db means no manual shims (so reshim could still
+    /// rebuild it).
+    fn manual_shims(&self) -> ShimsDB {
+        if !self.path.exists() {
+            return HashMap::new();
+        }
+        match self.load_db() {
+            Ok(db) => db.into_iter().filter(|(_, shim)| matches!(shim, ShimData::Manual { .. })).collect(),
+            Err(err) => {
+                warn!("Ignoring manually registered shims: {:#}", err);
+                HashMap::new()
+            }
+        }
+    }
+
+    /// Registers an executable outside of the installations directories as
+    /// the shim `exe` of the tool and creates the shim. Fails if the shim is
+    /// provided by an installed tool. A manual shim registered under another
+    /// case is replaced, keeping its name. Returns the name of the shim.
+    pub fn add_manual_shim(&self, exe: &str, tool: &str, path: &Path) -> Result<String> {
+        if !path.is_file() {
+            return Err(anyhow!("{:?} does not exist or is not a file", &path));
+        }
+        let (mut db, existing) = if self.path.exists() {
+            (self.load_db()?, self.find_db_entry(exe)?)
+        } else {
+            (HashMap::new(), None)
+        };
+        let name = match existing {
+            Some((name, ShimData::Tool(owner))) => {
+                return Err(anyhow!("{} is already provided by the installed tool {}", &name, &owner));
+            }
+            Some((name, ShimData::Manual { .. })) => name,
+            None => exe.to_owned(),
+        };
+        let shim = ShimData::Manual {
+            tool: tool.to_owned(),
+            path: path.to_path_buf(),
+        };
+        db.insert(name.clone(), shim);
+        self.save_db(&db)?;
+        self.create_shim(&name)?;
+        Ok(name)
+    }
+
+    /// Removes a shim registered using `add_manual_shim`. Returns the name of
+    /// the removed shim.
+    pub fn remove_manual_shim(&self, exe: &str) -> Result<String> {
+        match self.find_db_entry(exe)? {
+            Some((name, ShimData::Manual { .. })) => {
+                self.remove_shims(std::slice::from_ref(&name))?;
+                Ok(name)
+            }
+            Some((_, ShimData::Tool(tool))) => Err(anyhow!(
+                "{} is provided by the installed tool {}, it was not registered using `asdfw shim add`",
+                &exe,
+                &tool
+            )),
+            None => Err(anyhow!("No shim is registered for {}", &exe)),
+        }
+    }
+
+    /// Calculates the changes `create_shims` would perform for the provided
+    /// db without touching the shims directory.
+    pub fn plan_shims(&self, db: &ShimsDB, cleanup: bool) -> Result<ShimsChanges> {
+        let mut changes = ShimsChanges::default();
+        let mut existing = vec![];
+        // The directory is missing if a cleanup was interrupted while
+        // replacing it (restored by `create_shims`)
+        if self.shims_dir.is_dir() {
+            for name in self.shim_file_names()? {
+                if cleanup && !contains_ignore_case(db.keys(), &name) {
+                    changes.removed.push(name.clone());
+                }
+                existing.push(name);
+            }
+        }
+        for exe in db.keys() {
+            if contains_ignore_case(&existing, exe) {
+                changes.updated.push(exe.clone());
+            } else {
+                changes.created.push(exe.clone());
+            }
         }
+        changes.created.sort();
+        changes.updated.sort();
+        changes.removed.sort();
+        Ok(changes)
+    }
+
+    /// Generates all required shims. Stops between shims when interrupted
+    /// with Ctrl-C.
+    ///
+    /// With cleanup, the shims (only the ones in the db) are generated into a
+    /// staging directory which then replaces the shims directory, so a
+    /// failure (or Ctrl-C) leaves the existing shims untouched. Replacing the
+    /// directory fails if any of its shims is running.
+    pub fn create_shims(&self, cleanup: bool) -> Result<()> {
+        self.restore_shims_dir()?;
         let db = self.load_db()?;
+        if !cleanup {
+            let mut progress = Progress::new("Creating shims", Some(db.len()));
+            for exe in db.keys() {
+                interrupt::check().context("some shims may be missing (run `asdfw reshim` to create them)")?;
+                self.create_shim(exe)?;
+                progress.inc();
+            }
+            return Ok(());
+        }
+        debug!("Generating shims into a staging directory");
+        let staging = sibling_with_prefix(&self.shims_dir, STAGING_PREFIX)?;
+        remove_leftover(&staging)?;
+        fs::create_dir_all(&staging).context(format!("creating {:?}", &staging))?;
+        let mut progress = Progress::new("Creating shims", Some(db.len()));
         for exe in db.keys() {
-            let target = self.shims_dir.join(&exe);
-            debug!("Creating shim for {}", &exe);
-            fs::copy(&self.shim_exe, target).context(format!("creating shim for {}", &exe))?;
+            let result = interrupt::check()
+                .context("the shims were not changed")
+                .and_then(|_| self.copy_shim(&staging, exe));
+            if let Err(err) = result {
+                let _ = fs::remove_dir_all(&staging);
+                return Err(err);
+            }
+            progress.inc();
         }
+        let previous = sibling_with_prefix(&self.shims_dir, &format!("{}previous-", STAGING_PREFIX))?;
+        remove_leftover(&previous)?;
+        replace_dir(&staging, &self.shims_dir, &previous).context("replacing the shims directory")
+    }
+
+    /// Moves back the shims directory if a cleanup was interrupted after it
+    /// was moved aside (see `create_shims`), or creates it if missing.
+    fn restore_shims_dir(&self) -> Result<()> {
+        if self.shims_dir.is_dir() {
+            return Ok(());
+        }
+        let previous = sibling_with_prefix(&self.shims_dir, &format!("{}previous-", STAGING_PREFIX))?;
+        if previous.is_dir() {
+            warn!("Restoring shims directory from {:?}", &previous);
+            fs::rename(&previous, &self.shims_dir).context(format!("restoring {:?}", &self.shims_dir))
+        } else {
+            fs::create_dir_all(&self.shims_dir).context(format!("creating {:?}", &self.shims_dir))
+        }
+    }
+
+    /// Creates (or overwrites) a single shim.
+    pub fn create_shim(&self, exe: &str) -> Result<()> {
+        self.copy_shim(&self.shims_dir, exe)
+    }
+
+    fn copy_shim(&self, dir: &Path, exe: &str) -> Result<()> {
+        debug!("Creating shim for {}", &exe);
+        fs::copy(&self.shim_exe, dir.join(exe)).context(format!("creating shim for {}", &exe))?;
         Ok(())
     }
 
-    pub fn generate_db_from_installed_tools(&self) -> Result<ShimsDB> {
-        let mut db: ShimsDB = HashMap::new();
+    /// Returns the state of the executable's shim: missing if it has no entry
+    /// in the db or no shim file, stale if the shim is not a copy of the
+    /// current shim executable.
+    pub fn shim_state(&self, exe: &str) -> Result<ShimState> {
+        let name = match self.find_db_entry(exe)? {
+            Some((name, _)) => name,
+            None => return Ok(ShimState::Missing),
+        };
+        let shim = self.shims_dir.join(&name);
+        if !shim.is_file() {
+            return Ok(ShimState::Missing);
+        }
+        if fs::metadata(&shim)?.len() != fs::metadata(&self.shim_exe)?.len() {
+            return Ok(ShimState::Stale);
+        }
+        let expected = fs::read(&self.shim_exe).context(format!("reading {:?}", &self.shim_exe))?;
+        let actual = fs::read(&shim).context(format!("reading {:?}", &shim))?;
+        match actual == expected {
+            true => Ok(ShimState::Current),
+            false => Ok(ShimState::Stale),
+        }
+    }
+
+    /// Rebuilds the shims db in place from the installed tools (keeping
+    /// manually registered shims if the db is readable) and reconciles the
+    /// shims directory with it: missing shims are created and shims without
+    /// an entry are removed. Existing shims are left untouched.
+    pub fn repair_db(&self, config: &Config) -> Result<DbRepair> {
+        let mut repair = DbRepair::default();
+        let existing = if !self.path.exists() {
+            repair.discarded = Some("the shims db does not exist".to_owned());
+            HashMap::new()
+        } else {
+            self.load_db().unwrap_or_else(|err| {
+                repair.discarded = Some(err.root_cause().to_string());
+                HashMap::new()
+            })
+        };
+        let (db, conflicts) = self.generate_db_from_installed_tools(config)?;
+        repair.conflicts = conflicts;
+        // Executable names are case-insensitive (as are the file names)
+        for (exe, shim) in &db {
+            match existing.iter().find(|(name, _)| name.eq_ignore_ascii_case(exe)) {
+                None => repair.added.push(exe.clone()),
+                Some((_, existing)) if existing != shim => repair.changed.push(exe.clone()),
+                Some(_) => {}
+            }
+        }
+        repair.removed = existing
+            .keys()
+            .filter(|exe| !contains_ignore_case(db.keys(), exe))
+            .cloned()
+            .collect();
+        self.save_db(&db)?;
+
+        let files = self.shim_file_names()?;
+        for exe in db.keys().filter(|exe| !contains_ignore_case(&files, exe)) {
+            self.create_shim(exe)?;
+            repair.created_shims.push(exe.clone());
+        }
+        for name in files.into_iter().filter(|name| !contains_ignore_case(db.keys(), name)) {
+            let shim = self.shims_dir.join(&name);
+            debug!("Removing shim without db entry {:?}", &shim);
+            fs::remove_file(&shim).context(format!("removing shim {:?}", &shim))?;
+            repair.removed_shims.push(name);
+        }
+        for names in [
+            &mut repair.added,
+            &mut repair.changed,
+            &mut repair.removed,
+            &mut repair.created_shims,
+            &mut repair.removed_shims,
+        ] {
+            names.sort();
+        }
+        Ok(repair)
+    }
 
-        for entry in fs::read_dir(self.tools_install_dir)? {
-            let entry = entry?;
-            let tool = entry.file_name().into_string().unwrap(); // Can we trust NTFS to always have unicode filenames?
-            for version in fs::read_dir(entry.path())? {
+    /// Returns the shims (sorted) that are no longer backed by any installed
+    /// version: db entries whose tool does not provide the executable anymore,
+    /// manual shims whose executable was removed and shim files without a db
+    /// entry.
+    pub fn find_orphaned_shims(&self, config: &Config) -> Result<Vec<String>> {
+        let db = self.load_db()?;
+        let owners = self.executable_owners(config)?;
+        let mut orphans: Vec<String> = db
+            .iter()
+            .filter(|(exe, shim)| match shim {
+                ShimData::Tool(tool) => !owners.get(*exe).map_or(false, |tools| tools.contains(tool)),
+                ShimData::Manual { path, .. } => !path.is_file(),
+            })
+            .map(|(exe, _)| exe.clone())
+            .collect();
+        for name in self.shim_file_names()? {
+            if !contains_ignore_case(db.keys(), &name) {
+                orphans.push(name);
+            }
+        }
+        orphans.sort();
+        Ok(orphans)
+    }
+
+    /// Returns the names of the files in the shims directory.
+    fn shim_file_names(&self) -> Result<Vec<String>> {
+        let context = || format!("reading {:?}", &self.shims_dir);
+        let mut names = vec![];
+        for entry in fs::read_dir(&self.shims_dir).with_context(context)? {
+            let name = entry
+                .with_context(context)?
+                .file_name()
+                .into_string()
+                .map_err(|e| anyhow!("could not convert {:?} to string", e))
+                .with_context(context)?;
+            names.push(name);
+        }
+        Ok(names)
+    }
+
+    /// Removes the provided shims and their db entries (matched ignoring
+    /// case, like the shims directory).
+    pub fn remove_shims(&self, exes: &[String]) -> Result<()> {
+        let mut db = self.load_db()?;
+        for exe in exes {
+            db.retain(|name, _| !name.eq_ignore_ascii_case(exe));
+            let shim = self.shims_dir.join(exe);
+            if shim.exists() {
+                debug!("Removing shim {:?}", &shim);
+                fs::remove_file(&shim).context(format!("removing shim {:?}", &shim))?;
+            }
+        }
+        self.save_db(&db)
+    }
+
+    /// All the installed tools with their root directory (the one containing
+    /// the versions) and whether it's in the system install dir.
+    fn tool_roots(&self) -> Result<Vec<(String, PathBuf, bool)>> {
+        let mut roots = vec![];
+        for install_dir in self.install_dirs() {
+            let system = Some(install_dir) == self.system_install_dir.as_deref();
+            for entry in fs::read_dir(install_dir)? {
+                let entry = entry?;
+                let tool = entry.file_name().into_string().unwrap(); // Can we trust NTFS to always have unicode filenames?
+                roots.push((tool, entry.path(), system));
+            }
+        }
+        for (tool, dir) in self.tool_install_dirs.iter() {
+            if dir.is_dir() {
+                roots.push((tool.to_string(), dir.to_path_buf(), false));
+            } else {
+                warn!("Install dir of {} ({:?}) does not exist, ignoring", &tool, &dir);
+            }
+        }
+        Ok(roots)
+    }
+
+    /// Returns all the installed versions of all the tools, sorted by tool and
+    /// version.
+    pub fn installed_versions(&self) -> Result<Vec<InstalledVersion>> {
+        let mut versions = vec![];
+        for (tool, root, system) in self.tool_roots()? {
+            for version in fs::read_dir(root)? {
                 let version = version?;
-                if version.path().is_dir() {
-                    let mut path = version.path();
-                    path.push("bin");
-                    for exe in fs::read_dir(path)? {
-                        let exe = exe?;
-                        if valid_exe_extension(exe.path().extension()) {
-                            let exe_name = exe.file_name().into_string().unwrap();
-                            let old_value = db.insert(exe_name.clone(), tool.clone());
-                            if let Some(value) = old_value {
-                                if value != tool {
-                                    return Err(anyhow!(
-                                        "{} appears in two tools: {} and {}",
-                                        &exe_name,
-                                        &tool,
-                                        &value
-                                    ));
-                                }
-                            }
-                        }
+                let name = version.file_name();
+                let name = name.to_string_lossy();
+                if version.path().is_dir() && name != CURRENT_LINK && !name.starts_with(STAGING_PREFIX) {
+                    versions.push(InstalledVersion {
+                        tool: tool.clone(),
+                        version: version.file_name().into_string().unwrap(),
+                        dir: version.path(),
+                        system,
+                    });
+                }
+            }
+        }
+        versions.sort_by(|a, b| (&a.tool, &a.version).cmp(&(&b.tool, &b.version)));
+        Ok(versions)
+    }
+
+    /// Returns the staging directories left behind by interrupted installs
+    /// (never in the system install dir).
+    pub fn leftover_staging_dirs(&self) -> Result<Vec<PathBuf>> {
+        let mut dirs = vec![];
+        for (_, root, system) in self.tool_roots()? {
+            if system {
+                continue;
+            }
+            for entry in fs::read_dir(root)? {
+                let path = entry?.path();
+                let name = path.file_name().unwrap_or_default().to_string_lossy();
+                if path.is_dir() && name.starts_with(STAGING_PREFIX) {
+                    dirs.push(path);
+                }
+            }
+        }
+        dirs.sort();
+        Ok(dirs)
+    }
+
+    /// Returns all the executables provided by the installed (and enabled)
+    /// tools along with the tools providing each of them.
+    pub fn executable_owners(&self, config: &Config) -> Result<HashMap<String, Vec<String>>> {
+        let mut owners: HashMap<String, Vec<String>> = HashMap::new();
+        // Executables differing only by case share a shim (named after the
+        // first one found)
+        let mut names: HashMap<String, String> = HashMap::new();
+
+        for installed in self.installed_versions()? {
+            if config.is_disabled(&installed.tool) {
+                debug!("Skipping disabled tool: {}", &installed.tool);
+                continue;
+            }
+            for exe_name in self.version_executables(config, &installed)? {
+                let exe_name = names.entry(exe_name.to_lowercase()).or_insert(exe_name).clone();
+                let tools = owners.entry(exe_name).or_default();
+                if !tools.contains(&installed.tool) {
+                    tools.push(installed.tool.clone());
+                }
+            }
+        }
+        Ok(owners)
+    }
+
+    /// Returns the (sorted) names of the executables the installed version
+    /// provides (in all its bin dirs), including the shims of its configured
+    /// scripts.
+    pub fn version_executables(&self, config: &Config, installed: &InstalledVersion) -> Result<Vec<String>> {
+        let mut executables = vec![];
+        let bin_dirs = self.bin_dirs(&installed.tool, &installed.dir);
+        for (i, dir) in bin_dirs.iter().enumerate() {
+            // Additional bin dirs may not exist (yet)
+            if i > 0 && !dir.is_dir() {
+                continue;
+            }
+            for exe in fs::read_dir(dir)? {
+                let path = exe?.path();
+                let name = match path.file_name().and_then(OsStr::to_str) {
+                    Some(name) => name,
+                    None => return Err(anyhow!("could not convert {:?} to string", &path)),
+                };
+                if valid_exe_extension(path.extension()) {
+                    executables.push(name.to_owned());
+                } else if i > 0 && is_launcher(&path) {
+                    executables.push(format!("{}.exe", path.file_stem().and_then(OsStr::to_str).unwrap_or(name)));
+                }
+            }
+        }
+        let scripts = config.tools.get(&installed.tool).map(|t| &t.scripts);
+        for script in scripts.into_iter().flat_map(|s| s.keys()) {
+            if bin_dirs.iter().any(|dir| dir.join(script).is_file()) {
+                executables.push(format!("{}.exe", script));
+            }
+        }
+        executables.sort();
+        executables.dedup();
+        Ok(executables)
+    }
+
+    /// Generates the shims db from the installed tools. Executables provided
+    /// by more than one tool are handled according to the configured conflict
+    /// policy and returned along with the db. Manually registered shims are
+    /// kept (and take precedence over installed tools).
+    pub fn generate_db_from_installed_tools(&self, config: &Config) -> Result<(ShimsDB, Vec<Conflict>)> {
+        let owners = self.executable_owners(config)?;
+        let mut db: ShimsDB = HashMap::new();
+        let mut conflicts = vec![];
+        for (exe_name, mut tools) in owners {
+            if tools.len() == 1 {
+                db.insert(exe_name, ShimData::Tool(tools.remove(0)));
+                continue;
+            }
+            tools.sort();
+            let selected = match config.conflict_policy {
+                ConflictPolicy::Error => {
+                    return Err(anyhow!("{} appears in multiple tools: {}", &exe_name, tools.join(", ")));
+                }
+                ConflictPolicy::Skip => {
+                    warn!("{} appears in multiple tools ({}), skipping", &exe_name, tools.join(", "));
+                    None
+                }
+                ConflictPolicy::Priority => {
+                    let selected = config.tool_priority.iter().find(|t| tools.contains(t)).cloned();
+                    if selected.is_none() {
+                        return Err(anyhow!(
+                            "{} appears in multiple tools ({}) and none of them is in the tool priority list",
+                            &exe_name,
+                            tools.join(", ")
+                        ));
                     }
+                    selected
                 }
+            };
+            if let Some(tool) = &selected {
+                db.insert(exe_name.clone(), ShimData::Tool(tool.clone()));
+            }
+            conflicts.push(Conflict {
+                exe: exe_name,
+                tools,
+                selected,
+            });
+        }
+        conflicts.sort_by(|a, b| a.exe.cmp(&b.exe));
+        for (exe, shim) in self.manual_shims() {
+            if let Some(ShimData::Tool(tool)) = db.insert(exe.clone(), shim) {
+                warn!("{} is registered manually, ignoring the one provided by {}", &exe, &tool);
             }
         }
 
+        Ok((db, conflicts))
+    }
+}
+
+/// Returns the directory a `path:` version points at (`None` for any other
+/// version). Relative directories read from `.tool-versions` files were
+/// already made relative to the file's directory while reading it.
+pub fn path_version_dir(version: &str) -> Option<&Path> {
+    version
+        .strip_prefix(PATH_VERSION_PREFIX)
+        .filter(|dir| !dir.is_empty())
+        .map(Path::new)
+}
+
+/// Encodes the shims db in the current (indexed) format: the number of
+/// entries, an index sorted by the case-insensitive executable name and a
+/// data section with the names and the (bincode) records.
+fn encode_db(db: &ShimsDB) -> Result<Vec<u8>> {
+    let mut entries: Vec<(&String, &ShimData)> = db.iter().collect();
+    entries.sort_by(|(a, _), (b, _)| cmp_ignore_case(a.as_bytes(), b.as_bytes()).then_with(|| a.cmp(b)));
+    let mut index = Vec::with_capacity(entries.len() * INDEX_ENTRY_SIZE);
+    let mut data = vec![];
+    for (exe, shim) in entries {
+        let record = bincode::serialize(shim)?;
+        let key_offset = data.len();
+        data.extend_from_slice(exe.as_bytes());
+        let record_offset = data.len();
+        data.extend_from_slice(&record);
+        for value in [key_offset, exe.len(), record_offset, record.len()] {
+            index.extend_from_slice(&u32::try_from(value)?.to_le_bytes());
+        }
+    }
+    let mut encoded = DB_MAGIC.to_vec();
+    encoded.extend_from_slice(&DB_SCHEMA_VERSION.to_le_bytes());
+    encoded.extend_from_slice(&u32::try_from(db.len())?.to_le_bytes());
+    encoded.append(&mut index);
+    encoded.append(&mut data);
+    Ok(encoded)
+}
+
+/// Returns the payload of a db in the current (indexed) format, `None` for
+/// older formats.
+fn indexed_payload(contents: &[u8]) -> Option<&[u8]> {
+    let rest = contents.strip_prefix(DB_MAGIC)?;
+    let (version, payload) = (rest.get(..4)?, &rest[4..]);
+    match u32::from_le_bytes(version.try_into().ok()?) {
+        DB_SCHEMA_VERSION => Some(payload),
+        _ => None,
+    }
+}
+
+fn cmp_ignore_case(a: &[u8], b: &[u8]) -> std::cmp::Ordering {
+    a.iter().map(u8::to_ascii_lowercase).cmp(b.iter().map(u8::to_ascii_lowercase))
+}
+
+/// A view over an indexed shims db, decoding only the records looked up.
+struct IndexedDb<'a> {
+    index: &'a [u8],
+    data: &'a [u8],
+}
+
+impl<'a> IndexedDb<'a> {
+    /// Validates the index so lookups could not read out of bounds.
+    fn parse(payload: &'a [u8]) -> Result<Self> {
+        let count = payload.get(..4).ok_or_else(|| anyhow!("Shims db index is truncated"))?;
+        let count = u32::from_le_bytes(count.try_into()?) as usize;
+        let index_end = count
+            .checked_mul(INDEX_ENTRY_SIZE)
+            .and_then(|size| size.checked_add(4))
+            .filter(|&end| end <= payload.len())
+            .ok_or_else(|| anyhow!("Shims db index is truncated"))?;
+        let db = IndexedDb {
+            index: &payload[4..index_end],
+            data: &payload[index_end..],
+        };
+        for i in 0..count {
+            let (key, record) = db.ranges(i);
+            if key.end > db.data.len() || record.end > db.data.len() {
+                return Err(anyhow!("Shims db entry {} is out of bounds", i));
+            }
+        }
         Ok(db)
     }
+
+    fn len(&self) -> usize {
+        self.index.len() / INDEX_ENTRY_SIZE
+    }
+
+    fn ranges(&self, i: usize) -> (std::ops::Range<usize>, std::ops::Range<usize>) {
+        let entry = &self.index[i * INDEX_ENTRY_SIZE..(i + 1) * INDEX_ENTRY_SIZE];
+        let field = |n: usize| u32::from_le_bytes(entry[n * 4..n * 4 + 4].try_into().unwrap()) as usize;
+        let (key, record) = ((field(0), field(1)), (field(2), field(3)));
+        (key.0..key.0.saturating_add(key.1), record.0..record.0.saturating_add(record.1))
+    }
+
+    fn key(&self, i: usize) -> &'a [u8] {
+        &self.data[self.ranges(i).0]
+    }
+
+    fn entry(&self, i: usize) -> Result<(String, ShimData)> {
+        let key = String::from_utf8(self.key(i).to_vec())?;
+        let record = bincode::deserialize(&self.data[self.ranges(i).1])
+            .map_err(|err| anyhow!("Error deserializing ShimsDB: {}", err))?;
+        Ok((key, record))
+    }
+
+    /// Binary searches the executable (case-insensitive, preferring an exact
+    /// match).
+    fn find(&self, exe: &str) -> Result<Option<(String, ShimData)>> {
+        let exe = exe.as_bytes();
+        let (mut start, mut end) = (0, self.len());
+        while start < end {
+            let middle = start + (end - start) / 2;
+            match cmp_ignore_case(self.key(middle), exe).is_lt() {
+                true => start = middle + 1,
+                false => end = middle,
+            }
+        }
+        let matches: Vec<usize> = (start..self.len()).take_while(|&i| self.key(i).eq_ignore_ascii_case(exe)).collect();
+        match matches.iter().find(|&&i| self.key(i) == exe).or_else(|| matches.first()) {
+            Some(&i) => self.entry(i).map(Some),
+            None => Ok(None),
+        }
+    }
+
+    fn decode_all(&self) -> Result<ShimsDB> {
+        (0..self.len()).map(|i| self.entry(i)).collect()
+    }
+}
+
+/// Decodes the shims db, migrating older schema versions to the current one.
+fn decode_db(contents: &[u8]) -> Result<ShimsDB> {
+    let deserialize_v2 = |payload: &[u8]| -> Result<ShimsDB> {
+        bincode::deserialize(payload).map_err(|err| anyhow!("Error deserializing ShimsDB: {}", err))
+    };
+    // Version 1 mapped each executable to the tool providing it
+    let deserialize_v1 = |payload: &[u8]| -> Result<ShimsDB> {
+        let db: HashMap<String, String> =
+            bincode::deserialize(payload).map_err(|err| anyhow!("Error deserializing ShimsDB: {}", err))?;
+        Ok(db.into_iter().map(|(exe, tool)| (exe, ShimData::Tool(tool))).collect())
+    };
+    let rest = match contents.strip_prefix(DB_MAGIC) {
+        Some(rest) => rest,
+        None => {
+            // Databases created before the schema header was introduced have
+            // the same shape as version 1.
+            debug!("Shims db has no schema header, migrating from legacy format");
+            return deserialize_v1(contents);
+        }
+    };
+    if rest.len() < 4 {
+        return Err(anyhow!("Shims db header is truncated"));
+    }
+    let (version, payload) = rest.split_at(4);
+    let version = u32::from_le_bytes(version.try_into()?);
+    match version {
+        1 => deserialize_v1(payload),
+        // Version 2 was the whole map serialized at once (without an index)
+        2 => deserialize_v2(payload),
+        DB_SCHEMA_VERSION => IndexedDb::parse(payload)?.decode_all(),
+        _ => Err(anyhow!(
+            "Unsupported shims db schema version {} (supported: {})",
+            version,
+            DB_SCHEMA_VERSION
+        )),
+    }
 }
 
 fn valid_exe_extension(extension: Option<&OsStr>) -> bool {
-    for item in EXTENSIONS.iter() {
-        if Some(OsStr::new(item)) == extension {
-            return true;
+    let extension = match extension.and_then(OsStr::to_str) {
+        Some(extension) => extension,
+        None => return false,
+    };
+    EXTENSIONS.iter().any(|item| item.eq_ignore_ascii_case(extension))
+}
+
+fn is_launcher(path: &Path) -> bool {
+    let extension = path.extension().and_then(OsStr::to_str).unwrap_or_default();
+    LAUNCHER_EXTENSIONS.iter().any(|ext| ext.eq_ignore_ascii_case(extension))
+}
+
+/// Whether the name is one of the names (executable names are
+/// case-insensitive).
+fn contains_ignore_case<'a, I>(names: I, name: &str) -> bool
+where
+    I: IntoIterator<Item = &'a String>,
+{
+    names.into_iter().any(|n| n.eq_ignore_ascii_case(name))
+}
+
+/// Returns the name of the shim in the directory matching the executable
+/// name (case-insensitive, the extension could be omitted).
+fn resolve_command_in(shims_dir: &Path, exe: &str) -> Result<Option<String>> {
+    for entry in fs::read_dir(shims_dir)? {
+        let name = entry?
+            .file_name()
+            .into_string()
+            .map_err(|e| anyhow!("could not convert {:?} to string", e))?;
+        if exe.eq_ignore_ascii_case(&name) {
+            return Ok(Some(name));
+        }
+        for ext in EXTENSIONS.iter() {
+            let with_ext = format!("{}.{}", exe, ext);
+            if with_ext.eq_ignore_ascii_case(&name) {
+                return Ok(Some(name));
+            }
         }
     }
-    return false;
+    Ok(None)
 }
 
 #[cfg(test)]
@@ -183,14 +1179,18 @@ mod tests {
 
     fn test_data() -> ShimsDB {
         HashMap::from([
-            ("kubectl.exe".to_string(), "kubectl".to_string()),
-            ("docker.exe".to_string(), "docker".to_string()),
-            ("minikube.exe".to_string(), "minikube".to_string()),
-            ("kubectx.exe".to_string(), "kubectx".to_string()),
-            ("kubens.exe".to_string(), "kubectx".to_string()),
+            ("kubectl.exe".to_string(), ShimData::Tool("kubectl".to_string())),
+            ("docker.exe".to_string(), ShimData::Tool("docker".to_string())),
+            ("minikube.exe".to_string(), ShimData::Tool("minikube".to_string())),
+            ("kubectx.exe".to_string(), ShimData::Tool("kubectx".to_string())),
+            ("kubens.exe".to_string(), ShimData::Tool("kubectx".to_string())),
         ])
     }
 
+    fn v1_test_data() -> HashMap<String, String> {
+        test_data().into_iter().map(|(exe, shim)| (exe, shim.tool().to_string())).collect()
+    }
+
     #[test]
     fn save_and_load_shims_db() {
         let db = test_data();
@@ -203,11 +1203,97 @@ mod tests {
         assert_eq!(db, loaded);
     }
 
+    #[test]
+    fn load_shims_db_migrates_legacy_db_without_header() {
+        let db = test_data();
+        let tmp_dir = TempDir::new().unwrap();
+        let paths = test_paths(&tmp_dir);
+        #[rustfmt::skip]
+        let shims = Shims::new(&paths.db_path, &paths.tools_install_dir, &paths.shims_dir, &paths.shim_exe).unwrap();
+        fs::write(&paths.db_path, bincode::serialize(&v1_test_data()).unwrap()).unwrap();
+        let loaded = shims.load_db().unwrap();
+        assert_eq!(db, loaded);
+    }
+
+    #[test]
+    fn load_shims_db_migrates_version_1() {
+        let tmp_dir = TempDir::new().unwrap();
+        let paths = test_paths(&tmp_dir);
+        #[rustfmt::skip]
+        let shims = Shims::new(&paths.db_path, &paths.tools_install_dir, &paths.shims_dir, &paths.shim_exe).unwrap();
+        let contents = [
+            DB_MAGIC,
+            &1u32.to_le_bytes(),
+            &bincode::serialize(&v1_test_data()).unwrap(),
+        ]
+        .concat();
+        fs::write(&paths.db_path, contents).unwrap();
+        assert_eq!(shims.load_db().unwrap(), test_data());
+    }
+
+    #[test]
+    fn load_shims_db_migrates_version_2() {
+        let tmp_dir = TempDir::new().unwrap();
+        let paths = test_paths(&tmp_dir);
+        #[rustfmt::skip]
+        let shims = Shims::new(&paths.db_path, &paths.tools_install_dir, &paths.shims_dir, &paths.shim_exe).unwrap();
+        let contents = [
+            DB_MAGIC,
+            &2u32.to_le_bytes(),
+            &bincode::serialize(&test_data()).unwrap(),
+        ]
+        .concat();
+        fs::write(&paths.db_path, contents).unwrap();
+        assert_eq!(shims.load_db().unwrap(), test_data());
+        let found = shims.find_shim_entry("KUBENS.exe").unwrap();
+        assert_eq!(found, Some(("kubens.exe".to_string(), ShimData::Tool("kubectx".to_string()))));
+    }
+
+    #[test]
+    fn find_shim_entry_in_indexed_db_prefers_exact_case() {
+        let tmp_dir = TempDir::new().unwrap();
+        let paths = test_paths(&tmp_dir);
+        #[rustfmt::skip]
+        let shims = Shims::new(&paths.db_path, &paths.tools_install_dir, &paths.shims_dir, &paths.shim_exe).unwrap();
+        let mut db = test_data();
+        db.insert("Docker.exe".to_string(), ShimData::Tool("docker-desktop".to_string()));
+        shims.save_db(&db).unwrap();
+        let find = |exe: &str| {
+            shims
+                .find_shim_entry(exe)
+                .unwrap()
+                .map(|(name, shim)| (name, shim.tool().to_string()))
+        };
+        assert_eq!(find("Docker.exe"), Some(("Docker.exe".to_string(), "docker-desktop".to_string())));
+        assert_eq!(find("docker.exe"), Some(("docker.exe".to_string(), "docker".to_string())));
+        assert_eq!(find("MINIKUBE.EXE"), Some(("minikube.exe".to_string(), "minikube".to_string())));
+        assert_eq!(find("kube.exe"), None);
+    }
+
+    #[rstest]
+    #[case([DB_MAGIC, &99u32.to_le_bytes()].concat(), "unsupported schema version")]
+    #[case([DB_MAGIC, &[1, 0]].concat(), "truncated header")]
+    #[case([DB_MAGIC, &DB_SCHEMA_VERSION.to_le_bytes(), &3u32.to_le_bytes(), &[0; 20]].concat(), "truncated index")]
+    #[case(b"garbage".to_vec(), "corrupt file")]
+    fn load_shims_db_with_invalid_db_suggests_reshim(#[case] contents: Vec<u8>, #[case] msg: &str) {
+        let tmp_dir = TempDir::new().unwrap();
+        let paths = test_paths(&tmp_dir);
+        #[rustfmt::skip]
+        let shims = Shims::new(&paths.db_path, &paths.tools_install_dir, &paths.shims_dir, &paths.shim_exe).unwrap();
+        fs::write(&paths.db_path, contents).unwrap();
+        let err = shims.load_db().unwrap_err();
+        assert!(format!("{}", err).contains("asdfw reshim"), "{}: wrong error: {}", msg, err);
+        let err = shims.find_shim_entry("kubectl.exe").unwrap_err();
+        assert!(format!("{}", err).contains("asdfw reshim"), "{}: wrong lookup error: {}", msg, err);
+    }
+
     #[rustfmt::skip]
     #[rstest]
     #[case(vec!["hello.exe", "world.exe"], "hello.exe", Some("hello.exe".to_string()), "exact match")]
     #[case(vec!["hello.exe", "world.exe"], "hello", Some("hello.exe".to_string()), "missing extension")]
     #[case(vec!["hello.exe", "world.exe"], "what.exe", None, "invalid command")]
+    #[case(vec!["hello.exe", "world.exe"], "Hello", Some("hello.exe".to_string()), "mixed case")]
+    #[case(vec!["Hello.exe", "world.exe"], "HELLO.EXE", Some("Hello.exe".to_string()), "mixed case with extension")]
     fn resolve_command_tests(
         #[case] existing_shims: Vec<&str>,
         #[case] exe: &str,
@@ -225,6 +1311,44 @@ mod tests {
         assert_eq!(result, expected, "test case: {}", &msg);
     }
 
+    #[test]
+    fn extra_shims_dirs_map_their_shims_in_order() {
+        let tmp_dir = TempDir::new().unwrap();
+        let paths = test_paths(&tmp_dir);
+        let project = tmp_dir.child("project").child(".asdfw").child("shims");
+        project
+            .child(SHIMS_MAP_FILE_NAME)
+            .write_str("\"Kubectl.exe\" = \"kubectl-pinned\"\n")
+            .unwrap();
+        let shared = tmp_dir.child("shared");
+        shared
+            .child(SHIMS_MAP_FILE_NAME)
+            .write_str("\"kubectl.exe\" = \"other\"\n\"lint.exe\" = \"golangci-lint\"\n")
+            .unwrap();
+        let unmapped = tmp_dir.child("unmapped");
+        unmapped.child("helm.exe").touch().unwrap();
+        paths.shims_dir.child("kubectl.exe").touch().unwrap();
+        paths.shims_dir.child("helm.exe").touch().unwrap();
+        let shims = Shims::new(&paths.db_path, &paths.tools_install_dir, &paths.shims_dir, &paths.shim_exe)
+            .unwrap()
+            .with_extra_shims_dirs(vec![
+                tmp_dir.child("missing").to_path_buf(),
+                unmapped.to_path_buf(),
+                project.to_path_buf(),
+                shared.to_path_buf(),
+            ]);
+        let mut db = HashMap::new();
+        db.insert("kubectl.exe".to_owned(), ShimData::Tool("kubectl".to_owned()));
+        db.insert("helm.exe".to_owned(), ShimData::Tool("helm".to_owned()));
+        shims.save_db(&db).unwrap();
+        assert_eq!(shims.resolve_command("kubectl").unwrap(), Some("Kubectl.exe".to_owned()));
+        assert_eq!(shims.find_plugin("kubectl.exe").unwrap(), Some("kubectl-pinned".to_owned()));
+        assert_eq!(shims.find_plugin("lint").unwrap(), Some("golangci-lint".to_owned()));
+        assert_eq!(shims.resolve_command("helm").unwrap(), Some("helm.exe".to_owned()));
+        assert_eq!(shims.find_plugin("helm.exe").unwrap(), Some("helm".to_owned()));
+        assert_eq!(shims.resolve_command("minikube").unwrap(), None);
+    }
+
     #[test]
     fn find_plugin_with_existing_plugin_returns_valid_plugin() {
         let db = test_data();
@@ -237,6 +1361,28 @@ mod tests {
         assert_eq!(result, Some("kubectx".to_string()));
     }
 
+    #[test]
+    fn find_shim_entry_ignores_case() {
+        let db = test_data();
+        let tmp_dir = TempDir::new().unwrap();
+        let paths = test_paths(&tmp_dir);
+        #[rustfmt::skip]
+        let shims = Shims::new(&paths.db_path, &paths.tools_install_dir, &paths.shims_dir, &paths.shim_exe).unwrap();
+        shims.save_db(&db).unwrap();
+        let expected = ("kubectl.exe".to_string(), ShimData::Tool("kubectl".to_string()));
+        assert_eq!(shims.find_shim_entry("Kubectl.EXE").unwrap(), Some(expected));
+        assert_eq!(shims.find_plugin("KUBENS.exe").unwrap(), Some("kubectx".to_string()));
+    }
+
+    #[rstest]
+    #[case("path:C:\\dev\\kubectl", Some("C:\\dev\\kubectl"))]
+    #[case("path:", None)]
+    #[case("1.2.4", None)]
+    #[case("mypath:C:\\dev", None)]
+    fn path_version_dir_of_path_versions_only(#[case] version: &str, #[case] expected: Option<&str>) {
+        assert_eq!(path_version_dir(version), expected.map(Path::new));
+    }
+
     #[test]
     fn find_plugin_with_invalid_plugin_returns_none() {
         let db = test_data();
@@ -270,8 +1416,25 @@ mod tests {
         paths.tools_install_dir.child("kubectx").child("0.12").child("bin").child("kubens.exe").touch().unwrap();
 
         let db = test_data();
-        let generated = shims.generate_db_from_installed_tools().unwrap();
+        let (generated, conflicts) = shims.generate_db_from_installed_tools(&Config::default()).unwrap();
         assert_eq!(db, generated);
+        assert!(conflicts.is_empty(), "should not report conflicts");
+    }
+
+    #[test]
+    #[rustfmt::skip]
+    fn generate_shims_merges_executables_differing_by_case() {
+        let tmp_dir = TempDir::new().unwrap();
+        let paths = test_paths(&tmp_dir);
+        let shims = Shims::new(&paths.db_path, &paths.tools_install_dir, &paths.shims_dir, &paths.shim_exe).unwrap();
+        paths.tools_install_dir.child("kubectl").child("1.1").child("bin").create_dir_all().unwrap();
+        paths.tools_install_dir.child("kubectl").child("1.1").child("bin").child("kubectl.exe").touch().unwrap();
+        paths.tools_install_dir.child("kubectl").child("1.2.4").child("bin").create_dir_all().unwrap();
+        paths.tools_install_dir.child("kubectl").child("1.2.4").child("bin").child("Kubectl.EXE").touch().unwrap();
+        let (generated, conflicts) = shims.generate_db_from_installed_tools(&Config::default()).unwrap();
+        let expected = HashMap::from([("kubectl.exe".to_string(), ShimData::Tool("kubectl".to_string()))]);
+        assert_eq!(generated, expected);
+        assert!(conflicts.is_empty(), "should not report conflicts");
     }
 
     #[test]
@@ -285,7 +1448,7 @@ mod tests {
         paths.tools_install_dir.child("kubectl").child("1.1").child("bin").create_dir_all().unwrap();
         paths.tools_install_dir.child("kubectl").child("1.1").child("bin").child("kubectl.exe").touch().unwrap();
         paths.tools_install_dir.child("kubectl").child("1.1").child("bin").child("kubectl.txt").touch().unwrap();
-        let generated = shims.generate_db_from_installed_tools().unwrap();
+        let (generated, _) = shims.generate_db_from_installed_tools(&Config::default()).unwrap();
         assert!(!generated.contains_key("kubectl.txt"), "should not contain files with wrong extension");
     }
 
@@ -304,11 +1467,327 @@ mod tests {
         paths.tools_install_dir.child("kubectx").child("0.12").child("bin").child("kubectx.exe").touch().unwrap();
         paths.tools_install_dir.child("kubectx").child("0.12").child("bin").child("kubens.exe").touch().unwrap();
 
-        let res = shims.generate_db_from_installed_tools().unwrap_err();
+        let res = shims.generate_db_from_installed_tools(&Config::default()).unwrap_err();
         let err = format!("{:?}", res);
         assert!(err.contains("kubens.exe"), "Wrong error was triggered ({:?}), should contain 'kubens.exe'", err);
     }
 
+    #[rustfmt::skip]
+    fn fixture_conflicting_tools(paths: &TestPaths) {
+        paths.tools_install_dir.child("kubectl").child("1.2.4").child("bin").create_dir_all().unwrap();
+        paths.tools_install_dir.child("kubectl").child("1.2.4").child("bin").child("kubectl.exe").touch().unwrap();
+        paths.tools_install_dir.child("gcloud").child("370.0").child("bin").create_dir_all().unwrap();
+        paths.tools_install_dir.child("gcloud").child("370.0").child("bin").child("gcloud.exe").touch().unwrap();
+        paths.tools_install_dir.child("gcloud").child("370.0").child("bin").child("kubectl.exe").touch().unwrap();
+    }
+
+    #[test]
+    #[rustfmt::skip]
+    fn generate_shims_with_skip_policy_skips_conflicting_executables() {
+        let tmp_dir = TempDir::new().unwrap();
+        let paths = test_paths(&tmp_dir);
+        let shims = Shims::new(&paths.db_path, &paths.tools_install_dir, &paths.shims_dir, &paths.shim_exe).unwrap();
+        fixture_conflicting_tools(&paths);
+        let config = Config { conflict_policy: ConflictPolicy::Skip, ..Config::default() };
+        let (generated, conflicts) = shims.generate_db_from_installed_tools(&config).unwrap();
+        assert_eq!(generated, HashMap::from([("gcloud.exe".to_string(), ShimData::Tool("gcloud".to_string()))]));
+        let expected = vec![Conflict {
+            exe: "kubectl.exe".to_string(),
+            tools: vec!["gcloud".to_string(), "kubectl".to_string()],
+            selected: None,
+        }];
+        assert_eq!(conflicts, expected);
+    }
+
+    #[test]
+    #[rustfmt::skip]
+    fn find_and_remove_orphaned_shims() {
+        let tmp_dir = TempDir::new().unwrap();
+        let paths = test_paths(&tmp_dir);
+        let shims = Shims::new(&paths.db_path, &paths.tools_install_dir, &paths.shims_dir, &paths.shim_exe).unwrap();
+        fixture_conflicting_tools(&paths);
+        let config = Config { conflict_policy: ConflictPolicy::Skip, ..Config::default() };
+        let (db, _) = shims.generate_db_from_installed_tools(&config).unwrap();
+        shims.save_db(&db).unwrap();
+        shims.create_shims(false).unwrap();
+        paths.shims_dir.child("leftover.exe").touch().unwrap();
+        fs::remove_dir_all(paths.tools_install_dir.child("gcloud").path()).unwrap();
+        let orphans = shims.find_orphaned_shims(&config).unwrap();
+        assert_eq!(orphans, vec!["gcloud.exe", "leftover.exe"]);
+        shims.remove_shims(&orphans).unwrap();
+        assert!(shims.load_db().unwrap().is_empty(), "orphaned entries should be removed from db");
+        assert!(!paths.shims_dir.child("gcloud.exe").exists(), "orphaned shim should be removed");
+        assert!(shims.find_orphaned_shims(&config).unwrap().is_empty());
+    }
+
+    #[test]
+    #[rustfmt::skip]
+    fn repair_db_reconciles_db_and_shims() {
+        let tmp_dir = TempDir::new().unwrap();
+        let paths = test_paths(&tmp_dir);
+        let shims = Shims::new(&paths.db_path, &paths.tools_install_dir, &paths.shims_dir, &paths.shim_exe).unwrap();
+        fixture_conflicting_tools(&paths);
+        let config = Config { conflict_policy: ConflictPolicy::Skip, ..Config::default() };
+        shims.save_db(&HashMap::from([
+            ("gcloud.exe".to_string(), ShimData::Tool("kubectl".to_string())),
+            ("helm.exe".to_string(), ShimData::Tool("helm".to_string())),
+        ])).unwrap();
+        paths.shims_dir.child("helm.exe").touch().unwrap();
+        let repair = shims.repair_db(&config).unwrap();
+        assert_eq!(repair.discarded, None);
+        assert_eq!(repair.changed, vec!["gcloud.exe"]);
+        assert_eq!(repair.removed, vec!["helm.exe"]);
+        assert_eq!(repair.created_shims, vec!["gcloud.exe"]);
+        assert_eq!(repair.removed_shims, vec!["helm.exe"]);
+        assert_eq!(repair.conflicts.len(), 1);
+        assert_eq!(shims.load_db().unwrap(), HashMap::from([("gcloud.exe".to_string(), ShimData::Tool("gcloud".to_string()))]));
+        assert!(shims.repair_db(&config).unwrap().is_clean(), "second repair should find nothing");
+
+        fs::write(paths.db_path.path(), b"garbage").unwrap();
+        let repair = shims.repair_db(&config).unwrap();
+        assert!(repair.discarded.is_some(), "corrupt db should be reported");
+        assert_eq!(repair.added, vec!["gcloud.exe"]);
+        assert!(repair.created_shims.is_empty());
+
+        // Names differing only by case are the same executable
+        fs::rename(paths.shims_dir.child("gcloud.exe").path(), paths.shims_dir.child("GCloud.exe").path()).unwrap();
+        shims.save_db(&HashMap::from([("GCLOUD.exe".to_string(), ShimData::Tool("gcloud".to_string()))])).unwrap();
+        let repair = shims.repair_db(&config).unwrap();
+        assert!(repair.is_clean(), "case differences should be ignored: {:?}", repair);
+        assert!(paths.shims_dir.child("GCloud.exe").path().is_file());
+    }
+
+    #[test]
+    #[rustfmt::skip]
+    fn manual_shims_survive_regeneration() {
+        let tmp_dir = TempDir::new().unwrap();
+        let paths = test_paths(&tmp_dir);
+        let shims = Shims::new(&paths.db_path, &paths.tools_install_dir, &paths.shims_dir, &paths.shim_exe).unwrap();
+        paths.tools_install_dir.child("kubectl").child("1.2.4").child("bin").child("kubectl.exe").touch().unwrap();
+        let external = tmp_dir.child("opt").child("mytool.exe");
+        external.touch().unwrap();
+        shims.add_manual_shim("mytool.exe", "mytool", external.path()).unwrap();
+        assert!(paths.shims_dir.child("mytool.exe").exists(), "shim should be created");
+        let (generated, _) = shims.generate_db_from_installed_tools(&Config::default()).unwrap();
+        let expected = ShimData::Manual { tool: "mytool".to_string(), path: external.to_path_buf() };
+        assert_eq!(generated.get("mytool.exe"), Some(&expected));
+        assert_eq!(generated.get("kubectl.exe"), Some(&ShimData::Tool("kubectl".to_string())));
+        shims.save_db(&generated).unwrap();
+        assert!(shims.add_manual_shim("kubectl.exe", "mytool", external.path()).is_err(), "should not replace installed tools");
+        assert!(shims.remove_manual_shim("kubectl.exe").is_err(), "should only remove manual shims");
+        fs::remove_file(external.path()).unwrap();
+        assert_eq!(shims.find_orphaned_shims(&Config::default()).unwrap(), vec!["mytool.exe"]);
+        shims.remove_manual_shim("mytool.exe").unwrap();
+        assert_eq!(shims.find_shim("mytool.exe").unwrap(), None);
+        assert!(!paths.shims_dir.child("mytool.exe").exists(), "shim should be removed");
+    }
+
+    #[test]
+    #[rustfmt::skip]
+    fn manual_shims_are_matched_ignoring_case() {
+        let tmp_dir = TempDir::new().unwrap();
+        let paths = test_paths(&tmp_dir);
+        let shims = Shims::new(&paths.db_path, &paths.tools_install_dir, &paths.shims_dir, &paths.shim_exe).unwrap();
+        paths.tools_install_dir.child("kubectl").child("1.2.4").child("bin").child("kubectl.exe").touch().unwrap();
+        let (generated, _) = shims.generate_db_from_installed_tools(&Config::default()).unwrap();
+        shims.save_db(&generated).unwrap();
+        let external = tmp_dir.child("opt").child("mytool.exe");
+        external.touch().unwrap();
+        assert!(shims.add_manual_shim("Kubectl.exe", "mytool", external.path()).is_err(), "should not replace installed tools");
+        assert_eq!(shims.add_manual_shim("mytool.exe", "mytool", external.path()).unwrap(), "mytool.exe");
+        assert_eq!(shims.add_manual_shim("MyTool.exe", "other", external.path()).unwrap(), "mytool.exe");
+        let db = shims.load_db().unwrap();
+        assert_eq!(db.len(), 2, "wrong db: {:?}", db);
+        let expected = ShimData::Manual { tool: "other".to_string(), path: external.to_path_buf() };
+        assert_eq!(db.get("mytool.exe"), Some(&expected));
+        assert_eq!(shims.remove_manual_shim("MYTOOL.exe").unwrap(), "mytool.exe");
+        assert_eq!(shims.load_db().unwrap().keys().collect::<Vec<_>>(), vec!["kubectl.exe"]);
+        assert!(!paths.shims_dir.child("mytool.exe").exists(), "shim should be removed");
+    }
+
+    #[test]
+    #[rustfmt::skip]
+    fn generate_shims_ignores_disabled_tools() {
+        let tmp_dir = TempDir::new().unwrap();
+        let paths = test_paths(&tmp_dir);
+        let shims = Shims::new(&paths.db_path, &paths.tools_install_dir, &paths.shims_dir, &paths.shim_exe).unwrap();
+        fixture_conflicting_tools(&paths);
+        let config: Config = toml::from_str("[tools.gcloud]\ndisabled = true\n").unwrap();
+        let (generated, conflicts) = shims.generate_db_from_installed_tools(&config).unwrap();
+        assert_eq!(generated, HashMap::from([("kubectl.exe".to_string(), ShimData::Tool("kubectl".to_string()))]));
+        assert!(conflicts.is_empty(), "disabled tool should not conflict");
+    }
+
+    #[test]
+    #[rustfmt::skip]
+    fn generate_shims_with_priority_policy_selects_tool_by_priority() {
+        let tmp_dir = TempDir::new().unwrap();
+        let paths = test_paths(&tmp_dir);
+        let shims = Shims::new(&paths.db_path, &paths.tools_install_dir, &paths.shims_dir, &paths.shim_exe).unwrap();
+        fixture_conflicting_tools(&paths);
+        let config = Config {
+            conflict_policy: ConflictPolicy::Priority,
+            tool_priority: vec!["other".to_string(), "kubectl".to_string(), "gcloud".to_string()],
+            ..Config::default()
+        };
+        let (generated, conflicts) = shims.generate_db_from_installed_tools(&config).unwrap();
+        assert_eq!(generated.get("kubectl.exe"), Some(&ShimData::Tool("kubectl".to_string())));
+        assert_eq!(conflicts[0].selected, Some("kubectl".to_string()));
+    }
+
+    #[test]
+    #[rustfmt::skip]
+    fn generate_shims_with_priority_policy_fails_if_no_tool_has_priority() {
+        let tmp_dir = TempDir::new().unwrap();
+        let paths = test_paths(&tmp_dir);
+        let shims = Shims::new(&paths.db_path, &paths.tools_install_dir, &paths.shims_dir, &paths.shim_exe).unwrap();
+        fixture_conflicting_tools(&paths);
+        let config = Config { conflict_policy: ConflictPolicy::Priority, ..Config::default() };
+        let res = shims.generate_db_from_installed_tools(&config);
+        assert!(res.is_err(), "conflict without priority should fail");
+    }
+
+    #[test]
+    #[rustfmt::skip]
+    fn generate_shims_includes_tools_from_custom_install_dirs() {
+        let tmp_dir = TempDir::new().unwrap();
+        let paths = test_paths(&tmp_dir);
+        let custom_dir = tmp_dir.child("sdk");
+        custom_dir.child("31.0").child("bin").create_dir_all().unwrap();
+        custom_dir.child("31.0").child("bin").child("adb.exe").touch().unwrap();
+        let shims = Shims::new(&paths.db_path, &paths.tools_install_dir, &paths.shims_dir, &paths.shim_exe)
+            .unwrap()
+            .with_tool_install_dirs(HashMap::from([("android-sdk", custom_dir.path())]));
+        let (generated, _) = shims.generate_db_from_installed_tools(&Config::default()).unwrap();
+        assert_eq!(generated, HashMap::from([("adb.exe".to_string(), ShimData::Tool("android-sdk".to_string()))]));
+        let result = shims.get_full_executable_path("adb.exe", "android-sdk", "31.0").unwrap();
+        assert_eq!(result, Some(custom_dir.child("31.0").child("bin").child("adb.exe").to_path_buf()));
+    }
+
+    #[test]
+    #[rustfmt::skip]
+    fn generate_shims_includes_executables_of_additional_bin_dirs() {
+        let tmp_dir = TempDir::new().unwrap();
+        let paths = test_paths(&tmp_dir);
+        let version_dir = paths.tools_install_dir.child("python").child("3.11.4");
+        version_dir.child("bin").child("python.exe").touch().unwrap();
+        let shims = Shims::new(&paths.db_path, &paths.tools_install_dir, &paths.shims_dir, &paths.shim_exe)
+            .unwrap()
+            .with_tool_bin_dirs(HashMap::from([("python", vec!["Scripts"])]));
+        let (generated, _) = shims.generate_db_from_installed_tools(&Config::default()).unwrap();
+        assert_eq!(generated.keys().collect::<Vec<_>>(), ["python.exe"], "missing dirs should be skipped");
+
+        version_dir.child("Scripts").child("black.exe").touch().unwrap();
+        let (generated, _) = shims.generate_db_from_installed_tools(&Config::default()).unwrap();
+        assert_eq!(generated.get("black.exe"), Some(&ShimData::Tool("python".to_string())));
+        let result = shims.get_full_executable_path("black.exe", "python", "3.11.4").unwrap();
+        assert_eq!(result, Some(version_dir.child("Scripts").child("black.exe").to_path_buf()));
+    }
+
+    #[test]
+    #[rustfmt::skip]
+    fn generate_shims_includes_launchers_of_additional_bin_dirs() {
+        let tmp_dir = TempDir::new().unwrap();
+        let paths = test_paths(&tmp_dir);
+        let version_dir = paths.tools_install_dir.child("nodejs").child("18.16.0");
+        version_dir.child("bin").child("node.exe").touch().unwrap();
+        version_dir.child("bin").child("setup.cmd").touch().unwrap();
+        version_dir.child("npm").child("tsc.cmd").touch().unwrap();
+        version_dir.child("npm").child("tsc").touch().unwrap();
+        let shims = Shims::new(&paths.db_path, &paths.tools_install_dir, &paths.shims_dir, &paths.shim_exe)
+            .unwrap()
+            .with_tool_bin_dirs(HashMap::from([("nodejs", vec!["npm"])]));
+        let (generated, _) = shims.generate_db_from_installed_tools(&Config::default()).unwrap();
+        let mut names = generated.keys().collect::<Vec<_>>();
+        names.sort();
+        assert_eq!(names, ["node.exe", "tsc.exe"], "only launchers of additional bin dirs get shims");
+        let expected = version_dir.child("npm").child("tsc.cmd").to_path_buf();
+        let result = shims.get_full_executable_path("tsc.exe", "nodejs", "18.16.0").unwrap();
+        assert_eq!(result, Some(expected.clone()));
+        assert_eq!(shims.find_in_bin_dirs("nodejs", &version_dir, "tsc.exe"), Some(expected));
+        assert_eq!(shims.find_in_bin_dirs("nodejs", &version_dir, "setup.exe"), None);
+    }
+
+    #[test]
+    #[rustfmt::skip]
+    fn generate_shims_includes_configured_scripts() {
+        let tmp_dir = TempDir::new().unwrap();
+        let paths = test_paths(&tmp_dir);
+        let shims = Shims::new(&paths.db_path, &paths.tools_install_dir, &paths.shims_dir, &paths.shim_exe).unwrap();
+        let bin = paths.tools_install_dir.child("httpie").child("3.2").child("bin");
+        bin.child("http").touch().unwrap();
+        bin.child("README").touch().unwrap();
+        let config: Config = toml::from_str("[tools.httpie.scripts]\nhttp = 'python'\nhttps = 'python'\n").unwrap();
+        let (generated, _) = shims.generate_db_from_installed_tools(&config).unwrap();
+        assert_eq!(generated.get("http.exe"), Some(&ShimData::Tool("httpie".to_owned())));
+        assert!(!generated.contains_key("https.exe"), "missing scripts should not get shims");
+        assert!(!generated.contains_key("README.exe"), "unmapped files should not get shims");
+    }
+
+    #[test]
+    #[rustfmt::skip]
+    fn generate_shims_ignores_current_link() {
+        let tmp_dir = TempDir::new().unwrap();
+        let paths = test_paths(&tmp_dir);
+        let shims = Shims::new(&paths.db_path, &paths.tools_install_dir, &paths.shims_dir, &paths.shim_exe).unwrap();
+        paths.tools_install_dir.child("kubectl").child("1.2.4").child("bin").create_dir_all().unwrap();
+        paths.tools_install_dir.child("kubectl").child("1.2.4").child("bin").child("kubectl.exe").touch().unwrap();
+        paths.tools_install_dir.child("kubectl").child(CURRENT_LINK).child("bin").create_dir_all().unwrap();
+        paths.tools_install_dir.child("kubectl").child(CURRENT_LINK).child("bin").child("other.exe").touch().unwrap();
+        let (generated, _) = shims.generate_db_from_installed_tools(&Config::default()).unwrap();
+        assert!(!generated.contains_key("other.exe"), "should not scan the current link");
+    }
+
+    #[cfg(windows)]
+    #[test]
+    #[rustfmt::skip]
+    fn update_current_link_moves_to_the_custom_install_dir() {
+        let tmp_dir = TempDir::new().unwrap();
+        let paths = test_paths(&tmp_dir);
+        let custom_dir = tmp_dir.child("android-sdk");
+        custom_dir.child("31.0").child("bin").child("adb.exe").touch().unwrap();
+        paths.tools_install_dir.child("android-sdk").child("30.0").child("bin").child("adb.exe").touch().unwrap();
+        let shims = Shims::new(&paths.db_path, &paths.tools_install_dir, &paths.shims_dir, &paths.shim_exe)
+            .unwrap()
+            .with_tool_install_dirs(HashMap::from([("android-sdk", custom_dir.path())]));
+        let old_link = shims.update_current_link("android-sdk", "30.0").unwrap();
+        let link = shims.update_current_link("android-sdk", "31.0").unwrap();
+        assert_eq!(link, custom_dir.child(CURRENT_LINK).to_path_buf());
+        assert!(fs::symlink_metadata(&old_link).is_err(), "the previous junction should be removed");
+
+        fs::remove_dir_all(custom_dir.child("31.0").path()).unwrap();
+        shims.remove_dangling_current_links("android-sdk").unwrap();
+        assert!(fs::symlink_metadata(&link).is_err(), "the dangling junction should be removed");
+    }
+
+    #[test]
+    fn update_current_link_skips_path_versions() {
+        let tmp_dir = TempDir::new().unwrap();
+        let paths = test_paths(&tmp_dir);
+        #[rustfmt::skip]
+        let shims = Shims::new(&paths.db_path, &paths.tools_install_dir, &paths.shims_dir, &paths.shim_exe).unwrap();
+        let build = tmp_dir.child("build");
+        build.child("bin").child("tool.exe").touch().unwrap();
+        let version = format!("{}{}", PATH_VERSION_PREFIX, build.path().display());
+        assert!(shims.update_current_link("mytool", &version).is_err());
+        assert!(!tmp_dir.child(CURRENT_LINK).exists(), "no junction next to the build directory");
+    }
+
+    #[cfg(windows)]
+    #[test]
+    #[rustfmt::skip]
+    fn update_current_link_points_at_version() {
+        let tmp_dir = TempDir::new().unwrap();
+        let paths = test_paths(&tmp_dir);
+        let shims = Shims::new(&paths.db_path, &paths.tools_install_dir, &paths.shims_dir, &paths.shim_exe).unwrap();
+        paths.tools_install_dir.child("kubectl").child("1.1").child("bin").child("kubectl.exe").touch().unwrap();
+        paths.tools_install_dir.child("kubectl").child("1.2.4").child("bin").child("kubectl.exe").touch().unwrap();
+        shims.update_current_link("kubectl", "1.1").unwrap();
+        let link = shims.update_current_link("kubectl", "1.2.4").unwrap();
+        assert_eq!(fs::canonicalize(&link).unwrap(), fs::canonicalize(paths.tools_install_dir.child("kubectl").child("1.2.4")).unwrap());
+        assert!(paths.tools_install_dir.child("kubectl").child("1.1").child("bin").child("kubectl.exe").exists());
+    }
+
     #[test]
     fn test_get_full_executable_path_when_version_does_not_exist_returns_none() {
         let tmp_dir = TempDir::new().unwrap();
@@ -368,6 +1847,35 @@ mod tests {
         assert!(dangling.exists(), "dangling file was deleted in create shims without cleanup");
     }
 
+    #[rstest]
+    #[case(false, vec![], "without cleanup")]
+    #[case(true, vec!["invalid.exe".to_string()], "with cleanup")]
+    fn plan_shims_reports_changes(#[case] cleanup: bool, #[case] removed: Vec<String>, #[case] msg: &str) {
+        let db = test_data();
+        let tmp_dir = TempDir::new().unwrap();
+        let paths = test_paths(&tmp_dir);
+        #[rustfmt::skip]
+        let shims = Shims::new(&paths.db_path, &paths.tools_install_dir, &paths.shims_dir, &paths.shim_exe).unwrap();
+        paths.shims_dir.child("invalid.exe").touch().unwrap();
+        paths.shims_dir.child("docker.exe").touch().unwrap();
+        let changes = shims.plan_shims(&db, cleanup).unwrap();
+        let expected = ShimsChanges {
+            created: vec!["kubectl.exe", "kubectx.exe", "kubens.exe", "minikube.exe"]
+                .iter()
+                .map(|s| s.to_string())
+                .collect(),
+            updated: vec!["docker.exe".to_string()],
+            removed,
+        };
+        assert_eq!(changes, expected, "{}", msg);
+        assert_eq!(
+            paths.shims_dir.read_dir().unwrap().count(),
+            2,
+            "{}: plan should not touch shims",
+            msg
+        );
+    }
+
     #[test]
     #[rustfmt::skip]
     fn create_shims_with_cleanup_removes_dangling_shims() {
@@ -381,5 +1889,39 @@ mod tests {
         shims.create_shims(true).unwrap();
         assert!(!dangling.exists(), "dangling file was not deleted in create shims with cleanup");
         assert_eq!(shims.shims_dir.read_dir().unwrap().count(), 5);
+        assert!(!tmp_dir.child(".staging-shims").exists(), "staging directory left behind");
+        assert!(!tmp_dir.child(".staging-previous-shims").exists(), "previous shims left behind");
+    }
+
+    #[test]
+    fn create_shims_with_cleanup_keeps_existing_shims_on_failure() {
+        let db = test_data();
+        let tmp_dir = TempDir::new().unwrap();
+        let paths = test_paths(&tmp_dir);
+        let missing_shim_exe = tmp_dir.child("no-such-shim.exe");
+        let shims = Shims::new(&paths.db_path, &paths.tools_install_dir, &paths.shims_dir, &missing_shim_exe).unwrap();
+        let existing = shims.shims_dir.join("kubectl.exe");
+        fs::write(&existing, "shim").unwrap();
+        shims.save_db(&db).unwrap();
+        assert!(shims.create_shims(true).is_err());
+        assert!(existing.exists(), "existing shim was removed by a failed cleanup");
+        assert!(!tmp_dir.child(".staging-shims").exists(), "staging directory left behind");
+    }
+
+    #[test]
+    fn create_shims_restores_shims_dir_moved_aside_by_interrupted_cleanup() {
+        let db = test_data();
+        let tmp_dir = TempDir::new().unwrap();
+        let paths = test_paths(&tmp_dir);
+        let shims = Shims::new(&paths.db_path, &paths.tools_install_dir, &paths.shims_dir, &paths.shim_exe).unwrap();
+        let previous = tmp_dir.child(".staging-previous-shims");
+        fs::rename(&shims.shims_dir, previous.path()).unwrap();
+        previous.child("invalid.exe").touch().unwrap();
+        shims.save_db(&db).unwrap();
+        let changes = shims.plan_shims(&db, true).unwrap();
+        assert_eq!(changes.created.len(), 5);
+        shims.create_shims(false).unwrap();
+        assert!(shims.shims_dir.join("invalid.exe").exists(), "previous shims were not restored");
+        assert!(!previous.exists());
     }
 }
@@ -0,0 +1,255 @@
+use anyhow::{anyhow, Context, Result};
+use log::debug;
+use std::collections::HashMap;
+use std::fs::{self, OpenOptions};
+use std::io::{ErrorKind, Write};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// A single command executed through a shim. Saved as a tab separated line
+/// (`<timestamp> <command> <tool> <version> <resolution> <duration> <exit
+/// code>`) with the timestamp in seconds since the epoch and the durations in
+/// microseconds.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Invocation {
+    pub timestamp: u64,
+    pub command: String,
+    pub tool: String,
+    pub version: String,
+    /// The time it took the shim to find the executable.
+    pub resolution: Duration,
+    /// The time the executable ran.
+    pub duration: Duration,
+    pub exit_code: i32,
+}
+
+/// Aggregated invocations of a single command.
+#[derive(Debug, PartialEq, Eq)]
+pub struct CommandStats {
+    pub command: String,
+    pub count: usize,
+    pub total_duration: Duration,
+}
+
+impl CommandStats {
+    pub fn average_duration(&self) -> Duration {
+        self.total_duration / self.count as u32
+    }
+}
+
+impl Invocation {
+    fn to_line(&self) -> String {
+        format!(
+            "{}\t{}\t{}\t{}\t{}\t{}\t{}\n",
+            self.timestamp,
+            &self.command,
+            &self.tool,
+            &self.version,
+            self.resolution.as_micros(),
+            self.duration.as_micros(),
+            self.exit_code
+        )
+    }
+
+    fn parse(line: &str) -> Result<Self> {
+        let fields: Vec<&str> = line.split('\t').collect();
+        if fields.len() != 7 {
+            return Err(anyhow!("expected 7 fields, found {}", fields.len()));
+        }
+        Ok(Invocation {
+            timestamp: fields[0].parse()?,
+            command: fields[1].to_owned(),
+            tool: fields[2].to_owned(),
+            version: fields[3].to_owned(),
+            resolution: Duration::from_micros(fields[4].parse()?),
+            duration: Duration::from_micros(fields[5].parse()?),
+            exit_code: fields[6].parse()?,
+        })
+    }
+}
+
+/// Returns the current time in seconds since the epoch.
+pub fn now() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |d| d.as_secs())
+}
+
+/// Once the stats file grows beyond this size it's rotated (see `record`).
+pub const MAX_STATS_FILE_SIZE: u64 = 1024 * 1024;
+
+/// The previous stats file, kept after rotating (`<stats file>.1`).
+pub fn rotated_path(path: &Path) -> PathBuf {
+    let mut name = path.file_name().unwrap_or_default().to_os_string();
+    name.push(".1");
+    path.with_file_name(name)
+}
+
+/// Appends the invocation to the stats file. The file is rotated first if it
+/// exceeds `MAX_STATS_FILE_SIZE`, replacing the previous rotated file, so the
+/// stats never take more than twice that size.
+pub fn record(path: &Path, invocation: &Invocation) -> Result<()> {
+    record_with_limit(path, invocation, MAX_STATS_FILE_SIZE)
+}
+
+fn record_with_limit(path: &Path, invocation: &Invocation, max_size: u64) -> Result<()> {
+    let context = || format!("recording stats in {:?}", &path);
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent).with_context(context)?;
+    }
+    if matches!(fs::metadata(path), Ok(metadata) if metadata.len() >= max_size) {
+        debug!("Rotating stats file {:?}", &path);
+        match fs::rename(path, rotated_path(path)) {
+            // another shim rotated it in the meantime
+            Err(err) if err.kind() == ErrorKind::NotFound => {}
+            result => result.with_context(context)?,
+        }
+    }
+    let mut file = OpenOptions::new().create(true).append(true).open(path).with_context(context)?;
+    file.write_all(invocation.to_line().as_bytes()).with_context(context)
+}
+
+/// Loads all the recorded invocations (oldest first), including the rotated
+/// file. A missing file means no invocations and malformed lines (e.g. a
+/// partial write) are skipped.
+pub fn load(path: &Path) -> Result<Vec<Invocation>> {
+    let mut invocations = vec![];
+    for path in [rotated_path(path), path.to_path_buf()] {
+        if !path.exists() {
+            continue;
+        }
+        let contents = fs::read_to_string(&path).context(format!("loading stats from {:?}", &path))?;
+        for line in contents.lines().filter(|l| !l.trim().is_empty()) {
+            match Invocation::parse(line) {
+                Ok(invocation) => invocations.push(invocation),
+                Err(err) => debug!("Skipping invalid stats line '{}': {}", &line, err),
+            }
+        }
+    }
+    Ok(invocations)
+}
+
+/// The most invoked commands (up to `limit`).
+pub fn top_commands(invocations: &[Invocation], limit: usize) -> Vec<CommandStats> {
+    let mut by_command: HashMap<&str, CommandStats> = HashMap::new();
+    for invocation in invocations {
+        let stats = by_command.entry(&invocation.command).or_insert_with(|| CommandStats {
+            command: invocation.command.clone(),
+            count: 0,
+            total_duration: Duration::ZERO,
+        });
+        stats.count += 1;
+        stats.total_duration += invocation.duration;
+    }
+    let mut stats: Vec<CommandStats> = by_command.into_values().collect();
+    stats.sort_by(|a, b| b.count.cmp(&a.count).then_with(|| a.command.cmp(&b.command)));
+    stats.truncate(limit);
+    stats
+}
+
+/// The invocations with the slowest resolution (up to `limit`).
+pub fn slowest_resolutions(invocations: &[Invocation], limit: usize) -> Vec<&Invocation> {
+    let mut slowest: Vec<&Invocation> = invocations.iter().collect();
+    slowest.sort_by(|a, b| b.resolution.cmp(&a.resolution));
+    slowest.truncate(limit);
+    slowest
+}
+
+/// The most recent invocations that exited with a non zero code (up to
+/// `limit`, newest first).
+pub fn recent_failures(invocations: &[Invocation], limit: usize) -> Vec<&Invocation> {
+    invocations.iter().rev().filter(|i| i.exit_code != 0).take(limit).collect()
+}
+
+/// Formats the time passed since the timestamp (e.g. `5m ago`).
+pub fn format_age(timestamp: u64, now: u64) -> String {
+    let seconds = now.saturating_sub(timestamp);
+    match seconds {
+        0..=59 => format!("{}s ago", seconds),
+        60..=3599 => format!("{}m ago", seconds / 60),
+        3600..=86399 => format!("{}h ago", seconds / 3600),
+        _ => format!("{}d ago", seconds / 86400),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use assert_fs::{prelude::*, TempDir};
+    use rstest::rstest;
+
+    fn invocation(timestamp: u64, command: &str, resolution_ms: u64, exit_code: i32) -> Invocation {
+        Invocation {
+            timestamp,
+            command: command.to_owned(),
+            tool: "mytool".to_owned(),
+            version: "1.0".to_owned(),
+            resolution: Duration::from_millis(resolution_ms),
+            duration: Duration::from_millis(100),
+            exit_code,
+        }
+    }
+
+    #[test]
+    fn record_and_load_roundtrip_skipping_invalid_lines() {
+        let tmp_dir = TempDir::new().unwrap();
+        let file = tmp_dir.child("stats.log");
+        record(file.path(), &invocation(1, "kubectl.exe", 3, 0)).unwrap();
+        fs::write(file.path(), fs::read_to_string(file.path()).unwrap() + "garbage\n").unwrap();
+        record(file.path(), &invocation(2, "helm.exe", 5, 1)).unwrap();
+        let loaded = load(file.path()).unwrap();
+        assert_eq!(loaded, vec![invocation(1, "kubectl.exe", 3, 0), invocation(2, "helm.exe", 5, 1)]);
+    }
+
+    #[test]
+    fn record_rotates_full_file() {
+        let tmp_dir = TempDir::new().unwrap();
+        let file = tmp_dir.child("stats.log");
+        let max_size = invocation(1, "kubectl.exe", 3, 0).to_line().len() as u64 * 2;
+        for timestamp in 1..=5 {
+            record_with_limit(file.path(), &invocation(timestamp, "kubectl.exe", 3, 0), max_size).unwrap();
+        }
+        let timestamps = |path: &Path| -> Vec<u64> { load_file(path).iter().map(|i| i.timestamp).collect() };
+        assert_eq!(timestamps(&rotated_path(file.path())), vec![3, 4]);
+        assert_eq!(timestamps(file.path()), vec![5]);
+        let loaded: Vec<u64> = load(file.path()).unwrap().iter().map(|i| i.timestamp).collect();
+        assert_eq!(loaded, vec![3, 4, 5]);
+    }
+
+    fn load_file(path: &Path) -> Vec<Invocation> {
+        let contents = fs::read_to_string(path).unwrap();
+        contents.lines().map(|line| Invocation::parse(line).unwrap()).collect()
+    }
+
+    #[test]
+    fn load_missing_file_returns_no_invocations() {
+        let tmp_dir = TempDir::new().unwrap();
+        assert_eq!(load(tmp_dir.child("stats.log").path()).unwrap(), vec![]);
+    }
+
+    #[test]
+    fn summaries_of_invocations() {
+        let invocations = vec![
+            invocation(1, "helm.exe", 2, 0),
+            invocation(2, "kubectl.exe", 9, 1),
+            invocation(3, "kubectl.exe", 1, 0),
+            invocation(4, "helm.exe", 4, 2),
+            invocation(5, "kubectl.exe", 3, 0),
+        ];
+        let top = top_commands(&invocations, 5);
+        let top: Vec<(&str, usize)> = top.iter().map(|s| (s.command.as_str(), s.count)).collect();
+        assert_eq!(top, vec![("kubectl.exe", 3), ("helm.exe", 2)]);
+        let slowest: Vec<u64> = slowest_resolutions(&invocations, 2).iter().map(|i| i.timestamp).collect();
+        assert_eq!(slowest, vec![2, 4]);
+        let failures: Vec<u64> = recent_failures(&invocations, 5).iter().map(|i| i.timestamp).collect();
+        assert_eq!(failures, vec![4, 2]);
+    }
+
+    #[rstest]
+    #[case(100, 100, "0s ago")]
+    #[case(100, 159, "59s ago")]
+    #[case(100, 400, "5m ago")]
+    #[case(100, 7300, "2h ago")]
+    #[case(100, 200_000, "2d ago")]
+    fn format_age_of_timestamps(#[case] timestamp: u64, #[case] now: u64, #[case] expected: &str) {
+        assert_eq!(format_age(timestamp, now), expected);
+    }
+}
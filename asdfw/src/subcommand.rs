@@ -1,33 +1,255 @@
 use anyhow::{Context, Result};
-use std::{ffi::OsStr, path::Path, process::Command};
+use std::{
+    ffi::{OsStr, OsString},
+    path::{Path, PathBuf},
+    process::{Child, Command, Output, Stdio},
+    time::Duration,
+};
+
+pub const TOOL_VARIABLE: &str = "ASDFW_TOOL";
+pub const TOOL_VERSION_VARIABLE: &str = "ASDFW_TOOL_VERSION";
+pub const INSTALL_DIR_VARIABLE: &str = "ASDFW_INSTALL_DIR";
+pub const SHIM_DEPTH_VARIABLE: &str = "ASDFW_SHIM_DEPTH";
+/// The directory the version was resolved for.
+pub const RESOLVED_DIR_VARIABLE: &str = "ASDFW_RESOLVED_DIR";
+/// The commands of the outer shims (`;` separated, outermost first), set by
+/// each shim for the executable it runs.
+pub const SHIM_CHAIN_VARIABLE: &str = "ASDFW_SHIM_CHAIN";
+/// A command nested in this many shims of itself is considered an endless
+/// loop.
+pub const MAX_SHIM_REPEATS: usize = 5;
+
+/// The resolved tool and version an executable is running under. Exposed to
+/// the child process (and anything it runs) as `ASDFW_TOOL`,
+/// `ASDFW_TOOL_VERSION` and `ASDFW_INSTALL_DIR`.
+#[derive(Debug)]
+pub struct ExecutableContext<'a> {
+    pub tool: &'a str,
+    pub version: &'a str,
+    pub install_dir: &'a Path,
+    /// The number of shims this invocation is nested in (0 when invoked
+    /// directly).
+    pub depth: u32,
+    /// Extra environment variables for the child (on top of the inherited
+    /// ones).
+    pub env: &'a [(String, String)],
+    /// The directory to run the child in (defaults to the current directory).
+    pub cwd: Option<&'a Path>,
+    /// The script to pass to the command (when the command is the
+    /// interpreter of a configured script).
+    pub script: Option<&'a Path>,
+    /// Arguments passed before the user's arguments.
+    pub default_args: &'a [String],
+    /// Kill the command (and the processes it started) if it does not finish
+    /// in time. Only `asdfw exec` sets it (`ResolvedCommand::context` leaves
+    /// it unset), so shims never time out.
+    pub timeout: Option<Duration>,
+}
+
+impl<'a> ExecutableContext<'a> {
+    /// Builds the command running `cmd`. Batch launchers (`.cmd`/`.bat`) are
+    /// run by the standard library through `cmd.exe /d /c`, which also escapes
+    /// the arguments for it.
+    pub fn mk_command<I, S>(&self, cmd: &Path, args: I) -> Command
+    where
+        I: IntoIterator<Item = S>,
+        S: AsRef<OsStr>,
+    {
+        let mut command = Command::new(&cmd);
+        if let Some(cwd) = self.cwd {
+            command.current_dir(cwd);
+        }
+        command
+            .args(self.script)
+            .args(self.default_args)
+            .args(args)
+            .envs(self.env.iter().map(|(key, value)| (key, value)))
+            .env(TOOL_VARIABLE, self.tool)
+            .env(TOOL_VERSION_VARIABLE, self.version)
+            .env(INSTALL_DIR_VARIABLE, self.install_dir)
+            .env(SHIM_DEPTH_VARIABLE, (self.depth + 1).to_string());
+        if let Some(dir) = self.cwd.map(Path::to_path_buf).or_else(|| std::env::current_dir().ok()) {
+            command.env(RESOLVED_DIR_VARIABLE, dir);
+        }
+        command
+    }
+
+    /// The program and every argument the command actually runs with (the
+    /// script and default arguments followed by the user's arguments).
+    pub fn argv<I, S>(&self, cmd: &Path, args: I) -> Vec<OsString>
+    where
+        I: IntoIterator<Item = S>,
+        S: AsRef<OsStr>,
+    {
+        let command = self.mk_command(cmd, args);
+        let mut argv = vec![command.get_program().to_os_string()];
+        argv.extend(command.get_args().map(OsStr::to_os_string));
+        argv
+    }
+}
+
+/// The tool and version resolved by an outer shim, for the directory it was
+/// resolved for.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct InheritedContext {
+    pub tool: String,
+    pub version: String,
+    pub install_dir: PathBuf,
+    pub dir: PathBuf,
+}
+
+/// The context resolved by the outer shim (`None` if not nested).
+pub fn inherited_context() -> Option<InheritedContext> {
+    if shim_depth() == 0 {
+        return None;
+    }
+    Some(InheritedContext {
+        tool: std::env::var(TOOL_VARIABLE).ok()?,
+        version: std::env::var(TOOL_VERSION_VARIABLE).ok()?,
+        install_dir: std::env::var_os(INSTALL_DIR_VARIABLE).map(PathBuf::from)?,
+        dir: std::env::var_os(RESOLVED_DIR_VARIABLE).map(PathBuf::from)?,
+    })
+}
+
+/// Returns the commands of the outer shims (outermost first).
+pub fn shim_chain() -> Vec<String> {
+    std::env::var(SHIM_CHAIN_VARIABLE)
+        .unwrap_or_default()
+        .split(';')
+        .filter(|name| !name.is_empty())
+        .map(str::to_owned)
+        .collect()
+}
+
+/// How many times the command appears in the chain of outer shims.
+pub fn shim_repeats(chain: &[String], command: &str) -> usize {
+    chain.iter().filter(|name| name.eq_ignore_ascii_case(command)).count()
+}
+
+/// Returns the nesting depth of the current shim invocation as set by the
+/// outer shim (0 if not nested).
+pub fn shim_depth() -> u32 {
+    std::env::var(SHIM_DEPTH_VARIABLE)
+        .ok()
+        .and_then(|depth| depth.parse().ok())
+        .unwrap_or(0)
+}
 
 /// A sort of `exec` implementation. Windows does not really have `exec` so we
 /// are wrapping the executable to run and returning it's exit code (passing all
-/// signals into the child process).
-pub fn exec<I, S>(cmd: &Path, args: I) -> Result<i32>
+/// signals into the child process). If the context has a timeout and the
+/// command does not finish in time, the command and every process it started
+/// are killed and a `TimedOut` error is returned.
+pub fn exec<I, S>(cmd: &Path, args: I, context: &ExecutableContext) -> Result<i32>
+where
+    I: IntoIterator<Item = S>,
+    S: AsRef<OsStr>,
+{
+    let mut command = context.mk_command(cmd, args);
+    imp::wrap_exec(&mut command, context.timeout).context(format!("Executing command: {:?}", &cmd))
+}
+
+/// Runs the command to completion, capturing its stdout and stderr (stdin is
+/// not inherited). Unlike `exec`, a non-zero exit code is not an error, it's
+/// available in the returned status.
+pub fn output<I, S>(cmd: &Path, args: I, context: &ExecutableContext) -> Result<Output>
+where
+    I: IntoIterator<Item = S>,
+    S: AsRef<OsStr>,
+{
+    context
+        .mk_command(cmd, args)
+        .stdin(Stdio::null())
+        .output()
+        .context(format!("Executing command: {:?}", &cmd))
+}
+
+/// Starts the command without waiting for it, leaving the standard streams
+/// as configured by `configure` (inherited by default).
+pub fn spawn<I, S, F>(cmd: &Path, args: I, context: &ExecutableContext, configure: F) -> Result<Child>
 where
     I: IntoIterator<Item = S>,
     S: AsRef<OsStr>,
+    F: FnOnce(&mut Command),
 {
-    let mut command = Command::new(&cmd);
-    command.args(args);
-    imp::wrap_exec(&mut command).context(format!("Executing command: {:?}", &cmd))
+    let mut command = context.mk_command(cmd, args);
+    configure(&mut command);
+    command.spawn().context(format!("Executing command: {:?}", &cmd))
+}
+
+/// Joins the arguments into a command line, quoting them the way Windows
+/// programs split their command line.
+pub fn command_line<S: AsRef<str>>(args: &[S]) -> String {
+    let quoted: Vec<String> = args.iter().map(|arg| quote_arg(arg.as_ref())).collect();
+    quoted.join(" ")
+}
+
+fn quote_arg(arg: &str) -> String {
+    if !arg.is_empty() && !arg.contains(&[' ', '\t', '"'][..]) {
+        return arg.to_owned();
+    }
+    let mut quoted = String::from('"');
+    let mut backslashes = 0;
+    for c in arg.chars() {
+        match c {
+            '\\' => backslashes += 1,
+            '"' => {
+                // Backslashes preceding a quote (and the quote) are escaped
+                quoted.push_str(&"\\".repeat(backslashes * 2 + 1));
+                backslashes = 0;
+            }
+            _ => {
+                quoted.push_str(&"\\".repeat(backslashes));
+                backslashes = 0;
+            }
+        }
+        if c != '\\' {
+            quoted.push(c);
+        }
+    }
+    quoted.push_str(&"\\".repeat(backslashes * 2));
+    quoted.push('"');
+    quoted
 }
 
 // The idea for this wrapping of executable was taken from cargo-utils
 #[cfg(windows)]
 pub(super) mod imp {
     use anyhow::{anyhow, Result};
-    use std::process::Command;
+    use std::fmt::Display;
+    use std::io;
+    use std::os::windows::io::{AsRawHandle, FromRawHandle, OwnedHandle};
+    use std::os::windows::process::CommandExt;
+    use std::process::{Command, ExitStatus};
+    use std::ptr;
+    use std::time::Duration;
     use winapi::shared::minwindef::{BOOL, DWORD, FALSE, TRUE};
+    use winapi::shared::winerror::WAIT_TIMEOUT;
     use winapi::um::consoleapi::SetConsoleCtrlHandler;
+    use winapi::um::handleapi::{CloseHandle, INVALID_HANDLE_VALUE};
+    use winapi::um::jobapi2::{
+        AssignProcessToJobObject, CreateJobObjectW, SetInformationJobObject, TerminateJobObject,
+    };
+    use winapi::um::processthreadsapi::{OpenThread, ResumeThread};
+    use winapi::um::synchapi::WaitForSingleObject;
+    use winapi::um::tlhelp32::{
+        CreateToolhelp32Snapshot, Thread32First, Thread32Next, TH32CS_SNAPTHREAD, THREADENTRY32,
+    };
+    use winapi::um::winbase::{CREATE_SUSPENDED, WAIT_OBJECT_0};
+    use winapi::um::winnt::{
+        JobObjectExtendedLimitInformation, HANDLE, JOBOBJECT_EXTENDED_LIMIT_INFORMATION,
+        JOB_OBJECT_LIMIT_KILL_ON_JOB_CLOSE, THREAD_SUSPEND_RESUME,
+    };
+
+    use crate::errors::ErrorKind;
+    use crate::output::message;
 
     unsafe extern "system" fn ctrlc_handler(_: DWORD) -> BOOL {
         // Do nothing, pass everything to child process
         TRUE
     }
 
-    pub(super) fn wrap_exec(command: &mut Command) -> Result<i32> {
+    pub(super) fn wrap_exec(command: &mut Command, timeout: Option<Duration>) -> Result<i32> {
         unsafe {
             if SetConsoleCtrlHandler(Some(ctrlc_handler), TRUE) == FALSE {
                 return Err(anyhow!("Could not set Ctrl-C handler."));
@@ -37,15 +259,266 @@ pub(super) mod imp {
         // Acts under the (possibly false) assumption that if status returns an
         // error it means that it didn't run. If the process has ran it will
         // return status.
-        match command.status() {
+        let status = match timeout {
+            None => command.status(),
+            Some(timeout) => return status_with_timeout(command, timeout),
+        };
+        match status {
             Err(err) => Err(anyhow!(err)),
-            Ok(status) => {
-                if status.success() {
-                    Ok(0)
-                } else {
-                    Ok(status.code().unwrap_or(-1))
+            Ok(status) => Ok(exit_code(status)),
+        }
+    }
+
+    fn exit_code(status: ExitStatus) -> i32 {
+        if status.success() {
+            0
+        } else {
+            status.code().unwrap_or(-1)
+        }
+    }
+
+    /// A job object killing the processes assigned to it (and the processes
+    /// they start) when closed.
+    struct Job(HANDLE);
+
+    impl Job {
+        fn new() -> io::Result<Self> {
+            unsafe {
+                let handle = CreateJobObjectW(ptr::null_mut(), ptr::null());
+                if handle.is_null() {
+                    return Err(io::Error::last_os_error());
+                }
+                let job = Job(handle);
+                let mut info: JOBOBJECT_EXTENDED_LIMIT_INFORMATION = std::mem::zeroed();
+                info.BasicLimitInformation.LimitFlags = JOB_OBJECT_LIMIT_KILL_ON_JOB_CLOSE;
+                let result = SetInformationJobObject(
+                    job.0,
+                    JobObjectExtendedLimitInformation,
+                    &mut info as *mut _ as *mut _,
+                    std::mem::size_of_val(&info) as DWORD,
+                );
+                if result == FALSE {
+                    return Err(io::Error::last_os_error());
                 }
+                Ok(job)
             }
         }
     }
+
+    impl Drop for Job {
+        fn drop(&mut self) {
+            unsafe {
+                CloseHandle(self.0);
+            }
+        }
+    }
+
+    fn timed_out(timeout: Duration) -> anyhow::Error {
+        let args: [(&str, &dyn Display); 1] = [("seconds", &timeout.as_secs())];
+        ErrorKind::TimedOut.error(message("error.timed_out", &args))
+    }
+
+    /// Resumes the threads of a process started suspended (just its main
+    /// thread, as a suspended process can't start any other).
+    fn resume_process(pid: DWORD) -> io::Result<()> {
+        unsafe {
+            let snapshot = CreateToolhelp32Snapshot(TH32CS_SNAPTHREAD, 0);
+            if snapshot == INVALID_HANDLE_VALUE {
+                return Err(io::Error::last_os_error());
+            }
+            let snapshot = OwnedHandle::from_raw_handle(snapshot as _);
+            let mut entry: THREADENTRY32 = std::mem::zeroed();
+            entry.dwSize = std::mem::size_of::<THREADENTRY32>() as DWORD;
+            let mut resumed = 0;
+            let mut more = Thread32First(snapshot.as_raw_handle() as HANDLE, &mut entry) != FALSE;
+            while more {
+                if entry.th32OwnerProcessID == pid {
+                    let thread = OpenThread(THREAD_SUSPEND_RESUME, FALSE, entry.th32ThreadID);
+                    if thread.is_null() {
+                        return Err(io::Error::last_os_error());
+                    }
+                    let thread = OwnedHandle::from_raw_handle(thread as _);
+                    if ResumeThread(thread.as_raw_handle() as HANDLE) == DWORD::MAX {
+                        return Err(io::Error::last_os_error());
+                    }
+                    resumed += 1;
+                }
+                more = Thread32Next(snapshot.as_raw_handle() as HANDLE, &mut entry) != FALSE;
+            }
+            if resumed == 0 {
+                return Err(io::Error::new(io::ErrorKind::NotFound, "no thread to resume"));
+            }
+            Ok(())
+        }
+    }
+
+    /// Runs the command in a job object, terminating the whole job (the
+    /// command and any process it started) once the timeout expires. The
+    /// command is started suspended and only resumed once it's in the job, so
+    /// none of the processes it starts could escape it.
+    fn status_with_timeout(command: &mut Command, timeout: Duration) -> Result<i32> {
+        let job = Job::new()?;
+        let mut child = command.creation_flags(CREATE_SUSPENDED).spawn()?;
+        let process = child.as_raw_handle() as HANDLE;
+        let started = if unsafe { AssignProcessToJobObject(job.0, process) } == FALSE {
+            Err(io::Error::last_os_error())
+        } else {
+            resume_process(child.id())
+        };
+        if let Err(err) = started {
+            let _ = child.kill();
+            let _ = child.wait();
+            return Err(anyhow!(err));
+        }
+        let millis = timeout.as_millis().min(DWORD::MAX as u128 - 1) as DWORD;
+        match unsafe { WaitForSingleObject(process, millis) } {
+            WAIT_OBJECT_0 => Ok(exit_code(child.wait()?)),
+            WAIT_TIMEOUT => {
+                unsafe {
+                    TerminateJobObject(job.0, ErrorKind::TimedOut.code() as u32);
+                }
+                let _ = child.wait();
+                Err(timed_out(timeout))
+            }
+            _ => Err(anyhow!(io::Error::last_os_error())),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn context(install_dir: &Path) -> ExecutableContext<'_> {
+        ExecutableContext {
+            tool: "mytool",
+            version: "1.0",
+            install_dir,
+            depth: 0,
+            env: &[],
+            cwd: None,
+            script: None,
+            default_args: &[],
+            timeout: None,
+        }
+    }
+
+    #[test]
+    fn mk_command_exposes_tool_and_version_to_child() {
+        let install_dir = Path::new("installs").join("mytool").join("1.0");
+        let context = context(&install_dir);
+        let command = context.mk_command(Path::new("mytool.exe"), ["--version"]);
+        let envs: Vec<(&OsStr, Option<&OsStr>)> = command.get_envs().collect();
+        assert!(envs.contains(&(OsStr::new(TOOL_VARIABLE), Some(OsStr::new("mytool")))));
+        assert!(envs.contains(&(OsStr::new(TOOL_VERSION_VARIABLE), Some(OsStr::new("1.0")))));
+        assert!(envs.contains(&(OsStr::new(INSTALL_DIR_VARIABLE), Some(install_dir.as_os_str()))));
+        assert!(envs.contains(&(OsStr::new(SHIM_DEPTH_VARIABLE), Some(OsStr::new("1")))));
+        let args: Vec<OsString> = command.get_args().map(OsStr::to_os_string).collect();
+        assert_eq!(args, vec![OsString::from("--version")]);
+    }
+
+    #[test]
+    fn command_line_quotes_arguments_when_needed() {
+        let args = [
+            r"C:\Program Files\Python\python.exe",
+            r"C:\tools\http",
+            "",
+            r#"say "hi""#,
+            r"dir\",
+        ];
+        let expected = r#""C:\Program Files\Python\python.exe" C:\tools\http "" "say \"hi\"" dir\"#;
+        assert_eq!(command_line(&args), expected);
+        assert_eq!(command_line(&[r"C:\my dir\"]), r#""C:\my dir\\""#);
+    }
+
+    #[test]
+    fn mk_command_applies_extra_env_and_cwd() {
+        let install_dir = Path::new("installs").join("mytool").join("1.0");
+        let env = vec![
+            ("MYTOOL_OPTS".to_owned(), "--verbose".to_owned()),
+            (TOOL_VARIABLE.to_owned(), "other".to_owned()),
+        ];
+        let context = ExecutableContext {
+            env: &env,
+            cwd: Some(Path::new("project")),
+            ..context(&install_dir)
+        };
+        let command = context.mk_command(Path::new("mytool.exe"), ["--version"]);
+        let envs: Vec<(&OsStr, Option<&OsStr>)> = command.get_envs().collect();
+        assert!(envs.contains(&(OsStr::new("MYTOOL_OPTS"), Some(OsStr::new("--verbose")))));
+        assert!(
+            envs.contains(&(OsStr::new(TOOL_VARIABLE), Some(OsStr::new("mytool")))),
+            "extra env should not override the resolved tool"
+        );
+        assert_eq!(command.get_current_dir(), Some(Path::new("project")));
+    }
+
+    #[test]
+    fn mk_command_prepends_script_and_default_args() {
+        let install_dir = Path::new("installs").join("httpie").join("3.2");
+        let script = install_dir.join("bin").join("http");
+        let default_args = vec!["--pretty=none".to_owned()];
+        let context = ExecutableContext {
+            tool: "httpie",
+            version: "3.2",
+            script: Some(&script),
+            default_args: &default_args,
+            ..context(&install_dir)
+        };
+        let command = context.mk_command(Path::new("python.exe"), ["GET", "example.com"]);
+        let args: Vec<&OsStr> = command.get_args().collect();
+        let expected = [
+            script.as_os_str(),
+            OsStr::new("--pretty=none"),
+            OsStr::new("GET"),
+            OsStr::new("example.com"),
+        ];
+        assert_eq!(args, expected);
+        let argv = context.argv(Path::new("python.exe"), ["GET"]);
+        assert_eq!(
+            argv[..3],
+            [
+                OsStr::new("python.exe"),
+                script.as_os_str(),
+                OsStr::new("--pretty=none")
+            ]
+        );
+    }
+
+    #[cfg(windows)]
+    #[test]
+    fn output_captures_stdout_and_exit_code() {
+        let install_dir = Path::new("installs").join("mytool").join("1.0");
+        let context = context(&install_dir);
+        let result = output(Path::new("cmd.exe"), ["/c", "echo %ASDFW_TOOL%& exit /b 3"], &context).unwrap();
+        assert_eq!(String::from_utf8_lossy(&result.stdout).trim(), "mytool");
+        assert_eq!(result.status.code(), Some(3));
+        let child = spawn(Path::new("cmd.exe"), ["/c", "exit /b 0"], &context, |command| {
+            command.stdout(Stdio::null());
+        });
+        assert!(child.unwrap().wait().unwrap().success());
+    }
+
+    #[cfg(windows)]
+    #[test]
+    fn exec_kills_command_after_timeout() {
+        let install_dir = Path::new("installs").join("mytool").join("1.0");
+        let context = ExecutableContext {
+            timeout: Some(Duration::from_secs(1)),
+            ..context(&install_dir)
+        };
+        let err = exec(Path::new("cmd.exe"), ["/c", "ping -n 10 127.0.0.1 >nul"], &context).unwrap_err();
+        assert_eq!(crate::errors::error_kind(&err), Some(crate::errors::ErrorKind::TimedOut));
+        assert_eq!(exec(Path::new("cmd.exe"), ["/c", "exit /b 3"], &context).unwrap(), 3);
+    }
+
+    #[test]
+    fn shim_repeats_counts_the_command_only() {
+        let chain: Vec<String> = ["npm.cmd", "node.exe", "git.exe", "Node.exe"]
+            .iter()
+            .map(|s| s.to_string())
+            .collect();
+        assert_eq!(shim_repeats(&chain, "node.exe"), 2);
+        assert_eq!(shim_repeats(&chain, "python.exe"), 0);
+    }
 }
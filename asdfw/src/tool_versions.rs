@@ -1,20 +1,65 @@
 use std::collections::HashMap;
+use std::fmt::Display;
 use std::fs::{self, File};
 use std::io::{self, BufRead};
-use std::iter::FromIterator;
 use std::path::{Path, PathBuf};
 
-use anyhow::{anyhow, Context, Result};
+use anyhow::{Context, Result};
 use log::{debug, info};
+use serde::Deserialize;
+
+use crate::errors::ErrorKind;
+use crate::fsutil::write_atomically;
+use crate::output::{message, print_warning};
+use crate::shims::PATH_VERSION_PREFIX;
 
 const FILE_NAME: &str = ".tool-versions";
+/// Name of the machine-level defaults file in the system directory.
+pub const SYSTEM_FILE_NAME: &str = "tool-versions";
 
 type ToolVersionsData = HashMap<String, String>;
 
-pub struct ToolVersions<'a> {
-    tool: &'a str,
-    global_path: &'a Path,
-    current_dir: &'a Path,
+/// Where a version could be configured, in the order they are checked.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum VersionSource {
+    /// The environment variable with the provided name.
+    Environment(String),
+    /// A `.tool-versions` file in the current directory or one of its parents.
+    LocalFile(PathBuf),
+    /// The global `.tool-versions` file.
+    GlobalFile(PathBuf),
+    /// The machine-level defaults file managed by the administrator.
+    SystemFile(PathBuf),
+}
+
+/// A single step of the version resolution along with the version found there
+/// (if any).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ResolutionStep {
+    pub source: VersionSource,
+    pub version: Option<String>,
+}
+
+/// How invalid lines in `.tool-versions` files are treated while resolving
+/// versions.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ParseMode {
+    /// Fail the resolution (the default).
+    #[default]
+    Strict,
+    /// Log a warning and skip the line.
+    Lenient,
+}
+
+#[derive(Debug, Clone)]
+pub struct ToolVersions {
+    tool: String,
+    aliases: Vec<String>,
+    mode: ParseMode,
+    global_path: PathBuf,
+    system_path: Option<PathBuf>,
+    current_dir: PathBuf,
 }
 
 /// Used to chain `<Result<Option<_>>` when you want to evaluate the next fn
@@ -28,45 +73,169 @@ macro_rules! eval_if_none {
     };
 }
 
-impl<'a> ToolVersions<'a> {
-    pub fn new(global_path: &'a Path, current_dir: &'a Path, tool: &'a str) -> Self {
+impl ToolVersions {
+    pub fn new<G: AsRef<Path>, C: AsRef<Path>>(global_path: G, current_dir: C, tool: &str) -> Self {
         ToolVersions {
-            global_path,
-            current_dir,
-            tool,
+            global_path: global_path.as_ref().to_path_buf(),
+            system_path: None,
+            current_dir: current_dir.as_ref().to_path_buf(),
+            tool: tool.to_owned(),
+            aliases: vec![],
+            mode: ParseMode::default(),
         }
     }
 
+    /// The machine-level defaults file, consulted after the global file.
+    pub fn with_system_file(mut self, path: Option<&Path>) -> Self {
+        self.system_path = path.map(Path::to_path_buf);
+        self
+    }
+
+    /// How invalid lines are treated while searching for the version.
+    /// Saving fails on invalid lines regardless of the mode.
+    pub fn with_mode(mut self, mode: ParseMode) -> Self {
+        self.mode = mode;
+        self
+    }
+
+    /// Alternate names of the tool. Versions pinned under any of the aliases
+    /// are honored, while saving always uses the tool name (replacing any
+    /// entry pinned under an alias).
+    pub fn with_aliases(mut self, aliases: &[String]) -> Self {
+        self.aliases = aliases.to_vec();
+        self
+    }
+
     pub fn get_version(&self) -> Result<Option<String>> {
         self.get_version_from_env()
             .and_then(|r| eval_if_none!(r, self.get_version_from_current_dir()))
-            .and_then(|r| eval_if_none!(r, self.get_version_from_global()))
+            .and_then(|r| eval_if_none!(r, self.get_global_version()))
+            .and_then(|r| eval_if_none!(r, self.get_system_version()))
+    }
+
+    /// Returns every source checked while resolving the version, in order,
+    /// up to (and including) the one the version was found in. Local files
+    /// are only listed if they exist.
+    pub fn resolution_chain(&self) -> Result<Vec<ResolutionStep>> {
+        let mut steps = vec![];
+        for name in self.names() {
+            let var = env_var_name_for_tool(name);
+            let version = std::env::var(&var).ok();
+            let found = version.is_some();
+            steps.push(ResolutionStep {
+                source: VersionSource::Environment(var),
+                version,
+            });
+            if found {
+                return Ok(steps);
+            }
+        }
+        let mut path = self.current_dir.clone();
+        loop {
+            path.push(FILE_NAME);
+            if path.is_file() {
+                let version = search_tool_in_file(&self.names(), &path, self.mode)?;
+                let found = version.is_some();
+                steps.push(ResolutionStep {
+                    source: VersionSource::LocalFile(path.clone()),
+                    version,
+                });
+                if found {
+                    return Ok(steps);
+                }
+            }
+            if !(path.pop() && path.pop()) {
+                break;
+            }
+        }
+        let version = self.get_global_version()?;
+        let found = version.is_some();
+        steps.push(ResolutionStep {
+            source: VersionSource::GlobalFile(self.global_path.to_path_buf()),
+            version,
+        });
+        if let Some(path) = self.system_path.as_ref().filter(|_| !found) {
+            steps.push(ResolutionStep {
+                source: VersionSource::SystemFile(path.clone()),
+                version: self.get_system_version()?,
+            });
+        }
+        Ok(steps)
+    }
+
+    /// Returns the version configured in the `.tool-versions` files
+    /// (including the machine-level defaults), ignoring the environment
+    /// variable.
+    pub fn get_pinned_version(&self) -> Result<Option<String>> {
+        self.get_version_from_current_dir()
+            .and_then(|r| eval_if_none!(r, self.get_global_version()))
+            .and_then(|r| eval_if_none!(r, self.get_system_version()))
     }
 
-    pub fn save_local(&self, version: &'a str) -> Result<()> {
+    /// Returns the version configured in the global file only.
+    pub fn get_global_version(&self) -> Result<Option<String>> {
+        if !self.global_path.exists() {
+            return Ok(None);
+        }
+        self.get_version_from_global()
+    }
+
+    /// Returns the version configured in the machine-level defaults file
+    /// only.
+    pub fn get_system_version(&self) -> Result<Option<String>> {
+        match &self.system_path {
+            Some(path) if path.exists() => {
+                search_tool_in_file(&self.names(), path, self.mode).context("Parsing system tool versions file")
+            }
+            _ => Ok(None),
+        }
+    }
+
+    pub fn save_local(&self, version: &str) -> Result<()> {
         info!("Setting local ({:?}) version for '{}': {}", self.current_dir, self.tool, &version);
         let context = format!("setting local version for {}: {}", self.tool, version);
         let local_file = self.current_dir.join(FILE_NAME);
-        set_tool_version(&local_file, self.tool, version).context(context)
+        set_tool_version(&local_file, &self.tool, &self.aliases, version).context(context)
     }
 
-    pub fn save_global(&self, version: &'a str) -> Result<()> {
+    pub fn save_global(&self, version: &str) -> Result<()> {
         info!("Setting global version for '{}': {}", self.tool, &version);
         let context = format!("setting global version for {}: {}", self.tool, version);
-        set_tool_version(self.global_path, self.tool, version).context(context)
+        set_tool_version(&self.global_path, &self.tool, &self.aliases, version).context(context)
+    }
+
+    /// Removes the tool (and its aliases) from the `.tool-versions` file in
+    /// the current directory. Returns whether the tool was pinned there.
+    pub fn unset_local(&self) -> Result<bool> {
+        info!("Unsetting local ({:?}) version for '{}'", self.current_dir, self.tool);
+        let local_file = self.current_dir.join(FILE_NAME);
+        unset_tool_version(&local_file, &self.names()).context(format!("unsetting local version for {}", self.tool))
+    }
+
+    /// Removes the tool (and its aliases) from the global file. Returns
+    /// whether the tool was pinned there.
+    pub fn unset_global(&self) -> Result<bool> {
+        info!("Unsetting global version for '{}'", self.tool);
+        unset_tool_version(&self.global_path, &self.names())
+            .context(format!("unsetting global version for {}", self.tool))
+    }
+
+    fn names(&self) -> Vec<&str> {
+        let mut names = vec![self.tool.as_str()];
+        names.extend(self.aliases.iter().map(|a| a.as_str()));
+        names
     }
 
     fn get_version_from_env(&self) -> Result<Option<String>> {
-        let env_name = env_var_name_for_tool(self.tool);
-        Ok(std::env::var(&env_name).ok())
+        Ok(self.names().iter().find_map(|name| std::env::var(env_var_name_for_tool(name)).ok()))
     }
 
     fn get_version_from_current_dir(&self) -> Result<Option<String>> {
-        let mut path = PathBuf::from(self.current_dir);
+        let mut path = self.current_dir.clone();
         Ok(loop {
             path.push(FILE_NAME);
             if path.is_file() {
-                if let Some(ver) = search_tool_in_file(self.tool, &path)? {
+                if let Some(ver) = search_tool_in_file(&self.names(), &path, self.mode)? {
                     break Some(ver);
                 }
             }
@@ -79,23 +248,117 @@ impl<'a> ToolVersions<'a> {
 
     fn get_version_from_global(&self) -> Result<Option<String>> {
         debug!("Searching for version in global file: {}", &self.tool);
-        search_tool_in_file(self.tool, self.global_path).context("Parsing global tool versions file")
+        search_tool_in_file(&self.names(), &self.global_path, self.mode).context("Parsing global tool versions file")
+    }
+}
+
+/// Returns the names of all the tools pinned in the `.tool-versions` files
+/// affecting the provided directory (including the global and the system
+/// files), sorted.
+pub fn pinned_tools(
+    global_path: &Path,
+    system_path: Option<&Path>,
+    current_dir: &Path,
+    mode: ParseMode,
+) -> Result<Vec<String>> {
+    let mut tools = vec![];
+    let mut path = PathBuf::from(current_dir);
+    loop {
+        path.push(FILE_NAME);
+        if path.is_file() {
+            tools.extend(load_file(&path, mode)?.into_keys());
+        }
+        if !(path.pop() && path.pop()) {
+            break;
+        }
     }
+    tools.extend(
+        load_file(global_path, mode)
+            .context("Parsing global tool versions file")?
+            .into_keys(),
+    );
+    if let Some(path) = system_path {
+        tools.extend(load_file(path, mode).context("Parsing system tool versions file")?.into_keys());
+    }
+    tools.sort_by_key(|tool| tool.to_lowercase());
+    tools.dedup_by(|a, b| same_tool(a, b));
+    Ok(tools)
 }
 
-fn set_tool_version<'a>(path: &'a Path, tool: &'a str, version: &'a str) -> Result<()> {
+/// Pins the version of the tool by rewriting the line pinning it (or any of
+/// its aliases) in normalized form, keeping its comment. All other lines are
+/// left untouched.
+fn set_tool_version<'a>(path: &'a Path, tool: &'a str, aliases: &'a [String], version: &'a str) -> Result<()> {
     debug!("reading current tool versions from {:?}", &path);
-    let mut tool_versions = load_file(&path)?;
-    let previous = tool_versions.insert(tool.to_string(), version.to_string());
-    if previous.is_none() {
-        debug!("setting new version for {}", &tool);
-    } else {
+    let contents = match path.exists() {
+        true => fs::read_to_string(path).context(format!("reading tool versions from {:?}", &path))?,
+        false => String::new(),
+    };
+    let mut lines: Vec<String> = vec![];
+    let mut replaced = false;
+    for line in contents.split_inclusive('\n') {
+        let pinned = parse_line(line)?.map(|(name, _)| name);
+        match pinned {
+            Some(name) if same_tool(name, tool) || aliases.iter().any(|alias| same_tool(alias, name)) => {
+                if name != tool {
+                    debug!("Replacing version pinned under alias {} with {}", &name, &tool);
+                }
+                if !replaced {
+                    let comment = line.find('#').map(|i| format!(" {}", line[i..].trim_end()));
+                    let ending = &line[line.trim_end_matches(&['\r', '\n'][..]).len()..];
+                    lines.push(format!("{} {}{}{}", tool, version, comment.unwrap_or_default(), ending));
+                    replaced = true;
+                }
+            }
+            _ => lines.push(line.to_owned()),
+        }
+    }
+    if replaced {
         debug!("Setting updated version for {}", &tool);
+    } else {
+        debug!("setting new version for {}", &tool);
+        let ending = line_ending(&contents);
+        if !contents.is_empty() && !contents.ends_with('\n') {
+            lines.push(ending.to_owned());
+        }
+        lines.push(format!("{} {}{}", tool, version, ending));
+    }
+    write_atomically(path, lines.concat().as_bytes()).context(format!("Saving tool versions to: {:?}", &path))
+}
+
+/// The line ending of the first line of the file, CRLF for files without
+/// one (e.g. new files).
+fn line_ending(contents: &str) -> &'static str {
+    match contents.find('\n') {
+        Some(i) if !contents[..i].ends_with('\r') => "\n",
+        _ => "\r\n",
+    }
+}
+
+/// Removes the lines pinning any of the names, leaving all other lines
+/// untouched.
+fn unset_tool_version(path: &Path, names: &[&str]) -> Result<bool> {
+    if !path.exists() {
+        return Ok(false);
+    }
+    let contents = fs::read_to_string(path).context(format!("reading tool versions from {:?}", &path))?;
+    let mut removed = false;
+    let mut kept = vec![];
+    for line in contents.split_inclusive('\n') {
+        let pinned = parse_line(line).ok().flatten().map(|(tool, _)| tool);
+        if pinned.map_or(false, |tool| names.iter().any(|name| same_tool(name, tool))) {
+            removed = true;
+        } else {
+            kept.push(line);
+        }
+    }
+    if removed {
+        write_atomically(path, kept.concat().as_bytes()).context(format!("Saving tool versions to: {:?}", &path))?;
     }
-    save_file(tool_versions, &path)
+    Ok(removed)
 }
 
-fn load_file<'a>(path: &'a Path) -> Result<ToolVersionsData> {
+fn load_file<'a>(path: &'a Path, mode: ParseMode) -> Result<ToolVersionsData> {
     if !path.exists() {
         info!("Tool versions file '{:?}' does not exist. Returning empty versions.", &path);
         return Ok(HashMap::new());
@@ -105,46 +368,76 @@ fn load_file<'a>(path: &'a Path) -> Result<ToolVersionsData> {
     let file = File::open(path).context(context.clone())?;
     let lines = io::BufReader::new(file).lines().collect::<Result<Vec<_>, _>>().context(context)?;
     for line in lines {
-        let (tool, version) = parse_line(&line).map(|(k, v)| (k.to_owned(), v.to_owned()))?;
-        data.insert(tool, version);
+        if let Some((tool, version)) = parse_line_in_mode(&line, path, mode)? {
+            data.insert(tool.to_owned(), version.to_owned());
+        }
     }
     Ok(data)
 }
 
-fn save_file<'a>(data: ToolVersionsData, path: &'a Path) -> Result<()> {
-    let pairs = Vec::from_iter(data.iter());
-    let mut strings = pairs.iter().map(|(k, v)| format!("{} {}", k, v)).collect::<Vec<String>>();
-    strings.push("".to_owned());
-    let content = strings.join("\r\n");
-    fs::write(&path, content).context(format!("Saving tool versions to: {:?}", &path))
-}
-
-fn search_tool_in_file<'a>(search_for: &'a str, path: &'a Path) -> Result<Option<String>> {
+fn search_tool_in_file<'a>(search_for: &'a [&str], path: &'a Path, mode: ParseMode) -> Result<Option<String>> {
     let file = File::open(path)?;
     let lines = io::BufReader::new(file).lines();
     for line in lines {
         let line = line?;
-        let (tool, ver) = parse_line(&line)?;
-        if tool == search_for {
-            return Ok(Some(ver.to_owned()));
+        if let Some((tool, ver)) = parse_line_in_mode(&line, path, mode)? {
+            if search_for.iter().any(|name| same_tool(name, tool)) {
+                return Ok(Some(resolve_path_version(ver, path)));
+            }
         }
     }
     return Ok(None);
 }
 
-fn parse_line<'a>(line: &'a str) -> Result<(&str, &str)> {
-    let mk_error = || anyhow!("Invalid tools versions line: {}", &line);
-
-    let result = line.split_once(" ").ok_or(mk_error());
-    if let Ok((tool, ver)) = result {
-        if tool.is_empty() || ver.is_empty() {
-            return Err(mk_error());
+/// Relative `path:` versions are relative to the directory of the file
+/// declaring them (not the current directory).
+fn resolve_path_version(version: &str, file: &Path) -> String {
+    match version.strip_prefix(PATH_VERSION_PREFIX) {
+        Some(dir) if !dir.is_empty() && Path::new(dir).is_relative() => {
+            let base = file.parent().unwrap_or_else(|| Path::new(""));
+            format!("{}{}", PATH_VERSION_PREFIX, base.join(dir).display())
         }
-        if ver.contains(" ") {
-            return Err(mk_error());
+        _ => version.to_owned(),
+    }
+}
+
+/// Parses a line into tool and version. They could be separated by any
+/// whitespace and followed by a `#` comment. Blank and comment only lines
+/// return `None`.
+fn parse_line<'a>(line: &'a str) -> Result<Option<(&'a str, &'a str)>> {
+    let mk_error = || {
+        let line = line.trim_end();
+        ErrorKind::InvalidToolVersions.error(message("error.invalid_tool_versions_line", &[("line", &line)]))
+    };
+
+    let content = line.split('#').next().unwrap_or_default().trim();
+    if content.is_empty() {
+        return Ok(None);
+    }
+    let mut fields = content.split_whitespace();
+    match (fields.next(), fields.next(), fields.next()) {
+        (Some(tool), Some(ver), None) => Ok(Some((tool, ver))),
+        _ => Err(mk_error()),
+    }
+}
+
+/// Parses the line, skipping it if it's invalid and the mode is lenient. The
+/// warning is printed to stderr, so it reaches the user even when a shim
+/// (whose stdout belongs to the executable) reads the file.
+fn parse_line_in_mode<'a>(line: &'a str, path: &Path, mode: ParseMode) -> Result<Option<(&'a str, &'a str)>> {
+    match (parse_line(line), mode) {
+        (Err(err), ParseMode::Lenient) => {
+            let args: [(&str, &dyn Display); 2] = [("file", &format!("{:?}", path)), ("error", &err)];
+            print_warning(&message("warning.tool_versions_invalid_line", &args));
+            Ok(None)
         }
+        (parsed, _) => parsed,
     }
-    result
+}
+
+/// Tool names are case-insensitive (like the file names they come from).
+fn same_tool(a: &str, b: &str) -> bool {
+    a.eq_ignore_ascii_case(b)
 }
 
 fn env_var_name_for_tool<'a>(tool: &'a str) -> String {
@@ -179,18 +472,30 @@ mod tests {
         (global_file, current_dir)
     }
 
-    #[test]
-    fn parse_line_returns_valid_values_on_valid_line() {
-        let line = "my-tool v1.2.3";
-        let (tool, ver) = parse_line(line).unwrap();
+    #[rstest]
+    #[case("my-tool v1.2.3")]
+    #[case("my-tool  v1.2.3")]
+    #[case("my-tool\tv1.2.3 ")]
+    #[case("  my-tool v1.2.3 # pinned for CI")]
+    #[case("my-tool v1.2.3#no space before comment\r\n")]
+    fn parse_line_returns_valid_values_on_valid_line(#[case] line: &str) {
+        let (tool, ver) = parse_line(line).unwrap().unwrap();
         assert_eq!(tool, "my-tool");
         assert_eq!(ver, "v1.2.3");
     }
 
+    #[rstest]
+    #[case("")]
+    #[case("   \r\n")]
+    #[case("# my-tool v1.2.3")]
+    fn parse_line_ignores_blank_and_comment_lines(#[case] line: &str) {
+        assert_eq!(parse_line(line).unwrap(), None);
+    }
+
     #[rstest]
     #[case("my-tool ", "missing version")]
-    #[case("my-tool v1 1.2", "more then one space")]
-    #[case("my-tool  v11.2", "two spaces separator")]
+    #[case("my-tool # v1.2", "commented out version")]
+    #[case("my-tool v1 1.2", "more then one version")]
     fn parse_invalid_line(#[case] line: &str, #[case] msg: &str) {
         let res = parse_line(line);
         assert!(res.is_err(), "{} should return error", msg)
@@ -198,10 +503,12 @@ mod tests {
 
     #[rstest]
     #[case("tool1 v1.2\r\ntool2 v2.1.3\r\ntool3 5.6\r\n", "tool3", "5.6")]
+    #[case("tool1 v1.2\r\nTool3 5.6\r\n", "tool3", "5.6")]
+    #[case("tool1 v1.2\r\ntool3 5.6\r\n", "TOOL3", "5.6")]
     fn find_version_in_file_existing_tool(#[case] content: &str, #[case] tool: &str, #[case] ver: String) {
         let temp_file = assert_fs::NamedTempFile::new(".tool_versions").unwrap();
         temp_file.write_str(content).unwrap();
-        let res = search_tool_in_file(tool, temp_file.path()).unwrap();
+        let res = search_tool_in_file(&[tool], temp_file.path(), ParseMode::Strict).unwrap();
         assert_eq!(res, Some(ver));
     }
 
@@ -210,19 +517,32 @@ mod tests {
     fn find_version_in_file_missing_tool(#[case] content: &str, #[case] tool: &str) {
         let temp_file = assert_fs::NamedTempFile::new(".tool_versions").unwrap();
         temp_file.write_str(content).unwrap();
-        let res = search_tool_in_file(tool, temp_file.path()).unwrap();
+        let res = search_tool_in_file(&[tool], temp_file.path(), ParseMode::Strict).unwrap();
         assert_eq!(res, None);
     }
 
     #[rstest]
-    #[case("tool1 v1.2\r\ntool2 v2.1.3\r\ntool3  5.6\r\n", "tool3")]
+    #[case("tool1 v1.2\r\ntool2 v2.1.3\r\ntool3 5.6 5.7\r\n", "tool3")]
     fn find_version_in_file_corrupt_file_if_reaches_corrupt_line(#[case] content: &str, #[case] tool: &str) {
         let temp_file = assert_fs::NamedTempFile::new(".tool_versions").unwrap();
         temp_file.write_str(content).unwrap();
-        let res = search_tool_in_file(tool, temp_file.path());
+        let res = search_tool_in_file(&[tool], temp_file.path(), ParseMode::Strict);
         assert!(res.is_err(), "Corrupt file should produce error");
     }
 
+    #[test]
+    fn lenient_mode_skips_corrupt_lines() {
+        let temp_file = assert_fs::NamedTempFile::new(".tool_versions").unwrap();
+        temp_file.write_str("tool1 v1.2\r\ntool2 v2 v3\r\ntool3 5.6\r\n").unwrap();
+        let res = search_tool_in_file(&["tool3"], temp_file.path(), ParseMode::Lenient).unwrap();
+        assert_eq!(res, Some("5.6".to_owned()));
+        let res = search_tool_in_file(&["tool2"], temp_file.path(), ParseMode::Lenient).unwrap();
+        assert_eq!(res, None);
+        let data = load_file(temp_file.path(), ParseMode::Lenient).unwrap();
+        assert_eq!(data.len(), 2);
+        assert!(load_file(temp_file.path(), ParseMode::Strict).is_err());
+    }
+
     #[test]
     fn get_version_when_environment_variable_is_set() {
         let tool = "justfortest";
@@ -245,6 +565,21 @@ mod tests {
         assert_eq!(result, Some(ver.to_string()));
     }
 
+    #[test]
+    fn relative_path_version_is_relative_to_the_declaring_file() {
+        let (global_file, current_dir) = gen_tool_versions_fixture();
+        let project = current_dir.child("project");
+        project.child(FILE_NAME).write_str("mytool path:build/out\r\n").unwrap();
+        let nested = project.child("src");
+        nested.create_dir_all().unwrap();
+        let tool_versions = ToolVersions::new(global_file.path(), nested.path(), "mytool");
+        let expected = format!("path:{}", project.path().join("build/out").display());
+        assert_eq!(tool_versions.get_version().unwrap(), Some(expected));
+        let absolute = format!("path:{}", current_dir.path().display());
+        project.child(FILE_NAME).write_str(&format!("mytool {}\r\n", &absolute)).unwrap();
+        assert_eq!(tool_versions.get_version().unwrap(), Some(absolute));
+    }
+
     #[test]
     fn get_version_from_nested_local_file() {
         let (global_file, current_dir) = gen_tool_versions_fixture();
@@ -265,6 +600,14 @@ mod tests {
         assert_eq!(result, Some(ver.to_string()));
     }
 
+    #[test]
+    fn pinned_tools_returns_tools_from_all_files() {
+        let (global_file, current_dir) = gen_tool_versions_fixture();
+        let current_dir = current_dir.child(SUBDIR);
+        let result = pinned_tools(global_file.path(), None, current_dir.path(), ParseMode::Strict).unwrap();
+        assert_eq!(result, vec!["tool1", "tool2", "tool3"]);
+    }
+
     #[test]
     fn save_global_creates_new_global_file_if_does_not_exist() {
         let global_file = assert_fs::NamedTempFile::new(FILE_NAME).unwrap();
@@ -318,4 +661,151 @@ mod tests {
         let res = tvs.get_version().unwrap();
         assert_eq!(res, Some(version.to_string()), "{}: loaded does not match saved", msg);
     }
+
+    #[test]
+    fn get_version_pinned_under_alias() {
+        let (global_file, current_dir) = gen_tool_versions_fixture();
+        let aliases = vec!["tool3".to_owned()];
+        let tvs = ToolVersions::new(global_file.path(), current_dir.path(), "renamed").with_aliases(&aliases);
+        let result = tvs.get_version().unwrap();
+        assert_eq!(result, Some("v10".to_string()));
+    }
+
+    #[test]
+    fn save_local_replaces_version_pinned_under_alias() {
+        let (global_file, current_dir) = gen_tool_versions_fixture();
+        let aliases = vec!["tool3".to_owned()];
+        let tvs = ToolVersions::new(global_file.path(), current_dir.path(), "renamed").with_aliases(&aliases);
+        tvs.save_local("v11").unwrap();
+        let local = load_file(current_dir.child(FILE_NAME).path(), ParseMode::Strict).unwrap();
+        assert_eq!(local.get("renamed"), Some(&"v11".to_string()));
+        assert!(!local.contains_key("tool3"), "alias entry should be replaced");
+    }
+
+    #[test]
+    fn resolution_chain_lists_sources_up_to_the_selected_version() {
+        let (global_file, current_dir) = gen_tool_versions_fixture();
+        let subdir = current_dir.child(SUBDIR);
+        let tvs = ToolVersions::new(global_file.path(), subdir.path(), "tool3");
+        let result = tvs.resolution_chain().unwrap();
+        let expected = vec![
+            ResolutionStep {
+                source: VersionSource::Environment(env_var_name_for_tool("tool3")),
+                version: None,
+            },
+            ResolutionStep {
+                source: VersionSource::LocalFile(subdir.child(FILE_NAME).to_path_buf()),
+                version: None,
+            },
+            ResolutionStep {
+                source: VersionSource::LocalFile(current_dir.child(FILE_NAME).to_path_buf()),
+                version: Some("v10".to_string()),
+            },
+        ];
+        assert_eq!(result, expected);
+    }
+
+    #[test]
+    fn system_file_is_consulted_after_global_file() {
+        let (global_file, current_dir) = gen_tool_versions_fixture();
+        let system_file = current_dir.child("system").child(SYSTEM_FILE_NAME);
+        system_file.write_str("tool2 v2.0\r\ntool4 v4.0\r\n").unwrap();
+        let tvs = |tool| {
+            ToolVersions::new(global_file.path(), current_dir.path(), tool).with_system_file(Some(system_file.path()))
+        };
+        assert_eq!(tvs("tool2").get_version().unwrap(), Some("v2.1.3".to_string()));
+        assert_eq!(tvs("tool4").get_version().unwrap(), Some("v4.0".to_string()));
+        assert_eq!(tvs("tool4").get_pinned_version().unwrap(), Some("v4.0".to_string()));
+        assert_eq!(tvs("tool4").get_global_version().unwrap(), None);
+        let last = tvs("tool4").resolution_chain().unwrap().pop().unwrap();
+        assert_eq!(last.source, VersionSource::SystemFile(system_file.to_path_buf()));
+        assert_eq!(last.version, Some("v4.0".to_string()));
+        let tools = pinned_tools(global_file.path(), Some(system_file.path()), current_dir.path(), ParseMode::Strict);
+        assert!(tools.unwrap().contains(&"tool4".to_string()));
+    }
+
+    #[test]
+    fn system_file_is_consulted_without_global_file() {
+        let (_, current_dir) = gen_tool_versions_fixture();
+        let global_file = current_dir.child("no-such-home").child(FILE_NAME);
+        let system_file = current_dir.child("system").child(SYSTEM_FILE_NAME);
+        system_file.write_str("tool4 v4.0\r\n").unwrap();
+        let tvs = ToolVersions::new(global_file.path(), current_dir.path(), "tool4")
+            .with_system_file(Some(system_file.path()));
+        assert_eq!(tvs.get_version().unwrap(), Some("v4.0".to_string()));
+        assert_eq!(tvs.get_pinned_version().unwrap(), Some("v4.0".to_string()));
+    }
+
+    #[test]
+    fn resolution_chain_ends_with_global_file() {
+        let (global_file, current_dir) = gen_tool_versions_fixture();
+        let tvs = ToolVersions::new(global_file.path(), current_dir.path(), "tool2");
+        let result = tvs.resolution_chain().unwrap();
+        let last = result.last().unwrap();
+        assert_eq!(last.source, VersionSource::GlobalFile(global_file.to_path_buf()));
+        assert_eq!(last.version, Some("v2.1.3".to_string()));
+    }
+
+    #[test]
+    fn save_local_rewrites_only_the_tool_line() {
+        let global_file = assert_fs::NamedTempFile::new(FILE_NAME).unwrap();
+        let current_dir = assert_fs::TempDir::new().unwrap();
+        let local_file = current_dir.child(FILE_NAME);
+        local_file.write_str("# project tools\ntool1   v1.3   # lts\ntool3\tv10\n").unwrap();
+        let tvs = ToolVersions::new(global_file.path(), current_dir.path(), "tool1");
+        tvs.save_local("v1.4").unwrap();
+        local_file.assert("# project tools\ntool1 v1.4 # lts\ntool3\tv10\n");
+        let tvs = ToolVersions::new(global_file.path(), current_dir.path(), "tool2");
+        tvs.save_local("v2.0").unwrap();
+        local_file.assert("# project tools\ntool1 v1.4 # lts\ntool3\tv10\ntool2 v2.0\n");
+    }
+
+    #[rstest]
+    #[case("tool1 v1.3\r\n", "tool1 v1.3\r\ntool2 v2.0\r\n")]
+    #[case("tool1 v1.3", "tool1 v1.3\r\ntool2 v2.0\r\n")]
+    #[case("tool1 v1.3\ntool3 v10", "tool1 v1.3\ntool3 v10\ntool2 v2.0\n")]
+    #[case("", "tool2 v2.0\r\n")]
+    fn save_local_appends_with_the_line_ending_of_the_file(#[case] contents: &str, #[case] expected: &str) {
+        let global_file = assert_fs::NamedTempFile::new(FILE_NAME).unwrap();
+        let current_dir = assert_fs::TempDir::new().unwrap();
+        let local_file = current_dir.child(FILE_NAME);
+        local_file.write_str(contents).unwrap();
+        let tvs = ToolVersions::new(global_file.path(), current_dir.path(), "tool2");
+        tvs.save_local("v2.0").unwrap();
+        local_file.assert(expected);
+    }
+
+    #[test]
+    fn save_local_normalizes_mixed_case_tool_line() {
+        let global_file = assert_fs::NamedTempFile::new(FILE_NAME).unwrap();
+        let current_dir = assert_fs::TempDir::new().unwrap();
+        let local_file = current_dir.child(FILE_NAME);
+        local_file.write_str("Tool1 v1.3\ntool3 v10\n").unwrap();
+        let tvs = ToolVersions::new(global_file.path(), current_dir.path(), "tool1");
+        assert_eq!(tvs.get_version().unwrap(), Some("v1.3".to_string()));
+        tvs.save_local("v1.4").unwrap();
+        local_file.assert("tool1 v1.4\ntool3 v10\n");
+        assert_eq!(
+            pinned_tools(global_file.path(), None, current_dir.path(), ParseMode::Strict).unwrap(),
+            vec!["tool1", "tool3"]
+        );
+    }
+
+    #[test]
+    fn unset_local_removes_only_the_tool_line() {
+        let (global_file, current_dir) = gen_tool_versions_fixture();
+        let tvs = ToolVersions::new(global_file.path(), current_dir.path(), "tool1");
+        assert!(tvs.unset_local().unwrap(), "tool1 should be pinned locally");
+        current_dir.child(FILE_NAME).assert("tool3 v10\r\n");
+        assert_eq!(tvs.get_version().unwrap(), Some("v1.2".to_string()), "should fall back to global");
+        assert!(!tvs.unset_local().unwrap(), "tool1 is no longer pinned locally");
+    }
+
+    #[test]
+    fn unset_global_with_missing_tool_leaves_file_untouched() {
+        let (global_file, current_dir) = gen_tool_versions_fixture();
+        let tvs = ToolVersions::new(global_file.path(), current_dir.path(), "tool4");
+        assert!(!tvs.unset_global().unwrap());
+        global_file.assert(FIXTURE_GLOBAL);
+    }
 }
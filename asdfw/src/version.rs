@@ -0,0 +1,161 @@
+use anyhow::{anyhow, Result};
+use std::cmp::Ordering;
+use std::fmt::Display;
+
+/// Compares versions component by component (split on `.`, `-` and `+`, an
+/// optional leading `v` is ignored). Numeric components are compared as
+/// numbers, others as strings, and missing components count as `0` (so `1.6`
+/// equals `1.6.0`).
+pub fn compare_versions(a: &str, b: &str) -> Ordering {
+    let a = components(a);
+    let b = components(b);
+    for i in 0..a.len().max(b.len()) {
+        let left = a.get(i).copied().unwrap_or("0");
+        let right = b.get(i).copied().unwrap_or("0");
+        let ordering = match (left.parse::<u64>(), right.parse::<u64>()) {
+            (Ok(l), Ok(r)) => l.cmp(&r),
+            _ => left.cmp(right),
+        };
+        if ordering != Ordering::Equal {
+            return ordering;
+        }
+    }
+    Ordering::Equal
+}
+
+/// Whether the leading components of the version equal the components of
+/// the prefix (so `18` matches `18.12.1` but not `180.1`).
+pub fn matches_prefix(version: &str, prefix: &str) -> bool {
+    let version = components(version);
+    let prefix = components(prefix);
+    prefix.len() <= version.len() && prefix.iter().zip(&version).all(|(p, v)| compare_versions(p, v) == Ordering::Equal)
+}
+
+/// Returns the highest of the versions matching the prefix.
+pub fn highest_matching<'a, I: IntoIterator<Item = &'a str>>(versions: I, prefix: &str) -> Option<&'a str> {
+    versions
+        .into_iter()
+        .filter(|version| matches_prefix(version, prefix))
+        .max_by(|a, b| compare_versions(a, b))
+}
+
+fn components(version: &str) -> Vec<&str> {
+    let version = version.strip_prefix('v').unwrap_or(version);
+    version.split(|c| c == '.' || c == '-' || c == '+').collect()
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Operator {
+    Less,
+    LessOrEqual,
+    Greater,
+    GreaterOrEqual,
+    Equal,
+}
+
+// Longer prefixes first
+const OPERATORS: [(&str, Operator); 5] = [
+    ("<=", Operator::LessOrEqual),
+    (">=", Operator::GreaterOrEqual),
+    ("<", Operator::Less),
+    (">", Operator::Greater),
+    ("=", Operator::Equal),
+];
+
+/// A set of comparators that must all match, e.g. `>= 1.2, < 2`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct VersionRange {
+    source: String,
+    comparators: Vec<(Operator, String)>,
+}
+
+impl VersionRange {
+    pub fn parse(range: &str) -> Result<Self> {
+        let mut comparators = vec![];
+        for part in range.split(',').map(str::trim) {
+            let (operator, version) = OPERATORS
+                .iter()
+                .find_map(|(prefix, operator)| part.strip_prefix(prefix).map(|version| (*operator, version)))
+                .unwrap_or((Operator::Equal, part));
+            let version = version.trim();
+            if version.is_empty() {
+                return Err(anyhow!("invalid version range: '{}'", &range));
+            }
+            comparators.push((operator, version.to_owned()));
+        }
+        Ok(VersionRange {
+            source: range.trim().to_owned(),
+            comparators,
+        })
+    }
+
+    pub fn matches(&self, version: &str) -> bool {
+        self.comparators.iter().all(|(operator, bound)| {
+            let ordering = compare_versions(version, bound);
+            match operator {
+                Operator::Less => ordering == Ordering::Less,
+                Operator::LessOrEqual => ordering != Ordering::Greater,
+                Operator::Greater => ordering == Ordering::Greater,
+                Operator::GreaterOrEqual => ordering != Ordering::Less,
+                Operator::Equal => ordering == Ordering::Equal,
+            }
+        })
+    }
+}
+
+impl Display for VersionRange {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", &self.source)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rstest::rstest;
+
+    #[rstest]
+    #[case("1.6", "1.6.0", Ordering::Equal)]
+    #[case("v1.10.0", "1.9.3", Ordering::Greater)]
+    #[case("1.2.3", "1.2.10", Ordering::Less)]
+    #[case("3.0.0-rc1", "3.0.0-rc2", Ordering::Less)]
+    #[case("21.0.1+12", "21.0.1+9", Ordering::Greater)]
+    fn compare_versions_by_components(#[case] a: &str, #[case] b: &str, #[case] expected: Ordering) {
+        assert_eq!(compare_versions(a, b), expected);
+    }
+
+    #[rstest]
+    #[case("18.12.1", "18", true)]
+    #[case("v18.2.0", "18.2", true)]
+    #[case("180.1.0", "18", false)]
+    #[case("18", "18.2", false)]
+    #[case("3.0.0-rc1", "3.0.0-rc", false)]
+    fn matches_prefix_by_components(#[case] version: &str, #[case] prefix: &str, #[case] expected: bool) {
+        assert_eq!(matches_prefix(version, prefix), expected);
+    }
+
+    #[test]
+    fn highest_matching_picks_highest_version_with_prefix() {
+        let versions = ["16.20.0", "18.9.0", "18.12.1", "19.0.0"];
+        assert_eq!(highest_matching(versions, "18"), Some("18.12.1"));
+        assert_eq!(highest_matching(versions, "20"), None);
+    }
+
+    #[rstest]
+    #[case("< 1.6", "1.5.7", true)]
+    #[case("< 1.6", "1.6.0", false)]
+    #[case(">= 1.2, < 2", "1.9", true)]
+    #[case(">= 1.2, < 2", "2.0.1", false)]
+    #[case("1.5.0", "v1.5", true)]
+    #[case("<=1.5", "1.5.1", false)]
+    fn version_range_matches(#[case] range: &str, #[case] version: &str, #[case] expected: bool) {
+        assert_eq!(VersionRange::parse(range).unwrap().matches(version), expected);
+    }
+
+    #[rstest]
+    #[case("<")]
+    #[case(">= 1.2,")]
+    fn invalid_version_range(#[case] range: &str) {
+        assert!(VersionRange::parse(range).is_err(), "'{}' should be invalid", range);
+    }
+}
@@ -0,0 +1,121 @@
+use anyhow::{bail, Context, Result};
+use log::debug;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Marks the wrapper scripts generated by `generate_wrappers` so stale ones
+/// could be removed without touching other files in the directory.
+const WRAPPER_MARKER: &str = "# Generated by asdfw, do not edit";
+
+/// Translates a Windows path on a drive (e.g. `C:\Users\me`) to the path WSL
+/// mounts it at (`/mnt/c/Users/me`).
+pub fn to_wsl_path(path: &Path) -> Result<String> {
+    let path = path.to_string_lossy();
+    let path = path.strip_prefix(r"\\?\").unwrap_or(&path);
+    let mut chars = path.chars();
+    let (drive, rest) = match (chars.next(), chars.next()) {
+        (Some(drive), Some(':')) if drive.is_ascii_alphabetic() => (drive.to_ascii_lowercase(), chars.as_str()),
+        _ => bail!("{} is not on a drive WSL could access", path),
+    };
+    let rest = rest.replace('\\', "/");
+    let rest = rest.trim_matches('/');
+    match rest.is_empty() {
+        true => Ok(format!("/mnt/{}", drive)),
+        false => Ok(format!("/mnt/{}/{}", drive, rest)),
+    }
+}
+
+/// A shell script running the shim, forwarding all the arguments.
+fn wrapper_script(shim: &Path) -> Result<String> {
+    let shim = to_wsl_path(shim)?.replace('\'', r"'\''");
+    Ok(format!("#!/bin/sh\n{}\nexec '{}' \"$@\"\n", WRAPPER_MARKER, shim))
+}
+
+/// Writes a wrapper script (named after the shim without `.exe`) for each of
+/// the shims into the directory and removes previously generated wrappers of
+/// shims that no longer exist. Returns the names of the wrappers written.
+///
+/// Files on Windows drives are executable from WSL, wrappers written
+/// elsewhere might need `chmod +x`.
+pub fn generate_wrappers(shims_dir: &Path, target: &Path) -> Result<Vec<String>> {
+    let mut names = vec![];
+    fs::create_dir_all(target).context(format!("creating directory {:?}", &target))?;
+    for shim in list_shims(shims_dir)? {
+        let name = shim.file_stem().unwrap_or_default().to_string_lossy().into_owned();
+        let path = target.join(&name);
+        debug!("Writing WSL wrapper {:?} for {:?}", &path, &shim);
+        fs::write(&path, wrapper_script(&shim)?).context(format!("writing wrapper {:?}", &path))?;
+        names.push(name);
+    }
+    for entry in fs::read_dir(target).context(format!("reading directory {:?}", &target))? {
+        let path = entry?.path();
+        let name = path.file_name().unwrap_or_default().to_string_lossy().into_owned();
+        if !names.contains(&name) && is_wrapper(&path) {
+            debug!("Removing stale WSL wrapper {:?}", &path);
+            fs::remove_file(&path).context(format!("removing {:?}", &path))?;
+        }
+    }
+    names.sort();
+    Ok(names)
+}
+
+fn list_shims(shims_dir: &Path) -> Result<Vec<PathBuf>> {
+    let mut shims = vec![];
+    for entry in fs::read_dir(shims_dir).context(format!("reading directory {:?}", &shims_dir))? {
+        let path = entry?.path();
+        if path.extension().map_or(false, |ext| ext == "exe") {
+            shims.push(path);
+        }
+    }
+    Ok(shims)
+}
+
+fn is_wrapper(path: &Path) -> bool {
+    path.is_file()
+        && fs::read_to_string(path)
+            .map(|contents| contents.lines().nth(1) == Some(WRAPPER_MARKER))
+            .unwrap_or(false)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use assert_fs::{prelude::*, TempDir};
+    use rstest::rstest;
+
+    #[rstest]
+    #[case(r"C:\Users\me\.asdfw\shims\kubectl.exe", "/mnt/c/Users/me/.asdfw/shims/kubectl.exe")]
+    #[case(r"\\?\D:\tools\", "/mnt/d/tools")]
+    #[case(r"e:\", "/mnt/e")]
+    fn to_wsl_path_translates_drive_paths(#[case] path: &str, #[case] expected: &str) {
+        assert_eq!(to_wsl_path(Path::new(path)).unwrap(), expected);
+    }
+
+    #[rstest]
+    #[case(r"\\server\share\tool.exe")]
+    #[case("/usr/bin/tool")]
+    fn to_wsl_path_rejects_paths_without_drive(#[case] path: &str) {
+        assert!(to_wsl_path(Path::new(path)).is_err());
+    }
+
+    #[test]
+    fn wrapper_script_runs_the_shim() {
+        let script = wrapper_script(Path::new(r"C:\Users\it's me\.asdfw\shims\kubectl.exe")).unwrap();
+        let expected = "#!/bin/sh\n# Generated by asdfw, do not edit\nexec '/mnt/c/Users/it'\\''s me/.asdfw/shims/kubectl.exe' \"$@\"\n";
+        assert_eq!(script, expected);
+    }
+
+    #[test]
+    fn is_wrapper_detects_generated_scripts_only() {
+        let tmp_dir = TempDir::new().unwrap();
+        let generated = tmp_dir.child("helm");
+        generated
+            .write_str(&wrapper_script(Path::new(r"C:\shims\helm.exe")).unwrap())
+            .unwrap();
+        let mine = tmp_dir.child("mine");
+        mine.write_str("#!/bin/sh\necho mine\n").unwrap();
+        assert!(is_wrapper(generated.path()));
+        assert!(!is_wrapper(mine.path()));
+        assert!(!is_wrapper(tmp_dir.child("missing").path()));
+    }
+}
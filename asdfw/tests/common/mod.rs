@@ -1,8 +1,11 @@
 use asdfw::{
+    config::Config,
+    policy::Policy,
     runtime::RuntimeEnvironment,
     shims::{Shims, ShimsDB},
 };
 use assert_fs::{fixture::ChildPath, prelude::*, TempDir};
+use std::sync::OnceLock;
 
 pub struct Paths {
     pub current_dir: ChildPath,
@@ -11,7 +14,15 @@ pub struct Paths {
     pub installs_dir: ChildPath,
     pub shims_dir: ChildPath,
     pub shim_exe: ChildPath,
+    pub completion_script: ChildPath,
     pub log_dir: ChildPath,
+    pub hooks_dir: ChildPath,
+    pub manifests_dir: ChildPath,
+    pub cache_dir: ChildPath,
+    pub stats_file: ChildPath,
+    pub shim_failures_dir: ChildPath,
+    pub allowed_env_file: ChildPath,
+    pub config_file: ChildPath,
     pub global_tool_versions_file: ChildPath,
 }
 
@@ -33,8 +44,16 @@ impl Paths {
         shims_dir.create_dir_all().unwrap();
         let log_dir = root.child("logs");
         log_dir.create_dir_all().unwrap();
+        let hooks_dir = root.child("hooks");
+        let manifests_dir = root.child("manifests");
+        let cache_dir = root.child("cache");
+        let stats_file = root.child("stats.log");
+        let shim_failures_dir = root.child("shim-failures");
+        let allowed_env_file = root.child("allowed-env.sha256");
+        let config_file = root.child("config.toml");
         let shim_exe = root.child("shim.exe");
         shim_exe.touch().unwrap();
+        let completion_script = root.child("completion.ps1");
         let shims_db = root.child("shims.db");
         Paths {
             current_dir,
@@ -43,7 +62,15 @@ impl Paths {
             shims_db,
             shims_dir,
             log_dir,
+            hooks_dir,
+            manifests_dir,
+            cache_dir,
+            stats_file,
+            shim_failures_dir,
+            allowed_env_file,
+            config_file,
             shim_exe,
+            completion_script,
             global_tool_versions_file,
         }
     }
@@ -53,17 +80,29 @@ impl Paths {
             current_dir: self.current_dir.to_path_buf(),
             home_dir: self.home_dir.to_path_buf(),
             installs_dir: self.installs_dir.to_path_buf(),
+            system_installs_dir: None,
             shims_db: self.shims_db.to_path_buf(),
             shims_dir: self.shims_dir.to_path_buf(),
             log_dir: self.log_dir.to_path_buf(),
+            hooks_dir: self.hooks_dir.to_path_buf(),
+            manifests_dir: self.manifests_dir.to_path_buf(),
+            cache_dir: self.cache_dir.to_path_buf(),
+            stats_file: self.stats_file.to_path_buf(),
+            shim_failures_dir: self.shim_failures_dir.to_path_buf(),
+            allowed_env_file: self.allowed_env_file.to_path_buf(),
+            config_file: self.config_file.to_path_buf(),
+            config: Config::default(),
+            policy: OnceLock::from(Policy::default()),
             shim_exe: self.shim_exe.to_path_buf(),
+            completion_script: self.completion_script.to_path_buf(),
             global_tool_versions_file: self.global_tool_versions_file.to_path_buf(),
+            system_tool_versions_file: None,
         }
     }
 
     pub fn generate_shims_db(&self) -> ShimsDB {
         let shims = Shims::new(&self.shims_db, &self.installs_dir, &self.shims_dir, &self.shim_exe).unwrap();
-        let db = shims.generate_db_from_installed_tools().unwrap();
+        let (db, _) = shims.generate_db_from_installed_tools(&Config::default()).unwrap();
         shims.save_db(&db).unwrap();
         db
     }
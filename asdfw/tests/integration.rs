@@ -1,9 +1,24 @@
 mod common;
 
+use asdfw::check::{self, CheckFailure};
 use asdfw::common::*;
+use asdfw::completion::{self, Candidates};
+use asdfw::config::Config;
+use asdfw::doctor::{self, diagnose, Problem};
+use asdfw::errors::{error_hint, error_kind, ErrorKind};
+use asdfw::gc::{self, GarbageKind};
+use asdfw::manifest::SYSTEM_MANIFESTS_DIR;
+use asdfw::policy::Policy;
+use asdfw::project_env;
+use asdfw::shim_health::{self, ShimFailure};
+use asdfw::shims::{ShimData, Shims, SHIMS_MAP_FILE_NAME};
+use asdfw::subcommand::InheritedContext;
 use assert_fs::{prelude::*, TempDir};
 use common::Paths;
 use rstest::rstest;
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
+use std::time::{Duration, SystemTime};
 
 #[test]
 fn which_with_no_tool_configured_should_return_error_no_tool() {
@@ -61,3 +76,965 @@ fn which_should_return_valid_executable_path(
     let result = find_path_for_cmd(&env, &search_exe).unwrap();
     assert_eq!(expected, result, "wrong path from `which`. case: {}", msg);
 }
+
+#[test]
+fn tool_of_returns_tool_and_configured_version() {
+    let versions = "mytool4 0.12";
+    let tmp_dir = TempDir::new().unwrap();
+    let paths = Paths::new(&tmp_dir, versions, None);
+    let env = paths.to_environment();
+    common::fixture_installed_tools(&paths.installs_dir);
+    let db = paths.generate_shims_db();
+    common::test_data_matching_shims(&paths.shims_dir, &db);
+    let result = find_tool_for_cmd(&env, "cmd5").unwrap();
+    assert_eq!(result, ("mytool4".to_string(), Some("0.12".to_string())));
+}
+
+#[test]
+fn tool_of_without_configured_version_returns_only_tool() {
+    let versions = "mytool1 1.2.4";
+    let tmp_dir = TempDir::new().unwrap();
+    let paths = Paths::new(&tmp_dir, versions, None);
+    let env = paths.to_environment();
+    common::fixture_installed_tools(&paths.installs_dir);
+    let db = paths.generate_shims_db();
+    common::test_data_matching_shims(&paths.shims_dir, &db);
+    let result = find_tool_for_cmd(&env, "cmd3.exe").unwrap();
+    assert_eq!(result, ("mytool3".to_string(), None));
+}
+
+#[test]
+fn which_resolves_tools_from_system_install_dir() {
+    let versions = "systool 2.0\r\nmytool1 1.1";
+    let tmp_dir = TempDir::new().unwrap();
+    let paths = Paths::new(&tmp_dir, versions, None);
+    common::fixture_installed_tools(&paths.installs_dir);
+    let system_installs_dir = tmp_dir.child("system").child("installs");
+    let systool_dir = system_installs_dir.child("systool").child("2.0").child("bin");
+    systool_dir.create_dir_all().unwrap();
+    systool_dir.child("sys.exe").touch().unwrap();
+    let mut env = paths.to_environment();
+    env.system_installs_dir = Some(system_installs_dir.to_path_buf());
+    let shims = shims_for_env(&env).unwrap();
+    let (db, _) = shims.generate_db_from_installed_tools(&Config::default()).unwrap();
+    shims.save_db(&db).unwrap();
+    common::test_data_matching_shims(&paths.shims_dir, &db);
+    let expected = systool_dir.child("sys.exe").path().to_string_lossy().into_owned();
+    assert_eq!(find_path_for_cmd(&env, "sys").unwrap(), expected);
+    let user_tool = paths.installs_dir.child("mytool1").child("1.1").child("bin").child("cmd1.exe");
+    assert_eq!(find_path_for_cmd(&env, "cmd1").unwrap(), user_tool.path().to_string_lossy());
+}
+
+#[test]
+fn configured_tools_returns_pinned_versions_with_install_dirs() {
+    let versions = "mytool1 1.1\r\nmytool2 v1.19\r\n";
+    let tmp_dir = TempDir::new().unwrap();
+    let paths = Paths::new(&tmp_dir, versions, Some("mytool2 v1.17\r\nmytool3 9.9\r\n"));
+    let env = paths.to_environment();
+    common::fixture_installed_tools(&paths.installs_dir);
+    let result = configured_tools(&env, &paths.current_dir).unwrap();
+    let expected = vec![
+        ConfiguredTool {
+            tool: "mytool1".to_string(),
+            version: "1.1".to_string(),
+            version_dir: Some(paths.installs_dir.child("mytool1").child("1.1").to_path_buf()),
+        },
+        ConfiguredTool {
+            tool: "mytool2".to_string(),
+            version: "v1.17".to_string(),
+            version_dir: Some(paths.installs_dir.child("mytool2").child("v1.17").to_path_buf()),
+        },
+        ConfiguredTool {
+            tool: "mytool3".to_string(),
+            version: "9.9".to_string(),
+            version_dir: None,
+        },
+    ];
+    assert_eq!(result, expected);
+}
+
+#[test]
+fn mixed_case_commands_and_tools_are_resolved() {
+    let versions = "mytool1 1.2.4\r\nMyTool2 v1.19\r\n";
+    let tmp_dir = TempDir::new().unwrap();
+    let paths = Paths::new(&tmp_dir, versions, Some("MYTOOL1 1.1\r\n"));
+    let env = paths.to_environment();
+    common::fixture_installed_tools(&paths.installs_dir);
+    let db = paths.generate_shims_db();
+    common::test_data_matching_shims(&paths.shims_dir, &db);
+    let bin_dir = paths.installs_dir.child("mytool1").child("1.1").child("bin");
+    let expected = bin_dir.child("cmd1.exe").path().to_string_lossy().into_owned();
+    assert_eq!(find_path_for_cmd(&env, "CMD1").unwrap(), expected);
+    assert_eq!(find_path_for_cmd(&env, "Cmd1.EXE").unwrap(), expected);
+    let configured = configured_tools(&env, &paths.current_dir).unwrap();
+    let configured: Vec<(&str, &str, bool)> = configured
+        .iter()
+        .map(|c| (c.tool.as_str(), c.version.as_str(), c.version_dir.is_some()))
+        .collect();
+    assert_eq!(configured, vec![("mytool1", "1.1", true), ("mytool2", "v1.19", true)]);
+}
+
+#[test]
+fn unreferenced_versions_keeps_global_and_project_versions() {
+    let versions = "mytool1 1.2.4\r\nmytool2 v1.19\r\n";
+    let tmp_dir = TempDir::new().unwrap();
+    let paths = Paths::new(&tmp_dir, versions, None);
+    let project = tmp_dir.child("project");
+    project.child(".tool-versions").write_str("mytool2 v1.17\r\n").unwrap();
+    let env = paths.to_environment();
+    common::fixture_installed_tools(&paths.installs_dir);
+    let result = unreferenced_versions(&env, None, &[project.to_path_buf()]).unwrap();
+    let result: Vec<(&str, &str)> = result.iter().map(|v| (v.tool.as_str(), v.version.as_str())).collect();
+    assert_eq!(result, vec![("mytool1", "1.1"), ("mytool3", "2.5"), ("mytool4", "0.12")]);
+}
+
+#[test]
+fn unreferenced_versions_of_single_tool() {
+    let versions = "mytool1 1.2.4\r\n";
+    let tmp_dir = TempDir::new().unwrap();
+    let paths = Paths::new(&tmp_dir, versions, None);
+    let env = paths.to_environment();
+    common::fixture_installed_tools(&paths.installs_dir);
+    let result = unreferenced_versions(&env, Some("mytool2"), &[]).unwrap();
+    let result: Vec<(&str, &str)> = result.iter().map(|v| (v.tool.as_str(), v.version.as_str())).collect();
+    assert_eq!(result, vec![("mytool2", "v1.17"), ("mytool2", "v1.19")]);
+}
+
+#[rstest]
+#[case("mytool1", "1", Some("1.2.4"))]
+#[case("mytool1", "1.1", None)]
+#[case("mytool2", "1", Some("v1.19"))]
+#[case("mytool2", "v1.17", None)]
+#[case("mytool3", "3", None)]
+fn resolve_version_prefix_picks_highest_installed(
+    #[case] tool: &str,
+    #[case] version: &str,
+    #[case] expected: Option<&str>,
+) {
+    let tmp_dir = TempDir::new().unwrap();
+    let paths = Paths::new(&tmp_dir, "", None);
+    let env = paths.to_environment();
+    common::fixture_installed_tools(&paths.installs_dir);
+    let result = resolve_version_prefix(&env, tool, version).unwrap();
+    assert_eq!(result.as_deref(), expected);
+}
+
+#[test]
+fn ensure_installed_lists_installed_versions() {
+    let tmp_dir = TempDir::new().unwrap();
+    let paths = Paths::new(&tmp_dir, "", None);
+    let env = paths.to_environment();
+    common::fixture_installed_tools(&paths.installs_dir);
+    assert!(ensure_installed(&env, "mytool1", "1.1").is_ok());
+    let err = ensure_installed(&env, "mytool1", "1.99.99").unwrap_err();
+    assert_eq!(error_kind(&err), Some(ErrorKind::NotInstalled));
+    assert!(err.to_string().contains("installed: 1.1, 1.2.4"), "wrong message: {}", err);
+    let err = ensure_installed(&env, "unknown", "1.0").unwrap_err();
+    assert!(err.to_string().contains("no versions are installed"), "wrong message: {}", err);
+}
+
+#[test]
+fn which_honors_versions_pinned_under_tool_alias() {
+    let versions = "tool-one 1.1\r\n";
+    let tmp_dir = TempDir::new().unwrap();
+    let paths = Paths::new(&tmp_dir, versions, None);
+    let mut env = paths.to_environment();
+    env.config = toml::from_str("[tools.mytool1]\naliases = ['tool-one']\n").unwrap();
+    common::fixture_installed_tools(&paths.installs_dir);
+    let db = paths.generate_shims_db();
+    common::test_data_matching_shims(&paths.shims_dir, &db);
+    let expected = paths.installs_dir.child("mytool1").child("1.1").child("bin").child("cmd1.exe");
+    let result = find_path_for_cmd(&env, "cmd1").unwrap();
+    assert_eq!(result, expected.path().to_string_lossy());
+    let configured = configured_tools(&env, &env.current_dir).unwrap();
+    assert_eq!(configured[0].tool, "mytool1");
+}
+
+#[rstest]
+#[case("mytool1", "cmd1", "mytool1", "1.2.4")]
+#[case("mytool1", "cmd1.exe", "mytool1", "1.2.4")]
+#[case("tool-five", "cmd1", "mytool5", "3.0")]
+fn which_in_tool_returns_executable_of_requested_tool(
+    #[case] requested: &str,
+    #[case] cmd: &str,
+    #[case] tool: &str,
+    #[case] version: &str,
+) {
+    let versions = "mytool1 1.2.4\r\nmytool5 3.0\r\n";
+    let tmp_dir = TempDir::new().unwrap();
+    let paths = Paths::new(&tmp_dir, versions, None);
+    let mut env = paths.to_environment();
+    env.config = toml::from_str("[tools.mytool5]\naliases = ['tool-five']\n").unwrap();
+    common::fixture_installed_tools(&paths.installs_dir);
+    let mytool5_bin = paths.installs_dir.child("mytool5").child("3.0").child("bin");
+    mytool5_bin.child("cmd1.exe").touch().unwrap();
+    let expected = paths.installs_dir.child(tool).child(version).child("bin").child("cmd1.exe");
+    let result = find_path_for_cmd_in_tool(&env, cmd, requested).unwrap();
+    assert_eq!(result, expected.path().to_string_lossy());
+}
+
+#[rstest]
+#[case(None, "cmd1", ("mytool1", "1.2.4"))]
+#[case(Some("tool-five"), "cmd6", ("mytool5", "3.0"))]
+#[case(None, "cmd5.exe", ("mytool4", "0.12"))]
+fn which_version_returns_tool_and_version_running_the_command(
+    #[case] requested: Option<&str>,
+    #[case] cmd: &str,
+    #[case] expected: (&str, &str),
+) {
+    let versions = "mytool1 1.2.4\r\nmytool4 0.12\r\nmytool5 3.0\r\n";
+    let tmp_dir = TempDir::new().unwrap();
+    let paths = Paths::new(&tmp_dir, versions, None);
+    let mut env = paths.to_environment();
+    env.config = toml::from_str("[tools.mytool5]\naliases = ['tool-five']\n").unwrap();
+    common::fixture_installed_tools(&paths.installs_dir);
+    let mytool5_bin = paths.installs_dir.child("mytool5").child("3.0").child("bin");
+    mytool5_bin.child("cmd6.exe").touch().unwrap();
+    let db = paths.generate_shims_db();
+    common::test_data_matching_shims(&paths.shims_dir, &db);
+    let (tool, version) = find_version_for_cmd(&env, cmd, requested).unwrap();
+    assert_eq!((tool.as_str(), version.as_str()), expected);
+    let err = find_version_for_cmd(&env, "cmd3", None).unwrap_err();
+    assert_eq!(error_kind(&err), Some(ErrorKind::NoVersion));
+}
+
+#[test]
+fn which_in_tool_not_providing_command_returns_error() {
+    let versions = "mytool2 v1.19\r\n";
+    let tmp_dir = TempDir::new().unwrap();
+    let paths = Paths::new(&tmp_dir, versions, None);
+    let env = paths.to_environment();
+    common::fixture_installed_tools(&paths.installs_dir);
+    let err = find_path_for_cmd_in_tool(&env, "cmd1", "mytool2").unwrap_err();
+    assert!(format!("{}", err).contains("does not provide"), "wrong error: {}", err);
+}
+
+#[test]
+fn which_with_disabled_tool_returns_error() {
+    let versions = "mytool3 2.5\r\n";
+    let tmp_dir = TempDir::new().unwrap();
+    let paths = Paths::new(&tmp_dir, versions, None);
+    let mut env = paths.to_environment();
+    common::fixture_installed_tools(&paths.installs_dir);
+    let db = paths.generate_shims_db();
+    common::test_data_matching_shims(&paths.shims_dir, &db);
+    env.config = toml::from_str("[tools.mytool3]\ndisabled = true\n").unwrap();
+    let err = find_path_for_cmd(&env, "cmd3").unwrap_err();
+    assert!(format!("{}", err).contains("is disabled"), "wrong error: {}", err);
+}
+
+#[test]
+fn doctor_finds_and_fixes_shims_of_removed_tools() {
+    let versions = "mytool3 2.5\r\n";
+    let tmp_dir = TempDir::new().unwrap();
+    let paths = Paths::new(&tmp_dir, versions, None);
+    let env = paths.to_environment();
+    common::fixture_installed_tools(&paths.installs_dir);
+    let db = paths.generate_shims_db();
+    common::test_data_matching_shims(&paths.shims_dir, &db);
+    assert_eq!(diagnose(&env).unwrap(), vec![]);
+    std::fs::remove_dir_all(paths.installs_dir.child("mytool3").path()).unwrap();
+    let problems = diagnose(&env).unwrap();
+    assert_eq!(problems, vec![Problem::OrphanedShim("cmd3.exe".to_string())]);
+    let fixed = doctor::fix(&env, &problems).unwrap();
+    assert_eq!(fixed, problems);
+    assert!(!paths.shims_dir.child("cmd3.exe").exists(), "orphaned shim should be removed");
+    assert_eq!(diagnose(&env).unwrap(), vec![]);
+}
+
+#[test]
+fn diagnose_reports_shims_failing_repeatedly() {
+    let versions = "mytool1 1.0\r\n";
+    let tmp_dir = TempDir::new().unwrap();
+    let paths = Paths::new(&tmp_dir, versions, None);
+    let env = paths.to_environment();
+    common::fixture_installed_tools(&paths.installs_dir);
+    let db = paths.generate_shims_db();
+    common::test_data_matching_shims(&paths.shims_dir, &db);
+    let failure = |reason: &str| ShimFailure {
+        timestamp: 1,
+        command: "cmd1.exe".to_owned(),
+        error_id: "E_NO_VERSION".to_owned(),
+        dir: paths.current_dir.to_path_buf(),
+        reason: reason.to_owned(),
+    };
+    for reason in ["no version", "no version"] {
+        shim_health::record(&env.shim_failures_dir, &failure(reason)).unwrap();
+    }
+    assert_eq!(diagnose(&env).unwrap(), vec![]);
+    shim_health::record(&env.shim_failures_dir, &failure("not installed")).unwrap();
+    let expected = Problem::FailingShim {
+        exe: "cmd1.exe".to_owned(),
+        count: 3,
+        reason: "not installed".to_owned(),
+    };
+    assert_eq!(diagnose(&env).unwrap(), vec![expected]);
+    shim_health::clear(&env.shim_failures_dir, "cmd1.exe").unwrap();
+    assert_eq!(diagnose(&env).unwrap(), vec![]);
+}
+
+#[test]
+fn gc_removes_leftovers_of_removed_versions() {
+    let versions = "mytool3 2.5\r\n";
+    let tmp_dir = TempDir::new().unwrap();
+    let paths = Paths::new(&tmp_dir, versions, None);
+    let env = paths.to_environment();
+    common::fixture_installed_tools(&paths.installs_dir);
+    let db = paths.generate_shims_db();
+    common::test_data_matching_shims(&paths.shims_dir, &db);
+    paths
+        .cache_dir
+        .child("mytool3")
+        .child("2.5")
+        .child("tool.zip")
+        .write_str("kept")
+        .unwrap();
+    paths
+        .cache_dir
+        .child("mytool4")
+        .child("0.12")
+        .child("tool.zip")
+        .write_str("removed")
+        .unwrap();
+    paths.manifests_dir.child("mytool4").child("0.12.sha256").write_str("x").unwrap();
+    paths.manifests_dir.child("mytool3").child("2.5.sha256").write_str("kept").unwrap();
+    // mytool3 2.5 is only installed by the user
+    let system_manifests = paths.manifests_dir.child(SYSTEM_MANIFESTS_DIR);
+    system_manifests.child("mytool3").child("2.5.sha256").write_str("yy").unwrap();
+    paths
+        .installs_dir
+        .child("mytool1")
+        .child(".staging-1.3")
+        .child("bin")
+        .create_dir_all()
+        .unwrap();
+    paths.shim_failures_dir.child("cmd1.exe.log").write_str("x\n").unwrap();
+    paths.shim_failures_dir.child("gone.exe.log").write_str("x\n").unwrap();
+    std::fs::remove_dir_all(paths.installs_dir.child("mytool4").path()).unwrap();
+    let found = gc::find(&env, SystemTime::now()).unwrap();
+    assert!(
+        found.iter().all(|g| g.kind != GarbageKind::StagingDir),
+        "recent staging directory should be kept"
+    );
+    let later = SystemTime::now() + gc::STAGING_DIR_MIN_AGE + Duration::from_secs(60);
+    let garbage = gc::find(&env, later).unwrap();
+    let found: Vec<(GarbageKind, u64)> = garbage.iter().map(|g| (g.kind, g.size)).collect();
+    let expected = vec![
+        (GarbageKind::CachedArtifact, 7),
+        (GarbageKind::Manifest, 2),
+        (GarbageKind::Manifest, 1),
+        (GarbageKind::StagingDir, 0),
+        (GarbageKind::OrphanedShim, 0),
+        (GarbageKind::OrphanedShim, 0),
+        (GarbageKind::ShimFailures, 2),
+    ];
+    assert_eq!(found, expected);
+    assert_eq!(gc::collect(&env, &garbage).unwrap(), 12);
+    assert!(paths.cache_dir.child("mytool3").child("2.5").child("tool.zip").exists());
+    assert!(paths.manifests_dir.child("mytool3").child("2.5.sha256").exists());
+    assert!(!paths.shims_dir.child("cmd4.exe").exists(), "orphaned shim should be removed");
+    assert_eq!(gc::find(&env, later).unwrap(), vec![]);
+    let much_later = SystemTime::now() + gc::SHIM_FAILURES_MAX_AGE + Duration::from_secs(60);
+    let stale: Vec<PathBuf> = gc::find(&env, much_later).unwrap().into_iter().map(|g| g.path).collect();
+    assert_eq!(stale, vec![paths.shim_failures_dir.child("cmd1.exe.log").to_path_buf()]);
+}
+
+#[rstest]
+#[case(false)]
+#[case(true)]
+fn install_version_updates_shims_unless_disabled(#[case] no_reshim: bool) {
+    let tmp_dir = TempDir::new().unwrap();
+    let paths = Paths::new(&tmp_dir, "mytool5 1.0\r\n", None);
+    let env = paths.to_environment();
+    common::fixture_installed_tools(&paths.installs_dir);
+    let db = paths.generate_shims_db();
+    common::test_data_matching_shims(&paths.shims_dir, &db);
+    let installer = tmp_dir.child("cmd9.exe");
+    installer.write_str("installer").unwrap();
+    let update = install_version(&env, "mytool5", "1.0", installer.path(), no_reshim).unwrap();
+    let bin = paths.installs_dir.child("mytool5").child("1.0").child("bin");
+    assert!(bin.child("cmd9.exe").path().is_file(), "version should be installed");
+    let manifest = paths.manifests_dir.child("mytool5").child("1.0.sha256");
+    assert!(manifest.exists(), "manifest should be recorded at install time");
+    match update {
+        ShimsUpdate::Skipped => assert!(no_reshim),
+        ShimsUpdate::Updated(changes) => assert_eq!(changes.created, vec!["cmd9.exe"]),
+        ShimsUpdate::Failed(err) => panic!("updating shims failed: {:#}", err),
+    }
+    assert_eq!(paths.shims_dir.child("cmd9.exe").exists(), !no_reshim);
+}
+
+#[rstest]
+#[case(false)]
+#[case(true)]
+fn uninstall_versions_updates_shims_unless_disabled(#[case] no_reshim: bool) {
+    let tmp_dir = TempDir::new().unwrap();
+    let paths = Paths::new(&tmp_dir, "mytool3 2.5\r\n", None);
+    let env = paths.to_environment();
+    common::fixture_installed_tools(&paths.installs_dir);
+    let db = paths.generate_shims_db();
+    common::test_data_matching_shims(&paths.shims_dir, &db);
+    let installed = shims_for_env(&env).unwrap().find_installed_version("mytool3", "2.5").unwrap();
+    let update = uninstall_versions(&env, &[installed], no_reshim).unwrap();
+    assert!(
+        !paths.installs_dir.child("mytool3").child("2.5").exists(),
+        "version should be removed"
+    );
+    match update {
+        ShimsUpdate::Skipped => assert!(no_reshim),
+        ShimsUpdate::Updated(changes) => assert_eq!(changes.removed, vec!["cmd3.exe"]),
+        ShimsUpdate::Failed(err) => panic!("updating shims failed: {:#}", err),
+    }
+    assert_eq!(paths.shims_dir.child("cmd3.exe").exists(), no_reshim);
+}
+
+#[cfg(windows)]
+#[test]
+fn install_version_fails_when_pre_install_hook_fails() {
+    let tmp_dir = TempDir::new().unwrap();
+    let paths = Paths::new(&tmp_dir, "mytool5 1.0\r\n", None);
+    let env = paths.to_environment();
+    paths.hooks_dir.child("pre-install.cmd").write_str("@exit /b 3\r\n").unwrap();
+    let installer = tmp_dir.child("cmd9.exe");
+    installer.write_str("installer").unwrap();
+    let err = install_version(&env, "mytool5", "1.0", installer.path(), true).unwrap_err();
+    assert!(format!("{:#}", err).contains("pre-install"), "wrong error: {:#}", err);
+    assert!(!paths.installs_dir.child("mytool5").exists(), "nothing should be extracted");
+}
+
+#[test]
+fn install_version_succeeds_when_updating_shims_fails() {
+    let tmp_dir = TempDir::new().unwrap();
+    let paths = Paths::new(&tmp_dir, "mytool5 1.0\r\n", None);
+    let env = paths.to_environment();
+    common::fixture_installed_tools(&paths.installs_dir);
+    let db = paths.generate_shims_db();
+    common::test_data_matching_shims(&paths.shims_dir, &db);
+    // mytool1 provides cmd1.exe too, which fails reshim with the default
+    // conflict policy
+    let installer = tmp_dir.child("cmd1.exe");
+    installer.write_str("installer").unwrap();
+    let update = install_version(&env, "mytool5", "1.0", installer.path(), false).unwrap();
+    assert!(matches!(update, ShimsUpdate::Failed(_)), "unexpected update: {:?}", update);
+    let bin = paths.installs_dir.child("mytool5").child("1.0").child("bin");
+    assert!(bin.child("cmd1.exe").path().is_file(), "version should be installed");
+}
+
+#[test]
+fn incremental_reshim_creates_new_and_removes_dangling_shims() {
+    let versions = "mytool3 2.5\r\n";
+    let tmp_dir = TempDir::new().unwrap();
+    let paths = Paths::new(&tmp_dir, versions, None);
+    let env = paths.to_environment();
+    common::fixture_installed_tools(&paths.installs_dir);
+    let db = paths.generate_shims_db();
+    common::test_data_matching_shims(&paths.shims_dir, &db);
+    std::fs::remove_dir_all(paths.installs_dir.child("mytool3").path()).unwrap();
+    let mytool5_bin = paths.installs_dir.child("mytool5").child("1.0").child("bin");
+    mytool5_bin.child("cmd6.exe").touch().unwrap();
+    let shims = shims_for_env(&env).unwrap();
+    let changes = incremental_reshim(&env, &shims).unwrap();
+    assert_eq!(changes.created, vec!["cmd6.exe"]);
+    assert_eq!(changes.removed, vec!["cmd3.exe"]);
+    assert!(paths.shims_dir.child("cmd6.exe").exists(), "new shim should be created");
+    assert!(!paths.shims_dir.child("cmd3.exe").exists(), "dangling shim should be removed");
+}
+
+#[rstest]
+#[case("mytool1", Some(("1.2.4", true)))]
+#[case("mytool2", Some(("v2.0", false)))]
+#[case("mytool3", None)]
+fn current_version_of_single_tool(#[case] tool: &str, #[case] expected: Option<(&str, bool)>) {
+    let versions = "mytool1 1.2.4\r\nmytool2 v2.0\r\n";
+    let tmp_dir = TempDir::new().unwrap();
+    let paths = Paths::new(&tmp_dir, versions, None);
+    let env = paths.to_environment();
+    common::fixture_installed_tools(&paths.installs_dir);
+    let result = current_version(&env, tool).unwrap();
+    let result = result.as_ref().map(|c| (c.version.as_str(), c.version_dir.is_some()));
+    assert_eq!(result, expected);
+}
+
+#[test]
+fn scripts_are_resolved_with_the_configured_interpreter() {
+    let versions = "mytool6 1.0\r\nmytool7 3.10\r\n";
+    let tmp_dir = TempDir::new().unwrap();
+    let paths = Paths::new(&tmp_dir, versions, None);
+    let mut env = paths.to_environment();
+    env.config = toml::from_str("[tools.mytool6.scripts]\nhttp = 'python'\n").unwrap();
+    let script = paths.installs_dir.child("mytool6").child("1.0").child("bin").child("http");
+    script.touch().unwrap();
+    let python = paths.installs_dir.child("mytool7").child("3.10").child("bin").child("python.exe");
+    python.touch().unwrap();
+    let shims = shims_for_env(&env).unwrap();
+    let (db, _) = shims.generate_db_from_installed_tools(&env.config).unwrap();
+    shims.save_db(&db).unwrap();
+    common::test_data_matching_shims(&paths.shims_dir, &db);
+    let resolved = resolve_executable(&env, &shims, "http.exe", "mytool6", "1.0").unwrap().unwrap();
+    assert_eq!(resolved.program, python.path());
+    assert_eq!(resolved.script.as_deref(), Some(script.path()));
+    let result = find_path_for_cmd(&env, "http").unwrap();
+    assert_eq!(result, script.path().to_string_lossy());
+}
+
+#[test]
+fn invocation_of_script_runs_its_interpreter_with_default_args() {
+    let versions = "mytool6 1.0\r\nmytool7 3.10\r\n";
+    let tmp_dir = TempDir::new().unwrap();
+    let paths = Paths::new(&tmp_dir, versions, None);
+    let mut env = paths.to_environment();
+    let config = "[tools.mytool6.scripts]\nhttp = 'python'\n[tools.mytool6.default_args]\nhttp = ['-v']\n";
+    env.config = toml::from_str(config).unwrap();
+    let script = paths.installs_dir.child("mytool6").child("1.0").child("bin").child("http");
+    script.touch().unwrap();
+    let python = paths.installs_dir.child("mytool7").child("3.10").child("bin").child("python.exe");
+    python.touch().unwrap();
+    let shims = shims_for_env(&env).unwrap();
+    let (db, _) = shims.generate_db_from_installed_tools(&env.config).unwrap();
+    shims.save_db(&db).unwrap();
+    common::test_data_matching_shims(&paths.shims_dir, &db);
+    let path = |child: &assert_fs::fixture::ChildPath| child.path().to_string_lossy().into_owned();
+    let result = find_invocation_for_cmd(&env, "http", None).unwrap();
+    assert_eq!(result, vec![path(&python), path(&script), "-v".to_owned()]);
+    let result = find_invocation_for_cmd(&env, "python", Some("mytool7")).unwrap();
+    assert_eq!(result, vec![path(&python)]);
+}
+
+#[rstest]
+#[case(r"C:\bin\tool.exe", vec![r"C:\bin\tool.exe"])]
+#[case(r"C:\bin\tool.CMD", vec!["cmd.exe", "/d", "/c", r"C:\bin\tool.CMD"])]
+#[case(r"C:\bin\tool.ps1", vec!["powershell.exe", "-NoProfile", "-ExecutionPolicy", "Bypass", "-File", r"C:\bin\tool.ps1"])]
+fn invocation_launches_batch_and_powershell_scripts(#[case] program: &str, #[case] expected: Vec<&str>) {
+    assert_eq!(invocation(Path::new(program), None, &[]), expected);
+}
+
+#[rstest]
+#[case("no-cmd", None, ErrorKind::NoTool)]
+#[case("cmd1", None, ErrorKind::NoVersion)]
+#[case("cmd1", Some("mytool1 9.9\r\n"), ErrorKind::NotInstalled)]
+#[case("cmd3", Some("mytool3 2.5 extra\r\n"), ErrorKind::InvalidToolVersions)]
+fn which_failures_are_classified(#[case] cmd: &str, #[case] local: Option<&str>, #[case] expected: ErrorKind) {
+    let versions = "mytool3 2.5\r\n";
+    let tmp_dir = TempDir::new().unwrap();
+    let paths = Paths::new(&tmp_dir, versions, local);
+    let env = paths.to_environment();
+    common::fixture_installed_tools(&paths.installs_dir);
+    let db = paths.generate_shims_db();
+    common::test_data_matching_shims(&paths.shims_dir, &db);
+    let err = find_path_for_cmd(&env, cmd).unwrap_err();
+    assert_eq!(error_kind(&err), Some(expected), "wrong error: {:#}", err);
+    assert!(format!("{}", err).starts_with(expected.id()), "missing identifier: {}", err);
+}
+
+#[test]
+fn lenient_parsing_skips_corrupt_lines_in_global_file() {
+    let versions = "mytool2 v1.19 extra\r\nmytool1 1.1\r\n";
+    let tmp_dir = TempDir::new().unwrap();
+    let paths = Paths::new(&tmp_dir, versions, None);
+    let mut env = paths.to_environment();
+    common::fixture_installed_tools(&paths.installs_dir);
+    let db = paths.generate_shims_db();
+    common::test_data_matching_shims(&paths.shims_dir, &db);
+    let err = find_path_for_cmd(&env, "cmd1").unwrap_err();
+    assert_eq!(error_kind(&err), Some(ErrorKind::InvalidToolVersions), "wrong error: {:#}", err);
+    env.config = toml::from_str("tool_versions_parsing = 'lenient'\n").unwrap();
+    let expected = paths.installs_dir.child("mytool1").child("1.1").child("bin").child("cmd1.exe");
+    assert_eq!(find_path_for_cmd(&env, "cmd1").unwrap(), expected.path().to_string_lossy());
+    let configured = configured_tools(&env, &env.current_dir).unwrap();
+    let configured: Vec<&str> = configured.iter().map(|c| c.tool.as_str()).collect();
+    assert_eq!(configured, vec!["mytool1"]);
+}
+
+#[test]
+fn path_versions_resolve_to_the_provided_directory() {
+    let tmp_dir = TempDir::new().unwrap();
+    let build = tmp_dir.child("mytool1-build");
+    build.child("bin").child("cmd1.exe").touch().unwrap();
+    let versions = format!("mytool1 path:{}\r\n", build.path().display());
+    let paths = Paths::new(&tmp_dir, &versions, None);
+    let env = paths.to_environment();
+    common::fixture_installed_tools(&paths.installs_dir);
+    let db = paths.generate_shims_db();
+    common::test_data_matching_shims(&paths.shims_dir, &db);
+    let resolved = resolve_cmd(&env, "cmd1", &env.current_dir).unwrap();
+    assert_eq!(resolved.install_dir, build.path());
+    assert_eq!(resolved.executable.program, build.child("bin").child("cmd1.exe").path());
+    let configured = current_version(&env, "mytool1").unwrap().unwrap();
+    assert_eq!(configured.version_dir.as_deref(), Some(build.path()));
+
+    std::fs::remove_dir_all(build.path()).unwrap();
+    let err = resolve_cmd(&env, "cmd1", &env.current_dir).unwrap_err();
+    assert_eq!(error_kind(&err), Some(ErrorKind::NotInstalled), "wrong error: {:#}", err);
+    let hint = error_hint(&err).unwrap();
+    assert!(hint.contains("mytool1-build"), "wrong hint: {}", hint);
+}
+
+#[test]
+fn system_defaults_apply_unless_overridden_by_the_user() {
+    let versions = "mytool1 1.2.4\r\n";
+    let tmp_dir = TempDir::new().unwrap();
+    let paths = Paths::new(&tmp_dir, versions, None);
+    let mut env = paths.to_environment();
+    let system_file = tmp_dir.child("system").child("tool-versions");
+    system_file.write_str("mytool1 1.1\r\nmytool2 v1.19\r\n").unwrap();
+    env.system_tool_versions_file = Some(system_file.to_path_buf());
+    common::fixture_installed_tools(&paths.installs_dir);
+    let configured = configured_tools(&env, &env.current_dir).unwrap();
+    let configured: Vec<(&str, &str)> = configured.iter().map(|c| (c.tool.as_str(), c.version.as_str())).collect();
+    assert_eq!(configured, vec![("mytool1", "1.2.4"), ("mytool2", "v1.19")]);
+
+    // Users without a global file get the system defaults too
+    std::fs::remove_file(paths.global_tool_versions_file.path()).unwrap();
+    let db = paths.generate_shims_db();
+    common::test_data_matching_shims(&paths.shims_dir, &db);
+    assert_eq!(resolve_cmd(&env, "cmd1", &env.current_dir).unwrap().version, "1.1");
+    let configured = configured_tools(&env, &env.current_dir).unwrap();
+    let configured: Vec<(&str, &str)> = configured.iter().map(|c| (c.tool.as_str(), c.version.as_str())).collect();
+    assert_eq!(configured, vec![("mytool1", "1.1"), ("mytool2", "v1.19")]);
+}
+
+#[test]
+fn resolve_cmd_uses_version_configured_in_provided_dir() {
+    let versions = "mytool1 1.2.4\r\n";
+    let tmp_dir = TempDir::new().unwrap();
+    let paths = Paths::new(&tmp_dir, versions, None);
+    let env = paths.to_environment();
+    common::fixture_installed_tools(&paths.installs_dir);
+    let db = paths.generate_shims_db();
+    common::test_data_matching_shims(&paths.shims_dir, &db);
+    let project = tmp_dir.child("project");
+    project.child(".tool-versions").write_str("mytool1 1.1\r\n").unwrap();
+    let resolved = resolve_cmd(&env, "cmd1", project.path()).unwrap();
+    assert_eq!(resolved.version, "1.1");
+    assert_eq!(resolved.install_dir, paths.installs_dir.child("mytool1").child("1.1").path());
+    let resolved = resolve_cmd(&env, "cmd1", &env.current_dir).unwrap();
+    assert_eq!(resolved.version, "1.2.4");
+}
+
+#[rstest]
+#[case("nodejs,python", "python", true)]
+#[case(" nodejs , python ", "nodejs", true)]
+#[case("node", "nodejs", true)]
+#[case("nodejs", "python", false)]
+#[case("", "nodejs", false)]
+fn is_skipped_matches_listed_tools_and_aliases(#[case] skip: &str, #[case] tool: &str, #[case] expected: bool) {
+    let tmp_dir = TempDir::new().unwrap();
+    let paths = Paths::new(&tmp_dir, "", None);
+    let mut env = paths.to_environment();
+    env.config = toml::from_str("[tools.nodejs]\naliases = ['node']\n").unwrap();
+    assert_eq!(is_skipped(&env, skip, tool), expected);
+}
+
+#[test]
+fn command_env_expands_tool_env_and_lets_project_override() {
+    let versions = "mytool1 1.2.4\r\n";
+    let tmp_dir = TempDir::new().unwrap();
+    let paths = Paths::new(&tmp_dir, versions, None);
+    let mut env = paths.to_environment();
+    env.config =
+        toml::from_str("[tools.mytool1.env]\nMYTOOL_PREFIX = '{install_dir}/global'\nMYTOOL_ID = '{tool}-{version}'\n")
+            .unwrap();
+    common::fixture_installed_tools(&paths.installs_dir);
+    let db = paths.generate_shims_db();
+    common::test_data_matching_shims(&paths.shims_dir, &db);
+    let resolved = resolve_cmd(&env, "cmd1", &env.current_dir).unwrap();
+    let install_dir = paths.installs_dir.child("mytool1").child("1.2.4");
+    let expected = vec![
+        ("MYTOOL_ID".to_owned(), "mytool1-1.2.4".to_owned()),
+        (
+            "MYTOOL_PREFIX".to_owned(),
+            format!("{}/global", install_dir.path().to_string_lossy()),
+        ),
+    ];
+    assert_eq!(command_env(&env, &resolved, &env.current_dir, "").unwrap(), expected);
+
+    let project = tmp_dir.child("project");
+    let env_file = project.child(".asdfw.env");
+    env_file.write_str("MYTOOL_ID=custom\n").unwrap();
+    let vars = command_env(&env, &resolved, project.path(), "").unwrap();
+    assert_eq!(vars.len(), 2, "loaded a file that was not allowed");
+    project_env::allow(&env.allowed_env_file, env_file.path()).unwrap();
+    let vars = command_env(&env, &resolved, project.path(), "").unwrap();
+    assert_eq!(vars.last(), Some(&("MYTOOL_ID".to_owned(), "custom".to_owned())));
+}
+
+#[rstest]
+#[case("java,mytool2", true)]
+#[case(" java , mt2 ", true)]
+#[case(",mt2", true)]
+#[case("java", false)]
+#[case("", false)]
+fn command_env_skips_tool_env_listed_in_no_plugin_env(#[case] no_plugin_env: &str, #[case] disabled: bool) {
+    let versions = "mytool2 v1.19\r\n";
+    let tmp_dir = TempDir::new().unwrap();
+    let paths = Paths::new(&tmp_dir, versions, None);
+    let mut env = paths.to_environment();
+    env.config =
+        toml::from_str("[tools.mytool2]\naliases = ['mt2']\n[tools.mytool2.env]\nMYTOOL_HOME = '{install_dir}'\n")
+            .unwrap();
+    common::fixture_installed_tools(&paths.installs_dir);
+    let db = paths.generate_shims_db();
+    common::test_data_matching_shims(&paths.shims_dir, &db);
+    assert_eq!(is_plugin_env_disabled(&env, no_plugin_env, "mytool2"), disabled);
+    let resolved = resolve_cmd(&env, "cmd2", &env.current_dir).unwrap();
+    let vars = command_env(&env, &resolved, &env.current_dir, no_plugin_env).unwrap();
+    assert_eq!(vars.is_empty(), disabled);
+}
+
+#[test]
+fn find_in_path_skips_shims_dir() {
+    let tmp_dir = TempDir::new().unwrap();
+    let shims_dir = tmp_dir.child("shims");
+    shims_dir.child("kubectl.exe").touch().unwrap();
+    let project_shims_dir = tmp_dir.child("project").child(".asdfw").child("shims");
+    project_shims_dir.child("kubectl.exe").touch().unwrap();
+    let system_dir = tmp_dir.child("system");
+    system_dir.child("kubectl.exe").touch().unwrap();
+    let path = std::env::join_paths([
+        project_shims_dir.path(),
+        shims_dir.path(),
+        tmp_dir.child("missing").path(),
+        system_dir.path(),
+    ])
+    .unwrap();
+    let shims_dirs = [project_shims_dir.to_path_buf(), shims_dir.to_path_buf()];
+    let expected = system_dir.child("kubectl.exe").to_path_buf();
+    assert_eq!(find_in_path("kubectl.exe", &path, &shims_dirs), Some(expected));
+    assert_eq!(find_in_path("helm.exe", &path, &shims_dirs), None);
+}
+
+#[test]
+fn extra_shims_dirs_are_found_in_parent_directories() {
+    let tmp_dir = TempDir::new().unwrap();
+    let paths = Paths::new(&tmp_dir, "mytool1 1.2.4\r\n", None);
+    let project_shims_dir = paths.current_dir.child(".asdfw").child("shims");
+    project_shims_dir.create_dir_all().unwrap();
+    let nested = paths.current_dir.child("src").child("app");
+    nested.create_dir_all().unwrap();
+    let mut env = paths.to_environment();
+    env.config = toml::from_str("shim_dirs = ['.asdfw/shims', '.missing', '/no/such/dir']\n").unwrap();
+    assert_eq!(extra_shims_dirs(&env, nested.path()), vec![project_shims_dir.to_path_buf()]);
+}
+
+#[test]
+fn project_shims_dir_maps_commands_missing_from_the_shims_db() {
+    let tmp_dir = TempDir::new().unwrap();
+    let paths = Paths::new(&tmp_dir, "mytool4 0.12\r\n", None);
+    common::fixture_installed_tools(&paths.installs_dir);
+    let mut db = paths.generate_shims_db();
+    // e.g. skipped as a conflict or not reshimmed yet
+    db.remove("cmd4.exe");
+    let shims = Shims::new(&paths.shims_db, &paths.installs_dir, &paths.shims_dir, &paths.shim_exe).unwrap();
+    shims.save_db(&db).unwrap();
+    let mut env = paths.to_environment();
+    env.config = toml::from_str("shim_dirs = ['.asdfw/shims']\n").unwrap();
+    let err = resolve_cmd(&env, "cmd4", &env.current_dir).unwrap_err();
+    assert_eq!(error_kind(&err), Some(ErrorKind::NoTool));
+
+    let project_shims_dir = paths.current_dir.child(".asdfw").child("shims");
+    project_shims_dir.child("cmd4.exe").touch().unwrap();
+    project_shims_dir
+        .child(SHIMS_MAP_FILE_NAME)
+        .write_str("\"cmd4.exe\" = \"mytool4\"\n")
+        .unwrap();
+    let resolved = resolve_cmd(&env, "cmd4", &env.current_dir).unwrap();
+    assert_eq!(resolved.tool, "mytool4");
+    assert_eq!(resolved.version, "0.12");
+    let expected = paths.installs_dir.child("mytool4").child("0.12").child("bin").child("cmd4.exe");
+    assert_eq!(resolved.executable.program, expected.path());
+}
+
+#[test]
+fn with_current_dir_overrides_the_directory_versions_are_resolved_for() {
+    let tmp_dir = TempDir::new().unwrap();
+    let paths = Paths::new(&tmp_dir, "mytool1 1.2.4\r\n", None);
+    common::fixture_installed_tools(&paths.installs_dir);
+    let project = paths.current_dir.child("project");
+    project.child(".tool-versions").write_str("mytool1 1.1\r\n").unwrap();
+    let env = paths.to_environment().with_current_dir(Path::new("project")).unwrap();
+    assert_eq!(env.current_dir, project.path());
+    assert_eq!(current_version(&env, "mytool1").unwrap().unwrap().version, "1.1");
+    assert!(paths.to_environment().with_current_dir(Path::new("missing")).is_err());
+}
+
+#[rstest]
+#[case(Candidates::Tools, vec!["mt2", "mytool1", "mytool2", "mytool3", "mytool4"])]
+#[case(Candidates::Versions("mt2"), vec!["v1.17", "v1.19"])]
+#[case(Candidates::Versions("unknown"), vec![])]
+#[case(Candidates::Commands, vec!["cmd1", "cmd2", "cmd3", "cmd4", "cmd5"])]
+fn completion_candidates_list_installed_state(#[case] what: Candidates, #[case] expected: Vec<&str>) {
+    let tmp_dir = TempDir::new().unwrap();
+    let paths = Paths::new(&tmp_dir, "", None);
+    let mut env = paths.to_environment();
+    env.config = toml::from_str("[tools.mytool2]\naliases = ['mt2']\n").unwrap();
+    common::fixture_installed_tools(&paths.installs_dir);
+    paths.generate_shims_db();
+    assert_eq!(completion::candidates(&env, what).unwrap(), expected);
+}
+
+#[test]
+fn tools_overview_flags_inconsistencies() {
+    let versions = "mytool1 1.2.4\r\nmytool2 v1.19\r\n";
+    let tmp_dir = TempDir::new().unwrap();
+    let paths = Paths::new(&tmp_dir, versions, Some("mytool4 0.13\r\n"));
+    let mut env = paths.to_environment();
+    env.config = toml::from_str("[tools.mytool3]\ndisabled = true\n[tools.mytool5]\naliases = ['mt5']\n").unwrap();
+    common::fixture_installed_tools(&paths.installs_dir);
+    let overview = tools_overview(&env, &paths.current_dir).unwrap();
+    type Row<'a> = (&'a str, bool, bool, Vec<&'a str>, Option<&'a str>, &'a [ToolIssue]);
+    let overview: Vec<Row> = overview
+        .iter()
+        .map(|t| {
+            let installed = t.installed.iter().map(String::as_str).collect();
+            (
+                t.tool.as_str(),
+                t.configured,
+                t.disabled,
+                installed,
+                t.pinned.as_deref(),
+                t.issues.as_slice(),
+            )
+        })
+        .collect();
+    #[rustfmt::skip]
+    let expected: Vec<Row> = vec![
+        ("mytool1", false, false, vec!["1.1", "1.2.4"], Some("1.2.4"), &[]),
+        ("mytool2", false, false, vec!["v1.17", "v1.19"], Some("v1.19"), &[]),
+        ("mytool3", true, true, vec!["2.5"], None, &[]),
+        ("mytool4", false, false, vec!["0.12"], Some("0.13"), &[ToolIssue::PinnedNotInstalled]),
+        ("mytool5", true, false, vec![], None, &[ToolIssue::NoInstalls]),
+    ];
+    assert_eq!(overview, expected);
+}
+
+#[test]
+fn concurrent_resolutions_while_the_shims_db_is_rewritten() {
+    let versions = "mytool1 1.2.4\r\nmytool4 0.12\r\n";
+    let tmp_dir = TempDir::new().unwrap();
+    let paths = Paths::new(&tmp_dir, versions, Some("mytool2 v1.17\r\n"));
+    let env = paths.to_environment();
+    common::fixture_installed_tools(&paths.installs_dir);
+    let db = paths.generate_shims_db();
+    common::test_data_matching_shims(&paths.shims_dir, &db);
+    let shims = shims_for_env(&env).unwrap();
+    std::thread::scope(|scope| {
+        let resolvers: Vec<_> = (0..32)
+            .map(|i| {
+                let env = &env;
+                scope.spawn(move || {
+                    let cmd = ["cmd1", "cmd2", "cmd4", "cmd5"][i % 4];
+                    for _ in 0..25 {
+                        find_path_for_cmd(env, cmd).unwrap();
+                    }
+                })
+            })
+            .collect();
+        while resolvers.iter().any(|resolver| !resolver.is_finished()) {
+            shims.save_db(&db).unwrap();
+        }
+        for resolver in resolvers {
+            resolver.join().expect("resolution failed while the shims db was rewritten");
+        }
+    });
+}
+
+#[test]
+fn check_reports_pinned_tools_not_ready() {
+    let versions = "mytool1 1.2.4\r\nmytool3 2.5\r\n";
+    let tmp_dir = TempDir::new().unwrap();
+    let paths = Paths::new(&tmp_dir, versions, Some("mytool2 v1.20\r\nmytool4 0.12\r\n"));
+    let mut env = paths.to_environment();
+    env.policy = OnceLock::from(toml::from_str::<Policy>("[tools.mytool3]\nforbidden = ['< 3']\n").unwrap());
+    common::fixture_installed_tools(&paths.installs_dir);
+    let db = paths.generate_shims_db();
+    common::test_data_matching_shims(&paths.shims_dir, &db);
+    paths.shims_dir.child("cmd4.exe").write_str("old shim").unwrap();
+    std::fs::remove_file(paths.shims_dir.child("cmd5.exe").path()).unwrap();
+    let report = check::check(&env, &paths.current_dir).unwrap();
+    assert_eq!(report.checked, 4);
+    let failures: Vec<String> = report.failures.iter().map(ToString::to_string).collect();
+    assert_eq!(
+        failures,
+        vec![
+            "mytool2 v1.20 is not installed",
+            "mytool3 2.5 is forbidden by policy (< 3)",
+            "mytool4 has an out-of-date shim for cmd4.exe",
+            "mytool4 has no shim for cmd5.exe",
+        ]
+    );
+    assert!(matches!(&report.failures[0], CheckFailure::NotInstalled { tool, .. } if tool == "mytool2"));
+}
+
+#[test]
+fn check_only_reports_executables_reshim_maps_to_the_tool() {
+    let tmp_dir = TempDir::new().unwrap();
+    let paths = Paths::new(&tmp_dir, "mytool1 1.2.4\r\nmytool3 2.5\r\nmytool4 0.12\r\n", None);
+    let mut env = paths.to_environment();
+    env.config = toml::from_str("conflict_policy = 'skip'\n[tools.mytool1]\ndisabled = true\n").unwrap();
+    common::fixture_installed_tools(&paths.installs_dir);
+    paths
+        .installs_dir
+        .child("mytool3")
+        .child("2.5")
+        .child("bin")
+        .child("cmd4.exe")
+        .touch()
+        .unwrap();
+    paths
+        .installs_dir
+        .child("mytool4")
+        .child("0.12")
+        .child("bin")
+        .child("cmd6.exe")
+        .touch()
+        .unwrap();
+    let shims = Shims::new(&paths.shims_db, &paths.installs_dir, &paths.shims_dir, &paths.shim_exe).unwrap();
+    let (mut db, _) = shims.generate_db_from_installed_tools(&env.config).unwrap();
+    assert!(!db.contains_key("cmd4.exe"), "conflicting executable should be skipped");
+    db.insert("cmd6.exe".to_owned(), ShimData::Tool("mytool3".to_owned()));
+    shims.save_db(&db).unwrap();
+    common::test_data_matching_shims(&paths.shims_dir, &db);
+    let report = check::check(&env, &paths.current_dir).unwrap();
+    let failures: Vec<String> = report.failures.iter().map(ToString::to_string).collect();
+    assert_eq!(failures, vec!["mytool4 has no shim for cmd6.exe (the shims db maps it to mytool3)"]);
+}
+
+#[test]
+fn resolve_reentrant_reuses_inherited_version_only_when_unchanged() {
+    let tmp_dir = TempDir::new().unwrap();
+    let paths = Paths::new(&tmp_dir, "mytool1 1.2.4\r\n", None);
+    common::fixture_installed_tools(&paths.installs_dir);
+    let mut env = paths.to_environment();
+    let install_dir = paths.installs_dir.child("mytool1").child("1.2.4");
+    let inherited = InheritedContext {
+        tool: "mytool1".to_owned(),
+        version: "1.2.4".to_owned(),
+        install_dir: install_dir.to_path_buf(),
+        dir: env.current_dir.clone(),
+    };
+    let resolved = resolve_reentrant(&env, "cmd1.exe", &inherited).unwrap().unwrap();
+    assert_eq!(resolved.executable.program, install_dir.child("bin").child("cmd1.exe").path());
+    assert_eq!(resolved.version, "1.2.4");
+    assert!(resolve_reentrant(&env, "cmd2.exe", &inherited).unwrap().is_none());
+
+    let project = paths.current_dir.child("project");
+    project.child(".tool-versions").write_str("mytool1 1.1\r\n").unwrap();
+    let moved = InheritedContext {
+        dir: project.to_path_buf(),
+        ..inherited.clone()
+    };
+    assert!(resolve_reentrant(&env, "cmd1.exe", &moved).unwrap().is_none(), "changed directory");
+    let env_in_project = paths.to_environment().with_current_dir(project.path()).unwrap();
+    assert!(
+        resolve_reentrant(&env_in_project, "cmd1.exe", &moved).unwrap().is_none(),
+        "changed version"
+    );
+
+    env.config = toml::from_str("[tools.mytool1.default_args]\nCmd1 = ['--verbose']\n").unwrap();
+    let resolved = resolve_reentrant(&env, "cmd1.exe", &inherited).unwrap().unwrap();
+    assert_eq!(resolved.context(&env, &[]).default_args, ["--verbose"], "default args should apply");
+
+    env.config = toml::from_str("[tools.mytool1]\ndisabled = true\n").unwrap();
+    let err = resolve_reentrant(&env, "cmd1.exe", &inherited).unwrap_err();
+    assert_eq!(error_kind(&err), Some(ErrorKind::ToolDisabled));
+}